@@ -0,0 +1,226 @@
+use brick_game_wasm::bootstrap::Action;
+use brick_game_wasm::error::invalid;
+use brick_game_wasm::game::tetris::schema::{self, Migration};
+use crossterm::event::KeyCode;
+use std::io::{self, Read, Write};
+
+// Layout changes since the first file, version 1 only added the header
+const MIGRATIONS: [Migration; 3] = [schema::unchanged, add_hold, add_rotate_left];
+
+// Keys tried in order for the hold in files from before it, the first one that is
+// still free gets it
+const HOLD_KEYS: [&str; 4] = ["c", "h", "Tab", "Insert"];
+const ROTATE_LEFT_KEYS: [&str; 4] = ["z", "q", "x", "Delete"];
+
+// Every action a key can be bound to, with its name in binding files
+const ACTIONS: [(&str, Action); 9] = [
+    ("up", Action::Up),
+    ("down", Action::Down),
+    ("left", Action::Left),
+    ("right", Action::Right),
+    ("drop", Action::Drop),
+    ("escape", Action::Escape),
+    ("screenshot", Action::Screenshot),
+    ("hold", Action::Hold),
+    ("rotate-left", Action::RotateLeft),
+];
+
+// What the actions do in a single game
+fn describe(action: Action) -> &'static str {
+    match action {
+        Action::Up => "Rotate",
+        Action::Down => "Soft drop",
+        Action::Left => "Move left",
+        Action::Right => "Move right",
+        Action::Drop => "Hard drop",
+        Action::Escape => "Menu",
+        Action::Screenshot => "Screenshot",
+        Action::Hold => "Hold",
+        Action::RotateLeft => "Rotate left",
+        Action::ReleaseLeft | Action::ReleaseRight => "Let go",
+    }
+}
+
+// Named keys, the others are written as the character they type
+const KEYS: [(&str, KeyCode); 16] = [
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Enter", KeyCode::Enter),
+    ("Space", KeyCode::Char(' ')),
+    // Separates the keys otherwise
+    ("Comma", KeyCode::Char(',')),
+    ("Esc", KeyCode::Esc),
+    ("Backspace", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Delete", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+];
+
+/// Keys of a single player, to be shared as a file with a line per action after
+/// the version header: the action name, `=` and its keys separated by commas, like
+/// `drop = Enter, Space`. Lines starting with `#` are comments. Every action needs a key and a key can
+/// only do one thing.
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    keys: Vec<(KeyCode, Action)>,
+}
+
+// Arrows or WASD, Enter or Space to drop, C to hold and Z to turn left, like
+// `ConsoleView::key_to_action`
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings {
+            keys: vec![
+                (KeyCode::Up, Action::Up),
+                (KeyCode::Char('w'), Action::Up),
+                (KeyCode::Down, Action::Down),
+                (KeyCode::Char('s'), Action::Down),
+                (KeyCode::Left, Action::Left),
+                (KeyCode::Char('a'), Action::Left),
+                (KeyCode::Right, Action::Right),
+                (KeyCode::Char('d'), Action::Right),
+                (KeyCode::Enter, Action::Drop),
+                (KeyCode::Char(' '), Action::Drop),
+                (KeyCode::Esc, Action::Escape),
+                (KeyCode::Backspace, Action::Escape),
+                (KeyCode::Char('p'), Action::Screenshot),
+                (KeyCode::Char('c'), Action::Hold),
+                (KeyCode::Char('z'), Action::RotateLeft),
+            ],
+        }
+    }
+}
+
+impl Bindings {
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        self.keys
+            .iter()
+            .find(|(bound, _)| *bound == key)
+            .map(|&(_, action)| action)
+    }
+
+    // Line per action like in the file, `drop = Enter, Space`
+    pub fn lines(&self) -> Vec<String> {
+        ACTIONS
+            .iter()
+            .map(|&(name, action)| format!("{} = {}", name, self.keys_of(action).join(", ")))
+            .collect()
+    }
+
+    // Line per action for the controls screen, `Hard drop: Enter, Space`
+    pub fn help(&self) -> Vec<String> {
+        ACTIONS
+            .iter()
+            .map(|&(_, action)| {
+                format!("{}: {}", describe(action), self.keys_of(action).join(", "))
+            })
+            .collect()
+    }
+
+    fn keys_of(&self, action: Action) -> Vec<String> {
+        self.keys
+            .iter()
+            .filter(|(_, bound)| *bound == action)
+            .map(|&(key, _)| key_name(key))
+            .collect()
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        schema::write_header(writer, "keys", &MIGRATIONS)?;
+        for line in self.lines() {
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Bindings> {
+        let text = schema::read_text(reader, "keys", &MIGRATIONS)?;
+        let mut keys: Vec<(KeyCode, Action)> = vec![];
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, list) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("expected action = keys, got {}", line)))?;
+            let name = name.trim();
+            let action = ACTIONS
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|&(_, action)| action)
+                .ok_or_else(|| invalid(format!("unknown action {}", name)))?;
+            for key in list.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+                let key = parse_key(key).ok_or_else(|| invalid(format!("unknown key {}", key)))?;
+                if keys.iter().any(|&(bound, _)| bound == key) {
+                    return Err(invalid(format!("{} is bound twice", key_name(key))));
+                }
+                keys.push((key, action));
+            }
+        }
+        for (name, action) in ACTIONS.iter() {
+            if !keys.iter().any(|(_, bound)| *bound == *action) {
+                return Err(invalid(format!("{} has no key", name)));
+            }
+        }
+        Ok(Bindings { keys })
+    }
+}
+
+fn key_name(key: KeyCode) -> String {
+    match KEYS.iter().find(|(_, named)| *named == key) {
+        Some((name, _)) => name.to_string(),
+        None => match key {
+            KeyCode::F(n) => format!("F{}", n),
+            KeyCode::Char(c) => c.to_string(),
+            key => format!("{:?}", key),
+        },
+    }
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    if let Some((_, key)) = KEYS.iter().find(|(known, _)| *known == name) {
+        return Some(*key);
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(KeyCode::Char(c)),
+        (Some('F'), Some(_)) => name[1..].parse().ok().map(KeyCode::F),
+        _ => None,
+    }
+}
+
+// Version 2 added the hold, older files get it on a key they leave free
+fn add_hold(body: Vec<u8>) -> Vec<u8> {
+    add_action(body, "hold", &HOLD_KEYS)
+}
+
+// Version 3 added turning left, the same way
+fn add_rotate_left(body: Vec<u8>) -> Vec<u8> {
+    add_action(body, "rotate-left", &ROTATE_LEFT_KEYS)
+}
+
+fn add_action(body: Vec<u8>, name: &str, keys: &[&str]) -> Vec<u8> {
+    let mut text = String::from_utf8_lossy(&body).into_owned();
+    let bound: Vec<&str> = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .flat_map(|(_, keys)| keys.split(',').map(str::trim))
+        .collect();
+    // Every key taken, reading the file tells the action has none
+    let key = match keys.iter().find(|key| !bound.contains(key)) {
+        Some(key) => key,
+        None => return body,
+    };
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(&format!("{} = {}\n", name, key));
+    text.into_bytes()
+}
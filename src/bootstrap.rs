@@ -1,20 +1,303 @@
+use crate::game::tetris::metrics::{self, Metrics};
+use crate::game::tetris::tetromino::RotationSystem;
+use crate::perf::{self, FrameStats};
+use std::cell;
+use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use wasm_bindgen::prelude::*;
 
 pub trait Game {
     type Pause;
-    fn new(settings: Settings, start: Timestamp) -> Self;
-    fn frame(&mut self, now: Timestamp, action: Option<Action>) -> GameChange<Self::Pause>;
+    // What frontends feed in, plain `Action` for single player games
+    type Input;
+    fn frame(&mut self, now: Timestamp, input: Option<Self::Input>) -> GameChange<'_, Self::Pause>;
+    // Opens the pause menu unless some menu is already shown
+    fn pause(&mut self);
+    // Starts over after the game returned `GameChange::Restart`
+    fn restart(&mut self, now: Timestamp);
 }
 
-// As milliseconds. Not very elegant solution but easiest for WASM
+pub trait Renderer {
+    type Error;
+    fn draw_game(&self, frame: &FrameOutput) -> Result<(), Self::Error>;
+    fn draw_text<T>(&self, menu: &MenuMode<T>) -> Result<(), Self::Error>;
+
+    // Several boards at once, renderers without split layout show the first one only
+    fn draw_split(&self, frames: &[FrameOutput]) -> Result<(), Self::Error> {
+        match frames.first() {
+            Some(frame) => self.draw_game(frame),
+            None => Ok(()),
+        }
+    }
+
+    // Frame timing overlay after every drawn frame while it is shown, `None` once to
+    // take it away. Renderers without an overlay ignore it.
+    fn draw_stats(&self, _stats: Option<&FrameStats>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+}
+
+/// Monotonic native clock. Starts at the wall clock time like the browser one,
+/// so game starts differ between runs and work as seeds.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct SystemClock {
+    start: Instant,
+    epoch: Timestamp,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis() as Timestamp);
+        SystemClock {
+            start: Instant::now(),
+            epoch,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        self.epoch + self.start.elapsed().as_millis() as Timestamp
+    }
+}
+
+/// Clock moved by its owner: the wasm frontend sets the browser time on every tick,
+/// tests advance it by exact amounts
+#[derive(Default)]
+pub struct ManualClock {
+    now: cell::Cell<Timestamp>,
+}
+
+impl ManualClock {
+    pub fn new(start: Timestamp) -> ManualClock {
+        ManualClock {
+            now: cell::Cell::new(start),
+        }
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, millis: Timestamp) {
+        self.now.set(self.now.get() + millis);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        self.now.get()
+    }
+}
+
+/// Drives a game: feeds it with time and input, hands frames to the renderer
+/// and restarts the game when asked to.
+pub struct GameLoop<G: Game, R: Renderer, C: Clock> {
+    game: G,
+    renderer: R,
+    clock: C,
+    stats: FrameStats,
+    // Overlay was drawn with the last frame
+    overlaid: bool,
+    // When the input of the next step came in, in `perf::micros`
+    received: Option<u64>,
+}
+
+impl<G: Game, R: Renderer, C: Clock> GameLoop<G, R, C> {
+    // Game is expected to be started at `clock.now()`
+    pub fn new(game: G, renderer: R, clock: C) -> GameLoop<G, R, C> {
+        GameLoop {
+            game,
+            renderer,
+            clock,
+            stats: FrameStats::default(),
+            overlaid: false,
+            received: None,
+        }
+    }
+
+    /// Runs a single frame. Returns `false` once the game wants to exit.
+    pub fn step(&mut self, input: Option<G::Input>) -> Result<bool, R::Error> {
+        let now = self.clock.now();
+        let start = perf::micros();
+        let allocations = perf::allocations();
+        // Frontends without a queue hand the input over right as it comes
+        let received = input
+            .as_ref()
+            .map(|_| self.received.take().unwrap_or(start));
+        let change = {
+            let _span = perf::span!("frame");
+            self.game.frame(now, input)
+        };
+        let _span = perf::span!("render");
+        match change {
+            GameChange::Draw(frame) => self.renderer.draw_game(&frame)?,
+            GameChange::Split(frames) => self.renderer.draw_split(&frames)?,
+            GameChange::Text(menu) => self.renderer.draw_text(menu)?,
+            GameChange::Restart => {
+                self.game.restart(now);
+                return Ok(true);
+            }
+            GameChange::Exit => return Ok(false),
+            GameChange::Idle => return Ok(true),
+        }
+        if perf::shown() {
+            let made = perf::allocations().wrapping_sub(allocations);
+            self.stats.record(start, perf::micros(), received, made);
+            self.renderer.draw_stats(Some(&self.stats))?;
+            self.overlaid = true;
+        } else if self.overlaid {
+            self.renderer.draw_stats(None)?;
+            self.overlaid = false;
+        }
+        Ok(true)
+    }
+
+    /// Frontends call it when the game window or tab goes to background
+    pub fn focus_lost(&mut self) {
+        self.game.pause();
+    }
+
+    /// Blocking loop for native frontends. Waits for input at most one tick,
+    /// then processes everything queued in the meantime. Ends when the game exits
+    /// or the input side hangs up.
+    pub fn run(&mut self, input: &Receiver<G::Input>) -> Result<(), R::Error> {
+        self.run_mapped(input, Some)
+    }
+
+    /// Same as `run` for raw input like key codes, one receiver can then feed
+    /// several games in a row. Input `map` returns `None` for is dropped.
+    pub fn run_mapped<I>(
+        &mut self,
+        input: &Receiver<I>,
+        map: impl Fn(I) -> Option<G::Input>,
+    ) -> Result<(), R::Error> {
+        loop {
+            let mut action = match input.recv_timeout(LOOP_TICK) {
+                Ok(action) => {
+                    let _span = perf::span!("input");
+                    self.received = Some(perf::micros());
+                    map(action)
+                }
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+            loop {
+                let _span = perf::span!("step");
+                if !self.step(action)? {
+                    return Ok(());
+                }
+                match input.try_recv() {
+                    Ok(next) => {
+                        let _span = perf::span!("input");
+                        self.received = Some(perf::micros());
+                        action = map(next)
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+
+    pub fn game(&self) -> &G {
+        &self.game
+    }
+
+    pub fn game_mut(&mut self) -> &mut G {
+        &mut self.game
+    }
+
+    pub fn renderer(&self) -> &R {
+        &self.renderer
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    // Timing of the frames drawn while the overlay was shown
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+}
+
+const LOOP_TICK: Duration = Duration::from_millis(10);
+
+// As milliseconds. Not very elegant solution but easiest for WASM.
+// Only differences matter, each Clock picks its own zero.
 pub type Timestamp = u64;
 
+/// What a cell of the board holds, so renderers can draw each kind its own way
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Cell {
+    #[default]
+    Empty,
+    // Block of a piece, by the id of its tetromino from 1 to 7
+    Piece(u8),
+    // Outline of where the falling block would land
+    Ghost,
+    Garbage,
+}
+
+impl Cell {
+    // Taken by a block, ghosts are drawn over empty cells
+    pub fn is_filled(self) -> bool {
+        matches!(self, Cell::Piece(_) | Cell::Garbage)
+    }
+
+    // Number for the web frontend, the network and hashes: 0 for empty cells, the
+    // piece ids, then garbage and ghosts
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Cell::Empty => 0,
+            Cell::Piece(id) => id,
+            Cell::Garbage => 8,
+            Cell::Ghost => 9,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Cell {
+        match byte {
+            0 => Cell::Empty,
+            8 => Cell::Garbage,
+            9 => Cell::Ghost,
+            id => Cell::Piece(id),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct Renderable(pub Vec<Vec<u8>>);
+pub struct Renderable(pub Vec<Vec<Cell>>);
+
+impl Renderable {
+    // Rows as numbers, see `Cell::to_byte`
+    pub fn bytes(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.iter()
+            .map(|row| row.iter().map(|cell| cell.to_byte()).collect())
+    }
+}
 
 impl Deref for Renderable {
-    type Target = Vec<Vec<u8>>;
+    type Target = Vec<Vec<Cell>>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -26,17 +309,69 @@ impl DerefMut for Renderable {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct Settings {
     // If cols, rows < 5 something will crash.
     pub cols: u16,
     pub rows: u16,
     pub delay: u64,
+    // Level the game is played at, 1 is the usual start
+    pub level: u32,
+    // Milliseconds a block resting on the stack can still be moved before it locks,
+    // 0 locks it right away
+    pub lock_delay: u64,
+    // Milliseconds cleared rows stay on the board before they collapse, for renderers
+    // to flash them
+    pub clear_delay: u64,
+    // Milliseconds left or right is held before the block starts to slide, 0 leaves
+    // repeating to the keyboard
+    pub das: u64,
+    // Milliseconds between the steps of a slide, 0 slides to the wall at once
+    pub arr: u64,
+    // Milliseconds from a lock, or the end of a clear, until the next block comes
+    pub entry_delay: u64,
+    pub top_out: TopOut,
+    pub rotation: RotationSystem,
+    // Every mino of a block takes two by two cells
+    pub big: bool,
+}
+
+/// What else ends a game besides a block-out, the next block having no room where it
+/// comes in. A block-out always ends it, the block would have nowhere to go.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TopOut {
+    // A block locks with some of it above the top of the board
+    pub lock_out: bool,
+    // Incoming garbage pushes blocks over the top
+    pub garbage_out: bool,
+}
+
+impl TopOut {
+    pub const BLOCK_OUT: TopOut = TopOut {
+        lock_out: false,
+        garbage_out: false,
+    };
+    pub const GUIDELINE: TopOut = TopOut {
+        lock_out: true,
+        garbage_out: true,
+    };
+
+    // One bit per rule, for replays and checksums
+    pub fn to_byte(self) -> u8 {
+        self.lock_out as u8 | (self.garbage_out as u8) << 1
+    }
+
+    pub fn from_byte(byte: u8) -> TopOut {
+        TopOut {
+            lock_out: byte & 1 != 0,
+            garbage_out: byte & 2 != 0,
+        }
+    }
 }
 
 #[wasm_bindgen]
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Action {
     Up,
     Down,
@@ -44,34 +379,295 @@ pub enum Action {
     Right,
     Drop,
     Escape,
+    // Picture of the board for the listeners, the game itself ignores it
+    Screenshot,
+    // Swaps the falling block with the held one, once per piece
+    Hold,
+    // Left or right was let go, ends the slide. Terminals cannot tell, they keep
+    // repeating the key instead.
+    ReleaseLeft,
+    ReleaseRight,
+    // Turns the block counter-clockwise, `Up` turns it the other way
+    RotateLeft,
 }
 
-#[derive(Debug)]
+impl Action {
+    // Reverse of `action as u8`, for replays and the network
+    pub fn from_byte(byte: u8) -> Option<Action> {
+        let action = match byte {
+            0 => Action::Up,
+            1 => Action::Down,
+            2 => Action::Left,
+            3 => Action::Right,
+            4 => Action::Drop,
+            5 => Action::Escape,
+            6 => Action::Screenshot,
+            7 => Action::Hold,
+            8 => Action::ReleaseLeft,
+            9 => Action::ReleaseRight,
+            10 => Action::RotateLeft,
+            _ => return None,
+        };
+        Some(action)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct GameMode {
     pub main: Renderable,
     pub preview: Renderable,
+    // Piece put aside with `Action::Hold` in the shape of the preview, empty while
+    // there is none
+    pub hold: Renderable,
     pub score: u32,
+    // Current level, the cleared lines are in the metrics
+    pub level: u32,
+    // Full rows about to collapse, see `Settings::clear_delay`
+    pub clearing: Vec<u16>,
+    pub metrics: Metrics,
+    // Last chat line, shown under the board
+    pub chat: Option<String>,
+    // Incoming garbage lines not on the field yet, drawn as a meter
+    pub garbage: u16,
+    // Ahead or behind the personal best, under the metrics
+    pub pace: Option<String>,
+    // What is left before a game with a goal is over or a turn ends, drawn under
+    // the garbage meter
+    pub goals: Vec<Goal>,
+}
+
+/// Counts down to the end of a game with a goal, like the lines and pieces of a drill,
+/// or of the turn of a hot seat game
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Goal {
+    // Player on turn, counted from 1
+    Turn(u32),
+    Lines(u32),
+    Pieces(u32),
+    // Milliseconds
+    Time(Timestamp),
+    // Last split of a sprint, the time it took to clear `lines`
+    Split { lines: u32, time: Timestamp },
+}
+
+impl Goal {
+    // Like `Lines left 3`
+    pub fn text(&self) -> String {
+        match self {
+            Goal::Turn(player) => format!("Player {} plays", player),
+            Goal::Lines(lines) => format!("Lines left {}", lines),
+            Goal::Pieces(pieces) => format!("Pieces left {}", pieces),
+            Goal::Time(time) => format!("Time left {}", metrics::clock(*time)),
+            Goal::Split { lines, time } => format!("Split {} {}", lines, metrics::clock(*time)),
+        }
+    }
 }
 
 #[derive(Debug)]
+pub struct FrameOutput {
+    pub game: GameMode,
+    pub redraw: Redraw,
+}
+
+/// Hints about what changed since the previous `FrameOutput`,
+/// so renderers can skip the parts of the screen that stayed the same
+#[derive(Debug, Default, Clone)]
+pub struct Redraw {
+    // Screen was used for something else (e.g. menu), draw everything
+    pub full: bool,
+    // Falling block moved, rotated or was spawned
+    pub piece: bool,
+    // Locked cells changed
+    pub field: bool,
+    // Indices of rows removed by the last lock, as they were before removal
+    pub cleared: Vec<u16>,
+    pub score: bool,
+    pub preview: bool,
+    pub chat: bool,
+    pub garbage: bool,
+    pub hold: bool,
+    // Elapsed time shown in the sidebar went on
+    pub time: bool,
+}
+
+impl Redraw {
+    pub fn full() -> Redraw {
+        Redraw {
+            full: true,
+            ..Redraw::default()
+        }
+    }
+
+    pub fn main(&self) -> bool {
+        self.full || self.piece || self.field || !self.cleared.is_empty()
+    }
+
+    pub fn preview(&self) -> bool {
+        self.full || self.preview
+    }
+
+    pub fn score(&self) -> bool {
+        self.full || self.score
+    }
+
+    pub fn chat(&self) -> bool {
+        self.full || self.chat
+    }
+
+    pub fn garbage(&self) -> bool {
+        self.full || self.garbage
+    }
+
+    pub fn hold(&self) -> bool {
+        self.full || self.hold
+    }
+
+    pub fn time(&self) -> bool {
+        self.full || self.time
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MenuKind<T> {
+    // Plain text, like a menu title
+    Label,
+    Separator,
+    Button,
+    // One of several values, changed with left and right
+    Value { values: Vec<String>, current: usize },
+    // Text typed by the player, see `MenuMode::type_char`
+    Input { text: String, max: usize },
+    // Opens nested items when selected
+    Submenu(Vec<MenuItem<T>>),
+    // Closes the submenu it is in when selected
+    Back,
+}
+
+#[derive(Debug, Clone)]
 pub struct MenuItem<T> {
     pub id: T,
-    pub string: &'static str,
-    pub selectable: bool,
+    pub string: String,
+    pub kind: MenuKind<T>,
 }
 
-#[derive(Debug)]
+impl<T> MenuItem<T> {
+    pub fn label(id: T, string: impl Into<String>) -> MenuItem<T> {
+        MenuItem::new(id, string.into(), MenuKind::Label)
+    }
+
+    pub fn separator(id: T) -> MenuItem<T> {
+        MenuItem::new(id, String::new(), MenuKind::Separator)
+    }
+
+    pub fn button(id: T, string: impl Into<String>) -> MenuItem<T> {
+        MenuItem::new(id, string.into(), MenuKind::Button)
+    }
+
+    pub fn value(
+        id: T,
+        string: impl Into<String>,
+        values: Vec<String>,
+        current: usize,
+    ) -> MenuItem<T> {
+        let current = current.min(values.len().saturating_sub(1));
+        MenuItem::new(id, string.into(), MenuKind::Value { values, current })
+    }
+
+    /// Value item of numbers shown with `unit` after them, like 150%. The first one
+    /// is picked when `current` is none of them.
+    pub fn choice(
+        id: T,
+        string: impl Into<String>,
+        numbers: &[u32],
+        unit: &str,
+        current: u32,
+    ) -> MenuItem<T> {
+        let values = numbers
+            .iter()
+            .map(|number| format!("{}{}", number, unit))
+            .collect();
+        let current = numbers
+            .iter()
+            .position(|&number| number == current)
+            .unwrap_or(0);
+        MenuItem::value(id, string, values, current)
+    }
+
+    pub fn input(id: T, string: impl Into<String>, max: usize) -> MenuItem<T> {
+        let kind = MenuKind::Input {
+            text: String::new(),
+            max,
+        };
+        MenuItem::new(id, string.into(), kind)
+    }
+
+    pub fn submenu(id: T, string: impl Into<String>, items: Vec<MenuItem<T>>) -> MenuItem<T> {
+        MenuItem::new(id, string.into(), MenuKind::Submenu(items))
+    }
+
+    pub fn back(id: T, string: impl Into<String>) -> MenuItem<T> {
+        MenuItem::new(id, string.into(), MenuKind::Back)
+    }
+
+    /// Asks `question` before anything is lost, `id` only comes out of the menu
+    /// once the player says yes
+    pub fn confirm(id: T, string: impl Into<String>, question: &str) -> MenuItem<T>
+    where
+        T: Clone,
+    {
+        let items = vec![
+            MenuItem::label(id.clone(), question),
+            MenuItem::button(id.clone(), "Yes"),
+            MenuItem::back(id.clone(), "No"),
+        ];
+        MenuItem::submenu(id, string, items)
+    }
+
+    fn new(id: T, string: String, kind: MenuKind<T>) -> MenuItem<T> {
+        MenuItem { id, string, kind }
+    }
+
+    pub fn selectable(&self) -> bool {
+        !matches!(self.kind, MenuKind::Label | MenuKind::Separator)
+    }
+
+    // Text renderers should show for the item
+    pub fn text(&self) -> String {
+        match &self.kind {
+            // Arrows where left and right still change it, like `Level: < 5 >`
+            MenuKind::Value { values, current } => match values.get(*current) {
+                Some(value) => {
+                    let left = if *current > 0 { "< " } else { "" };
+                    let right = if *current + 1 < values.len() {
+                        " >"
+                    } else {
+                        ""
+                    };
+                    format!("{}: {}{}{}", self.string, left, value, right)
+                }
+                None => self.string.clone(),
+            },
+            MenuKind::Input { text, .. } => format!("{}: {}_", self.string, text),
+            _ => self.string.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct MenuMode<T> {
     items: Vec<MenuItem<T>>,
     selected: Option<usize>,
+    // Items of the outer menus while a submenu is open, with the index of the opened item
+    parents: Vec<(Vec<MenuItem<T>>, usize)>,
 }
 
 impl<T> MenuMode<T> {
     pub fn new(items: Vec<MenuItem<T>>) -> MenuMode<T> {
-        let first_selectable = items.iter().position(|item| item.selectable);
+        let first_selectable = items.iter().position(|item| item.selectable());
         MenuMode {
             items,
             selected: first_selectable,
+            parents: vec![],
         }
     }
 
@@ -83,11 +679,11 @@ impl<T> MenuMode<T> {
                     .items
                     .iter()
                     .enumerate()
-                    .find(|(i, item)| *i > selected && item.selectable);
+                    .find(|(i, item)| *i > selected && item.selectable());
                 self.selected = if let Some((i, _)) = next {
                     Some(i)
                 } else {
-                    self.items.iter().position(|item| item.selectable)
+                    self.items.iter().position(|item| item.selectable())
                 }
             }
         };
@@ -102,21 +698,155 @@ impl<T> MenuMode<T> {
                     .iter()
                     .enumerate()
                     .rev()
-                    .find(|(i, item)| *i < selected && item.selectable);
+                    .find(|(i, item)| *i < selected && item.selectable());
                 self.selected = if let Some((i, _)) = next {
                     Some(i)
                 } else {
-                    self.items.iter().rposition(|item| item.selectable)
+                    self.items.iter().rposition(|item| item.selectable())
                 }
             }
         };
     }
 
-    pub fn select(&self) -> Option<&T> {
-        match self.selected {
-            None => None,
-            Some(idx) => Some(&self.items[idx].id),
+    pub fn left(&mut self) {
+        if let Some(MenuKind::Value { current, .. }) = self.selected_kind() {
+            *current = current.saturating_sub(1);
+        }
+    }
+
+    pub fn right(&mut self) {
+        if let Some(MenuKind::Value { values, current }) = self.selected_kind() {
+            if *current + 1 < values.len() {
+                *current += 1;
+            }
+        }
+    }
+
+    // Typing goes into the selected input item, other items ignore it
+    pub fn type_char(&mut self, c: char) {
+        if let Some(MenuKind::Input { text, max }) = self.selected_kind() {
+            if text.chars().count() < *max {
+                text.push(c);
+            }
+        }
+    }
+
+    pub fn erase(&mut self) {
+        if let Some(MenuKind::Input { text, .. }) = self.selected_kind() {
+            text.pop();
+        }
+    }
+
+    // Submenus are opened and closed right away, so only other items produce an id
+    pub fn select(&mut self) -> Option<&T> {
+        let idx = self.selected?;
+        if let MenuKind::Back = self.items[idx].kind {
+            self.back();
+            return None;
         }
+        if let MenuKind::Submenu(items) = &mut self.items[idx].kind {
+            let items = mem::take(items);
+            let parent = mem::replace(&mut self.items, items);
+            self.parents.push((parent, idx));
+            self.selected = self.items.iter().position(|item| item.selectable());
+            return None;
+        }
+        Some(&self.items[idx].id)
+    }
+
+    /// Closes the current submenu. Returns `false` if there was none.
+    pub fn back(&mut self) -> bool {
+        match self.parents.pop() {
+            None => false,
+            Some((parent, idx)) => {
+                let items = mem::replace(&mut self.items, parent);
+                self.items[idx].kind = MenuKind::Submenu(items);
+                self.selected = Some(idx);
+                true
+            }
+        }
+    }
+
+    /// Current value index of the value item with given id, wherever it is nested
+    pub fn value_of(&self, id: &T) -> Option<usize>
+    where
+        T: PartialEq,
+    {
+        let parents = self.parents.iter().map(|(items, _)| items);
+        std::iter::once(&self.items)
+            .chain(parents)
+            .find_map(|items| MenuMode::find_value(items, id))
+    }
+
+    fn find_value(items: &[MenuItem<T>], id: &T) -> Option<usize>
+    where
+        T: PartialEq,
+    {
+        items.iter().find_map(|item| match &item.kind {
+            MenuKind::Value { current, .. } if item.id == *id => Some(*current),
+            MenuKind::Submenu(items) => MenuMode::find_value(items, id),
+            _ => None,
+        })
+    }
+
+    /// Text of the input item with given id, wherever it is nested
+    pub fn text_of(&self, id: &T) -> Option<&str>
+    where
+        T: PartialEq,
+    {
+        let parents = self.parents.iter().map(|(items, _)| items);
+        std::iter::once(&self.items)
+            .chain(parents)
+            .find_map(|items| MenuMode::find_text(items, id))
+    }
+
+    fn find_text<'a>(items: &'a [MenuItem<T>], id: &T) -> Option<&'a str>
+    where
+        T: PartialEq,
+    {
+        items.iter().find_map(|item| match &item.kind {
+            MenuKind::Input { text, .. } if item.id == *id => Some(text.as_str()),
+            MenuKind::Submenu(items) => MenuMode::find_text(items, id),
+            _ => None,
+        })
+    }
+
+    /// Puts `item` in place of the item with its id, wherever it is nested, for
+    /// menus that change while they are shown. Returns `false` if there was none.
+    pub fn replace(&mut self, item: MenuItem<T>) -> bool
+    where
+        T: PartialEq,
+    {
+        let mut item = Some(item);
+        let parents = self.parents.iter_mut().map(|(items, _)| items);
+        std::iter::once(&mut self.items)
+            .chain(parents)
+            .any(|items| MenuMode::replace_in(items, &mut item))
+    }
+
+    fn replace_in(items: &mut [MenuItem<T>], item: &mut Option<MenuItem<T>>) -> bool
+    where
+        T: PartialEq,
+    {
+        for existing in items.iter_mut() {
+            if item.as_ref().is_some_and(|item| item.id == existing.id) {
+                if let Some(item) = item.take() {
+                    *existing = item;
+                }
+                return true;
+            }
+            if let MenuKind::Submenu(nested) = &mut existing.kind {
+                if MenuMode::replace_in(nested, item) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn selected_kind(&mut self) -> Option<&mut MenuKind<T>> {
+        let idx = self.selected?;
+        Some(&mut self.items[idx].kind)
     }
 
     pub fn get_items(&self) -> &Vec<MenuItem<T>> {
@@ -130,7 +860,10 @@ impl<T> MenuMode<T> {
 
 #[derive(Debug)]
 pub enum GameChange<'a, T> {
-    Draw(GameMode),
+    // Boxed, a frame is many times the size of the other changes
+    Draw(Box<FrameOutput>),
+    // One output per board for multiplayer games
+    Split(Vec<FrameOutput>),
     Text(&'a MenuMode<T>),
     Restart,
     Exit,
@@ -1,53 +1,1644 @@
+mod bindings;
+mod dirs;
 mod renderer {
     pub mod console;
+    #[cfg(feature = "gif")]
+    pub mod gif;
+    #[cfg(feature = "png")]
+    pub mod png;
 }
 
-mod game {
-    pub mod tetris;
-}
-
-mod bootstrap;
-
-use crate::bootstrap::{Action, Game, GameChange, Settings, Timestamp};
-use crate::game::tetris::Tetris;
-use crate::renderer::console::ConsoleView;
+use crate::bindings::Bindings;
+#[cfg(feature = "netplay")]
+use crate::renderer::console::key_to_lobby_input;
+use crate::renderer::console::{
+    self, key_to_first_player, key_to_versus_action, ConsoleView, SplitView,
+};
+#[cfg(feature = "png")]
+use brick_game_wasm::bootstrap::GameMode;
+use brick_game_wasm::bootstrap::{
+    Action, Clock, GameLoop, MenuItem, Settings, SystemClock, TopOut,
+};
+use brick_game_wasm::game::tetris::ai::{self, Bot, Difficulty, Outcome, Weights};
+use brick_game_wasm::game::tetris::bests::PersonalBests;
+#[cfg(feature = "debug-tools")]
+use brick_game_wasm::game::tetris::cheats::CheatConsole;
+use brick_game_wasm::game::tetris::coop::Coop;
+use brick_game_wasm::game::tetris::daily::{self, DailyResults};
+use brick_game_wasm::game::tetris::demo::Demo;
+use brick_game_wasm::game::tetris::drill::{self, Drill, DrillProgress, DrillSet};
+use brick_game_wasm::game::tetris::eventlog::EventLog;
+use brick_game_wasm::game::tetris::fumen::{self, Page};
+use brick_game_wasm::game::tetris::ghost::Ghost;
+use brick_game_wasm::game::tetris::handicap::Handicap;
+use brick_game_wasm::game::tetris::history::{Entry, History, Totals};
+use brick_game_wasm::game::tetris::hotseat::Turns;
+use brick_game_wasm::game::tetris::listener::TetrisListener;
+use brick_game_wasm::game::tetris::metrics::{self, Metrics};
+use brick_game_wasm::game::tetris::profile::Profile;
+use brick_game_wasm::game::tetris::replay::Replay;
+use brick_game_wasm::game::tetris::saves::{Save, SaveSlots, SLOTS};
+use brick_game_wasm::game::tetris::tetromino::RotationSystem;
+use brick_game_wasm::game::tetris::versus::{PlayerAction, Versus};
+use brick_game_wasm::game::tetris::{snapshot, stats};
+use brick_game_wasm::game::tetris::{Tetris, TetrisPause, RULES, RULES_VERSION};
+use brick_game_wasm::game::title::{Mode, Preferences, Title};
+#[cfg(feature = "leaderboard")]
+use brick_game_wasm::leaderboard::{Leaderboard, LeaderboardListener};
+#[cfg(feature = "netplay")]
+use brick_game_wasm::net::{
+    handshake,
+    lobby::Lobby,
+    lockstep::{Lockstep, TICK},
+    observer::Observer,
+    remote::RemoteVersus,
+    server,
+    tcp::TcpPeer,
+    websocket::WsPeer,
+    Transport,
+};
+use brick_game_wasm::perf;
+use crossterm::event::KeyCode;
+use crossterm::style::Color;
 use log::LevelFilter;
+use std::alloc::System;
+#[cfg(feature = "trace")]
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::env;
 use std::error::Error;
-use std::sync::mpsc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+#[cfg(feature = "netplay")]
+use std::sync::mpsc::Receiver;
+use std::sync::{mpsc, Mutex, Once};
+use std::time::{Duration, Instant};
+
+// Counts allocations for the frame overlay, F3 in any game
+#[global_allocator]
+static ALLOC: perf::Counting<System> = perf::Counting(System);
 
 fn main() -> Result<(), Box<dyn Error>> {
-    simple_logging::log_to_file("test.log", LevelFilter::Info)?;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let data = dirs::data_dir(optional(&args, "--data-dir")?);
+    fs::create_dir_all(&data)?;
+    simple_logging::log_to_file(data.join("test.log"), LevelFilter::Info)?;
+    // ASCII and basic colors for old consoles, found out or asked for
+    if args.iter().any(|arg| arg == "--compat") || console::legacy_terminal() {
+        log::info!("Drawing for a legacy console");
+        console::set_compat(true);
+    }
 
     let settings = Settings {
         cols: 10,
         rows: 20,
         delay: 500,
+        level: 1,
+        lock_delay: 500,
+        clear_delay: 200,
+        das: 0,
+        arr: 0,
+        entry_delay: 0,
+        top_out: TopOut::GUIDELINE,
+        // Blocks turn like the guideline, or like older games with --rotation nes or ars
+        rotation: optional(&args, "--rotation")?.unwrap_or(RotationSystem::Srs),
+        // Blocks twice as large in every game
+        big: args.iter().any(|arg| arg == "--big"),
     };
-    let (tx, rx) = mpsc::channel::<Action>();
-    let renderer = ConsoleView::new(settings, 2, 1, '\u{2588}', None);
+    // Single games write the final board there, or print it when it is -
+    let snapshot = optional(&args, "--snapshot")?;
+    // Summary of the last single game as JSON, - for stdout
+    let stats = optional(&args, "--stats-json")?;
+    // File single games append their events to as JSON lines
+    let events = optional(&args, "--event-log")?;
+    let output = Output {
+        snapshot,
+        stats,
+        events,
+        data,
+        bell: args.iter().any(|arg| arg == "--bell"),
+    };
+    // Timing of the game loop, as folded stacks for flamegraphs when the file ends in .folded
+    #[cfg(feature = "trace")]
+    let _trace = match optional::<String>(&args, "--trace")? {
+        Some(path) => Some(trace(&path)?),
+        None => None,
+    };
+    play(&args, settings, output)
+}
+
+// Sends the spans of the game loop to `path` as Chrome trace events or folded
+// stacks, they are written out when the guard is dropped
+#[cfg(feature = "trace")]
+fn trace(path: &str) -> Result<Box<dyn Any>, Box<dyn Error>> {
+    use tracing_subscriber::layer::SubscriberExt;
+    if path.ends_with(".folded") {
+        let (layer, guard) = tracing_flame::FlameLayer::with_file(path)?;
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+        Ok(Box::new(guard))
+    } else {
+        let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new().file(path).build();
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+        Ok(Box::new(guard))
+    }
+}
+
+// Runs the mode the first argument asks for
+fn play(args: &[String], settings: Settings, output: Output) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        // Both versus modes take --handicap1 and --handicap2, like garbage=4,gravity=150,attack=50,
+        // and --first-to with the rounds needed to win the match
+        Some("--versus") => versus(
+            settings,
+            None,
+            handicaps(args)?,
+            option(args, "--first-to", 1)?,
+        ),
+        Some("--ai") => {
+            let difficulty = match args
+                .get(1)
+                .map(String::as_str)
+                .filter(|arg| !arg.starts_with("--"))
+            {
+                Some("easy") => Difficulty::Easy,
+                None | Some("normal") => Difficulty::Normal,
+                Some("hard") => Difficulty::Hard,
+                Some(_) => return Err("--ai takes easy, normal or hard".into()),
+            };
+            versus(
+                settings,
+                Some(difficulty),
+                handicaps(args)?,
+                option(args, "--first-to", 1)?,
+            )
+        }
+        Some("--coop") => coop(settings),
+        // Topping out clears the board for some points instead of ending the game
+        Some("--zen") => single(settings, None, Single::Zen, false, output),
+        // Two players take turns every --turn-pieces pieces, or every --turn-seconds
+        // seconds when it is given
+        Some("--hot-seat") => {
+            let turns = match optional::<u64>(args, "--turn-seconds")? {
+                Some(seconds) => Turns::Time(seconds * 1000),
+                None => Turns::Pieces(option(args, "--turn-pieces", TURN_PIECES)?),
+            };
+            single(settings, None, Single::HotSeat(turns), false, output)
+        }
+        // No preview of the next piece, ranked apart from the usual marathons
+        Some("--blind") => single(settings, None, Single::Blind, false, output),
+        // Pieces rise from the bottom and stack against the ceiling
+        Some("--upside-down") => single(settings, None, Single::UpsideDown, false, output),
+        // Locked blocks are not shown, only for a moment after they lock
+        Some("--invisible") => single(settings, None, Single::Invisible, false, output),
+        // Race to clear --dig-rows rows of garbage
+        Some("--dig") => {
+            let rows = option(args, "--dig-rows", DIG_ROWS)?;
+            single(settings, None, Single::Dig(rows), false, output)
+        }
+        // Race to clear `SPRINT_LINES` lines, bests are the fastest times
+        Some("--sprint") => single(settings, None, Single::Sprint, false, output),
+        // Small board of large cells, slow and without a game over
+        Some("--easy") => single(EASY, None, Single::Easy, false, output),
+        Some("--record") => {
+            let path = args.get(1).ok_or("--record needs a file")?;
+            single(settings, Some(path), Single::Marathon, false, output)
+        }
+        // Starting stack from a text file, like a snapshot
+        Some("--board") => {
+            let path = args.get(1).ok_or("--board needs a file")?;
+            let field = snapshot::parse_board(&fs::read_to_string(path)?, &settings)?;
+            let puzzle = Page { field, piece: None };
+            single(settings, None, Single::Puzzle(puzzle), false, output)
+        }
+        // Practice a shared setup, pages are counted from 1
+        Some("--fumen") => {
+            let code = args
+                .get(1)
+                .ok_or("--fumen needs a fumen like v115@vhAAgH")?;
+            let page = match args.get(2) {
+                Some(page) => page.parse()?,
+                None => 1,
+            };
+            let mut pages = fumen::decode(code, &settings)?;
+            if page == 0 || page > pages.len() {
+                return Err(format!("the fumen has {} pages", pages.len()).into());
+            }
+            let puzzle = pages.swap_remove(page - 1);
+            single(settings, None, Single::Puzzle(puzzle), false, output)
+        }
+        // Same pieces for everyone on the same day, one attempt a day
+        Some("--daily") => daily(settings, output),
+        Some("--drills") => {
+            let dir = args
+                .get(1)
+                .ok_or("--drills needs a directory of drill files")?;
+            drills(settings, Path::new(dir), output)
+        }
+        // Openers that come with the game, as drills
+        Some("--practice") => play_drills(settings, drill::bundled(&settings)?, output),
+        Some("--ghost") => {
+            let path = args.get(1).ok_or("--ghost needs a replay file")?;
+            let bindings = load(&output.data.join(KEYS_FILE), Bindings::read_from)?;
+            ghost(Replay::read_from(&mut File::open(path)?)?, bindings)
+        }
+        // Keys of single games as a file to share, the import is checked first
+        Some("--export-keys") => {
+            let path = args.get(1).ok_or("--export-keys needs a file")?;
+            let bindings = load(&output.data.join(KEYS_FILE), Bindings::read_from)?;
+            write_output(path, &bindings_text(&bindings)?)
+        }
+        Some("--import-keys") => {
+            let path = args.get(1).ok_or("--import-keys needs a file")?;
+            let bindings = Bindings::read_from(&mut File::open(path)?)
+                .map_err(|e| format!("{} is no key binding file: {}", path, e))?;
+            fs::write(output.data.join(KEYS_FILE), bindings_text(&bindings)?)?;
+            println!("Key bindings imported from {}", path);
+            Ok(())
+        }
+        #[cfg(feature = "netplay")]
+        Some("--host") => {
+            let addr = args.get(1).map_or(DEFAULT_ADDR, String::as_str);
+            println!("Waiting for opponent on {}", addr);
+            netplay(settings, TcpPeer::host(addr)?)
+        }
+        #[cfg(feature = "netplay")]
+        Some("--connect") => {
+            let addr = args.get(1).ok_or("--connect needs host:port")?;
+            netplay(settings, TcpPeer::connect(addr.as_str())?)
+        }
+        // Lockstep takes optional input delay and rollback in milliseconds after the address
+        #[cfg(feature = "netplay")]
+        Some("--lockstep-host") => {
+            let addr = args.get(1).map_or(DEFAULT_ADDR, String::as_str);
+            println!("Waiting for opponent on {}", addr);
+            let peer = handshake(TcpPeer::host(addr)?, &settings)?;
+            let clock = SystemClock::new();
+            let game = Lockstep::host(settings, clock.now(), peer, ticks(args, 2, INPUT_DELAY)?)
+                .with_rollback(ticks(args, 3, 0)?);
+            lockstep(settings, game, clock)
+        }
+        #[cfg(feature = "netplay")]
+        Some("--lockstep-connect") => {
+            let addr = args.get(1).ok_or("--lockstep-connect needs host:port")?;
+            let peer = handshake(TcpPeer::connect(addr.as_str())?, &settings)?;
+            let game = Lockstep::join(settings, peer, ticks(args, 2, INPUT_DELAY)?)
+                .with_rollback(ticks(args, 3, 0)?);
+            lockstep(settings, game, SystemClock::new())
+        }
+        // Room codes instead of addresses, the port is only needed by the host
+        #[cfg(feature = "netplay")]
+        Some("--lan") => {
+            let port = match args.get(1) {
+                Some(port) => port.parse()?,
+                None => DEFAULT_PORT,
+            };
+            lan(settings, port)
+        }
+        // Through a relay server, both players pick the same room name
+        #[cfg(feature = "netplay")]
+        Some("--server") => {
+            let addr = args.get(1).ok_or("--server needs host:port and a room")?;
+            let room = args.get(2).ok_or("--server needs a room")?;
+            println!("Waiting for opponent in room {}", room);
+            netplay(settings, server::join(addr.as_str(), room, &settings)?)
+        }
+        #[cfg(feature = "netplay")]
+        Some("--watch") => {
+            let addr = args.get(1).ok_or("--watch needs host:port and a room")?;
+            let room = args.get(2).ok_or("--watch needs a room")?;
+            println!("Waiting for the match in room {}", room);
+            watch(settings, server::watch(addr.as_str(), room, &settings)?)
+        }
+        // Browsers can only speak WebSocket, so this is what they connect to
+        #[cfg(feature = "netplay")]
+        Some("--ws-host") => {
+            let addr = args.get(1).map_or(DEFAULT_WS_ADDR, String::as_str);
+            println!("Waiting for opponent on ws://{}", addr);
+            netplay(settings, WsPeer::host(addr)?)
+        }
+        #[cfg(feature = "netplay")]
+        Some("--ws-connect") => {
+            let url = args.get(1).ok_or("--ws-connect needs ws://host:port")?;
+            netplay(settings, WsPeer::connect(url)?)
+        }
+        Some("bot") => bot(
+            settings,
+            option(args, "--games", 100)?,
+            option(args, "--depth", 1)?,
+            option(args, "--pieces", 1000)?,
+            option(args, "--seed", 1)?,
+        ),
+        // Looks into a replay file without playing it on screen
+        Some("replay") => {
+            let path = args
+                .get(2)
+                .ok_or_else(|| format!("replay needs {} and a file", REPLAY_COMMANDS))?;
+            let replay = Replay::read_from(&mut File::open(path)?)?;
+            match args.get(1).map(String::as_str) {
+                Some("info") => replay_info(&replay),
+                Some("verify") => replay_verify(&replay),
+                #[cfg(feature = "gif")]
+                Some("gif") => {
+                    let out = args.get(3).ok_or("replay gif needs a file to write")?;
+                    let mut writer = io::BufWriter::new(File::create(out)?);
+                    renderer::gif::export(&replay, &mut writer)?;
+                    Ok(writer.flush()?)
+                }
+                _ => Err(format!("replay takes {}", REPLAY_COMMANDS).into()),
+            }
+        }
+        // Finished games as CSV, to stdout without a file
+        Some("history") => match args.get(1).map(String::as_str) {
+            Some("csv") => {
+                let history = load(&output.data.join(HISTORY_FILE), History::read_from)?;
+                let mut csv = vec![];
+                history.write_csv(&mut csv)?;
+                let path = match args.get(2) {
+                    Some(path) if !path.starts_with("--") => path.as_str(),
+                    _ => "-",
+                };
+                write_output(path, &String::from_utf8_lossy(&csv))
+            }
+            _ => Err("history takes csv".into()),
+        },
+        // Modes come from the main menu without a mode argument
+        _ => title(settings, output),
+    }
+}
+
+// Value following `name` anywhere in the arguments
+fn option<T: FromStr>(args: &[String], name: &str, default: T) -> Result<T, Box<dyn Error>>
+where
+    T::Err: Into<Box<dyn Error>>,
+{
+    Ok(optional(args, name)?.unwrap_or(default))
+}
+
+fn optional<T: FromStr>(args: &[String], name: &str) -> Result<Option<T>, Box<dyn Error>>
+where
+    T::Err: Into<Box<dyn Error>>,
+{
+    match args.iter().position(|arg| arg == name) {
+        Some(i) => {
+            let value = args.get(i + 1).ok_or(format!("{} needs a value", name))?;
+            value.parse().map(Some).map_err(Into::into)
+        }
+        None => Ok(None),
+    }
+}
+
+fn handicaps(args: &[String]) -> Result<[Handicap; 2], Box<dyn Error>> {
+    Ok([
+        option(args, "--handicap1", Handicap::default())?,
+        option(args, "--handicap2", Handicap::default())?,
+    ])
+}
+
+// Lets the AI play many games headlessly and prints how they went,
+// for tuning heuristics and catching changes in the core
+fn bot(
+    settings: Settings,
+    games: u32,
+    depth: usize,
+    pieces: u32,
+    seed: u64,
+) -> Result<(), Box<dyn Error>> {
+    let weights = Weights::default();
+    let outcomes: Vec<Outcome> = (0..games as u64)
+        .map(|game| ai::play(settings, seed + game, depth, &weights, pieces))
+        .collect();
+    if outcomes.is_empty() {
+        return Err("--games must be at least 1".into());
+    }
+    let count = outcomes.len() as f32;
+    let mean =
+        |value: fn(&Outcome) -> u32| outcomes.iter().map(|o| value(o) as f32).sum::<f32>() / count;
+    println!("games      {}", outcomes.len());
+    println!(
+        "topped out {}",
+        outcomes.iter().filter(|o| o.topped_out).count()
+    );
+    println!("lines      {:.1} mean", mean(|o| o.lines));
+    println!("pieces     {:.1} mean", mean(|o| o.pieces));
+    let mut scores: Vec<u32> = outcomes.iter().map(|o| o.score).collect();
+    scores.sort_unstable();
+    let percentile = |p: usize| scores[(scores.len() - 1) * p / 100];
+    println!(
+        "score      {:.1} mean, min {} / 25% {} / median {} / 75% {} / max {}",
+        mean(|o| o.score),
+        percentile(0),
+        percentile(25),
+        percentile(50),
+        percentile(75),
+        percentile(100)
+    );
+    Ok(())
+}
+
+fn replay_info(replay: &Replay) -> Result<(), Box<dyn Error>> {
+    let build = match replay.build.as_str() {
+        "" => "unknown",
+        build => build,
+    };
+    let tetris = replay.play();
+    let seconds = replay.end / 1000;
+    let settings = replay.settings;
+    // The mode a game was played in is not recorded, only what the settings change
+    println!("build      {}", build);
+    println!("rules      {}", replay.rules);
+    println!(
+        "board      {}x{}, delay {} ms, level {}, lock delay {} ms, clear delay {} ms",
+        settings.cols,
+        settings.rows,
+        settings.delay,
+        settings.level,
+        settings.lock_delay,
+        settings.clear_delay
+    );
+    println!("rotation   {}", settings.rotation.name());
+    println!("blocks     {}", if settings.big { "big" } else { "normal" });
+    println!("seed       {}", replay.seed);
+    println!("inputs     {}", replay.inputs.len());
+    println!("duration   {}:{:02}", seconds / 60, seconds % 60);
+    println!("score      {}", tetris.score());
+    println!("pieces     {}", tetris.metrics().pieces);
+    println!("lines      {}", tetris.metrics().lines);
+    Ok(())
+}
+
+// Plays it again headlessly, errors when it does not end on the recorded state
+fn replay_verify(replay: &Replay) -> Result<(), Box<dyn Error>> {
+    if replay.rules != RULES_VERSION {
+        return Err(format!(
+            "recorded with rules {}, this build plays rules {}",
+            replay.rules, RULES_VERSION
+        )
+        .into());
+    }
+    let hash = replay.play().state_hash();
+    if hash != replay.hash {
+        return Err(format!(
+            "state hash {:016x} does not match the recorded {:016x}",
+            hash, replay.hash
+        )
+        .into());
+    }
+    println!("ok, state hash {:016x}", hash);
+    Ok(())
+}
+
+#[cfg(feature = "netplay")]
+const DEFAULT_ADDR: &str = "0.0.0.0:7878";
+#[cfg(feature = "netplay")]
+const DEFAULT_PORT: u16 = 7878;
+#[cfg(feature = "netplay")]
+const DEFAULT_WS_ADDR: &str = "0.0.0.0:7879";
+// Enough to hide the latency of a local network
+#[cfg(feature = "netplay")]
+const INPUT_DELAY: u64 = 50;
+
+// Milliseconds argument in lockstep ticks, rounded up
+#[cfg(feature = "netplay")]
+fn ticks(args: &[String], index: usize, default: u64) -> Result<u32, Box<dyn Error>> {
+    let millis = match args.get(index) {
+        Some(arg) => arg.parse()?,
+        None => default,
+    };
+    Ok(millis.div_ceil(TICK) as u32)
+}
+
+// Where single games write what they leave behind, - is stdout for the ones
+// written after the game
+#[derive(Clone)]
+struct Output {
+    snapshot: Option<String>,
+    stats: Option<String>,
+    events: Option<String>,
+    // Bests, the profile, saves and screenshots go on from game to game there
+    data: PathBuf,
+    // Terminal bell as the only sound there is
+    bell: bool,
+}
+
+// Writes `text` once the terminal is back to normal
+fn write_output(path: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    match path {
+        "-" => print!("{}", text),
+        path => fs::write(path, text)?,
+    }
+    Ok(())
+}
+
+// Kinds of single games, only marathons start from the seed alone
+enum Single {
+    Marathon,
+    // Marathon without the preview
+    Blind,
+    // Marathon without a game over
+    Zen,
+    // Marathon drawn upside down
+    UpsideDown,
+    // Marathon without the stack drawn
+    Invisible,
+    // Clearing that many rows of garbage as fast as possible
+    Dig(u16),
+    // Clearing `SPRINT_LINES` lines as fast as possible
+    Sprint,
+    // Marathon of two players taking turns
+    HotSeat(Turns),
+    // Marathon on the `EASY` board without pressure
+    Easy,
+    // Every game starts from the page
+    Puzzle(Page),
+    // The first game is the daily challenge
+    Daily(DailyListener),
+    Drills(DrillSet, DrillListener),
+}
+
+impl Single {
+    fn practice(&self) -> bool {
+        matches!(
+            self,
+            Single::Zen
+                | Single::UpsideDown
+                | Single::Invisible
+                | Single::Dig(_)
+                | Single::HotSeat(_)
+                | Single::Easy
+                | Single::Puzzle(_)
+                | Single::Drills(..)
+        )
+    }
+
+    // Only the first daily game counts, loading a save would give it another go
+    fn saves(&self) -> bool {
+        !self.practice() && !matches!(self, Single::Daily(_))
+    }
+}
+
+// Saves the replay of the last game into `record` when asked to. Marathons go on
+// from the autosave right away with `resume`, otherwise the player is asked first.
+fn single(
+    settings: Settings,
+    record: Option<&str>,
+    kind: Single,
+    resume: bool,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let practice = kind.practice();
+    let saves = kind.saves();
+    let zen = matches!(kind, Single::Zen);
+    let upside_down = matches!(kind, Single::UpsideDown);
+    // The leaderboard ranks marathons, the other modes would be posted as one
+    #[cfg(feature = "leaderboard")]
+    let unranked = !matches!(kind, Single::Marathon | Single::Blind);
+    // Only marathons are autosaved, the others do not start from the seed alone
+    let autosave = match &kind {
+        Single::Marathon | Single::Blind => Some(output.data.join(AUTOSAVE_FILE)),
+        _ => None,
+    };
+    let restore = match &autosave {
+        Some(path) if resume => read_autosave(path),
+        Some(path) => ask_restore(path)?,
+        None => None,
+    };
+    // A restored marathon goes on with or without the preview like it started
+    let blind = match &restore {
+        Some(save) => save.mode == BLIND_MODE,
+        None => matches!(kind, Single::Blind),
+    };
+    // Blind runs and sprints have bests of their own
+    let ranked = match (&kind, blind) {
+        (Single::Sprint, _) => SPRINT_MODE,
+        (_, true) => BLIND_MODE,
+        (_, false) => MODE,
+    };
+    let mode = match &kind {
+        Single::Marathon | Single::Blind | Single::Sprint => ranked,
+        Single::Zen => "zen",
+        Single::UpsideDown => "upside-down",
+        Single::Invisible => "invisible",
+        Single::Dig(_) => "dig",
+        Single::HotSeat(_) => "hot-seat",
+        Single::Easy => "easy",
+        Single::Puzzle(_) => "puzzle",
+        Single::Daily(_) => "daily",
+        Single::Drills(..) => "drill",
+    };
+    let bindings = load(&output.data.join(KEYS_FILE), Bindings::read_from)?;
+    let controls = ControlsListener {
+        lines: controls(&bindings),
+    };
+    // Keys are mapped in the game loop, the cheat console can take them first
+    let (tx, keys) = mpsc::channel::<KeyCode>();
+    // Cells of easy games are twice as large
+    let (width, height) = match kind {
+        Single::Easy => (4, 2),
+        _ => (2, 1),
+    };
+    let renderer = ConsoleView::new(settings, width, height, '\u{2588}', None);
     renderer.prepare()?;
     renderer.init_field()?;
-    renderer.keypress(tx);
+    renderer.keypress(tx, Some);
 
-    let now = || {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as Timestamp
+    let clock = SystemClock::new();
+    let mut tetris = match &kind {
+        Single::Daily(listener) => {
+            Tetris::with_seed(settings, clock.now(), daily::seed(listener.day))
+        }
+        _ => Tetris::new(settings, clock.now()),
+    };
+    tetris.add_listener(Box::new(controls));
+    tetris.set_zen(zen);
+    tetris.set_upside_down(upside_down);
+    tetris.set_blind(blind);
+    tetris.set_invisible(matches!(kind, Single::Invisible));
+    tetris.set_easy(matches!(kind, Single::Easy));
+    if let Single::Dig(rows) = kind {
+        tetris.set_dig(Some(rows));
+    }
+    if let Single::Sprint = kind {
+        tetris.set_sprint(Some(SPRINT_LINES));
+    }
+    #[cfg(feature = "debug-tools")]
+    let cheats = Rc::new(RefCell::new(CheatConsole::default()));
+    #[cfg(feature = "debug-tools")]
+    tetris.set_cheats(Some(cheats.clone()));
+    match kind {
+        Single::Marathon
+        | Single::Blind
+        | Single::Zen
+        | Single::UpsideDown
+        | Single::Invisible
+        | Single::Dig(_)
+        | Single::Sprint
+        | Single::Easy => {}
+        Single::HotSeat(turns) => tetris.set_turns(Some(turns)),
+        Single::Puzzle(page) => tetris.set_puzzle(Some(page)),
+        Single::Daily(listener) => tetris.add_listener(Box::new(listener)),
+        Single::Drills(set, listener) => {
+            tetris.set_drills(Some(set));
+            tetris.add_listener(Box::new(listener));
+        }
+    }
+    // Puzzles, drills and dig races start from some other stack, zen games play by other rules
+    // and upside down, invisible, hot seat and easy ones are for fun, their runs are no personal bests and they cannot be saved
+    let mut bests = match practice {
+        true => None,
+        false => Some(load(
+            &output.data.join(BESTS_FILE),
+            PersonalBests::read_from,
+        )?),
+    };
+    if saves {
+        tetris.set_saves(Some(load_saves(&output.data)?));
+    }
+    tetris.set_personal_best(bests.as_ref().and_then(|b| b.get(ranked)).cloned());
+    let profile = load(&output.data.join(PROFILE_FILE), Profile::read_from)?;
+    tetris.set_profile(Some(profile));
+    let last = Rc::new(RefCell::new(None));
+    if output.snapshot.is_some() {
+        tetris.add_listener(Box::new(SnapshotListener { last: last.clone() }));
+    }
+    #[cfg(feature = "png")]
+    tetris.add_listener(Box::new(ScreenshotListener {
+        dir: output.data.clone(),
+    }));
+    let watch = Rc::new(Cell::new(None));
+    tetris.add_listener(Box::new(HistoryListener {
+        mode: mode.to_string(),
+        // Replays start from the seed alone with the usual rules, puzzles, drills and
+        // zen games would not play back
+        replays: !practice,
+        history: load(&output.data.join(HISTORY_FILE), History::read_from)?,
+        data: output.data.clone(),
+        totals: None,
+        watch: watch.clone(),
+    }));
+    if output.bell {
+        tetris.add_listener(Box::new(BellListener));
+    }
+    tetris.add_listener(Box::new(ReplayListener {
+        dir: output.data.join(REPLAYS_DIR),
+    }));
+    if let Some(path) = &output.events {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        tetris.add_listener(Box::new(EventLog::new(file)));
+    }
+    if let Some(path) = &autosave {
+        autosave_on_panic();
+        tetris.add_listener(Box::new(AutosaveListener {
+            mode: mode.to_string(),
+            path: path.clone(),
+            written: Instant::now(),
+        }));
+    }
+    #[cfg(feature = "leaderboard")]
+    let mut tetris = match unranked {
+        true => tetris,
+        false => with_leaderboard(tetris, ranked)?,
     };
-    let mut tetris = Tetris::new(settings, now());
+    if let Some(save) = restore {
+        tetris.resume(&save.replay, clock.now());
+    }
+    let demo = Demo::new(tetris, clock.now());
+    let mut game = GameLoop::new(demo, renderer, clock);
+    // A restored game waits in the menu, it is there before the first key
+    if let Some(menu) = game.game().game().menu() {
+        game.renderer().draw_text(menu)?;
+    }
+    game.run_mapped(&keys, |key| {
+        #[cfg(feature = "debug-tools")]
+        if cheat_key(&cheats, key) {
+            return None;
+        }
+        bindings.action(key)
+    })?;
+    game.renderer().clear()?;
+    // Leaving on purpose needs no restoring, saving is what the slots are for
+    if let Some(path) = &autosave {
+        remove_autosave(path);
+    }
+    if let Some(path) = record {
+        game.game()
+            .game()
+            .replay()
+            .write_to(&mut File::create(path)?)?;
+    }
+    if let (Some(path), Some(text)) = (&output.snapshot, last.take()) {
+        write_output(path, &text)?;
+    }
+    let tetris = game.game().game();
+    if let Some(path) = &output.stats {
+        let json = stats::to_json(
+            ranked,
+            tetris.seed(),
+            tetris.score(),
+            tetris.level(),
+            tetris.metrics(),
+        );
+        write_output(path, &json)?;
+    }
+    if let Some(saves) = tetris.saves().filter(|saves| saves.changed()) {
+        write_saves(&output.data, saves)?;
+    }
+    if let Some(played) = tetris.profile().filter(|&played| *played != profile) {
+        played.write_to(&mut File::create(output.data.join(PROFILE_FILE))?)?;
+    }
+    if let (Some(bests), Some(best)) = (&mut bests, tetris.personal_best()) {
+        if bests.record(ranked, best.clone()) {
+            bests.write_to(&mut File::create(output.data.join(BESTS_FILE))?)?;
+        }
+    }
+    // Chosen in the history menu, it is raced as a ghost
+    if let Some(name) = watch.take() {
+        let path = output.data.join(HISTORY_DIR).join(name);
+        let bindings = load(&output.data.join(KEYS_FILE), Bindings::read_from)?;
+        ghost(Replay::read_from(&mut File::open(path)?)?, bindings)?;
+    }
+    Ok(())
+}
+
+const SETTINGS_FILE: &str = "settings.txt";
+// High scores of the history in the main menu
+const HIGH_SCORES: usize = 10;
+
+// Main menu, it comes back after every game until the player quits. The settings
+// picked there are kept for the next run.
+fn title(settings: Settings, output: Output) -> Result<(), Box<dyn Error>> {
+    let path = output.data.join(SETTINGS_FILE);
+    let mut preferences = load(&path, Preferences::read_from)?;
+    let bindings = load(&output.data.join(KEYS_FILE), Bindings::read_from)?;
+    let autosave = output.data.join(AUTOSAVE_FILE);
+    let resume = || read_autosave(&autosave).map(|save| save.summary());
+    let title = Title::new(
+        preferences,
+        high_scores(&output.data)?,
+        controls(&bindings),
+        resume(),
+    );
+    let renderer = ConsoleView::new(settings, 2, 1, '\u{2588}', None);
+    let mut menu = GameLoop::new(title, renderer, SystemClock::new());
     loop {
-        let action = rx.try_recv().ok();
-        let frame = tetris.frame(now(), action);
-        match frame {
-            GameChange::Draw(gameview) => renderer.draw_game(&gameview)?,
-            GameChange::Text(ref menuview) => renderer.draw_text(&menuview)?,
-            GameChange::Restart => tetris = Tetris::new(settings, now()),
-            GameChange::Exit => break,
-            GameChange::Idle => continue,
+        let (tx, rx) = mpsc::channel::<Action>();
+        menu.renderer().prepare()?;
+        let bindings = bindings.clone();
+        menu.renderer()
+            .keypress(tx, move |key| bindings.action(key));
+        menu.run(&rx)?;
+        menu.renderer().clear()?;
+        menu.renderer().restore()?;
+        if menu.game().preferences() != preferences {
+            preferences = menu.game().preferences();
+            preferences.write_to(&mut File::create(&path)?)?;
+        }
+        let mode = match menu.game().chosen() {
+            Some(mode) => mode,
+            None => return Ok(()),
+        };
+        let settings = preferences.apply(settings);
+        let marathon = Settings {
+            level: preferences.level,
+            ..settings
+        };
+        let mut played = output.clone();
+        played.bell |= preferences.bell;
+        match mode {
+            Mode::Resume => single(settings, None, Single::Marathon, true, played),
+            // A new game over the one the menu offered to resume
+            Mode::Marathon => {
+                remove_autosave(&autosave);
+                single(marathon, None, Single::Marathon, false, played)
+            }
+            Mode::Blind => single(marathon, None, Single::Blind, false, played),
+            Mode::Zen => single(settings, None, Single::Zen, false, played),
+            Mode::HotSeat => {
+                let turns = Turns::Pieces(TURN_PIECES);
+                single(settings, None, Single::HotSeat(turns), false, played)
+            }
+            Mode::UpsideDown => single(settings, None, Single::UpsideDown, false, played),
+            Mode::Invisible => single(settings, None, Single::Invisible, false, played),
+            Mode::Dig => single(settings, None, Single::Dig(DIG_ROWS), false, played),
+            Mode::Sprint => single(settings, None, Single::Sprint, false, played),
+            Mode::Easy => single(EASY, None, Single::Easy, false, played),
+            Mode::Daily => daily(settings, played),
+            Mode::Practice => play_drills(settings, drill::bundled(&settings)?, played),
+            Mode::Versus => versus(settings, None, Default::default(), 1),
+            Mode::Computer => versus(settings, Some(Difficulty::Normal), Default::default(), 1),
+            Mode::Coop => coop(settings),
+        }?;
+        menu.game_mut().reopen(high_scores(&output.data)?, resume());
+    }
+}
+
+// Keys of the actions and the rules after them
+fn controls(bindings: &Bindings) -> Vec<String> {
+    let mut lines = bindings.help();
+    lines.push(String::new());
+    lines.extend(RULES.iter().map(|rule| rule.to_string()));
+    lines
+}
+
+// Controls screen of the pause menu, from the keys the game is played with
+struct ControlsListener {
+    lines: Vec<String>,
+}
+
+impl TetrisListener for ControlsListener {
+    fn menu_items(&self) -> Vec<MenuItem<TetrisPause>> {
+        let items = self
+            .lines
+            .iter()
+            .map(|line| MenuItem::label(TetrisPause::Title, line.as_str()))
+            .collect();
+        vec![MenuItem::submenu(TetrisPause::Title, "Controls", items)]
+    }
+}
+
+// Best marathon, fastest sprint and the best games of the history
+fn high_scores(data: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut lines = vec![];
+    let bests = load(&data.join(BESTS_FILE), PersonalBests::read_from)?;
+    for mode in [MODE, BLIND_MODE, SPRINT_MODE] {
+        if let Some(best) = bests.get(mode) {
+            let result = best.time.map_or(best.score.to_string(), metrics::clock);
+            lines.push(format!("Best {} {}", mode, result));
+        }
+    }
+    let history = load(&data.join(HISTORY_FILE), History::read_from)?;
+    // Practice games start from some other stack, their scores do not count
+    let mut entries: Vec<&Entry> = history
+        .entries()
+        .iter()
+        .filter(|entry| entry.replay != NO_REPLAY)
+        .collect();
+    entries.sort_by_key(|entry| Reverse(entry.score));
+    lines.extend(
+        entries
+            .iter()
+            .take(HIGH_SCORES)
+            .map(|entry| entry.summary()),
+    );
+    Ok(lines)
+}
+
+// What `replay` does with a file, as told when it is missing
+#[cfg(feature = "gif")]
+const REPLAY_COMMANDS: &str = "info, verify or gif";
+#[cfg(not(feature = "gif"))]
+const REPLAY_COMMANDS: &str = "info or verify";
+
+// Single games are marathons until there are other modes
+const MODE: &str = "marathon";
+const BLIND_MODE: &str = "marathon-blind";
+const SPRINT_MODE: &str = "sprint";
+// Pieces a hot seat turn lasts unless told otherwise
+const TURN_PIECES: u32 = 10;
+// Garbage rows of a dig race unless told otherwise
+const DIG_ROWS: u16 = 10;
+// Lines a sprint ends at
+const SPRINT_LINES: u32 = 40;
+// Board of easy games whatever the settings, with its large cells it still fits
+// a terminal of 80 by 24
+const EASY: Settings = Settings {
+    cols: 8,
+    rows: 10,
+    delay: 1500,
+    level: 1,
+    lock_delay: 0,
+    clear_delay: 200,
+    das: 0,
+    arr: 0,
+    entry_delay: 0,
+    top_out: TopOut::BLOCK_OUT,
+    rotation: RotationSystem::Srs,
+    big: false,
+};
+const BESTS_FILE: &str = "bests.txt";
+const PROFILE_FILE: &str = "profile.txt";
+
+// Files that are not there yet load as empty
+fn load<T: Default>(
+    path: &Path,
+    read: fn(&mut File) -> io::Result<T>,
+) -> Result<T, Box<dyn Error>> {
+    match File::open(path) {
+        Ok(mut file) => Ok(read(&mut file)?),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+// Keys of single games, the defaults until some are imported
+const KEYS_FILE: &str = "keys.txt";
+
+fn bindings_text(bindings: &Bindings) -> io::Result<String> {
+    let mut text = vec![];
+    bindings.write_to(&mut text)?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
+const AUTOSAVE_FILE: &str = "autosave.sav";
+// Autosaves are written at most this often, a crash loses less than that
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Offers the game a crash or a dropped connection left behind, before the terminal
+// is taken over. Files broken by the crash are dropped.
+fn ask_restore(path: &Path) -> Result<Option<Save>, Box<dyn Error>> {
+    let save = match read_autosave(path) {
+        Some(save) => save,
+        None => return Ok(None),
+    };
+    print!("Restore the unfinished game, {}? [Y/n] ", save.summary());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if answer.trim().to_lowercase().starts_with('n') {
+        remove_autosave(path);
+        return Ok(None);
+    }
+    Ok(Some(save))
+}
+
+// Keys type into the cheat console while it is open, the backquote opens it. Returns
+// whether the key was taken.
+#[cfg(feature = "debug-tools")]
+fn cheat_key(console: &RefCell<CheatConsole>, key: KeyCode) -> bool {
+    let mut console = console.borrow_mut();
+    match key {
+        KeyCode::Char('`') if !console.is_open() => console.open(),
+        _ if !console.is_open() => return false,
+        KeyCode::Enter => console.enter(),
+        KeyCode::Esc => console.close(),
+        KeyCode::Backspace => console.erase(),
+        KeyCode::Char(c) => console.type_char(c),
+        _ => {}
+    }
+    true
+}
+
+// Autosaves that cannot be read are dropped
+fn read_autosave(path: &Path) -> Option<Save> {
+    match File::open(path).and_then(|mut file| Save::read_from(&mut file)) {
+        Ok(save) => Some(save),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+        Err(error) => {
+            log::warn!("Autosave dropped: {}", error);
+            remove_autosave(path);
+            None
+        }
+    }
+}
+
+// Nothing is left to restore, a panic from here on writes no autosave either
+fn remove_autosave(path: &Path) {
+    if let Ok(mut latest) = LATEST_AUTOSAVE.lock() {
+        *latest = None;
+    }
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => {
+            log::warn!("Autosave not removed: {}", error)
+        }
+        _ => {}
+    }
+}
+
+// Written next to the autosave first, a crash while writing leaves the last one whole
+fn write_autosave(path: &Path, save: &Save) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    save.write_to(&mut File::create(&temp)?)?;
+    fs::rename(temp, path)
+}
+
+// Last state of the game being autosaved and where it goes, cleared when it ends
+static LATEST_AUTOSAVE: Mutex<Option<(PathBuf, Save)>> = Mutex::new(None);
+static PANIC_HOOK: Once = Once::new();
+
+// Panics write the latest state as well, it is newer than the last periodic write.
+// The hook goes in with the first autosaved game and serves all that come after.
+fn autosave_on_panic() {
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Ok(latest) = LATEST_AUTOSAVE.try_lock() {
+                if let Some((path, save)) = latest.as_ref() {
+                    let _ = write_autosave(path, save);
+                }
+            }
+            previous(info);
+        }));
+    });
+}
+
+// Keeps the game in progress for restoring it after a crash, finished games are
+// removed
+struct AutosaveListener {
+    mode: String,
+    path: PathBuf,
+    written: Instant,
+}
+
+impl TetrisListener for AutosaveListener {
+    fn on_progress(&mut self, replay: &Replay, score: u32) {
+        let save = Save {
+            mode: self.mode.clone(),
+            score,
+            time: SystemClock::new().now(),
+            replay: replay.clone(),
+        };
+        if self.written.elapsed() >= AUTOSAVE_INTERVAL {
+            if let Err(e) = write_autosave(&self.path, &save) {
+                log::warn!("Autosave failed: {}", e);
+            }
+            self.written = Instant::now();
+        }
+        if let Ok(mut latest) = LATEST_AUTOSAVE.lock() {
+            *latest = Some((self.path.clone(), save));
         }
     }
-    renderer.clear()?;
+
+    fn on_game_over(&mut self, _score: u32) {
+        remove_autosave(&self.path);
+    }
+}
+
+const DAILY_FILE: &str = "daily.txt";
+
+fn daily(settings: Settings, output: Output) -> Result<(), Box<dyn Error>> {
+    let path = output.data.join(DAILY_FILE);
+    let mut results = load(&path, DailyResults::read_from)?;
+    let day = daily::day(SystemClock::new().now());
+    if !results.start(day) {
+        for line in results.calendar(day) {
+            println!("{}", line);
+        }
+        match results.score(day) {
+            Some(score) => println!("Today's daily is done, it scored {}", score),
+            None => println!("Today's daily was attempted already"),
+        }
+        return Ok(());
+    }
+    // Written before the game, a crash or quitting takes the attempt as well
+    results.write_to(&mut File::create(&path)?)?;
+    let listener = DailyListener { day, results, path };
+    single(settings, None, Single::Daily(listener), false, output)
+}
+
+// Keeps the first game over of the daily challenge, the month of results is in
+// the game over menu
+struct DailyListener {
+    day: i64,
+    results: DailyResults,
+    path: PathBuf,
+}
+
+impl TetrisListener for DailyListener {
+    fn on_game_over(&mut self, score: u32) {
+        if self.results.finish(self.day, score) {
+            let written =
+                File::create(&self.path).and_then(|mut file| self.results.write_to(&mut file));
+            if let Err(e) = written {
+                log::warn!("Daily result not saved: {}", e);
+            }
+        }
+    }
+
+    fn over_items(&mut self) -> Vec<MenuItem<TetrisPause>> {
+        let items = self
+            .results
+            .calendar(self.day)
+            .into_iter()
+            .map(|line| MenuItem::label(TetrisPause::Title, line))
+            .collect();
+        vec![MenuItem::submenu(
+            TetrisPause::Title,
+            "Daily Results",
+            items,
+        )]
+    }
+}
+
+const HISTORY_FILE: &str = "history.txt";
+// Replays of the games in the history
+const HISTORY_DIR: &str = "history";
+// Replay file of entries without one
+const NO_REPLAY: &str = "-";
+const REPLAYS_DIR: &str = "replays";
+
+// Keeps the replays saved from the game over menu, named after when they were saved
+struct ReplayListener {
+    dir: PathBuf,
+}
+
+impl TetrisListener for ReplayListener {
+    fn on_save_replay(&mut self, replay: &Replay) {
+        let path = self.dir.join(format!("{}.rep", SystemClock::new().now()));
+        let saved =
+            fs::create_dir_all(&self.dir).and_then(|_| replay.write_to(&mut File::create(&path)?));
+        match saved {
+            Ok(()) => log::info!("Replay saved to {}", path.display()),
+            Err(e) => log::warn!("Saving the replay failed: {}", e),
+        }
+    }
+}
+
+// Rings on tetrises, level ups and game overs
+struct BellListener;
+
+impl BellListener {
+    fn ring(&self) {
+        print!("\x07");
+        io::stdout().flush().ok();
+    }
+}
+
+impl TetrisListener for BellListener {
+    fn on_lines_cleared(&mut self, lines: u16) {
+        if lines == 4 {
+            self.ring();
+        }
+    }
+
+    fn on_level_up(&mut self, _level: u32) {
+        self.ring();
+    }
+
+    fn on_game_over(&mut self, _score: u32) {
+        self.ring();
+    }
+}
+
+// Adds every finished game to the history and keeps its replay. The history is a
+// submenu of the pause and game over menus to pick a game to watch again from.
+struct HistoryListener {
+    mode: String,
+    replays: bool,
+    history: History,
+    data: PathBuf,
+    // Of the game that just ended, they come before its replay
+    totals: Option<Totals>,
+    // Replay file of the game chosen to watch, for after the game is left
+    watch: Rc<Cell<Option<String>>>,
+}
+
+impl HistoryListener {
+    fn submenu(&self) -> MenuItem<TetrisPause> {
+        let mut items: Vec<_> = self
+            .history
+            .entries()
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, entry)| match entry.replay.as_str() {
+                NO_REPLAY => MenuItem::label(TetrisPause::Title, entry.summary()),
+                _ => MenuItem::button(TetrisPause::History(i), entry.summary()),
+            })
+            .collect();
+        if items.is_empty() {
+            items.push(MenuItem::label(TetrisPause::Title, "No Games Yet"));
+        }
+        MenuItem::submenu(TetrisPause::Title, "History", items)
+    }
+
+    fn add(&mut self, replay: &Replay, score: u32) -> io::Result<()> {
+        let time = SystemClock::new().now();
+        let dir = self.data.join(HISTORY_DIR);
+        let name = match self.replays {
+            true => format!("{}.rep", time),
+            false => NO_REPLAY.to_string(),
+        };
+        if self.replays {
+            fs::create_dir_all(&dir)?;
+            replay.write_to(&mut File::create(dir.join(&name))?)?;
+        }
+        let entry = Entry {
+            mode: self.mode.clone(),
+            score,
+            duration: replay.end,
+            time,
+            replay: name,
+            totals: self.totals.take(),
+        };
+        for old in self.history.push(entry) {
+            if old.replay != NO_REPLAY {
+                fs::remove_file(dir.join(old.replay)).ok();
+            }
+        }
+        self.history
+            .write_to(&mut File::create(self.data.join(HISTORY_FILE))?)
+    }
+}
+
+impl TetrisListener for HistoryListener {
+    fn on_metrics(&mut self, metrics: &Metrics) {
+        self.totals = Some(metrics.into());
+    }
+
+    fn on_finished(&mut self, replay: &Replay, score: u32) {
+        if let Err(e) = self.add(replay, score) {
+            log::warn!("Game not added to the history: {}", e);
+        }
+    }
+
+    fn on_watch(&mut self, entry: usize) {
+        let entry = self
+            .history
+            .entries()
+            .get(entry)
+            .filter(|e| e.replay != NO_REPLAY);
+        self.watch.set(entry.map(|entry| entry.replay.clone()));
+    }
+
+    fn menu_items(&self) -> Vec<MenuItem<TetrisPause>> {
+        vec![self.submenu()]
+    }
+
+    fn over_items(&mut self) -> Vec<MenuItem<TetrisPause>> {
+        vec![self.submenu()]
+    }
+}
+
+// Drill files end in .drill, they are played in the order of their names
+const DRILL_EXTENSION: &str = "drill";
+const DRILLS_FILE: &str = "drills.txt";
+
+// Drills of the files in `dir`
+fn drills(settings: Settings, dir: &Path, output: Output) -> Result<(), Box<dyn Error>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == DRILL_EXTENSION)
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut list = vec![];
+    for path in paths {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let drill = Drill::parse(&name, &fs::read_to_string(&path)?, &settings)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        list.push(drill);
+    }
+    if list.is_empty() {
+        return Err(format!("{} has no .{} files", dir.display(), DRILL_EXTENSION).into());
+    }
+    play_drills(settings, list, output)
+}
+
+// Starts with the first drill not cleared yet
+fn play_drills(settings: Settings, list: Vec<Drill>, output: Output) -> Result<(), Box<dyn Error>> {
+    let path = output.data.join(DRILLS_FILE);
+    let progress = load(&path, DrillProgress::read_from)?;
+    let first = list
+        .iter()
+        .position(|drill| !progress.is_cleared(&drill.name))
+        .unwrap_or(0);
+    let listener = DrillListener {
+        names: list.iter().map(|drill| drill.name.clone()).collect(),
+        progress,
+        path,
+        cleared: None,
+    };
+    let set = DrillSet::new(list, first);
+    single(settings, None, Single::Drills(set, listener), false, output)
+}
+
+// Keeps which drills were cleared and how fast, how many of them is in the game
+// over menu. The pause menu has them all with their best times to pick from.
+struct DrillListener {
+    names: Vec<String>,
+    progress: DrillProgress,
+    path: PathBuf,
+    // Drill the game that just ended cleared, its time comes with the metrics
+    cleared: Option<String>,
+}
+
+impl DrillListener {
+    fn best(&self, name: &str) -> String {
+        match self.progress.best(name) {
+            Some(time) => format!("{} {:.1}s", name, time as f32 / 1000.),
+            None if self.progress.is_cleared(name) => format!("{} cleared", name),
+            None => name.to_string(),
+        }
+    }
+}
+
+impl TetrisListener for DrillListener {
+    fn on_drill(&mut self, name: &str, cleared: bool) {
+        self.cleared = Some(name.to_string()).filter(|_| cleared);
+    }
+
+    fn on_metrics(&mut self, metrics: &Metrics) {
+        let name = match self.cleared.take() {
+            Some(name) => name,
+            None => return,
+        };
+        if self.progress.clear(&name, metrics.elapsed) {
+            let written =
+                File::create(&self.path).and_then(|mut file| self.progress.write_to(&mut file));
+            if let Err(e) = written {
+                log::warn!("Drill progress not saved: {}", e);
+            }
+        }
+    }
+
+    fn menu_items(&self) -> Vec<MenuItem<TetrisPause>> {
+        let items = self
+            .names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| MenuItem::button(TetrisPause::Drill(i), self.best(name)))
+            .collect();
+        vec![MenuItem::submenu(TetrisPause::Title, "Practice", items)]
+    }
+
+    fn over_items(&mut self) -> Vec<MenuItem<TetrisPause>> {
+        let cleared = self
+            .names
+            .iter()
+            .filter(|name| self.progress.is_cleared(name))
+            .count();
+        let text = format!("{} of {} Drills Cleared", cleared, self.names.len());
+        vec![MenuItem::label(TetrisPause::Title, text)]
+    }
+}
+
+const SAVES_DIR: &str = "saves";
+
+fn slot_path(data: &Path, slot: usize) -> PathBuf {
+    data.join(SAVES_DIR).join(format!("slot{}.sav", slot + 1))
+}
+
+fn load_saves(data: &Path) -> Result<SaveSlots, Box<dyn Error>> {
+    let mut saves = SaveSlots::new(MODE);
+    for slot in 0..SLOTS {
+        let path = slot_path(data, slot);
+        match File::open(&path).and_then(|mut file| Save::read_from(&mut file)) {
+            Ok(save) => saves.set(slot, save),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            // The slot shows as empty, saving to it replaces the file
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                log::warn!("Save {} skipped: {}", path.display(), error)
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(saves)
+}
+
+fn write_saves(data: &Path, saves: &SaveSlots) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(data.join(SAVES_DIR))?;
+    for slot in 0..SLOTS {
+        if let Some(save) = saves.get(slot) {
+            save.write_to(&mut File::create(slot_path(data, slot))?)?;
+        }
+    }
+    Ok(())
+}
+
+// Keeps the final board of the last game over, it is written once the terminal is back
+struct SnapshotListener {
+    last: Rc<RefCell<Option<String>>>,
+}
+
+impl TetrisListener for SnapshotListener {
+    fn on_final_board(&mut self, snapshot: &str) {
+        *self.last.borrow_mut() = Some(snapshot.to_string());
+    }
+}
+
+// Saves a PNG of the board whenever the player asks for one
+#[cfg(feature = "png")]
+struct ScreenshotListener {
+    dir: PathBuf,
+}
+
+#[cfg(feature = "png")]
+impl TetrisListener for ScreenshotListener {
+    fn on_screenshot(&mut self, game: &GameMode) {
+        match renderer::png::save(&self.dir, game) {
+            Ok(path) => log::info!("Screenshot saved to {}", path.display()),
+            Err(e) => log::warn!("Screenshot failed: {}", e),
+        }
+    }
+}
+
+fn ghost(replay: Replay, bindings: Bindings) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel::<Action>();
+    let view = ConsoleView::new(replay.settings, 2, 1, '\u{2588}', None);
+    let renderer = SplitView::new(view, 2).with_color(1, Some(Color::DarkGrey));
+    renderer.prepare()?;
+    renderer.init_field()?;
+    renderer.keypress(tx, move |key| bindings.action(key));
+
+    let clock = SystemClock::new();
+    let ghost = Ghost::new(replay, clock.now());
+    let mut game = GameLoop::new(ghost, renderer, clock);
+    game.run(&rx)?;
+    game.renderer().clear()?;
     Ok(())
 }
+
+// Leaderboard server to submit results to, like http://example.com:8080/tetris
+#[cfg(feature = "leaderboard")]
+fn with_leaderboard(mut tetris: Tetris, mode: &str) -> Result<Tetris, Box<dyn Error>> {
+    if let Ok(url) = env::var("BRICK_GAME_LEADERBOARD") {
+        let player = env::var("BRICK_GAME_PLAYER")
+            .or_else(|_| env::var("USER"))
+            .unwrap_or_else(|_| "anonymous".to_string());
+        let mut listener = LeaderboardListener::new(Leaderboard::new(&url)?, player, mode);
+        // Runs are signed with a key shared with the server
+        if let Ok(key) = env::var("BRICK_GAME_LEADERBOARD_KEY") {
+            listener = listener.with_key(key);
+        }
+        tetris.add_listener(Box::new(listener));
+    }
+    Ok(tetris)
+}
+
+// Second player is the computer when a difficulty is given
+fn versus(
+    settings: Settings,
+    ai: Option<Difficulty>,
+    handicaps: [Handicap; 2],
+    first_to: u32,
+) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel::<PlayerAction>();
+    let renderer = SplitView::new(ConsoleView::new(settings, 2, 1, '\u{2588}', None), 2);
+    renderer.prepare()?;
+    renderer.init_field()?;
+    let clock = SystemClock::new();
+    let mut versus = Versus::new(settings, clock.now())
+        .with_handicap(0, handicaps[0])
+        .with_handicap(1, handicaps[1])
+        .with_first_to(first_to);
+    match ai {
+        Some(difficulty) => {
+            renderer.keypress(tx, key_to_first_player);
+            versus = versus.with_bot(1, Bot::new(difficulty, clock.now()));
+        }
+        None => {
+            renderer.keypress(tx, key_to_versus_action);
+        }
+    }
+    let mut game = GameLoop::new(versus, renderer, clock);
+    game.run(&rx)?;
+    game.renderer().clear()?;
+    Ok(())
+}
+
+// Both players on one keyboard like in versus, sharing a board twice as wide
+fn coop(settings: Settings) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel::<PlayerAction>();
+    let clock = SystemClock::new();
+    let coop = Coop::new(settings, clock.now());
+    let renderer = ConsoleView::new(*coop.settings(), 2, 1, '\u{2588}', None);
+    renderer.prepare()?;
+    renderer.init_field()?;
+    renderer.keypress(tx, key_to_versus_action);
+    let mut game = GameLoop::new(coop, renderer, clock);
+    game.run(&rx)?;
+    game.renderer().clear()?;
+    Ok(())
+}
+
+#[cfg(feature = "netplay")]
+fn netplay(settings: Settings, peer: impl Transport) -> Result<(), Box<dyn Error>> {
+    let peer = handshake(peer, &settings)?;
+    let (tx, keys) = mpsc::channel::<KeyCode>();
+    ConsoleView::new(settings, 2, 1, '\u{2588}', None).keypress(tx, Some);
+    remote_versus(settings, peer, Handicap::default(), &keys)
+}
+
+// Both boards of a match somebody else plays
+#[cfg(feature = "netplay")]
+fn watch(settings: Settings, peer: impl Transport) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel::<Action>();
+    let renderer = SplitView::new(ConsoleView::new(settings, 2, 1, '\u{2588}', None), 2);
+    renderer.prepare()?;
+    renderer.init_field()?;
+    renderer.keypress(tx, ConsoleView::key_to_action);
+    let mut game = GameLoop::new(Observer::new(settings, peer), renderer, SystemClock::new());
+    game.run(&rx)?;
+    game.renderer().clear()?;
+    Ok(())
+}
+
+// Keys are read once for the lobby and the match after it
+#[cfg(feature = "netplay")]
+fn lan(settings: Settings, port: u16) -> Result<(), Box<dyn Error>> {
+    let (tx, keys) = mpsc::channel::<KeyCode>();
+    let renderer = ConsoleView::new(settings, 2, 1, '\u{2588}', None);
+    renderer.prepare()?;
+    renderer.keypress(tx, Some);
+
+    let mut lobby = GameLoop::new(Lobby::new(settings, port), renderer, SystemClock::new());
+    lobby.run_mapped(&keys, key_to_lobby_input)?;
+    lobby.renderer().clear()?;
+    match lobby.game_mut().take_peer() {
+        Some(peer) => remote_versus(settings, peer, lobby.game().handicap(), &keys),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "netplay")]
+fn remote_versus(
+    settings: Settings,
+    peer: impl Transport,
+    handicap: Handicap,
+    keys: &Receiver<KeyCode>,
+) -> Result<(), Box<dyn Error>> {
+    let renderer = SplitView::new(ConsoleView::new(settings, 2, 1, '\u{2588}', None), 2);
+    renderer.prepare()?;
+    renderer.init_field()?;
+
+    let clock = SystemClock::new();
+    let versus = RemoteVersus::new(settings, clock.now(), peer).with_handicap(handicap);
+    let mut game = GameLoop::new(versus, renderer, clock);
+    game.run_mapped(keys, ConsoleView::key_to_action)?;
+    game.renderer().clear()?;
+    Ok(())
+}
+
+#[cfg(feature = "netplay")]
+fn lockstep(
+    settings: Settings,
+    game: Lockstep<TcpPeer>,
+    clock: SystemClock,
+) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel::<Action>();
+    let renderer = SplitView::new(ConsoleView::new(settings, 2, 1, '\u{2588}', None), 2);
+    renderer.prepare()?;
+    renderer.init_field()?;
+    renderer.keypress(tx, ConsoleView::key_to_action);
+
+    let mut game = GameLoop::new(game, renderer, clock);
+    game.run(&rx)?;
+    game.renderer().clear()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daily_has_no_saves() {
+        let daily = Single::Daily(DailyListener {
+            day: 0,
+            results: DailyResults::default(),
+            path: PathBuf::new(),
+        });
+        assert!(!daily.saves());
+        assert!(!Single::Zen.saves());
+        assert!(Single::Marathon.saves());
+    }
+}
@@ -0,0 +1,14 @@
+use directories::ProjectDirs;
+use std::path::PathBuf;
+
+// Folder of the game inside the platform one
+const NAME: &str = "brick_game";
+
+/// Where the console keeps what it writes between runs: the log, personal bests,
+/// the profile, saves and screenshots. That is the XDG data directory on Linux,
+/// Application Support on macOS and AppData on Windows. `over` is the one given
+/// with --data-dir, without a home to go by it is the current directory.
+pub fn data_dir(over: Option<PathBuf>) -> PathBuf {
+    over.or_else(|| Some(ProjectDirs::from("", "", NAME)?.data_dir().to_path_buf()))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
@@ -0,0 +1,7 @@
+use std::io;
+
+/// Error for input that is there but makes no sense, like a file or message in
+/// the wrong layout
+pub fn invalid(reason: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.into())
+}
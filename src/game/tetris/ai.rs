@@ -0,0 +1,382 @@
+use crate::bootstrap::{Action, Game, Settings, Timestamp};
+use crate::game::tetris::random::Random;
+use crate::game::tetris::tetrisfield::TetrisField;
+use crate::game::tetris::tetromino::{Block, Tetromino};
+use crate::game::tetris::Tetris;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Where a piece can end up, how to get it there and what the field looks like after
+#[derive(Clone)]
+pub struct Placement {
+    pub block: Block,
+    // Key presses from the spawn position, the last one is always a drop
+    pub path: Vec<Action>,
+    pub field: TetrisField,
+    pub lines: u16,
+    // Rotated into place with three corners taken, scores as a T-spin
+    pub tspin: bool,
+}
+
+/// Every placement the piece can reach from where it is, including tucks and spins
+/// that need wall kicks or sliding under an overhang. Placements with the same cells
+/// are listed once, with the shortest path.
+pub fn placements(field: &TetrisField, block: Block) -> Vec<Placement> {
+    // Landing spots in the order they were found, indexed by their cells since
+    // symmetric pieces reach the same cells with different rotations
+    let mut landings: Vec<(Block, Vec<Action>)> = vec![];
+    let mut by_cells: HashMap<Vec<(i16, i16)>, usize> = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert(position(&block));
+    queue.push_back((block, vec![]));
+    while let Some((block, path)) = queue.pop_front() {
+        let mut below = block;
+        if !field.try_move(&mut below, 0, 1) {
+            let path = without_soft_drops(path.clone());
+            match by_cells.get(&cells(&block)) {
+                Some(&i) if landings[i].1.len() > path.len() => landings[i] = (block, path),
+                Some(_) => {}
+                None => {
+                    by_cells.insert(cells(&block), landings.len());
+                    landings.push((block, path));
+                }
+            }
+        }
+        for &action in [Action::Left, Action::Right, Action::Up, Action::Down].iter() {
+            let mut next = block;
+            let moved = match action {
+                Action::Left => field.try_move(&mut next, -1, 0),
+                Action::Right => field.try_move(&mut next, 1, 0),
+                Action::Up => field.try_rotate(&mut next, true),
+                _ => field.try_move(&mut next, 0, 1),
+            };
+            if moved && seen.insert(position(&next)) {
+                let mut path = path.clone();
+                path.push(action);
+                queue.push_back((next, path));
+            }
+        }
+    }
+    landings
+        .into_iter()
+        .map(|(block, path)| place(field, block, path))
+        .collect()
+}
+
+/// Placements of the piece, each with the placements of the queued pieces that follow
+/// on the board it leaves. The tree grows with every queued piece, keep the queue short.
+pub fn search(
+    field: &TetrisField,
+    block: Block,
+    queue: &[Tetromino],
+    settings: &Settings,
+) -> Vec<SearchNode> {
+    placements(field, block)
+        .into_iter()
+        .map(|placement| {
+            let next = match queue.split_first() {
+                Some((&tetromino, rest)) => {
+                    let block = Block::spawn(tetromino, settings);
+                    if placement.field.has_collision(&block) {
+                        vec![]
+                    } else {
+                        search(&placement.field, block, rest, settings)
+                    }
+                }
+                None => vec![],
+            };
+            SearchNode { placement, next }
+        })
+        .collect()
+}
+
+/// One placement and what the next piece can do after it, no children when
+/// the queue has ended or the next piece would not fit
+#[derive(Clone)]
+pub struct SearchNode {
+    pub placement: Placement,
+    pub next: Vec<SearchNode>,
+}
+
+fn position(block: &Block) -> (i16, i16, u8) {
+    (block.x, block.y, block.rotation as u8)
+}
+
+// Soft drops right before locking are the same as a hard drop
+fn without_soft_drops(mut path: Vec<Action>) -> Vec<Action> {
+    while matches!(path.last(), Some(Action::Down)) {
+        path.pop();
+    }
+    path
+}
+
+fn place(field: &TetrisField, block: Block, mut path: Vec<Action>) -> Placement {
+    let tspin = matches!(path.last(), Some(Action::Up)) && field.is_tspin(&block);
+    path.push(Action::Drop);
+    let mut after = field.clone();
+    let lines = after.consume(block).len() as u16;
+    Placement {
+        block,
+        path,
+        field: after,
+        lines,
+        tspin,
+    }
+}
+
+fn cells(block: &Block) -> Vec<(i16, i16)> {
+    let mut cells = vec![];
+    for (j, row) in block.shape().iter().enumerate() {
+        for (i, &cell) in row.iter().enumerate() {
+            if cell.is_filled() {
+                cells.push((block.x + i as i16, block.y + j as i16));
+            }
+        }
+    }
+    cells
+}
+
+/// How much every heuristic matters, positive is good
+#[derive(Debug, Clone, Copy)]
+pub struct Weights {
+    pub height: f32,
+    pub lines: f32,
+    pub holes: f32,
+    pub bumpiness: f32,
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            height: -0.51,
+            lines: 0.76,
+            holes: -0.36,
+            bumpiness: -0.18,
+        }
+    }
+}
+
+impl Weights {
+    pub fn evaluate(&self, placement: &Placement) -> f32 {
+        let field = &placement.field;
+        self.height * field.aggregate_height() as f32
+            + self.lines * placement.lines as f32
+            + self.holes * field.holes() as f32
+            + self.bumpiness * field.bumpiness() as f32
+    }
+}
+
+/// Placements of the first piece from best to worst, looking at as many of the queued
+/// pieces as `depth` allows
+pub fn ranked_placements(
+    field: &TetrisField,
+    block: Block,
+    queue: &[Tetromino],
+    depth: usize,
+    weights: &Weights,
+    settings: &Settings,
+) -> Vec<(Placement, f32)> {
+    let mut ranked: Vec<(Placement, f32)> = placements(field, block)
+        .into_iter()
+        .map(|placement| {
+            let score = score(
+                &placement,
+                queue,
+                depth.saturating_sub(1),
+                weights,
+                settings,
+            );
+            (placement, score)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked
+}
+
+pub fn best_placement(
+    field: &TetrisField,
+    block: Block,
+    queue: &[Tetromino],
+    depth: usize,
+    weights: &Weights,
+    settings: &Settings,
+) -> Option<Placement> {
+    ranked_placements(field, block, queue, depth, weights, settings)
+        .into_iter()
+        .next()
+        .map(|(placement, _)| placement)
+}
+
+fn score(
+    placement: &Placement,
+    queue: &[Tetromino],
+    depth: usize,
+    weights: &Weights,
+    settings: &Settings,
+) -> f32 {
+    match queue.split_first() {
+        Some((&next, rest)) if depth > 0 => {
+            let block = Block::spawn(next, settings);
+            if placement.field.has_collision(&block) {
+                return f32::MIN;
+            }
+            placements(&placement.field, block)
+                .iter()
+                .map(|next| {
+                    weights.lines * placement.lines as f32
+                        + score(next, rest, depth - 1, weights, settings)
+                })
+                .fold(f32::MIN, f32::max)
+        }
+        _ => weights.evaluate(placement),
+    }
+}
+
+/// How a game the AI played on its own went
+#[derive(Debug, Clone, Copy)]
+pub struct Outcome {
+    pub pieces: u32,
+    pub lines: u32,
+    pub score: u32,
+    pub topped_out: bool,
+}
+
+/// Plays a whole game without a frontend, until the AI tops out or has placed
+/// `max_pieces`. Time stands still, so gravity never moves the pieces. Only the next
+/// piece is known, depths above 2 look no further.
+pub fn play(
+    settings: Settings,
+    seed: u64,
+    depth: usize,
+    weights: &Weights,
+    max_pieces: u32,
+) -> Outcome {
+    let mut tetris = Tetris::with_seed(settings, 0, seed);
+    tetris.frame(0, None);
+    let mut lines = 0;
+    while let Some((block, next)) = tetris.current() {
+        if tetris.metrics().pieces >= max_pieces {
+            break;
+        }
+        let placement =
+            match best_placement(tetris.field(), block, &[next], depth, weights, &settings) {
+                Some(placement) => placement,
+                None => break,
+            };
+        lines += placement.lines as u32;
+        for &action in placement.path.iter() {
+            tetris.frame(0, Some(action));
+        }
+    }
+    Outcome {
+        pieces: tetris.metrics().pieces,
+        lines,
+        score: tetris.to_drawable().score,
+        topped_out: tetris.is_over(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    // Pieces looked at when choosing a placement
+    fn depth(self) -> usize {
+        match self {
+            Difficulty::Easy | Difficulty::Normal => 1,
+            Difficulty::Hard => 2,
+        }
+    }
+
+    // Milliseconds between key presses
+    fn delay(self) -> Timestamp {
+        match self {
+            Difficulty::Easy => 350,
+            Difficulty::Normal => 180,
+            Difficulty::Hard => 70,
+        }
+    }
+
+    // Chance in percent to take one of the next best placements instead of the best one
+    fn misdrop(self) -> usize {
+        match self {
+            Difficulty::Easy => 15,
+            Difficulty::Normal => 4,
+            Difficulty::Hard => 0,
+        }
+    }
+}
+
+/// Computer player. Plans a placement whenever a new piece appears
+/// and presses one key at a time, as fast as the difficulty allows.
+pub struct Bot {
+    difficulty: Difficulty,
+    weights: Weights,
+    plan: VecDeque<Action>,
+    // Pieces the game had locked when the plan was made
+    pieces: Option<u32>,
+    next_at: Timestamp,
+    random: Random,
+}
+
+impl Bot {
+    pub fn new(difficulty: Difficulty, seed: u64) -> Bot {
+        Bot {
+            difficulty,
+            weights: Weights::default(),
+            plan: VecDeque::new(),
+            pieces: None,
+            next_at: 0,
+            random: Random::new(seed),
+        }
+    }
+
+    pub fn with_weights(mut self, weights: Weights) -> Bot {
+        self.weights = weights;
+        self
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    /// Next key press for the game, `None` while the bot waits
+    pub fn act(&mut self, tetris: &Tetris, now: Timestamp) -> Option<Action> {
+        if tetris.menu().is_some() {
+            return None;
+        }
+        let (block, next) = tetris.current()?;
+        let pieces = tetris.metrics().pieces;
+        if self.pieces != Some(pieces) {
+            self.pieces = Some(pieces);
+            self.plan = self.think(tetris, block, next).into();
+            self.next_at = now + self.difficulty.delay();
+        }
+        if now < self.next_at {
+            return None;
+        }
+        self.next_at = now + self.difficulty.delay();
+        self.plan.pop_front()
+    }
+
+    fn think(&mut self, tetris: &Tetris, block: Block, next: Tetromino) -> Vec<Action> {
+        let depth = self.difficulty.depth();
+        let (field, settings) = (tetris.field(), tetris.settings());
+        let mut ranked = ranked_placements(field, block, &[next], depth, &self.weights, settings);
+        let mut choice = 0;
+        if ranked.len() > 1 && self.random.below(100) < self.difficulty.misdrop() {
+            choice = 1 + self.random.below(ranked.len().min(MISDROP_CHOICES) - 1);
+        }
+        if ranked.is_empty() {
+            return vec![Action::Drop];
+        }
+        ranked.swap_remove(choice).0.path
+    }
+}
+
+// Misdrops pick among that many best placements
+const MISDROP_CHOICES: usize = 3;
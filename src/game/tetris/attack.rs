@@ -0,0 +1,165 @@
+use crate::game::tetris::random::Random;
+use std::str::FromStr;
+
+/// Garbage lines sent for clears, the same rules for every kind of versus
+#[derive(Debug, Clone)]
+pub struct AttackTable {
+    // Indexed by the number of cleared lines
+    pub lines: [u32; 5],
+    // Same for clears made with a T-spin, zero lines included
+    pub tspin: [u32; 4],
+    // Bonus for consecutive clears, the last value repeats for longer combos
+    pub combo: Vec<u32>,
+    // Extra lines for a tetris or T-spin right after another one
+    pub back_to_back: u32,
+}
+
+impl Default for AttackTable {
+    fn default() -> AttackTable {
+        AttackTable {
+            lines: [0, 0, 1, 2, 4],
+            tspin: [0, 2, 4, 6],
+            combo: vec![0, 0, 1, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5],
+            back_to_back: 1,
+        }
+    }
+}
+
+/// What a single lock did to the field
+#[derive(Debug, Clone, Copy)]
+pub struct Clear {
+    pub lines: u16,
+    pub tspin: bool,
+}
+
+/// Attack table together with combo and back-to-back state of one player
+#[derive(Debug, Clone)]
+pub struct AttackRules {
+    table: AttackTable,
+    // Clears in a row, zero when the last lock cleared nothing
+    combo: usize,
+    back_to_back: bool,
+    // Percent of the table's attack that is sent, for handicaps
+    multiplier: u32,
+}
+
+impl Default for AttackRules {
+    fn default() -> AttackRules {
+        AttackRules::new(AttackTable::default())
+    }
+}
+
+impl AttackRules {
+    pub fn new(table: AttackTable) -> AttackRules {
+        AttackRules {
+            table,
+            combo: 0,
+            back_to_back: false,
+            multiplier: 100,
+        }
+    }
+
+    pub fn set_multiplier(&mut self, percent: u32) {
+        self.multiplier = percent;
+    }
+
+    pub fn table(&self) -> &AttackTable {
+        &self.table
+    }
+
+    // Forgets combo and back-to-back, the table stays
+    pub fn reset(&mut self) {
+        self.combo = 0;
+        self.back_to_back = false;
+    }
+
+    /// Lines the clear sends, updates the combo and back-to-back state
+    pub fn attack(&mut self, clear: Clear) -> u32 {
+        if clear.lines == 0 {
+            self.combo = 0;
+            let lines = if clear.tspin { self.table.tspin[0] } else { 0 };
+            return lines * self.multiplier / 100;
+        }
+        let table = &self.table;
+        let mut lines = if clear.tspin {
+            table.tspin[(clear.lines as usize).min(table.tspin.len() - 1)]
+        } else {
+            table.lines[(clear.lines as usize).min(table.lines.len() - 1)]
+        };
+        let difficult = clear.tspin || clear.lines >= 4;
+        if difficult && self.back_to_back {
+            lines += table.back_to_back;
+        }
+        self.back_to_back = difficult;
+        if let Some(bonus) = table.combo.get(self.combo).or_else(|| table.combo.last()) {
+            lines += bonus;
+        }
+        self.combo += 1;
+        lines * self.multiplier / 100
+    }
+
+    pub fn combo(&self) -> usize {
+        self.combo
+    }
+
+    pub fn back_to_back(&self) -> bool {
+        self.back_to_back
+    }
+}
+
+/// Outgoing lines cancel queued incoming garbage first, returns what is left to send
+pub fn cancel(incoming: &mut u16, outgoing: u32) -> u32 {
+    let cancelled = (*incoming as u32).min(outgoing);
+    *incoming -= cancelled as u16;
+    outgoing - cancelled
+}
+
+/// Who gets the garbage when there is more than one opponent
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Targeting {
+    // Everything goes to the opponent with the highest score
+    Leader,
+    // A different opponent at random for every attack
+    Random,
+    // Shared out evenly, lines left over go to the leaders
+    #[default]
+    Even,
+}
+
+impl Targeting {
+    /// Lines for each opponent still in the game, given by their scores.
+    /// With a single opponent it all goes there whatever the strategy.
+    pub fn split(self, lines: u32, scores: &[u32], random: &mut Random) -> Vec<u32> {
+        let mut split = vec![0; scores.len()];
+        if scores.is_empty() {
+            return split;
+        }
+        // Ties go to whoever comes first
+        let mut leaders: Vec<usize> = (0..scores.len()).collect();
+        leaders.sort_by_key(|&i| std::cmp::Reverse(scores[i]));
+        match self {
+            Targeting::Leader => split[leaders[0]] = lines,
+            Targeting::Random => split[random.below(scores.len())] = lines,
+            Targeting::Even => {
+                let count = scores.len() as u32;
+                for (rank, &i) in leaders.iter().enumerate() {
+                    split[i] = lines / count + ((rank as u32) < lines % count) as u32;
+                }
+            }
+        }
+        split
+    }
+}
+
+impl FromStr for Targeting {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Targeting, String> {
+        match text {
+            "leader" => Ok(Targeting::Leader),
+            "random" => Ok(Targeting::Random),
+            "even" => Ok(Targeting::Even),
+            _ => Err(format!("targeting is leader, random or even, not {}", text)),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::bootstrap::Timestamp;
+use crate::game::tetris::schema::{self, Migration};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+// Lines between splits
+pub const SPLIT_LINES: u32 = 10;
+
+// Layout changes since the first file, version 1 only added the header
+const MIGRATIONS: [Migration; 1] = [schema::unchanged];
+
+/// Best run of a mode. Modes with a line goal compare the time it took, the
+/// others the score. Splits are the times every `SPLIT_LINES` lines were reached.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Best {
+    pub score: u32,
+    pub time: Option<Timestamp>,
+    pub splits: Vec<Timestamp>,
+}
+
+impl Best {
+    pub fn beats(&self, other: &Best) -> bool {
+        match (self.time, other.time) {
+            (Some(time), Some(other)) => time < other,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => self.score > other.score,
+        }
+    }
+
+    // How far ahead (negative) or behind the best the splits of a run are, at the
+    // last split both have
+    pub fn split_delta(&self, splits: &[Timestamp]) -> Option<i64> {
+        let index = splits.len().min(self.splits.len()).checked_sub(1)?;
+        Some(splits[index] as i64 - self.splits[index] as i64)
+    }
+}
+
+/// Personal bests of every mode, kept in a text file with a line per mode after the
+/// version header: mode, score, time or `-` and the splits separated by commas,
+/// all tab separated
+#[derive(Debug, Clone, Default)]
+pub struct PersonalBests {
+    bests: BTreeMap<String, Best>,
+}
+
+impl PersonalBests {
+    pub fn get(&self, mode: &str) -> Option<&Best> {
+        self.bests.get(mode)
+    }
+
+    // Keeps the run when it is the new best, returns whether it was
+    pub fn record(&mut self, mode: &str, run: Best) -> bool {
+        match self.bests.get(mode) {
+            Some(best) if !run.beats(best) => false,
+            _ => {
+                self.bests.insert(mode.to_string(), run);
+                true
+            }
+        }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        schema::write_header(writer, "bests", &MIGRATIONS)?;
+        for (mode, best) in self.bests.iter() {
+            let time = best.time.map_or("-".to_string(), |time| time.to_string());
+            let splits: Vec<String> = best.splits.iter().map(|split| split.to_string()).collect();
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                mode,
+                best.score,
+                time,
+                splits.join(",")
+            )?;
+        }
+        Ok(())
+    }
+
+    // Lines that cannot be read are skipped, a damaged file loses only those
+    pub fn read_from(reader: &mut impl Read) -> io::Result<PersonalBests> {
+        let text = schema::read_text(reader, "bests", &MIGRATIONS)?;
+        let mut bests = PersonalBests::default();
+        for line in text.lines() {
+            if let Some((mode, best)) = parse_line(line) {
+                bests.bests.insert(mode.to_string(), best);
+            }
+        }
+        Ok(bests)
+    }
+}
+
+fn parse_line(line: &str) -> Option<(&str, Best)> {
+    let mut parts = line.split('\t');
+    let mode = parts.next().filter(|mode| !mode.is_empty())?;
+    let score = parts.next()?.parse().ok()?;
+    let time = match parts.next()? {
+        "-" => None,
+        time => Some(time.parse().ok()?),
+    };
+    let splits = match parts.next().unwrap_or("") {
+        "" => vec![],
+        splits => splits
+            .split(',')
+            .map(|split| split.parse().ok())
+            .collect::<Option<_>>()?,
+    };
+    Some((
+        mode,
+        Best {
+            score,
+            time,
+            splits,
+        },
+    ))
+}
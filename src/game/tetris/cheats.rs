@@ -0,0 +1,133 @@
+use crate::game::tetris::tetromino::Tetromino;
+use std::str::FromStr;
+
+// Longest command the line takes
+const MAX_LINE: usize = 24;
+
+/// Shortcut for developing and testing the mechanics, see `Tetris::cheat`
+#[derive(Clone, Copy)]
+pub enum Cheat {
+    // Falling block becomes this piece, back at the top
+    Spawn(Tetromino),
+    // Garbage rows pushed in from the bottom with the hole in the given column
+    Fill { lines: u16, hole: u16 },
+    // Bottom rows taken away, the stack falls down
+    Clear(u16),
+    Level(u32),
+    // Topping out clears the board without ending the game, toggled
+    Invincible,
+}
+
+/// Parses `spawn T`, `fill 4`, `fill 4 9`, `clear 2`, `level 10` or `invincible`
+impl FromStr for Cheat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Cheat, String> {
+        let mut words = text.split_whitespace();
+        let command = words.next().unwrap_or_default().to_lowercase();
+        let mut number = |name: &str, default: Option<u32>| match words.next() {
+            Some(word) => word
+                .parse::<u32>()
+                .map_err(|_| format!("{} is no {}", word, name)),
+            None => default.ok_or_else(|| format!("{} needs the {}", command, name)),
+        };
+        let cheat = match command.as_str() {
+            "spawn" => {
+                let name = words.next().ok_or("spawn needs the piece")?;
+                let piece = Tetromino::all()
+                    .iter()
+                    .copied()
+                    .find(|piece| name.eq_ignore_ascii_case(&piece.name().to_string()))
+                    .ok_or_else(|| format!("unknown piece {}", name))?;
+                Cheat::Spawn(piece)
+            }
+            "fill" => Cheat::Fill {
+                lines: number("lines", None)? as u16,
+                hole: number("hole", Some(0))? as u16,
+            },
+            "clear" => Cheat::Clear(number("lines", None)? as u16),
+            "level" => Cheat::Level(number("level", None)?.max(1)),
+            "invincible" => Cheat::Invincible,
+            _ => return Err(format!("unknown command {}", command)),
+        };
+        Ok(cheat)
+    }
+}
+
+/// Command line the frontend types into. Commands wait here until the game picks
+/// them up with its next frame, the line and the last answer are shown under the
+/// board.
+#[derive(Default)]
+pub struct CheatConsole {
+    // Text typed so far, none while the line is closed
+    line: Option<String>,
+    pending: Vec<Cheat>,
+    // What came of the last command, until the line is opened again
+    answer: Option<String>,
+    changed: bool,
+}
+
+impl CheatConsole {
+    pub fn is_open(&self) -> bool {
+        self.line.is_some()
+    }
+
+    pub fn open(&mut self) {
+        self.line = Some(String::new());
+        self.answer = None;
+        self.changed = true;
+    }
+
+    pub fn close(&mut self) {
+        self.line = None;
+        self.changed = true;
+    }
+
+    pub fn type_char(&mut self, c: char) {
+        if let Some(line) = &mut self.line {
+            if line.chars().count() < MAX_LINE {
+                line.push(c);
+                self.changed = true;
+            }
+        }
+    }
+
+    pub fn erase(&mut self) {
+        if let Some(line) = &mut self.line {
+            self.changed |= line.pop().is_some();
+        }
+    }
+
+    // Runs the typed command with the next frame and closes the line
+    pub fn enter(&mut self) {
+        let line = match self.line.take() {
+            Some(line) => line,
+            None => return,
+        };
+        self.answer = match line.parse::<Cheat>() {
+            Ok(cheat) => {
+                self.pending.push(cheat);
+                Some(format!("ok: {}", line.trim()))
+            }
+            Err(error) => Some(error),
+        };
+        self.changed = true;
+    }
+
+    pub fn take(&mut self) -> Vec<Cheat> {
+        std::mem::take(&mut self.pending)
+    }
+
+    // Whether the text changed since the last call
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    // Line with a cursor like `> spawn T_`, or the answer to the last command
+    pub fn text(&self) -> Option<String> {
+        match &self.line {
+            Some(line) => Some(format!("> {}_", line)),
+            None => self.answer.clone(),
+        }
+    }
+}
@@ -0,0 +1,28 @@
+/// FNV-1a, small and stable unlike the std hasher. Values go in as little endian
+/// bytes so the same game gives the same checksum on every platform, which is what
+/// replays, saves and peers compare.
+#[derive(Debug, Clone, Copy)]
+pub struct Checksum {
+    hash: u64,
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Checksum {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl Checksum {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash ^= byte as u64;
+            self.hash = self.hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
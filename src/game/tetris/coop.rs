@@ -0,0 +1,330 @@
+use crate::bootstrap::{
+    Action, Cell, FrameOutput, Game, GameChange, GameMode, MenuItem, MenuMode, Redraw, Renderable,
+    Settings, Timestamp,
+};
+use crate::game::tetris::metrics::Metrics;
+use crate::game::tetris::random::Random;
+use crate::game::tetris::tetrisfield::TetrisField;
+use crate::game::tetris::tetromino::{Block, Tetromino};
+use crate::game::tetris::versus::PlayerAction;
+use crate::game::tetris::{deal, menu_action, MenuOutcome, Tetris, TetrisPause};
+
+/// Two players on one board twice as wide, each with a falling block of their own
+/// that starts over their half. Falling blocks cannot pass through each other and
+/// a block resting on the other one locks like on the stack. Score is shared.
+pub struct Coop {
+    // Of one player's half, the board has twice the columns
+    half: Settings,
+    settings: Settings,
+    start: Timestamp,
+    moment: Timestamp,
+    // Game time stands still from here while the menu is open
+    paused: Option<Timestamp>,
+    field: TetrisField,
+    players: Vec<CoopPlayer>,
+    bag: Vec<Tetromino>,
+    random: Random,
+    score: u32,
+    metrics: Metrics,
+    over: bool,
+    menu: Option<MenuMode<TetrisPause>>,
+    redraw: Redraw,
+}
+
+struct CoopPlayer {
+    // None while the spawn point is taken by the other block
+    block: Option<Block>,
+    next: Tetromino,
+}
+
+impl Coop {
+    pub fn new(half: Settings, start: Timestamp) -> Coop {
+        let settings = Settings {
+            cols: half.cols * 2,
+            ..half
+        };
+        let mut coop = Coop {
+            half,
+            settings,
+            start,
+            moment: start,
+            paused: None,
+            field: TetrisField::new(&settings),
+            players: vec![],
+            bag: vec![],
+            random: Random::new(start),
+            score: 0,
+            metrics: Metrics::default(),
+            over: false,
+            menu: None,
+            redraw: Redraw::full(),
+        };
+        for _ in 0..2 {
+            let next = coop.random_block();
+            coop.players.push(CoopPlayer { block: None, next });
+        }
+        coop.spawn();
+        coop
+    }
+
+    // Of the whole board, for the renderer
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    pub fn output(&mut self) -> FrameOutput {
+        FrameOutput {
+            game: self.to_drawable(),
+            redraw: std::mem::take(&mut self.redraw),
+        }
+    }
+
+    fn to_drawable(&self) -> GameMode {
+        // Next blocks side by side, the first player's on the left
+        let previews: Vec<Renderable> = self.players.iter().map(|p| p.next.preview()).collect();
+        let preview = previews[0]
+            .iter()
+            .zip(previews[1].iter())
+            .map(|(left, right)| [left.as_slice(), &[Cell::Empty], right.as_slice()].concat())
+            .collect();
+        GameMode {
+            main: self.board(None).field(),
+            preview: Renderable(preview),
+            hold: Renderable(vec![vec![]]),
+            score: self.score,
+            level: self.settings.level,
+            clearing: vec![],
+            metrics: self.metrics,
+            chat: None,
+            garbage: 0,
+            pace: None,
+            goals: vec![],
+        }
+    }
+
+    // Field with the falling blocks of every player except `except`
+    fn board(&self, except: Option<usize>) -> TetrisField {
+        let mut board = self.field.clone();
+        for (i, player) in self.players.iter().enumerate() {
+            match player.block {
+                Some(block) if Some(i) != except => board = board.with_block(&block),
+                _ => {}
+            }
+        }
+        board
+    }
+
+    fn random_block(&mut self) -> Tetromino {
+        deal(&mut self.bag, &mut self.random)
+    }
+
+    // Brings blocks in for players without one. The game is over when the stack
+    // is in the way, the other player's block only makes it wait.
+    fn spawn(&mut self) -> bool {
+        let mut spawned = false;
+        for i in 0..self.players.len() {
+            if self.players[i].block.is_some() {
+                continue;
+            }
+            let mut block = Block::spawn(self.players[i].next, &self.half);
+            block.x += (self.half.cols as usize * i) as i16;
+            if self.field.has_collision(&block) {
+                self.over = true;
+                return true;
+            }
+            if !self.board(Some(i)).has_collision(&block) {
+                let next = self.random_block();
+                let player = &mut self.players[i];
+                player.block = Some(block);
+                player.next = next;
+                self.redraw.preview = true;
+                spawned = true;
+            }
+        }
+        self.redraw.piece |= spawned;
+        spawned
+    }
+
+    // Moves the block of a player with the stack and others' blocks in the way
+    fn steer(
+        &mut self,
+        player: usize,
+        steer: impl FnOnce(&TetrisField, &mut Block) -> bool,
+    ) -> bool {
+        let board = self.board(Some(player));
+        let moved = match &mut self.players[player].block {
+            Some(block) => steer(&board, block),
+            None => false,
+        };
+        self.redraw.piece |= moved;
+        moved
+    }
+
+    fn advance(&mut self, now: Timestamp, input: Option<PlayerAction>) -> bool {
+        let mut changed = self.spawn();
+        while !self.over && now.saturating_sub(self.moment) >= self.settings.delay {
+            self.moment += self.settings.delay;
+            changed |= self.gravity();
+        }
+        if let Some(PlayerAction { player, action }) = input {
+            if !self.over && self.players[player].block.is_some() {
+                self.metrics.keys += 1;
+                changed |= self.act(player, action);
+            }
+        }
+        changed
+    }
+
+    fn act(&mut self, player: usize, action: Action) -> bool {
+        let (moved, lock) = match action {
+            Action::Left => (
+                self.steer(player, |board, block| board.try_move(block, -1, 0)),
+                false,
+            ),
+            Action::Right => (
+                self.steer(player, |board, block| board.try_move(block, 1, 0)),
+                false,
+            ),
+            Action::Up => (
+                self.steer(player, |board, block| board.try_rotate(block, true)),
+                false,
+            ),
+            Action::RotateLeft => (
+                self.steer(player, |board, block| board.try_rotate(block, false)),
+                false,
+            ),
+            Action::Down => {
+                let moved = self.steer(player, |board, block| board.try_move(block, 0, 1));
+                (moved, !moved)
+            }
+            Action::Drop => (
+                self.steer(player, |board, block| board.drop(block) > 0),
+                true,
+            ),
+            Action::Escape
+            | Action::Screenshot
+            | Action::Hold
+            | Action::ReleaseLeft
+            | Action::ReleaseRight => (false, false),
+        };
+        if lock {
+            self.lock(player);
+        }
+        moved || lock
+    }
+
+    // One row down for every block, a block resting on the other one falls along
+    fn gravity(&mut self) -> bool {
+        let mut falling: Vec<usize> = (0..self.players.len())
+            .filter(|&i| self.players[i].block.is_some())
+            .collect();
+        let count = falling.len();
+        loop {
+            let before = falling.len();
+            falling.retain(|&i| !self.steer(i, |board, block| board.try_move(block, 0, 1)));
+            if falling.len() == before {
+                break;
+            }
+        }
+        for &i in falling.iter() {
+            self.lock(i);
+        }
+        count > 0
+    }
+
+    fn lock(&mut self, player: usize) {
+        if let Some(block) = self.players[player].block.take() {
+            let cleared = self.field.consume(block);
+            let lines = cleared.len() as u32;
+            self.metrics.lock(block.tetromino, 0);
+            self.metrics.clear(lines as u16, false);
+            if lines > 0 {
+                self.score += lines * (lines + 1) / 2;
+                self.redraw.score = true;
+            }
+            self.redraw.field = true;
+            self.redraw.cleared.extend(cleared);
+            // Rows coming down may end up where the other block is, it makes way
+            for player in self.players.iter_mut() {
+                if let Some(block) = &mut player.block {
+                    while self.field.has_collision(block) {
+                        block.y -= 1;
+                    }
+                }
+            }
+            self.spawn();
+        }
+    }
+
+    fn over_menu(&self) -> MenuMode<TetrisPause> {
+        MenuMode::new(vec![
+            MenuItem::label(TetrisPause::Title, "Game Over"),
+            MenuItem::label(TetrisPause::Title, format!("Score {}", self.score)),
+            MenuItem::button(TetrisPause::Restart, "New Game"),
+            MenuItem::button(TetrisPause::Exit, "Exit"),
+        ])
+    }
+}
+
+impl Game for Coop {
+    type Pause = TetrisPause;
+    type Input = PlayerAction;
+
+    fn frame(
+        &mut self,
+        now: Timestamp,
+        input: Option<PlayerAction>,
+    ) -> GameChange<'_, TetrisPause> {
+        let action = input.map(|input| input.action);
+        if self.menu.is_some() {
+            self.paused.get_or_insert(now);
+        }
+        match &mut self.menu {
+            Some(menu) => match menu_action(menu, action) {
+                // Nothing to continue once the stack has reached the top
+                MenuOutcome::Continue if !self.over => {
+                    self.menu = None;
+                    let paused = now.saturating_sub(self.paused.take().unwrap_or(now));
+                    self.start += paused;
+                    self.moment += paused;
+                    self.redraw.full = true;
+                }
+                MenuOutcome::Restart => return GameChange::Restart,
+                MenuOutcome::Exit => return GameChange::Exit,
+                MenuOutcome::Unchanged => return GameChange::Idle,
+                _ => {}
+            },
+            None if matches!(action, Some(Action::Escape)) => self.pause(),
+            None => {
+                let elapsed = now.saturating_sub(self.start);
+                // Tenths of a second in the sidebar
+                self.redraw.time |= elapsed / 100 != self.metrics.elapsed / 100;
+                self.metrics.elapsed = elapsed;
+                if !self.advance(now, input) && !self.redraw.time {
+                    return GameChange::Idle;
+                }
+                if self.over {
+                    self.menu = Some(self.over_menu());
+                }
+            }
+        }
+        match self.menu {
+            Some(ref menu) => GameChange::Text(menu),
+            None => GameChange::Draw(Box::new(self.output())),
+        }
+    }
+
+    fn pause(&mut self) {
+        if self.menu.is_none() {
+            self.menu = Some(Tetris::pause_menu());
+        }
+    }
+
+    fn restart(&mut self, now: Timestamp) {
+        *self = Coop::new(self.half, now);
+    }
+}
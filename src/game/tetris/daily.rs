@@ -0,0 +1,161 @@
+use crate::bootstrap::Timestamp;
+use crate::error::invalid;
+use crate::game::tetris::schema::{self, Migration};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+const DAY: Timestamp = 24 * 60 * 60 * 1000;
+
+// Layout changes since the first file, version 1 only added the header
+const MIGRATIONS: [Migration; 1] = [schema::unchanged];
+
+/// Day of milliseconds since the epoch, counted in UTC so everyone has the same one
+pub fn day(time: Timestamp) -> i64 {
+    (time / DAY) as i64
+}
+
+/// Seed of the pieces everyone gets on `day`
+pub fn seed(day: i64) -> u64 {
+    // Neighbouring days should not get similar seeds, splitmix64 spreads them out
+    let mut z = (day as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Year, month and day of days since the epoch, from Howard Hinnant's algorithm
+pub fn civil(day: i64) -> (i64, u32, u32) {
+    let z = day + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let date = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + (month <= 2) as i64, month, date)
+}
+
+// Reverse of `civil`
+fn days_from_civil(year: i64, month: u32, date: u32) -> i64 {
+    let year = year - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + date as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// One attempt at the daily challenge a day. A day counts as attempted once the
+/// game starts, so quitting does not give another try, and only the first game
+/// over on it is kept.
+///
+/// The file has a line per day after the version header: days since the epoch and the score or `-`
+/// for a game that never ended, tab separated.
+#[derive(Debug, Clone, Default)]
+pub struct DailyResults {
+    days: BTreeMap<i64, Option<u32>>,
+}
+
+impl DailyResults {
+    pub fn attempted(&self, day: i64) -> bool {
+        self.days.contains_key(&day)
+    }
+
+    pub fn score(&self, day: i64) -> Option<u32> {
+        self.days.get(&day).copied().flatten()
+    }
+
+    // Returns false when the day was attempted already
+    pub fn start(&mut self, day: i64) -> bool {
+        if self.attempted(day) {
+            return false;
+        }
+        self.days.insert(day, None);
+        true
+    }
+
+    // Returns whether the score was kept, it is only for the first game over of the day
+    pub fn finish(&mut self, day: i64, score: u32) -> bool {
+        match self.days.get_mut(&day) {
+            Some(result @ None) => {
+                *result = Some(score);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Month of `day` like a calendar, with a row per week starting on Monday.
+    /// Attempted days are in brackets, the scores of the month follow.
+    pub fn calendar(&self, day: i64) -> Vec<String> {
+        let (year, month, _) = civil(day);
+        let first = days_from_civil(year, month, 1);
+        let (next_year, next_month) = if month == 12 {
+            (year + 1, 1)
+        } else {
+            (year, month + 1)
+        };
+        let last = days_from_civil(next_year, next_month, 1) - 1;
+        let mut lines = vec![
+            format!("{}-{:02}", year, month),
+            " Mo  Tu  We  Th  Fr  Sa  Su".to_string(),
+        ];
+        // The epoch was a Thursday
+        let mut week = "    ".repeat((first + 3).rem_euclid(7) as usize);
+        for day in first..=last {
+            let date = day - first + 1;
+            if self.attempted(day) {
+                week.push_str(&format!("[{:2}]", date));
+            } else {
+                week.push_str(&format!(" {:2} ", date));
+            }
+            if (day + 4).rem_euclid(7) == 0 {
+                lines.push(week.trim_end().to_string());
+                week.clear();
+            }
+        }
+        if !week.is_empty() {
+            lines.push(week.trim_end().to_string());
+        }
+        for (&day, score) in self.days.range(first..=last) {
+            let (_, _, date) = civil(day);
+            match score {
+                Some(score) => lines.push(format!("{:02}: {}", date, score)),
+                None => lines.push(format!("{:02}: unfinished", date)),
+            }
+        }
+        lines
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        schema::write_header(writer, "daily", &MIGRATIONS)?;
+        for (day, score) in self.days.iter() {
+            match score {
+                Some(score) => writeln!(writer, "{}\t{}", day, score)?,
+                None => writeln!(writer, "{}\t-", day)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<DailyResults> {
+        let text = schema::read_text(reader, "daily", &MIGRATIONS)?;
+        let mut results = DailyResults::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let broken = || invalid("broken daily line");
+            let mut fields = line.split('\t');
+            let day = fields
+                .next()
+                .and_then(|day| day.parse().ok())
+                .ok_or_else(broken)?;
+            let score = match fields.next().ok_or_else(broken)? {
+                "-" => None,
+                score => Some(score.parse().map_err(|_| broken())?),
+            };
+            results.days.insert(day, score);
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,84 @@
+use crate::bootstrap::{Action, Game, GameChange, Timestamp};
+use crate::game::tetris::ai::{Bot, Difficulty};
+use crate::game::tetris::{Tetris, TetrisPause};
+
+// Milliseconds a menu has to sit untouched before the demo starts
+pub const IDLE: Timestamp = 15_000;
+
+/// Attract mode like on arcade machines: once a menu has been left alone for a while
+/// the computer plays a game of its own. Any key ends the demo and brings the menu
+/// back, that key is not passed on.
+pub struct Demo {
+    game: Tetris,
+    demo: Option<(Tetris, Bot)>,
+    // Last time somebody pressed a key or the menu was not shown
+    active: Timestamp,
+}
+
+impl Demo {
+    pub fn new(game: Tetris, start: Timestamp) -> Demo {
+        Demo {
+            game,
+            demo: None,
+            active: start,
+        }
+    }
+
+    pub fn game(&self) -> &Tetris {
+        &self.game
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.demo.is_some()
+    }
+
+    fn start_demo(&mut self, now: Timestamp) {
+        let tetris = Tetris::new(*self.game.settings(), now);
+        self.demo = Some((tetris, Bot::new(Difficulty::Hard, now)));
+    }
+}
+
+impl Game for Demo {
+    type Pause = TetrisPause;
+    type Input = Action;
+
+    fn frame(&mut self, now: Timestamp, action: Option<Action>) -> GameChange<'_, TetrisPause> {
+        if let Some((tetris, bot)) = &mut self.demo {
+            if action.is_none() {
+                let action = bot.act(tetris, now);
+                return match tetris.frame(now, action) {
+                    GameChange::Draw(frame) => GameChange::Draw(frame),
+                    // Demo is over, the next one starts right away
+                    GameChange::Text(_) => {
+                        self.start_demo(now);
+                        GameChange::Idle
+                    }
+                    _ => GameChange::Idle,
+                };
+            }
+            self.demo = None;
+            self.active = now;
+            self.game.invalidate();
+            return match self.game.pause {
+                Some(ref menu) => GameChange::Text(menu),
+                None => GameChange::Draw(Box::new(self.game.output())),
+            };
+        }
+        if action.is_some() || self.game.menu().is_none() {
+            self.active = now;
+        } else if now.saturating_sub(self.active) >= IDLE {
+            self.start_demo(now);
+            return GameChange::Idle;
+        }
+        self.game.frame(now, action)
+    }
+
+    fn pause(&mut self) {
+        self.game.pause();
+    }
+
+    fn restart(&mut self, now: Timestamp) {
+        self.active = now;
+        self.game.restart(now);
+    }
+}
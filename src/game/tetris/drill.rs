@@ -0,0 +1,225 @@
+use crate::bootstrap::{Renderable, Settings, Timestamp};
+use crate::error::invalid;
+use crate::game::tetris::schema::{self, Migration};
+use crate::game::tetris::snapshot;
+use crate::game::tetris::tetromino::Tetromino;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+// Layout changes since the first progress file
+const MIGRATIONS: [Migration; 1] = [with_times];
+
+// Openers the game comes with, in the order they are played
+const BUNDLED: [(&str, &str); 3] = [
+    ("TKI", include_str!("drills/tki.drill")),
+    ("PCO", include_str!("drills/pco.drill")),
+    ("DT Cannon", include_str!("drills/dt_cannon.drill")),
+];
+
+/// Practice of a setup: a stack, the pieces that come in order and how many lines
+/// to clear with at most how many of them. Pieces after the queue are random.
+///
+/// The file has a line for the queue and one for the goal, an empty line and the
+/// board like board files have it, lines starting with `#` are comments:
+///
+/// ```text
+/// queue IOT
+/// clear 2 lines in 3 pieces
+///
+/// XXXX..XXXX
+/// XXXX.XXXXX
+/// ```
+#[derive(Clone)]
+pub struct Drill {
+    pub name: String,
+    pub field: Renderable,
+    pub queue: Vec<Tetromino>,
+    pub lines: u32,
+    pub pieces: u32,
+}
+
+impl Drill {
+    pub fn parse(name: &str, text: &str, settings: &Settings) -> io::Result<Drill> {
+        let mut queue = None;
+        let mut goal = None;
+        let mut lines = text.lines();
+        for line in lines.by_ref().map(str::trim) {
+            if line.is_empty() {
+                break;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [comment, ..] if comment.starts_with('#') => {}
+                ["queue", pieces] => queue = Some(parse_queue(pieces)?),
+                ["clear", lines, "lines" | "line", "in", pieces, "pieces" | "piece"] => {
+                    let number = |word: &str| {
+                        word.parse::<u32>()
+                            .map_err(|_| invalid(format!("expected a number, got {}", word)))
+                    };
+                    goal = Some((number(lines)?, number(pieces)?));
+                }
+                _ => return Err(invalid(format!("unknown drill line {}", line))),
+            }
+        }
+        let queue = queue.ok_or_else(|| invalid("drill has no queue".to_string()))?;
+        let (lines_goal, pieces) = goal.ok_or_else(|| invalid("drill has no goal".to_string()))?;
+        if lines_goal == 0 || pieces == 0 {
+            return Err(invalid("drill goals need lines and pieces".to_string()));
+        }
+        let board: Vec<&str> = lines.collect();
+        Ok(Drill {
+            name: name.to_string(),
+            field: snapshot::parse_board(&board.join("\n"), settings)?,
+            queue,
+            lines: lines_goal,
+            pieces,
+        })
+    }
+
+    // Cleared or failed once a game got `lines` with `pieces`, none while it goes on
+    pub fn result(&self, lines: u32, pieces: u32) -> Option<bool> {
+        if lines >= self.lines {
+            Some(true)
+        } else if pieces >= self.pieces {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    pub fn goal(&self) -> String {
+        format!("Clear {} lines in {} pieces", self.lines, self.pieces)
+    }
+}
+
+/// Drills of common openers that come with the game, their boards are ten columns
+/// wide
+pub fn bundled(settings: &Settings) -> io::Result<Vec<Drill>> {
+    BUNDLED
+        .iter()
+        .map(|(name, text)| Drill::parse(name, text, settings))
+        .collect()
+}
+
+fn parse_queue(pieces: &str) -> io::Result<Vec<Tetromino>> {
+    pieces
+        .chars()
+        .map(|c| {
+            Tetromino::all()
+                .iter()
+                .copied()
+                .find(|piece| piece.name() == c.to_ascii_uppercase())
+                .ok_or_else(|| invalid(format!("unknown piece {} in the queue", c)))
+        })
+        .collect()
+}
+
+/// Drills played one after another, clearing one moves on to the next
+#[derive(Clone)]
+pub struct DrillSet {
+    drills: Vec<Drill>,
+    current: usize,
+    // Picked in the menu, played next whatever the game before did
+    chosen: Option<usize>,
+}
+
+impl DrillSet {
+    // `drills` can not be empty
+    pub fn new(drills: Vec<Drill>, current: usize) -> DrillSet {
+        let current = current % drills.len();
+        DrillSet {
+            drills,
+            current,
+            chosen: None,
+        }
+    }
+
+    pub fn current(&self) -> &Drill {
+        &self.drills[self.current]
+    }
+
+    pub fn choose(&mut self, drill: usize) {
+        self.chosen = Some(drill % self.drills.len());
+    }
+
+    // The chosen drill when there is one, otherwise the next one after a clear and
+    // the same one again after a fail. After the last one it starts over.
+    pub fn next_game(&mut self, cleared: bool) {
+        match self.chosen.take() {
+            Some(drill) => self.current = drill,
+            None if cleared => self.current = (self.current + 1) % self.drills.len(),
+            None => {}
+        }
+    }
+}
+
+/// The cleared drills with the fastest clear of each, kept in a text file with a
+/// line per drill after the version header: the name and the time in
+/// milliseconds, tab separated. The time is `-` for clears from before there were
+/// times.
+#[derive(Debug, Clone, Default)]
+pub struct DrillProgress {
+    cleared: BTreeMap<String, Option<Timestamp>>,
+}
+
+impl DrillProgress {
+    pub fn is_cleared(&self, name: &str) -> bool {
+        self.cleared.contains_key(name)
+    }
+
+    pub fn best(&self, name: &str) -> Option<Timestamp> {
+        self.cleared.get(name).copied().flatten()
+    }
+
+    // Returns whether it was not cleared before or not as fast
+    pub fn clear(&mut self, name: &str, time: Timestamp) -> bool {
+        match self.best(name) {
+            Some(best) if best <= time => false,
+            _ => {
+                self.cleared.insert(name.to_string(), Some(time));
+                true
+            }
+        }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        schema::write_header(writer, "drills", &MIGRATIONS)?;
+        for (name, time) in self.cleared.iter() {
+            let time = time.map_or(UNKNOWN.to_string(), |time| time.to_string());
+            writeln!(writer, "{}\t{}", name, time)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<DrillProgress> {
+        let text = schema::read_text(reader, "drills", &MIGRATIONS)?;
+        let mut progress = DrillProgress::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (name, time) = line
+                .split_once('\t')
+                .ok_or_else(|| invalid("broken drill progress line".to_string()))?;
+            let time = match time {
+                UNKNOWN => None,
+                time => Some(
+                    time.parse()
+                        .map_err(|_| invalid(format!("broken drill time {}", time)))?,
+                ),
+            };
+            progress.cleared.insert(name.to_string(), time);
+        }
+        Ok(progress)
+    }
+}
+
+const UNKNOWN: &str = "-";
+
+// Version 1 added the times, the drills cleared before have none
+fn with_times(body: Vec<u8>) -> Vec<u8> {
+    let text = String::from_utf8_lossy(&body);
+    let mut migrated = String::new();
+    for name in text.lines().map(str::trim).filter(|name| !name.is_empty()) {
+        migrated.push_str(name);
+        migrated.push_str("\t-\n");
+    }
+    migrated.into_bytes()
+}
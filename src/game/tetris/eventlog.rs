@@ -0,0 +1,54 @@
+use crate::game::tetris::listener::TetrisListener;
+use crate::game::tetris::tetromino::Block;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Game events as JSON lines, for tools that follow a session. Every line has the
+/// event name and its time in milliseconds since the Unix epoch. Diagnostic logging
+/// stays in the `log` crate, this is only about what happens in the game.
+pub struct EventLog<W: Write> {
+    out: W,
+}
+
+impl<W: Write> EventLog<W> {
+    pub fn new(out: W) -> Self {
+        let mut log = EventLog { out };
+        log.write("session", "");
+        log
+    }
+
+    // Failing to write must not stop the game
+    fn write(&mut self, event: &str, fields: &str) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis());
+        let line = format!("{{\"time\":{},\"event\":\"{}\"{}}}\n", time, event, fields);
+        let _ = self.out.write_all(line.as_bytes());
+        let _ = self.out.flush();
+    }
+}
+
+impl<W: Write> TetrisListener for EventLog<W> {
+    fn on_lock(&mut self, block: &Block) {
+        let fields = format!(
+            ",\"piece\":\"{}\",\"x\":{},\"y\":{},\"rotation\":{}",
+            block.tetromino.name(),
+            block.x,
+            block.y,
+            block.rotation as u8
+        );
+        self.write("lock", &fields);
+    }
+
+    fn on_lines_cleared(&mut self, lines: u16) {
+        self.write("clear", &format!(",\"lines\":{}", lines));
+    }
+
+    fn on_level_up(&mut self, level: u32) {
+        self.write("level", &format!(",\"level\":{}", level));
+    }
+
+    fn on_game_over(&mut self, score: u32) {
+        self.write("game_over", &format!(",\"score\":{}", score));
+    }
+}
@@ -0,0 +1,34 @@
+use crate::game::tetris::tetrisfield::TetrisField;
+use crate::game::tetris::tetromino::Block;
+use std::collections::{HashSet, VecDeque};
+
+/// Fewest moves and rotations that bring a block from `spawn` above the place
+/// `target` landed on, so a hard drop puts it there. None for places only soft
+/// drops reach, like tucks and spins, those are not counted as faults.
+pub fn min_keys(field: &TetrisField, spawn: &Block, target: &Block) -> Option<u32> {
+    let key = |block: &Block| (block.x, block.y, block.rotation as u8);
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert(key(spawn));
+    queue.push_back((*spawn, 0));
+    while let Some((block, keys)) = queue.pop_front() {
+        let mut landed = block;
+        field.drop(&mut landed);
+        if key(&landed) == key(target) {
+            return Some(keys);
+        }
+        for turn in 0..4 {
+            let mut next = block;
+            let moved = match turn {
+                0 => field.try_move(&mut next, -1, 0),
+                1 => field.try_move(&mut next, 1, 0),
+                2 => field.try_rotate(&mut next, true),
+                _ => field.try_rotate(&mut next, false),
+            };
+            if moved && seen.insert(key(&next)) {
+                queue.push_back((next, keys + 1));
+            }
+        }
+    }
+    None
+}
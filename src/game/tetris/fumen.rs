@@ -0,0 +1,349 @@
+use crate::bootstrap::{Cell, Renderable, Settings};
+use crate::error::invalid;
+use crate::game::tetris::tetromino::{Block, Tetromino};
+use std::convert::TryInto;
+use std::io;
+
+// Only the current version of the format, older ones are rare by now
+const PREFIX: &str = "v115@";
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const WIDTH: usize = 10;
+// Visible rows, there is one more below them for the garbage to rise
+const HEIGHT: usize = 23;
+const BLOCKS: usize = WIDTH * (HEIGHT + 1);
+// Added to every cell difference so it is never negative
+const DIFF: usize = 8;
+// Action flag for colored pieces, after the piece, rotation, location, rise and mirror
+const COLOR: usize = 8 * 4 * BLOCKS * 4;
+
+// Piece numbers of the format are not ours, both ways indexed by `Cell::to_byte`
+const TO_FUMEN: [u8; 9] = [0, 1, 5, 6, 2, 7, 4, 3, 8];
+const FROM_FUMEN: [u8; 9] = [0, 1, 4, 7, 6, 2, 3, 5, 8];
+
+// Cells around the rotation center in spawn rotation, y up, by piece number
+#[rustfmt::skip]
+const PIECES: [[(i16, i16); 4]; 8] = [
+    [(0, 0); 4],
+    [(0, 0), (-1, 0), (1, 0), (2, 0)],
+    [(0, 0), (-1, 0), (1, 0), (1, 1)],
+    [(0, 0), (1, 0), (0, 1), (1, 1)],
+    [(0, 0), (1, 0), (0, 1), (-1, 1)],
+    [(0, 0), (-1, 0), (1, 0), (0, 1)],
+    [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+    [(0, 0), (-1, 0), (0, 1), (1, 1)],
+];
+
+// Rotations as numbered in the format
+const REVERSE: usize = 0;
+const RIGHT: usize = 1;
+const SPAWN: usize = 2;
+const LEFT: usize = 3;
+
+/// Board of a fumen page sized for our settings and the piece the page shows.
+/// Used as a puzzle: the stack to start from and the first piece to place. Board
+/// files make one without a piece.
+#[derive(Clone)]
+pub struct Page {
+    pub field: Renderable,
+    pub piece: Option<Tetromino>,
+}
+
+// Fumen field, the top row first and the garbage row last
+type Field = [u8; BLOCKS];
+
+struct Action {
+    piece: usize,
+    rotation: usize,
+    x: i16,
+    y: i16,
+    rise: bool,
+    mirror: bool,
+    comment: bool,
+    lock: bool,
+}
+
+/// Fumen of a board, the community format for sharing setups, like `v115@vhAAgH`
+/// for an empty one. Only boards ten columns wide fit, taller ones are cut at the
+/// top when nothing is lost there.
+pub fn encode(field: &Renderable, block: Option<&Block>) -> Option<String> {
+    let rows = field.len();
+    let mut cells = [0u8; BLOCKS];
+    for (y, row) in field.iter().enumerate() {
+        if row.len() != WIDTH {
+            return None;
+        }
+        for (x, &cell) in row.iter().enumerate().filter(|(_, cell)| cell.is_filled()) {
+            let index = index(x as i16, (rows - 1 - y) as i16)?;
+            cells[index] = TO_FUMEN.get(cell.to_byte() as usize).copied().unwrap_or(8);
+        }
+    }
+    let mut out = vec![];
+    let mut runs = vec![];
+    for &cell in cells.iter() {
+        match runs.last_mut() {
+            Some((diff, count)) if *diff == cell as usize + DIFF => *count += 1,
+            _ => runs.push((cell as usize + DIFF, 1)),
+        }
+    }
+    for &(diff, count) in runs.iter() {
+        push(&mut out, diff * BLOCKS + count - 1, 2);
+    }
+    // Unchanged fields say for how many more pages they stay the same
+    if runs.len() == 1 {
+        push(&mut out, 0, 1);
+    }
+    let action = block
+        .and_then(|block| locate(block, rows))
+        .map_or(0, |action| {
+            let location = (HEIGHT as i16 - action.y - 1) as usize * WIDTH + action.x as usize;
+            action.piece + action.rotation * 8 + location * 32
+        });
+    push(&mut out, action + COLOR, 3);
+    let data: String = out
+        .into_iter()
+        .map(|digit| ALPHABET[digit] as char)
+        .collect();
+    Some(format!("{}{}", PREFIX, data))
+}
+
+/// Every page of a fumen as a board of `settings`, which has to be ten columns
+/// wide. Comments and quizzes are skipped.
+pub fn decode(code: &str, settings: &Settings) -> io::Result<Vec<Page>> {
+    if settings.cols as usize != WIDTH {
+        return Err(invalid("fumen boards are 10 columns wide"));
+    }
+    let data = code
+        .trim()
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| invalid("not a v115 fumen"))?;
+    let mut digits = vec![];
+    for c in data.chars().filter(|&c| c != '?') {
+        let digit = ALPHABET
+            .iter()
+            .position(|&letter| letter as char == c)
+            .ok_or_else(|| invalid("not a fumen"))?;
+        digits.push(digit);
+    }
+    let mut input = digits.as_slice();
+    let mut pages = vec![];
+    let mut previous = [0u8; BLOCKS];
+    let mut repeat = 0;
+    while !input.is_empty() {
+        let mut field = previous;
+        if repeat > 0 {
+            repeat -= 1;
+        } else if !decode_field(&mut input, &mut field)? {
+            repeat = poll(&mut input, 1)?;
+        }
+        let action = decode_action(poll(&mut input, 3)?);
+        if action.comment {
+            let len = poll(&mut input, 2)?;
+            // Four characters in every five digits
+            let skip = len.div_ceil(4) * 5;
+            if input.len() < skip {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            input = &input[skip..];
+        }
+        pages.push(page(&field, &action, settings)?);
+        if action.lock {
+            place(&mut field, &action);
+            clear_lines(&mut field);
+            if action.rise {
+                field.copy_within(WIDTH.., 0);
+                field[BLOCKS - WIDTH..].fill(0);
+            }
+            if action.mirror {
+                for row in field.chunks_mut(WIDTH).take(HEIGHT) {
+                    row.reverse();
+                }
+            }
+        }
+        previous = field;
+    }
+    Ok(pages)
+}
+
+// Applies the differences to `field`, returns whether there were any
+fn decode_field(input: &mut &[usize], field: &mut Field) -> io::Result<bool> {
+    let mut index = 0;
+    let mut changed = false;
+    while index < BLOCKS {
+        let value = poll(input, 2)?;
+        let (diff, count) = (value / BLOCKS, value % BLOCKS + 1);
+        if index + count > BLOCKS {
+            return Err(invalid("fumen field is too long"));
+        }
+        for cell in field[index..index + count].iter_mut() {
+            let value = *cell as usize + diff;
+            if !(DIFF..=DIFF + 8).contains(&value) {
+                return Err(invalid("fumen field has unknown blocks"));
+            }
+            *cell = (value - DIFF) as u8;
+        }
+        changed |= diff != DIFF;
+        index += count;
+    }
+    Ok(changed)
+}
+
+fn decode_action(value: usize) -> Action {
+    let location = value / 32 % BLOCKS;
+    let flags = value / 32 / BLOCKS;
+    let mut action = Action {
+        piece: value % 8,
+        rotation: value / 8 % 4,
+        x: (location % WIDTH) as i16,
+        y: HEIGHT as i16 - (location / WIDTH) as i16 - 1,
+        rise: flags & 1 > 0,
+        mirror: flags & 2 > 0,
+        comment: flags & 8 > 0,
+        lock: flags & 16 == 0,
+    };
+    let (dx, dy) = offset(action.piece, action.rotation);
+    action.x += dx;
+    action.y += dy;
+    action
+}
+
+// The format keeps the center of some pieces apart from where rotation has it
+fn offset(piece: usize, rotation: usize) -> (i16, i16) {
+    match (piece, rotation) {
+        (3, LEFT) => (1, -1),
+        (3, REVERSE) | (1, REVERSE) | (4, LEFT) => (1, 0),
+        (3, SPAWN) | (1, LEFT) | (7, SPAWN) | (4, SPAWN) => (0, -1),
+        (7, RIGHT) => (-1, 0),
+        _ => (0, 0),
+    }
+}
+
+fn cells(piece: usize, rotation: usize, x: i16, y: i16) -> [(i16, i16); 4] {
+    let mut cells = PIECES[piece];
+    for cell in cells.iter_mut() {
+        let (dx, dy) = match rotation {
+            RIGHT => (cell.1, -cell.0),
+            REVERSE => (-cell.0, -cell.1),
+            LEFT => (-cell.1, cell.0),
+            _ => *cell,
+        };
+        *cell = (x + dx, y + dy);
+    }
+    cells
+}
+
+// Position of the falling block as an action, found by trying every rotation and
+// center until one covers the same cells
+fn locate(block: &Block, rows: usize) -> Option<Action> {
+    let mut target = vec![];
+    for (j, row) in block.shape().iter().enumerate() {
+        for (i, _) in row.iter().enumerate().filter(|(_, cell)| cell.is_filled()) {
+            let x = block.x + i as i16;
+            let y = rows as i16 - 1 - (block.y + j as i16);
+            target.push((x, y));
+        }
+    }
+    target.sort_unstable();
+    let piece = TO_FUMEN[block.tetromino.id() as usize] as usize;
+    let first = *target.first()?;
+    for &rotation in [SPAWN, RIGHT, REVERSE, LEFT].iter() {
+        for &(dx, dy) in cells(piece, rotation, 0, 0).iter() {
+            let (x, y) = (first.0 - dx, first.1 - dy);
+            let mut covered = cells(piece, rotation, x, y);
+            covered.sort_unstable();
+            if covered[..] == target[..] {
+                let (dx, dy) = offset(piece, rotation);
+                let (x, y) = (x - dx, y - dy);
+                index(x, y)?;
+                return Some(Action {
+                    piece,
+                    rotation,
+                    x,
+                    y,
+                    rise: false,
+                    mirror: false,
+                    comment: false,
+                    lock: true,
+                });
+            }
+        }
+    }
+    None
+}
+
+// Index of a visible cell, y counted from the bottom
+fn index(x: i16, y: i16) -> Option<usize> {
+    if (0..WIDTH as i16).contains(&x) && (0..HEIGHT as i16).contains(&y) {
+        Some((HEIGHT - 1 - y as usize) * WIDTH + x as usize)
+    } else {
+        None
+    }
+}
+
+fn place(field: &mut Field, action: &Action) {
+    if action.piece == 0 {
+        return;
+    }
+    for (x, y) in cells(action.piece, action.rotation, action.x, action.y) {
+        if let Some(index) = index(x, y) {
+            field[index] = action.piece as u8;
+        }
+    }
+}
+
+fn clear_lines(field: &mut Field) {
+    let mut rows: Vec<[u8; WIDTH]> = field
+        .chunks(WIDTH)
+        .take(HEIGHT)
+        .filter(|row| row.contains(&0))
+        .map(|row| row.try_into().unwrap())
+        .collect();
+    while rows.len() < HEIGHT {
+        rows.insert(0, [0; WIDTH]);
+    }
+    for (y, row) in rows.iter().enumerate() {
+        field[y * WIDTH..(y + 1) * WIDTH].copy_from_slice(row);
+    }
+}
+
+// Our board shows the bottom rows of the page, the garbage row stays below the floor
+fn page(field: &Field, action: &Action, settings: &Settings) -> io::Result<Page> {
+    let rows = settings.rows as usize;
+    let mut board = Renderable(vec![vec![Cell::Empty; WIDTH]; rows]);
+    for (index, &cell) in field.iter().enumerate().take(WIDTH * HEIGHT) {
+        if cell == 0 {
+            continue;
+        }
+        let from_bottom = HEIGHT - 1 - index / WIDTH;
+        if from_bottom >= rows {
+            return Err(invalid("fumen page does not fit the board"));
+        }
+        board[rows - 1 - from_bottom][index % WIDTH] = Cell::from_byte(FROM_FUMEN[cell as usize]);
+    }
+    let piece = match action.piece {
+        0 => None,
+        piece => Some(Tetromino::all()[FROM_FUMEN[piece] as usize - 1]),
+    };
+    Ok(Page {
+        field: board,
+        piece,
+    })
+}
+
+fn push(out: &mut Vec<usize>, mut value: usize, digits: usize) {
+    for _ in 0..digits {
+        out.push(value % 64);
+        value /= 64;
+    }
+}
+
+fn poll(input: &mut &[usize], digits: usize) -> io::Result<usize> {
+    if input.len() < digits {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (head, rest) = input.split_at(digits);
+    *input = rest;
+    Ok(head
+        .iter()
+        .rev()
+        .fold(0, |value, &digit| value * 64 + digit))
+}
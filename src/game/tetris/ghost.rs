@@ -0,0 +1,103 @@
+use crate::bootstrap::{Action, FrameOutput, Game, GameChange, Redraw, Timestamp};
+use crate::game::tetris::replay::Replay;
+use crate::game::tetris::{Tetris, TetrisPause};
+
+/// Race against a recorded game: the replay is played again next to the player's board,
+/// with the same seed so both get the same pieces
+pub struct Ghost {
+    local: Tetris,
+    ghost: Tetris,
+    replay: Replay,
+    // Next replay input to apply
+    next: usize,
+    // Ghost time is counted from here, moved forward while the game is paused
+    start: Timestamp,
+    paused: Option<Timestamp>,
+    redraw: Redraw,
+}
+
+impl Ghost {
+    pub fn new(replay: Replay, start: Timestamp) -> Ghost {
+        Ghost {
+            local: Tetris::with_seed(replay.settings, start, replay.seed),
+            ghost: Tetris::with_seed(replay.settings, 0, replay.seed),
+            replay,
+            next: 0,
+            start,
+            paused: None,
+            redraw: Redraw::full(),
+        }
+    }
+
+    pub fn local(&self) -> &Tetris {
+        &self.local
+    }
+
+    // Plays the replay up to the given time, returns whether the ghost board changed
+    fn follow(&mut self, now: Timestamp) -> bool {
+        if self.local.menu().is_some() {
+            self.paused.get_or_insert(now);
+            return false;
+        }
+        if let Some(paused) = self.paused.take() {
+            self.start += now - paused;
+            self.redraw.full = true;
+        }
+        let time = now.saturating_sub(self.start);
+        let mut changed = false;
+        while let Some(&(at, action)) = self.replay.inputs.get(self.next) {
+            if at > time {
+                break;
+            }
+            changed |= !matches!(self.ghost.frame(at, Some(action)), GameChange::Idle);
+            self.next += 1;
+        }
+        changed |= !matches!(self.ghost.frame(time, None), GameChange::Idle);
+        changed
+    }
+
+    fn ghost_output(&mut self) -> FrameOutput {
+        FrameOutput {
+            game: self.ghost.to_drawable(),
+            redraw: std::mem::take(&mut self.redraw),
+        }
+    }
+}
+
+impl Game for Ghost {
+    type Pause = TetrisPause;
+    type Input = Action;
+
+    fn frame(&mut self, now: Timestamp, action: Option<Action>) -> GameChange<'_, TetrisPause> {
+        if self.follow(now) {
+            // Console only prints cells that differ, the whole board can be offered
+            self.redraw.field = true;
+            self.redraw.preview = true;
+            self.redraw.score = true;
+        }
+        let local = match self.local.frame(now, action) {
+            GameChange::Draw(frame) => Some(*frame),
+            // Tetris never splits, a menu is picked up below
+            GameChange::Text(_) | GameChange::Split(_) => None,
+            GameChange::Restart => return GameChange::Restart,
+            GameChange::Exit => return GameChange::Exit,
+            GameChange::Idle if self.redraw.main() => None,
+            GameChange::Idle => return GameChange::Idle,
+        };
+        match self.local.pause {
+            Some(ref menu) => GameChange::Text(menu),
+            None => {
+                let local = local.unwrap_or_else(|| self.local.output());
+                GameChange::Split(vec![local, self.ghost_output()])
+            }
+        }
+    }
+
+    fn pause(&mut self) {
+        self.local.pause();
+    }
+
+    fn restart(&mut self, now: Timestamp) {
+        *self = Ghost::new(self.replay.clone(), now);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::bootstrap::{Action, Game, Settings, Timestamp};
+use crate::game::tetris::Tetris;
+
+// Game time passing with every step, gravity pulls the block once every few steps
+pub const STEP: Timestamp = 100;
+
+/// The game as an environment for training agents, in the style of OpenAI Gym.
+/// Time only moves when the agent steps, so training runs as fast as it can and
+/// the same seed and actions always give the same episode.
+pub struct Env {
+    tetris: Tetris,
+    time: Timestamp,
+}
+
+/// What the agent sees: the field with the falling block as one row after another
+/// and the blocks by the number their cells have, 0 when there is none
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub board: Vec<u8>,
+    pub piece: u8,
+    pub next: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub observation: Observation,
+    // Score gained by the step
+    pub reward: u32,
+    pub done: bool,
+}
+
+impl Env {
+    pub fn new(settings: Settings, seed: u64) -> Env {
+        let mut env = Env {
+            tetris: Tetris::with_seed(settings, 0, seed),
+            time: 0,
+        };
+        env.reset(seed);
+        env
+    }
+
+    // New episode, the first piece is already falling
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.tetris = Tetris::with_seed(*self.tetris.settings(), 0, seed);
+        self.time = 0;
+        self.tetris.frame(0, None);
+        self.observation()
+    }
+
+    // Escape would only open the pause menu, so it does nothing here
+    pub fn step(&mut self, action: Option<Action>) -> Step {
+        let score = self.tetris.score;
+        if !self.tetris.is_over() {
+            self.time += STEP;
+            let action = action.filter(|action| !matches!(action, Action::Escape));
+            self.tetris.frame(self.time, action);
+        }
+        Step {
+            observation: self.observation(),
+            reward: self.tetris.score - score,
+            done: self.tetris.is_over(),
+        }
+    }
+
+    pub fn observation(&self) -> Observation {
+        let current = self.tetris.current();
+        Observation {
+            board: self.tetris.board().bytes().flatten().collect(),
+            piece: current.map_or(0, |(block, _)| block.tetromino.id()),
+            next: current.map_or(0, |(_, next)| next.id()),
+        }
+    }
+
+    pub fn game(&self) -> &Tetris {
+        &self.tetris
+    }
+}
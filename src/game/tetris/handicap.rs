@@ -0,0 +1,52 @@
+use crate::bootstrap::Timestamp;
+use std::str::FromStr;
+
+/// Adjustments for one player of an uneven versus match
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handicap {
+    // Garbage rows the board starts with
+    pub garbage: u16,
+    // Gravity speed in percent, 200 falls twice as fast
+    pub gravity: u32,
+    // Percent of the usual attack that is sent, rounded down
+    pub attack: u32,
+}
+
+impl Default for Handicap {
+    fn default() -> Handicap {
+        Handicap {
+            garbage: 0,
+            gravity: 100,
+            attack: 100,
+        }
+    }
+}
+
+impl Handicap {
+    // Milliseconds between gravity steps instead of `delay`
+    pub fn delay(&self, delay: Timestamp) -> Timestamp {
+        (delay * 100 / self.gravity.max(1) as Timestamp).max(1)
+    }
+}
+
+/// Parses `garbage=4,gravity=150,attack=50`, missing keys keep their defaults
+impl FromStr for Handicap {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Handicap, String> {
+        let mut handicap = Handicap::default();
+        for pair in text.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {}", pair))?;
+            let number = |value: &str| value.parse::<u32>().map_err(|error| error.to_string());
+            match key {
+                "garbage" => handicap.garbage = number(value)? as u16,
+                "gravity" => handicap.gravity = number(value)?,
+                "attack" => handicap.attack = number(value)?,
+                _ => return Err(format!("unknown handicap {}", key)),
+            }
+        }
+        Ok(handicap)
+    }
+}
@@ -0,0 +1,180 @@
+use crate::bootstrap::Timestamp;
+use crate::error::invalid;
+use crate::game::tetris::metrics::Metrics;
+use crate::game::tetris::saves;
+use crate::game::tetris::schema::{self, Migration};
+use std::io::{self, Read, Write};
+
+// Layout changes since the first history file
+const MIGRATIONS: [Migration; 1] = [with_totals];
+
+// Games kept, the oldest go first
+pub const LIMIT: usize = 100;
+
+/// Summary of a finished game with the name of the file its replay is kept in
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub mode: String,
+    pub score: u32,
+    // Milliseconds from the start to the game over
+    pub duration: Timestamp,
+    // When it finished, milliseconds since the epoch
+    pub time: Timestamp,
+    pub replay: String,
+    // Not known for games added before the history kept them
+    pub totals: Option<Totals>,
+}
+
+/// Counters of a finished game the history keeps next to the score
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Totals {
+    pub lines: u32,
+    pub pieces: u32,
+    pub finesse: u32,
+}
+
+impl From<&Metrics> for Totals {
+    fn from(metrics: &Metrics) -> Totals {
+        Totals {
+            lines: metrics.lines,
+            pieces: metrics.pieces,
+            finesse: metrics.finesse,
+        }
+    }
+}
+
+impl Entry {
+    // What the history menu shows, like 2020-05-17 14:03 marathon 120 3:05
+    pub fn summary(&self) -> String {
+        let seconds = self.duration / 1000;
+        format!(
+            "{} {} {} {}:{:02}",
+            saves::date(self.time),
+            self.mode,
+            self.score,
+            seconds / 60,
+            seconds % 60
+        )
+    }
+
+    // Pieces per second over the whole game
+    fn pps(&self, totals: &Totals) -> f32 {
+        match self.duration {
+            0 => 0.,
+            duration => totals.pieces as f32 * 1000. / duration as f32,
+        }
+    }
+}
+
+/// Finished games in the order they ended. The file has a line per game after
+/// the version header: mode, score, duration, time, replay file, lines, pieces and
+/// finesse faults, tab separated. The counters are `-` when they are not known.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: Vec<Entry>,
+}
+
+impl History {
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    // Returns the entries that no longer fit, their replays can go
+    pub fn push(&mut self, entry: Entry) -> Vec<Entry> {
+        self.entries.push(entry);
+        let over = self.entries.len().saturating_sub(LIMIT);
+        self.entries.drain(..over).collect()
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        schema::write_header(writer, "history", &MIGRATIONS)?;
+        for entry in self.entries.iter() {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                entry.mode,
+                entry.score,
+                entry.duration,
+                entry.time,
+                entry.replay,
+                entry.totals.map_or(
+                    format!("{}\t{}\t{}", UNKNOWN, UNKNOWN, UNKNOWN),
+                    |totals| format!("{}\t{}\t{}", totals.lines, totals.pieces, totals.finesse)
+                )
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<History> {
+        let text = schema::read_text(reader, "history", &MIGRATIONS)?;
+        let mut history = History::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let broken = || invalid("broken history line");
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (mode, score, duration, time, replay, counters) = match fields.as_slice() {
+                [mode, score, duration, time, replay, counters @ ..] => {
+                    (mode, score, duration, time, replay, counters)
+                }
+                _ => return Err(broken()),
+            };
+            let totals = match counters {
+                [UNKNOWN, UNKNOWN, UNKNOWN] => None,
+                [lines, pieces, finesse] => Some(Totals {
+                    lines: lines.parse().map_err(|_| broken())?,
+                    pieces: pieces.parse().map_err(|_| broken())?,
+                    finesse: finesse.parse().map_err(|_| broken())?,
+                }),
+                _ => return Err(broken()),
+            };
+            history.entries.push(Entry {
+                mode: mode.to_string(),
+                score: score.parse().map_err(|_| broken())?,
+                duration: duration.parse().map_err(|_| broken())?,
+                time: time.parse().map_err(|_| broken())?,
+                replay: replay.to_string(),
+                totals,
+            });
+        }
+        Ok(history)
+    }
+
+    /// A row per game for spreadsheets, oldest first. Counters the history does
+    /// not know are left empty.
+    pub fn write_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "date,mode,score,lines,pps,finesse_faults")?;
+        for entry in self.entries.iter() {
+            let totals = match &entry.totals {
+                Some(totals) => format!(
+                    "{},{:.3},{}",
+                    totals.lines,
+                    entry.pps(totals),
+                    totals.finesse
+                ),
+                None => ",,".to_string(),
+            };
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                saves::date(entry.time),
+                entry.mode,
+                entry.score,
+                totals
+            )?;
+        }
+        Ok(())
+    }
+}
+
+const UNKNOWN: &str = "-";
+
+// Version 1 added the counters, older games have none
+fn with_totals(body: Vec<u8>) -> Vec<u8> {
+    let text = String::from_utf8_lossy(&body);
+    let mut migrated = String::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        migrated.push_str(line);
+        migrated.push_str("\t-\t-\t-\n");
+    }
+    migrated.into_bytes()
+}
@@ -0,0 +1,40 @@
+use crate::bootstrap::{Goal, Timestamp};
+use crate::game::tetris::metrics::Metrics;
+
+// Players taking turns at the keyboard
+const PLAYERS: u32 = 2;
+
+/// How long a turn of a hot seat game lasts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Turns {
+    Pieces(u32),
+    // Milliseconds of game time
+    Time(Timestamp),
+}
+
+impl Turns {
+    // Player on turn, counted from 1. It only depends on the counters, so replays
+    // and restored games agree on it.
+    pub fn player(&self, metrics: &Metrics) -> u32 {
+        let turn = match *self {
+            Turns::Pieces(pieces) => metrics.pieces / pieces.max(1),
+            Turns::Time(time) => (metrics.elapsed / time.max(1)) as u32,
+        };
+        turn % PLAYERS + 1
+    }
+
+    // Whose turn it is and how much of it is left
+    pub fn goals(&self, metrics: &Metrics) -> Vec<Goal> {
+        let left = match *self {
+            Turns::Pieces(pieces) => {
+                let pieces = pieces.max(1);
+                Goal::Pieces(pieces - metrics.pieces % pieces)
+            }
+            Turns::Time(time) => {
+                let time = time.max(1);
+                Goal::Time(time - metrics.elapsed % time)
+            }
+        };
+        vec![Goal::Turn(self.player(metrics)), left]
+    }
+}
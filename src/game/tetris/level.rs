@@ -0,0 +1,37 @@
+use crate::bootstrap::Timestamp;
+
+/// How fast blocks fall at each level, as thousandths of the delay in the settings.
+/// The last one goes on for all levels after it.
+#[derive(Debug, Clone)]
+pub struct GravityCurve {
+    // Indexed by the level, counted from 1
+    pub speeds: Vec<u32>,
+    // Cleared lines it takes to go up a level
+    pub lines: u32,
+}
+
+// The guideline curve, a level every 10 lines
+impl Default for GravityCurve {
+    fn default() -> GravityCurve {
+        GravityCurve {
+            speeds: vec![
+                1000, 793, 618, 473, 355, 262, 190, 135, 94, 64, 43, 28, 18, 11, 7,
+            ],
+            lines: 10,
+        }
+    }
+}
+
+impl GravityCurve {
+    // Milliseconds between gravity steps at `level` instead of `delay`
+    pub fn delay(&self, delay: Timestamp, level: u32) -> Timestamp {
+        let index = (level.max(1) as usize - 1).min(self.speeds.len().saturating_sub(1));
+        let speed = self.speeds.get(index).copied().unwrap_or(1000);
+        (delay * speed as Timestamp / 1000).max(1)
+    }
+
+    // Level of a game that started at `start` and cleared `lines` so far
+    pub fn level(&self, start: u32, lines: u32) -> u32 {
+        start.max(1) + lines / self.lines.max(1)
+    }
+}
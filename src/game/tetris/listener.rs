@@ -0,0 +1,57 @@
+use crate::bootstrap::{GameMode, MenuItem};
+use crate::game::tetris::metrics::Metrics;
+use crate::game::tetris::replay::Replay;
+use crate::game::tetris::tetromino::Block;
+use crate::game::tetris::TetrisPause;
+
+/// Receives notifications about game events. Every method has an empty default
+/// implementation, so listeners only override what they care about.
+pub trait TetrisListener {
+    /// Block has landed and became part of the field
+    fn on_lock(&mut self, _block: &Block) {}
+
+    /// Called right after `on_lock` when the block completed some lines
+    fn on_lines_cleared(&mut self, _lines: u16) {}
+
+    /// Cleared lines took the game to a new level, right after `on_lines_cleared`
+    fn on_level_up(&mut self, _level: u32) {}
+
+    fn on_game_over(&mut self, _score: u32) {}
+
+    /// Right before `on_game_over` of a drill, whether it was cleared
+    fn on_drill(&mut self, _name: &str, _cleared: bool) {}
+
+    /// The game so far with its score, after every lock the game goes on from.
+    /// The replay plays it again up to here, for autosaves.
+    fn on_progress(&mut self, _replay: &Replay, _score: u32) {}
+
+    /// Counters of the whole game, right after `on_game_over`
+    fn on_metrics(&mut self, _metrics: &Metrics) {}
+
+    /// Text snapshot of the final stack with the stats, right after `on_metrics`
+    fn on_final_board(&mut self, _snapshot: &str) {}
+
+    /// The whole game with its score, right after `on_final_board`
+    fn on_finished(&mut self, _replay: &Replay, _score: u32) {}
+
+    /// The player asked to keep the replay of the game just over
+    fn on_save_replay(&mut self, _replay: &Replay) {}
+
+    /// A game of the history was chosen in the menu, the game exits right after
+    /// so it can be watched
+    fn on_watch(&mut self, _entry: usize) {}
+
+    /// Board with the score and the next block as the player sees it, when they
+    /// ask for a screenshot
+    fn on_screenshot(&mut self, _game: &GameMode) {}
+
+    /// Extra entries for the pause menu of single games, they go before Exit
+    fn menu_items(&self) -> Vec<MenuItem<TetrisPause>> {
+        vec![]
+    }
+
+    /// Extra entries for the game over menu, asked for right after `on_game_over`
+    fn over_items(&mut self) -> Vec<MenuItem<TetrisPause>> {
+        vec![]
+    }
+}
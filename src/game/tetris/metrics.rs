@@ -0,0 +1,99 @@
+use crate::bootstrap::Timestamp;
+use crate::game::tetris::tetromino::Tetromino;
+
+/// Speed and efficiency counters of a single game
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Metrics {
+    pub pieces: u32,
+    // Actions pressed while controlling a block
+    pub keys: u32,
+    // Garbage lines the clears would send to an opponent, before cancelling
+    pub attack: u32,
+    // Milliseconds since the game start, without the time spent in menus
+    pub elapsed: Timestamp,
+    pub lines: u32,
+    // Clears of one to four lines
+    pub clears: [u32; 4],
+    pub tspins: u32,
+    // Locked pieces by cell value, I first
+    pub placed: [u32; 7],
+    // Blocks that came in at the top in the same order, the ones out of the hold too
+    pub spawned: [u32; 7],
+    // Pieces that took more keys than needed
+    pub finesse: u32,
+    pub points: Points,
+}
+
+/// Where the score came from, so the HUD can tell
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Points {
+    // Cleared lines, more for several at once and at higher levels
+    pub lines: u32,
+    // Rows blocks were moved down by the player
+    pub soft_drop: u32,
+    // Rows blocks fell by hard drops
+    pub hard_drop: u32,
+}
+
+impl Metrics {
+    /// Pieces per second
+    pub fn pps(&self) -> f32 {
+        Metrics::per(self.pieces as f32, self.elapsed as f32 / 1000.)
+    }
+
+    /// Attack per minute
+    pub fn apm(&self) -> f32 {
+        Metrics::per(self.attack as f32, self.elapsed as f32 / 60000.)
+    }
+
+    /// Share of the cleared lines that were cleared by tetrises
+    pub fn tetris_rate(&self) -> f32 {
+        Metrics::per(self.clears[3] as f32 * 4., self.lines as f32)
+    }
+
+    /// Keys per piece
+    pub fn kpp(&self) -> f32 {
+        Metrics::per(self.keys as f32, self.pieces as f32)
+    }
+
+    /// Elapsed time like 1:05.3
+    pub fn clock(&self) -> String {
+        clock(self.elapsed)
+    }
+
+    pub fn lock(&mut self, piece: Tetromino, attack: u32) {
+        self.pieces += 1;
+        self.attack += attack;
+        if let Some(placed) = self.placed.get_mut(piece.id() as usize - 1) {
+            *placed += 1;
+        }
+    }
+
+    pub fn spawn(&mut self, piece: Tetromino) {
+        if let Some(spawned) = self.spawned.get_mut(piece.id() as usize - 1) {
+            *spawned += 1;
+        }
+    }
+
+    pub fn clear(&mut self, lines: u16, tspin: bool) {
+        if lines > 0 {
+            self.lines += lines as u32;
+            self.clears[(lines as usize).min(4) - 1] += 1;
+        }
+        self.tspins += tspin as u32;
+    }
+
+    fn per(value: f32, divider: f32) -> f32 {
+        if divider > 0. {
+            value / divider
+        } else {
+            0.
+        }
+    }
+}
+
+/// Milliseconds as minutes, seconds and tenths like 1:05.3
+pub fn clock(time: Timestamp) -> String {
+    let tenths = time / 100;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
@@ -1,242 +1,1874 @@
+pub mod ai;
+pub mod attack;
+pub mod bests;
+#[cfg(feature = "debug-tools")]
+pub mod cheats;
+pub mod checksum;
+pub mod coop;
+pub mod daily;
+pub mod demo;
+pub mod drill;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod eventlog;
+pub mod finesse;
+pub mod fumen;
+pub mod ghost;
+pub mod gym;
+pub mod handicap;
+pub mod history;
+pub mod hmac;
+pub mod hotseat;
+pub mod level;
+pub mod listener;
+pub mod metrics;
+pub mod profile;
+pub mod random;
+pub mod replay;
+pub mod saves;
+pub mod schema;
+pub mod snapshot;
+pub mod stats;
 pub mod tetrisfield;
 pub mod tetromino;
+pub mod versus;
 
 use crate::bootstrap::{
-    Action, Game, GameChange, GameMode, MenuItem, MenuMode, Renderable, Settings, Timestamp,
+    Action, Cell, FrameOutput, Game, GameChange, GameMode, Goal, MenuItem, MenuMode, Redraw,
+    Renderable, Settings, Timestamp,
 };
-use crate::game::tetris::tetrisfield::TetrisField;
-use crate::game::tetris::tetromino::{Block, Tetromino, I, J, L, O, S, T, Z};
+use crate::game::tetris::ai::{best_placement, Weights};
+use crate::game::tetris::attack::{AttackRules, AttackTable, Clear};
+use crate::game::tetris::bests::{Best, SPLIT_LINES};
+#[cfg(feature = "debug-tools")]
+use crate::game::tetris::cheats::{Cheat, CheatConsole};
+use crate::game::tetris::checksum::Checksum;
+use crate::game::tetris::drill::{Drill, DrillSet};
+use crate::game::tetris::fumen::Page;
+use crate::game::tetris::handicap::Handicap;
+use crate::game::tetris::hotseat::Turns;
+use crate::game::tetris::level::GravityCurve;
+use crate::game::tetris::listener::TetrisListener;
+use crate::game::tetris::metrics::Metrics;
+use crate::game::tetris::profile::Profile;
+use crate::game::tetris::random::Random;
+use crate::game::tetris::replay::Replay;
+use crate::game::tetris::saves::{Save, SaveSlots, SLOTS};
+use crate::game::tetris::tetrisfield::{TetrisField, HIDDEN_ROWS};
+use crate::game::tetris::tetromino::{Block, Tetromino};
+#[cfg(feature = "debug-tools")]
+use std::cell::RefCell;
 use std::mem;
+#[cfg(feature = "debug-tools")]
+use std::rc::Rc;
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum TetrisPause {
     Title,
     Continue,
+    // Practice toggle showing where the AI would put the piece
+    Hints,
+    // Picture of the board for the listeners
+    Screenshot,
+    // Save slot to suspend the game in or to go on with
+    Save(usize),
+    Load(usize),
+    // Finished game to watch again, leaves the game
+    History(usize),
+    // Drill to play next, starts it right away
+    Drill(usize),
+    // Preset chat line with given index, for netplay
+    Chat(usize),
+    // Replay of the game just over for the listeners to keep
+    SaveReplay,
+    // Next game with the pieces of the one just over
+    Retry,
     Restart,
     Exit,
 }
 
+// What the game should do after an action in the pause menu
+pub enum MenuOutcome {
+    Changed,
+    Unchanged,
+    Continue,
+    Restart,
+    Exit,
+    // Any other item, for games with menu items of their own
+    Selected(TetrisPause),
+}
+
+// Next piece of the bag, refilled with all seven in random order once it is empty.
+// Every piece comes once per bag, so none is missing for more than 12 pieces in a
+// row and none comes more than twice in a row
+pub fn deal(bag: &mut Vec<Tetromino>, random: &mut Random) -> Tetromino {
+    if bag.is_empty() {
+        bag.extend_from_slice(&Tetromino::all());
+        // I had rand::thread_rng before but it costs 20kb of WASM code
+        random.shuffle(bag);
+    }
+    bag.remove(0)
+}
+
+pub fn menu_action(menu: &mut MenuMode<TetrisPause>, action: Option<Action>) -> MenuOutcome {
+    match action {
+        Some(Action::Escape) if !menu.back() => return MenuOutcome::Continue,
+        Some(Action::Escape) => {}
+        Some(Action::Up) => menu.up(),
+        Some(Action::Down) => menu.down(),
+        Some(Action::Left) => menu.left(),
+        Some(Action::Right) => menu.right(),
+        Some(Action::Drop) => match menu.select() {
+            Some(TetrisPause::Continue) => return MenuOutcome::Continue,
+            Some(TetrisPause::Restart) => return MenuOutcome::Restart,
+            Some(TetrisPause::Exit) => return MenuOutcome::Exit,
+            Some(other) => return MenuOutcome::Selected(other.clone()),
+            None => {}
+        },
+        _ => return MenuOutcome::Unchanged,
+    }
+    MenuOutcome::Changed
+}
+
 pub struct Tetris {
     settings: Settings,
+    start: Timestamp,
     moment: Timestamp,
+    // Game time stands still from here while a menu is open
+    paused: Option<Timestamp>,
     field: TetrisField,
     state: GameState,
     pause: Option<MenuMode<TetrisPause>>,
     score: u32,
+    // Starts at the level of the settings and goes up with cleared lines
+    level: u32,
+    curve: GravityCurve,
     bag: Vec<Tetromino>,
+    // Pieces a drill deals before the random ones
+    queue: Vec<Tetromino>,
+    listeners: Vec<Box<dyn TetrisListener>>,
+    redraw: Redraw,
+    metrics: Metrics,
+    // Garbage lines waiting for the next lock
+    garbage: u16,
+    // Garbage lines sent to opponents but not yet picked up
+    attack: u32,
+    rules: AttackRules,
+    // Last successful move of the falling block was a rotation, needed for T-spins
+    rotated: bool,
+    // Piece put aside with `Action::Hold`
+    hold: Option<Tetromino>,
+    // The hold was used for the falling block, it is free again once the block locks
+    held: bool,
+    // When the falling block came to rest on the stack, it locks a lock delay later
+    landed: Option<Timestamp>,
+    // Times moves and rotations restarted the lock delay of the falling block
+    lock_resets: u32,
+    // Left or right held down while the settings have an auto shift delay
+    shift: Option<AutoShift>,
+    // Rotation and hold pressed while no block falls, for the next one
+    buffered: Vec<Action>,
+    seed: u64,
+    random: Random,
+    // Every action with its time since the start, enough to play the game again
+    inputs: Vec<(Timestamp, Action)>,
+    hints: bool,
+    // Best placement of the falling block while hints are on
+    hint: Option<Block>,
+    handicap: Handicap,
+    // Stack and piece every game starts from, for practicing a setup
+    puzzle: Option<Page>,
+    // Moves and rotations of the falling block, for finesse
+    moves: u32,
+    // Personal best to chase, replaced when a game beats it
+    best: Option<Best>,
+    new_best: bool,
+    // Times every `SPLIT_LINES` lines were reached
+    splits: Vec<Timestamp>,
+    // Lifetime stats every finished game adds to
+    profile: Option<Profile>,
+    saves: Option<SaveSlots>,
+    // Games start from the current drill instead of the puzzle
+    drills: Option<DrillSet>,
+    // The next game gets the same seed
+    retry: bool,
+    // Topping out clears the board for a penalty instead of ending the game
+    zen: bool,
+    // Drawn with the rows reversed, as if blocks fell upwards
+    upside_down: bool,
+    // The next piece is not shown
+    blind: bool,
+    // Locked cells are not shown, but for a moment after a lock, while rows clear and
+    // once the game is over
+    invisible: bool,
+    // End of the moment the stack of an invisible game shows after a lock
+    flash: Option<Timestamp>,
+    // Players take turns at the keyboard, sharing the board and the score
+    turns: Option<Turns>,
+    // Gravity never locks the block, where it lands is always shown and topping out
+    // clears rows from the top
+    easy: bool,
+    // Rows of garbage with random holes a dig race starts with, it is won once they
+    // are all cleared
+    dig: Option<u16>,
+    // Lines a sprint ends at, the time it took is the result
+    sprint: Option<u32>,
+    // Time of the lock that cleared the last line of the sprint
+    finish: Option<Timestamp>,
+    // Command line the frontend types cheats into
+    #[cfg(feature = "debug-tools")]
+    cheats: Option<Rc<RefCell<CheatConsole>>>,
+    // Cheat clearing the board on a top out like in zen games, without the penalty
+    invincible: bool,
+    // Some cheat was used, the game is no personal best and adds nothing to the profile
+    cheated: bool,
 }
 
-impl Game for Tetris {
-    type Pause = TetrisPause;
+impl Tetris {
+    pub fn new(settings: Settings, start: Timestamp) -> Self {
+        Tetris::with_seed(settings, start, start)
+    }
 
-    fn new(settings: Settings, start: Timestamp) -> Self {
+    // Games with the same seed get the same pieces and garbage holes
+    pub fn with_seed(settings: Settings, start: Timestamp, seed: u64) -> Self {
         let field = TetrisField::new(&settings);
         Tetris {
+            level: settings.level.max(1),
+            curve: GravityCurve::default(),
+            start,
             moment: start,
+            paused: None,
             settings,
             field,
             state: GameState::Start,
             pause: None,
             score: 0,
             bag: vec![],
+            queue: vec![],
+            listeners: vec![],
+            redraw: Redraw::full(),
+            metrics: Metrics::default(),
+            garbage: 0,
+            attack: 0,
+            rules: AttackRules::default(),
+            rotated: false,
+            hold: None,
+            held: false,
+            landed: None,
+            lock_resets: 0,
+            shift: None,
+            buffered: vec![],
+            seed,
+            random: Random::new(seed),
+            inputs: vec![],
+            hints: false,
+            hint: None,
+            handicap: Handicap::default(),
+            puzzle: None,
+            moves: 0,
+            best: None,
+            new_best: false,
+            splits: vec![],
+            profile: None,
+            saves: None,
+            drills: None,
+            retry: false,
+            zen: false,
+            upside_down: false,
+            blind: false,
+            invisible: false,
+            flash: None,
+            turns: None,
+            easy: false,
+            dig: None,
+            sprint: None,
+            finish: None,
+            #[cfg(feature = "debug-tools")]
+            cheats: None,
+            invincible: false,
+            cheated: false,
         }
     }
+}
+
+impl Game for Tetris {
+    type Pause = TetrisPause;
+    type Input = Action;
 
-    fn frame(&mut self, now: Timestamp, action: Option<Action>) -> GameChange<TetrisPause> {
+    fn frame(&mut self, now: Timestamp, action: Option<Action>) -> GameChange<'_, TetrisPause> {
+        self.hold_clock(now);
+        self.tick(self.paused.unwrap_or(now).saturating_sub(self.start));
+        #[cfg(feature = "debug-tools")]
+        self.run_cheats();
+        // Works in menus as well and is no input of the game, replays go without it
+        if matches!(action, Some(Action::Screenshot)) {
+            self.screenshot();
+            return GameChange::Idle;
+        }
+        // Menus are left out of the recording, for playing the game again it only
+        // matters when it stopped and went on. Both are an Escape.
+        if let (Some(action), None) = (action, &self.pause) {
+            self.inputs.push((self.metrics.elapsed, action));
+        }
         match &mut self.pause {
             None => {
+                // Up to date when the menu opens, like after the frame before `pause`
                 if matches!(action, Some(Action::Escape)) {
-                    self.pause = Some(Tetris::pause_menu());
-                } else {
-                    match &self.state {
-                        GameState::Start => self.state_start(),
-                        GameState::Fall(_, _) => {
-                            if !self.state_fall(now, action) {
-                                return GameChange::Idle;
-                            }
-                        }
-                        GameState::Drop(_, _) => self.state_drop(),
-                        GameState::GameOver => self.pause = Some(Tetris::over_menu()),
-                        GameState::Temp => unreachable!(),
-                    }
+                    self.advance(now, None);
+                    self.pause = Some(self.practice_menu());
+                } else if self.is_over() {
+                    self.pause = Some(self.over_menu());
+                } else if !self.advance(now, action)
+                    && !self.redraw.garbage
+                    && !self.redraw.time
+                    && !self.redraw.chat
+                {
+                    return GameChange::Idle;
                 }
+                self.hold_clock(now);
             }
-            Some(menu) => match action {
-                Some(Action::Escape) => self.pause = None,
-                Some(Action::Up) => menu.up(),
-                Some(Action::Down) => menu.down(),
-                Some(Action::Drop) => match menu.select() {
-                    Some(TetrisPause::Continue) => self.pause = None,
-                    Some(TetrisPause::Restart) => return GameChange::Restart,
-                    Some(TetrisPause::Exit) => return GameChange::Exit,
-                    _ => unreachable!(),
-                },
-                _ => return GameChange::Idle,
+            Some(menu) => match menu_action(menu, action) {
+                MenuOutcome::Continue => {
+                    self.inputs.push((self.metrics.elapsed, Action::Escape));
+                    let hints = menu.value_of(&TetrisPause::Hints);
+                    self.unpause(now);
+                    if let Some(hints) = hints {
+                        self.set_hints(hints > 0);
+                    }
+                }
+                MenuOutcome::Restart => return GameChange::Restart,
+                MenuOutcome::Exit => return GameChange::Exit,
+                MenuOutcome::Selected(TetrisPause::Screenshot) => self.screenshot(),
+                MenuOutcome::Selected(TetrisPause::SaveReplay) => {
+                    let replay = self.replay();
+                    for listener in self.listeners.iter_mut() {
+                        listener.on_save_replay(&replay);
+                    }
+                }
+                MenuOutcome::Selected(TetrisPause::Retry) => {
+                    self.retry = true;
+                    return GameChange::Restart;
+                }
+                MenuOutcome::Selected(TetrisPause::Save(slot)) => self.save(slot, now),
+                MenuOutcome::Selected(TetrisPause::Load(slot)) => self.load(slot, now),
+                MenuOutcome::Selected(TetrisPause::History(entry)) => {
+                    for listener in self.listeners.iter_mut() {
+                        listener.on_watch(entry);
+                    }
+                    return GameChange::Exit;
+                }
+                MenuOutcome::Selected(TetrisPause::Drill(drill)) => {
+                    if let Some(drills) = &mut self.drills {
+                        drills.choose(drill);
+                        return GameChange::Restart;
+                    }
+                }
+                MenuOutcome::Changed | MenuOutcome::Selected(_) => {}
+                MenuOutcome::Unchanged => return GameChange::Idle,
             },
         }
         match self.pause {
             Some(ref menu) => GameChange::Text(menu),
-            None => GameChange::Draw(self.to_drawable()),
+            None => GameChange::Draw(Box::new(self.output())),
+        }
+    }
+
+    fn pause(&mut self) {
+        if self.pause.is_none() {
+            self.open_menu();
+        }
+    }
+
+    // Clearing a drill moves on to the next one, otherwise it is tried again unless
+    // another one was chosen. A retry plays the same drill with the same pieces.
+    fn restart(&mut self, now: Timestamp) {
+        let cleared = self.drill_result() == Some(true) && !self.retry;
+        let seed = match self.retry {
+            true => self.seed,
+            false => self.random.next_u64(),
+        };
+        let game = Tetris::with_seed(self.settings, now, seed);
+        self.carry_over(game);
+        if let Some(drills) = &mut self.drills {
+            drills.next_game(cleared);
         }
     }
 }
 
 impl Tetris {
-    pub fn random_block(&mut self) -> Tetromino {
-        if self.bag.is_empty() {
-            for _ in 0..BAG_SIZE {
-                self.bag.append(&mut vec![
-                    Tetromino::from(I()),
-                    Tetromino::from(T()),
-                    Tetromino::from(J()),
-                    Tetromino::from(L()),
-                    Tetromino::from(S()),
-                    Tetromino::from(Z()),
-                    Tetromino::from(O()),
-                ])
+    // Options, modes, listeners and what is kept between games go on in `game`, the
+    // game state starts fresh
+    fn carry_over(&mut self, game: Tetris) {
+        let mut old = mem::replace(self, game);
+        self.puzzle = old.puzzle.take();
+        self.drills = old.drills.take();
+        self.best = old.best.take();
+        self.profile = old.profile;
+        self.saves = old.saves.take();
+        self.listeners = old.listeners;
+        self.set_attack_table(old.rules.table().clone());
+        self.curve = old.curve;
+        self.hints = old.hints;
+        self.zen = old.zen;
+        self.upside_down = old.upside_down;
+        self.blind = old.blind;
+        self.invisible = old.invisible;
+        self.turns = old.turns;
+        self.easy = old.easy;
+        self.dig = old.dig;
+        self.sprint = old.sprint;
+        #[cfg(feature = "debug-tools")]
+        {
+            self.cheats = old.cheats;
+        }
+        self.invincible = old.invincible;
+        self.set_handicap(old.handicap);
+    }
+
+    // The sidebar shows tenths of a second, it is drawn again when they change
+    fn tick(&mut self, elapsed: Timestamp) {
+        self.redraw.time |= elapsed / 100 != self.metrics.elapsed / 100;
+        self.metrics.elapsed = elapsed;
+    }
+
+    // The clock stops from the frame that opened the menu
+    fn hold_clock(&mut self, now: Timestamp) {
+        if self.pause.is_some() {
+            self.paused.get_or_insert(now);
+        }
+    }
+
+    // Menus not opened by an Escape go into the recording as one all the same, at
+    // the last frame, or going on again would be an Escape too many when played back
+    fn open_menu(&mut self) {
+        if self.pause.is_none() {
+            self.inputs.push((self.metrics.elapsed, Action::Escape));
+            self.paused.get_or_insert(self.start + self.metrics.elapsed);
+        }
+        self.pause = Some(self.practice_menu());
+    }
+
+    // Gravity goes on where it stopped, the time spent in the menu does not make the
+    // block fall or count for the game time
+    fn unpause(&mut self, now: Timestamp) {
+        self.pause = None;
+        if let Some(paused) = self.paused.take() {
+            self.skip(now.saturating_sub(paused));
+        }
+        // Keys let go in the menu never came, the slide has to start over
+        self.shift = None;
+        self.redraw.full = true;
+    }
+
+    /// Leaves `time` out of gravity and the game time, for games that pause this
+    /// one with a menu of their own
+    pub fn skip(&mut self, time: Timestamp) {
+        self.start += time;
+        self.moment += time;
+        if let Some(landed) = &mut self.landed {
+            *landed += time;
+        }
+        if let GameState::Clearing(_, _, until) | GameState::Entry(_, until) = &mut self.state {
+            *until += time;
+        }
+        if let Some(shift) = &mut self.shift {
+            shift.next += time;
+        }
+        if let Some(flash) = &mut self.flash {
+            *flash += time;
+        }
+    }
+
+    // Brings the game to `now`: missed gravity steps first, then the action. The result
+    // depends only on action times, not on how often frames come, so the same inputs
+    // always play the same game.
+    fn advance(&mut self, now: Timestamp, action: Option<Action>) -> bool {
+        let mut changed = false;
+        if matches!(self.state, GameState::Start) {
+            self.state_start();
+            changed = true;
+        }
+        // Gravity waits while the block rests on the stack, the lock delay counts instead
+        loop {
+            let delay = self
+                .handicap
+                .delay(self.curve.delay(self.settings.delay, self.level));
+            match self.state {
+                GameState::Clearing(ref rows, next, until) if now >= until => {
+                    let rows = rows.clone();
+                    self.moment = until;
+                    self.collapse(until, rows, next);
+                    changed = true;
+                }
+                GameState::Entry(next, until) if now >= until => {
+                    self.moment = until;
+                    self.run_cicle(Block::spawn(next, &self.settings));
+                    changed = true;
+                }
+                GameState::Fall(_, _) => {
+                    // Slide steps before the next gravity step or lock come first
+                    let due = match self.landed {
+                        Some(landed) => landed + self.settings.lock_delay,
+                        None => self.moment + delay,
+                    };
+                    if let Some(shift) = self.shift.filter(|shift| shift.next <= now.min(due)) {
+                        changed |= self.auto_shift(shift);
+                        continue;
+                    }
+                    match self.landed {
+                        Some(landed) if !self.grounded() => {
+                            self.landed = None;
+                            self.moment = self.moment.max(landed);
+                        }
+                        Some(landed) if now.saturating_sub(landed) >= self.settings.lock_delay => {
+                            self.moment = landed + self.settings.lock_delay;
+                            self.lock(self.moment);
+                            changed = true;
+                        }
+                        Some(_) => break,
+                        None if now.saturating_sub(self.moment) >= delay => {
+                            self.moment += delay;
+                            changed |= self.state_gravity();
+                        }
+                        None => break,
+                    }
+                }
+                _ => break,
             }
         }
-        // I had rand::thread_rng before but it costs 20kb of WASM code
-        let rndidx = (self.moment % 1000) as usize % self.bag.len();
-        self.bag.remove(rndidx)
+        if action.is_some() {
+            changed |= self.state_fall(now, action);
+        }
+        // The stack of an invisible game goes dark again
+        if self.flash.is_some_and(|flash| now >= flash) {
+            self.flash = None;
+            self.redraw.field = true;
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn field(&self) -> &TetrisField {
+        &self.field
+    }
+
+    // Falling block with the next piece, while there is one
+    pub fn current(&self) -> Option<(Block, Tetromino)> {
+        match self.state {
+            GameState::Fall(block, next) => Some((block, next)),
+            _ => None,
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    // Everything needed to play this game again from the start
+    pub fn replay(&self) -> Replay {
+        Replay {
+            build: env!("CARGO_PKG_VERSION").to_string(),
+            rules: RULES_VERSION,
+            settings: self.settings,
+            seed: self.seed,
+            inputs: self.inputs.clone(),
+            end: self.metrics.elapsed,
+            hash: self.state_hash(),
+        }
+    }
+
+    // Fingerprint of everything that decides how the game goes on: the board, the
+    // falling, coming and held blocks, score, counters, garbage and the random state.
+    // It is the same on every platform, so peers, replays and saves can compare it.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = Checksum::default();
+        let piece =
+            |tetromino: &Tetromino| -> Vec<u8> { tetromino.preview().bytes().flatten().collect() };
+        let hidden = Renderable(self.field.hidden().to_vec());
+        for row in hidden.bytes().chain(self.board().bytes()) {
+            hash.write(&row);
+        }
+        for tetromino in self.bag.iter().chain(self.queue.iter()) {
+            hash.write(&piece(tetromino));
+        }
+        match &self.state {
+            GameState::Start => hash.write(&[0]),
+            GameState::Fall(block, next) | GameState::Drop(block, next) => {
+                hash.write(&[matches!(self.state, GameState::Fall(..)) as u8 + 1]);
+                hash.write(&block.x.to_le_bytes());
+                hash.write(&block.y.to_le_bytes());
+                hash.write(&piece(next));
+            }
+            GameState::GameOver => hash.write(&[3]),
+            GameState::Clearing(rows, next, until) => {
+                hash.write(&[4]);
+                for row in rows {
+                    hash.write(&row.to_le_bytes());
+                }
+                hash.write(&until.wrapping_sub(self.start).to_le_bytes());
+                hash.write(&piece(next));
+            }
+            GameState::Entry(next, until) => {
+                hash.write(&[5]);
+                hash.write(&until.wrapping_sub(self.start).to_le_bytes());
+                hash.write(&piece(next));
+            }
+        }
+        match &self.hold {
+            Some(held) => hash.write(&piece(held)),
+            None => hash.write(&[0]),
+        }
+        match self.landed {
+            Some(landed) => hash.write(&landed.wrapping_sub(self.start).to_le_bytes()),
+            None => hash.write(&[0]),
+        }
+        hash.write(&self.lock_resets.to_le_bytes());
+        match self.shift {
+            Some(shift) => {
+                hash.write(&shift.dx.to_le_bytes());
+                hash.write(&shift.next.wrapping_sub(self.start).to_le_bytes());
+            }
+            None => hash.write(&[0]),
+        }
+        for &action in self.buffered.iter() {
+            hash.write(&[action as u8]);
+        }
+        hash.write(&self.score.to_le_bytes());
+        hash.write(&self.level.to_le_bytes());
+        hash.write(&self.metrics.lines.to_le_bytes());
+        hash.write(&self.metrics.pieces.to_le_bytes());
+        hash.write(&self.moment.wrapping_sub(self.start).to_le_bytes());
+        hash.write(&self.random.state().to_le_bytes());
+        hash.write(&self.garbage.to_le_bytes());
+        hash.write(&self.attack.to_le_bytes());
+        hash.write(&(self.rules.combo() as u32).to_le_bytes());
+        hash.write(&[
+            self.rules.back_to_back() as u8,
+            self.rotated as u8,
+            self.held as u8,
+        ]);
+        hash.finish()
+    }
+
+    /// Copy of the game to go back to later, listeners are not part of it
+    pub fn snapshot(&self) -> TetrisSnapshot {
+        TetrisSnapshot {
+            start: self.start,
+            moment: self.moment,
+            paused: self.paused,
+            field: self.field.clone(),
+            state: self.state.clone(),
+            pause: self.pause.clone(),
+            score: self.score,
+            level: self.level,
+            bag: self.bag.clone(),
+            queue: self.queue.clone(),
+            metrics: self.metrics,
+            garbage: self.garbage,
+            attack: self.attack,
+            rules: self.rules.clone(),
+            rotated: self.rotated,
+            hold: self.hold,
+            held: self.held,
+            landed: self.landed,
+            lock_resets: self.lock_resets,
+            shift: self.shift,
+            buffered: self.buffered.clone(),
+            seed: self.seed,
+            random: self.random,
+            inputs: self.inputs.len(),
+        }
+    }
+
+    // Snapshots only go back in time, the inputs recorded since are forgotten
+    pub fn restore(&mut self, snapshot: &TetrisSnapshot) {
+        self.start = snapshot.start;
+        self.moment = snapshot.moment;
+        self.paused = snapshot.paused;
+        self.field = snapshot.field.clone();
+        self.state = snapshot.state.clone();
+        self.pause = snapshot.pause.clone();
+        self.score = snapshot.score;
+        self.level = snapshot.level;
+        self.bag = snapshot.bag.clone();
+        self.queue = snapshot.queue.clone();
+        self.metrics = snapshot.metrics;
+        self.garbage = snapshot.garbage;
+        self.attack = snapshot.attack;
+        self.rules = snapshot.rules.clone();
+        self.rotated = snapshot.rotated;
+        self.hold = snapshot.hold;
+        self.held = snapshot.held;
+        self.landed = snapshot.landed;
+        self.lock_resets = snapshot.lock_resets;
+        self.shift = snapshot.shift;
+        self.buffered = snapshot.buffered.clone();
+        self.seed = snapshot.seed;
+        self.random = snapshot.random;
+        self.inputs.truncate(snapshot.inputs);
+        // Only drawing waits for it, the loaded stack stays dark
+        self.flash = None;
+        self.redraw = Redraw::full();
+    }
+
+    pub fn menu(&self) -> Option<&MenuMode<TetrisPause>> {
+        self.pause.as_ref()
+    }
+
+    /// Shows the placement the AI would choose for every piece as an outline
+    pub fn set_hints(&mut self, hints: bool) {
+        self.hints = hints;
+        self.update_hint();
+        self.redraw.piece = true;
+    }
+
+    pub fn hints(&self) -> bool {
+        self.hints
+    }
+
+    fn update_hint(&mut self) {
+        self.hint = match self.state {
+            GameState::Fall(block, next) if self.hints => {
+                let weights = Weights::default();
+                best_placement(&self.field, block, &[next], 1, &weights, &self.settings)
+                    .map(|placement| placement.block)
+            }
+            _ => None,
+        };
+    }
+
+    pub fn is_over(&self) -> bool {
+        matches!(self.state, GameState::GameOver)
+    }
+
+    // Next output redraws everything, for games drawing over the field themselves
+    pub fn invalidate(&mut self) {
+        self.redraw.full = true;
+    }
+
+    // Queues garbage rows, they are added once the falling block is locked
+    pub fn add_garbage(&mut self, lines: u16) {
+        self.garbage += lines;
+        self.redraw.garbage = true;
+    }
+
+    pub fn set_attack_table(&mut self, table: AttackTable) {
+        self.rules = AttackRules::new(table);
+        self.rules.set_multiplier(self.handicap.attack);
+    }
+
+    // Replays and peers assume the default curve
+    pub fn set_gravity_curve(&mut self, curve: GravityCurve) {
+        self.curve = curve;
+    }
+
+    // Takes effect right away except for the starting garbage, which comes with
+    // the next start
+    pub fn set_handicap(&mut self, handicap: Handicap) {
+        self.handicap = handicap;
+        self.rules.set_multiplier(handicap.attack);
+    }
+
+    // Games start from the page until it is taken away again
+    pub fn set_puzzle(&mut self, page: Option<Page>) {
+        self.puzzle = page;
+    }
+
+    // Games are the drills in turn until they are taken away again
+    pub fn set_drills(&mut self, drills: Option<DrillSet>) {
+        self.drills = drills;
+    }
+
+    // Games without a game over, replays do not know about it
+    pub fn set_zen(&mut self, zen: bool) {
+        self.zen = zen;
+    }
+
+    // Only the drawing turns over, the field and replays are the same as usual
+    pub fn set_upside_down(&mut self, upside_down: bool) {
+        self.upside_down = upside_down;
+        self.redraw.full = true;
+    }
+
+    // Hides the preview, the game plays the same
+    pub fn set_blind(&mut self, blind: bool) {
+        self.blind = blind;
+        self.redraw.full = true;
+    }
+
+    // Hides the stack, the game plays the same
+    pub fn set_invisible(&mut self, invisible: bool) {
+        self.invisible = invisible;
+        self.redraw.full = true;
+    }
+
+    // Hot seat, the players only hand over the keyboard so the game plays the same
+    pub fn set_turns(&mut self, turns: Option<Turns>) {
+        self.turns = turns;
+    }
+
+    /// Picks up the commands typed into `console` with every frame and shows the line
+    /// under the board
+    #[cfg(feature = "debug-tools")]
+    pub fn set_cheats(&mut self, console: Option<Rc<RefCell<CheatConsole>>>) {
+        self.cheats = console;
+        self.redraw.chat = true;
+    }
+
+    #[cfg(feature = "debug-tools")]
+    fn run_cheats(&mut self) {
+        let cheats = match &self.cheats {
+            Some(console) => {
+                let mut console = console.borrow_mut();
+                self.redraw.chat |= console.take_changed();
+                console.take()
+            }
+            None => return,
+        };
+        for cheat in cheats {
+            self.cheat(cheat);
+        }
+    }
+
+    /// Changes the game right away, for trying out mechanics. Replays do not know
+    /// about cheats.
+    #[cfg(feature = "debug-tools")]
+    pub fn cheat(&mut self, cheat: Cheat) {
+        self.cheated = true;
+        match cheat {
+            Cheat::Spawn(piece) => {
+                if let GameState::Fall(_, next) = self.state {
+                    self.state = GameState::Fall(Block::spawn(piece, &self.settings), next);
+                    self.landed = None;
+                    self.lock_resets = 0;
+                    self.rotated = false;
+                    self.moves = 0;
+                }
+            }
+            Cheat::Fill { lines, hole } => self.field.add_garbage(lines, hole),
+            Cheat::Clear(lines) => self.field.remove_rows(lines),
+            Cheat::Level(level) => {
+                self.level = level;
+                self.redraw.score = true;
+            }
+            Cheat::Invincible => self.invincible = !self.invincible,
+        }
+        self.update_hint();
+        self.redraw.field = true;
+        self.redraw.piece = true;
+    }
+
+    // For young players and those who need more time, the block waits on the stack
+    // until it is dropped. Replays do not know about it.
+    pub fn set_easy(&mut self, easy: bool) {
+        self.easy = easy;
+        self.redraw.piece = true;
+    }
+
+    // Dig races, the time the rows took is the result
+    pub fn set_dig(&mut self, rows: Option<u16>) {
+        self.dig = rows;
+    }
+
+    // Whether all the rows of the dig race are cleared
+    fn dug_out(&self) -> bool {
+        self.dig.is_some() && self.field.garbage_rows() == 0
+    }
+
+    // Sprints of that many lines, racing the splits of the personal best
+    pub fn set_sprint(&mut self, lines: Option<u32>) {
+        self.sprint = lines;
+    }
+
+    fn sprinted(&self) -> bool {
+        self.finish.is_some()
+    }
+
+    pub fn drill(&self) -> Option<&Drill> {
+        self.drills.as_ref().map(DrillSet::current)
+    }
+
+    // Lines and pieces left for the drill, garbage rows left to dig, lines left to
+    // sprint with the last split or what is left of the turn
+    fn goals(&self) -> Vec<Goal> {
+        match (self.drill(), &self.turns) {
+            (Some(drill), _) => vec![
+                Goal::Lines(drill.lines.saturating_sub(self.metrics.lines)),
+                Goal::Pieces(drill.pieces.saturating_sub(self.metrics.pieces)),
+            ],
+            _ if self.dig.is_some() => vec![Goal::Lines(self.field.garbage_rows() as u32)],
+            _ if self.sprint.is_some() => {
+                let left = self.sprint.unwrap_or(0).saturating_sub(self.metrics.lines);
+                let split = self.splits.last().map(|&time| Goal::Split {
+                    lines: self.splits.len() as u32 * SPLIT_LINES,
+                    time,
+                });
+                let mut goals = vec![Goal::Lines(left)];
+                goals.extend(split);
+                goals
+            }
+            (None, Some(turns)) => turns.goals(&self.metrics),
+            (None, None) => vec![],
+        }
+    }
+
+    // Whether this game cleared the drill, none while it is still open
+    fn drill_result(&self) -> Option<bool> {
+        self.drill()?
+            .result(self.metrics.lines, self.metrics.pieces)
+    }
+
+    // Fumen of the stack and the falling block, for sharing the board
+    pub fn fumen(&self) -> Option<String> {
+        let block = self.current().map(|(block, _)| block);
+        fumen::encode(&self.field.field(), block.as_ref())
+    }
+
+    // Games over add to it, for the stats in the menu
+    pub fn set_profile(&mut self, profile: Option<Profile>) {
+        self.profile = profile;
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    // Puzzles are not offered the slots, their start is not in the replay
+    pub fn set_saves(&mut self, saves: Option<SaveSlots>) {
+        self.saves = saves;
+    }
+
+    pub fn saves(&self) -> Option<&SaveSlots> {
+        self.saves.as_ref()
+    }
+
+    fn save(&mut self, slot: usize, now: Timestamp) {
+        let replay = self.replay();
+        if let Some(saves) = &mut self.saves {
+            let save = Save {
+                mode: saves.mode().to_string(),
+                score: self.score,
+                time: now,
+                replay,
+            };
+            saves.save(slot, save);
+        }
+        self.pause = Some(self.practice_menu());
+    }
+
+    fn load(&mut self, slot: usize, now: Timestamp) {
+        if let Some(save) = self.saves.as_ref().and_then(|saves| saves.get(slot)) {
+            let replay = save.replay.clone();
+            self.resume(&replay, now);
+        }
+    }
+
+    /// Plays a saved game again up to where it was left and waits in the menu, the
+    /// listeners and options of this game go on with it
+    pub fn resume(&mut self, replay: &Replay, now: Timestamp) {
+        let start = now.saturating_sub(replay.end);
+        let mut game = Tetris::with_seed(replay.settings, start, replay.seed);
+        for &(time, action) in replay.inputs.iter() {
+            game.frame(start + time, Some(action));
+        }
+        game.frame(now, None);
+        self.carry_over(game);
+        self.open_menu();
+    }
+
+    // Best so far, including the game just over when it beat the one set before
+    pub fn set_personal_best(&mut self, best: Option<Best>) {
+        self.best = best;
+    }
+
+    pub fn personal_best(&self) -> Option<&Best> {
+        self.best.as_ref()
+    }
+
+    // Behind or ahead of the personal best at the last split, like PB +1.2s
+    fn pace(&self) -> Option<String> {
+        let delta = self.best.as_ref()?.split_delta(&self.splits)?;
+        Some(format!("PB {:+.1}s", delta as f32 / 1000.))
+    }
+
+    pub fn handicap(&self) -> &Handicap {
+        &self.handicap
+    }
+
+    // Returns garbage this game has sent since the last call
+    pub fn take_attack(&mut self) -> u32 {
+        mem::take(&mut self.attack)
+    }
+
+    pub fn add_listener(&mut self, listener: Box<dyn TetrisListener>) {
+        self.listeners.push(listener);
+    }
+
+    pub fn random_block(&mut self) -> Tetromino {
+        if !self.queue.is_empty() {
+            return self.queue.remove(0);
+        }
+        deal(&mut self.bag, &mut self.random)
     }
 
     pub fn state_start(&mut self) {
-        let block = Block::spawn(self.random_block(), &self.settings);
+        let first = match (self.drills.as_ref().map(DrillSet::current), &self.puzzle) {
+            (Some(drill), _) => {
+                self.field = TetrisField::from_field(drill.field.clone());
+                self.queue = drill.queue.clone();
+                None
+            }
+            (None, Some(page)) => {
+                self.field = TetrisField::from_field(page.field.clone());
+                page.piece
+            }
+            (None, None) => None,
+        };
+        if self.handicap.garbage > 0 {
+            let hole = self.random.below(self.settings.cols as usize) as u16;
+            self.field.add_garbage(self.handicap.garbage, hole);
+        }
+        // A hole of its own in every row
+        for _ in 0..self.dig.unwrap_or(0) {
+            let hole = self.random.below(self.settings.cols as usize) as u16;
+            self.field.add_garbage(1, hole);
+        }
+        let first = first.unwrap_or_else(|| self.random_block());
+        let block = Block::spawn(first, &self.settings);
         self.run_cicle(block);
     }
 
     pub fn state_fall(&mut self, now: Timestamp, action: Option<Action>) -> bool {
+        // Rotation and hold wait for the next block while the rows clear or it has
+        // not come yet
+        if let (
+            GameState::Clearing(..) | GameState::Entry(..),
+            Some(action @ (Action::Up | Action::RotateLeft | Action::Hold)),
+        ) = (&self.state, action)
+        {
+            if !self.buffered.contains(&action) {
+                self.buffered.push(action);
+            }
+            return false;
+        }
+        match action {
+            Some(Action::Hold) => return self.swap_hold(),
+            Some(Action::ReleaseLeft) => return self.release(-1),
+            Some(Action::ReleaseRight) => return self.release(1),
+            Some(Action::Left) => self.press(now, -1),
+            Some(Action::Right) => self.press(now, 1),
+            _ => {}
+        }
         let mut drop = false;
         let mut changed = false;
         if let GameState::Fall(ref mut block, _) = &mut self.state {
+            if action.is_some() {
+                self.metrics.keys += 1;
+            }
+            if matches!(
+                action,
+                Some(Action::Left | Action::Right | Action::Up | Action::RotateLeft)
+            ) {
+                self.moves += 1;
+            }
             match action {
                 Some(Action::Left) => changed = self.field.try_move(block, -1, 0),
                 Some(Action::Right) => changed = self.field.try_move(block, 1, 0),
                 Some(Action::Down) => {
                     self.moment = now;
                     if self.field.try_move(block, 0, 1) {
+                        self.score += SOFT_DROP_POINTS;
+                        self.metrics.points.soft_drop += SOFT_DROP_POINTS;
+                        self.redraw.score = true;
                         changed = true
                     } else {
                         drop = true
                     }
                 }
                 Some(Action::Drop) => {
-                    changed = self.field.drop(block) > 0;
+                    let rows = self.field.drop(block) as u32;
+                    self.score += rows * HARD_DROP_POINTS;
+                    self.metrics.points.hard_drop += rows * HARD_DROP_POINTS;
+                    self.redraw.score |= rows > 0;
+                    changed = rows > 0;
                     drop = true;
                 }
-                Some(Action::Up) => changed = self.field.try_rotate(block),
+                Some(Action::Up) => changed = self.field.try_rotate(block, true),
+                Some(Action::RotateLeft) => changed = self.field.try_rotate(block, false),
                 _ => changed = false,
             };
+            if changed {
+                self.rotated = matches!(action, Some(Action::Up | Action::RotateLeft));
+            }
+        }
+        if changed && !drop {
+            self.reset_lock(now);
+        }
+        self.redraw.piece |= changed;
+        if drop {
+            self.lock(now);
+        }
+        changed || drop
+    }
+
+    // The falling block goes into the hold and the held piece comes out at the top in
+    // its place, the next piece when the hold was empty. Once per piece, and not when
+    // the piece coming out has no room.
+    fn swap_hold(&mut self) -> bool {
+        let (block, next) = match self.state {
+            GameState::Fall(block, next) if !self.held => (block, next),
+            _ => return false,
+        };
+        self.metrics.keys += 1;
+        let out = self.hold.unwrap_or(next);
+        let spawn = Block::spawn(out, &self.settings);
+        if self.field.has_collision(&spawn) {
+            return false;
+        }
+        let next = match self.hold.replace(block.tetromino) {
+            Some(_) => next,
+            None => {
+                self.redraw.preview = true;
+                self.random_block()
+            }
+        };
+        self.state = GameState::Fall(spawn, next);
+        self.metrics.spawn(out);
+        self.held = true;
+        self.landed = None;
+        self.lock_resets = 0;
+        self.rotated = false;
+        self.moves = 0;
+        self.redraw.piece = true;
+        self.redraw.hold = true;
+        self.update_hint();
+        true
+    }
 
-            if !drop && (now - self.moment >= self.settings.delay) {
-                self.moment = now;
-                if self.field.try_move(block, 0, 1) {
-                    changed = true
-                } else {
-                    drop = true
+    // Buffered hold and rotation apply to the block as it comes, the hold first so
+    // the piece coming out of it is the one turned. Of both rotations the one
+    // pressed first counts.
+    fn initial_actions(&mut self) {
+        let buffered = mem::take(&mut self.buffered);
+        if buffered.contains(&Action::Hold) {
+            self.swap_hold();
+        }
+        let clockwise = buffered.iter().find_map(|action| match action {
+            Action::Up => Some(true),
+            Action::RotateLeft => Some(false),
+            _ => None,
+        });
+        if let Some(clockwise) = clockwise {
+            if let GameState::Fall(ref mut block, _) = &mut self.state {
+                self.metrics.keys += 1;
+                self.moves += 1;
+                self.rotated = self.field.try_rotate(block, clockwise);
+            }
+        }
+    }
+
+    // Left or right went down, the block slides that way after the auto shift delay
+    // unless the key is let go before. Also while no block falls, the next one slides
+    // right away then.
+    fn press(&mut self, now: Timestamp, dx: i16) {
+        if self.settings.das > 0 {
+            self.shift = Some(AutoShift {
+                dx,
+                next: now + self.settings.das,
+            });
+        }
+    }
+
+    // Letting go of the other key keeps the slide going
+    fn release(&mut self, dx: i16) -> bool {
+        if self.shift.is_some_and(|shift| shift.dx == dx) {
+            self.shift = None;
+        }
+        false
+    }
+
+    // Step of a slide that is due, all the way to the wall without a repeat rate.
+    // Against the wall it is tried again with every repeat, the block may get room.
+    fn auto_shift(&mut self, shift: AutoShift) -> bool {
+        let mut moved = false;
+        if let GameState::Fall(ref mut block, _) = &mut self.state {
+            while self.field.try_move(block, shift.dx, 0) {
+                moved = true;
+                if self.settings.arr > 0 {
+                    break;
                 }
             }
         }
-        if drop {
-            if let GameState::Fall(block, next) = mem::take(&mut self.state) {
-                self.state = GameState::Drop(block, next);
+        self.shift = Some(AutoShift {
+            next: shift.next + self.settings.arr.max(1),
+            ..shift
+        });
+        if moved {
+            self.rotated = false;
+            self.redraw.piece = true;
+            self.reset_lock(shift.next);
+        }
+        moved
+    }
+
+    // A landed block that was moved or rotated gets its lock delay again, up to
+    // `MAX_LOCK_RESETS` times so it cannot be kept from locking forever. Moved off
+    // the edge it falls again with the next gravity step.
+    fn reset_lock(&mut self, now: Timestamp) {
+        if self.landed.is_none() {
+            return;
+        }
+        if !self.grounded() {
+            self.landed = None;
+            self.moment = now;
+        } else if self.lock_resets < MAX_LOCK_RESETS {
+            self.lock_resets += 1;
+            self.landed = Some(now);
+        }
+    }
+
+    // The falling block cannot go down any further
+    fn grounded(&self) -> bool {
+        match &self.state {
+            GameState::Fall(block, _) => self.field.altitude(block) == 0,
+            _ => false,
+        }
+    }
+
+    // One row down. Without room the block locks, or starts its lock delay, unless
+    // the game is easy.
+    pub fn state_gravity(&mut self) -> bool {
+        if let GameState::Fall(ref mut block, _) = &mut self.state {
+            if self.field.try_move(block, 0, 1) {
+                self.rotated = false;
+                self.redraw.piece = true;
+            } else if self.easy {
+                return false;
+            } else if self.settings.lock_delay > 0 {
+                self.landed = Some(self.moment);
+                return false;
+            } else {
+                self.lock(self.moment);
             }
+            return true;
         }
-        changed
+        false
     }
 
-    pub fn state_drop(&mut self) {
-        if let GameState::Drop(prev, current) = mem::take(&mut self.state) {
-            let lines = self.field.consume(prev);
-            self.score += (lines * (lines + 1) / 2) as u32;
-            let block = Block::spawn(current, &self.settings);
-            self.run_cicle(block);
+    // Landed blocks are consumed right away, a frame never ends with a block in the air
+    fn lock(&mut self, now: Timestamp) {
+        if let GameState::Fall(block, next) = self.state {
+            self.state = GameState::Drop(block, next);
+            self.state_drop(now);
         }
     }
 
+    // Scores the block locked at `now`. Full rows stay on the board for the clear
+    // delay before they collapse and the next block comes.
+    pub fn state_drop(&mut self, now: Timestamp) {
+        if let GameState::Drop(prev, current) = self.state {
+            // Only keys pressed after the lock are meant for the next block
+            self.buffered.clear();
+            if self.invisible {
+                self.flash = Some(now + INVISIBLE_FLASH);
+            }
+            for listener in self.listeners.iter_mut() {
+                listener.on_lock(&prev);
+            }
+            let tspin = self.rotated && self.field.is_tspin(&prev);
+            let spawn = Block::spawn(prev.tetromino, &self.settings);
+            if finesse::min_keys(&self.field, &spawn, &prev).is_some_and(|keys| self.moves > keys) {
+                self.metrics.finesse += 1;
+            }
+            let cleared = self.field.place(prev);
+            // Big blocks count two rows as a line
+            let size = prev.size as u16;
+            let lines = (cleared.len() as u16).div_ceil(size);
+            let attack = self.rules.attack(Clear { lines, tspin });
+            self.metrics.lock(prev.tetromino, attack);
+            self.metrics.clear(lines, tspin);
+            while self.metrics.lines >= (self.splits.len() as u32 + 1) * SPLIT_LINES {
+                self.splits.push(self.metrics.elapsed);
+            }
+            if self.finish.is_none() && self.sprint.is_some_and(|goal| self.metrics.lines >= goal) {
+                self.finish = Some(self.metrics.elapsed);
+            }
+            // Cancelled or landed below, the meter is empty after every lock
+            self.redraw.garbage |= self.garbage > 0;
+            self.attack += attack::cancel(&mut self.garbage, attack);
+            self.redraw.field = true;
+            if lines > 0 {
+                let points = (lines * (lines + 1) / 2) as u32 * self.level;
+                self.score += points;
+                self.metrics.points.lines += points;
+                self.redraw.score = true;
+                for listener in self.listeners.iter_mut() {
+                    listener.on_lines_cleared(lines);
+                }
+                let level = self.curve.level(self.settings.level, self.metrics.lines);
+                if level > self.level {
+                    self.level = level;
+                    for listener in self.listeners.iter_mut() {
+                        listener.on_level_up(level);
+                    }
+                }
+            }
+            if self.settings.top_out.lock_out && self.field.over_top(&prev) && self.top_out() {
+                return;
+            }
+            if lines > 0 && self.settings.clear_delay > 0 {
+                self.state = GameState::Clearing(cleared, current, now + self.settings.clear_delay);
+            } else {
+                self.collapse(now, cleared, current);
+            }
+        }
+    }
+
+    // Cleared rows go away at `now`, garbage comes in below and the next block spawns
+    // after the entry delay
+    fn collapse(&mut self, now: Timestamp, cleared: Vec<u16>, next: Tetromino) {
+        self.field.remove_lines(&cleared);
+        self.redraw.garbage |= self.garbage > 0;
+        if self.garbage > 0 {
+            let garbage = mem::take(&mut self.garbage);
+            let out = self.settings.top_out.garbage_out && self.field.overflows(garbage);
+            let hole = self.random.below(self.settings.cols as usize) as u16;
+            self.field.add_garbage(garbage, hole);
+            if out && self.top_out() {
+                return;
+            }
+        }
+        self.redraw.field = true;
+        self.redraw.cleared = cleared;
+        if self.settings.entry_delay > 0 {
+            self.state = GameState::Entry(next, now + self.settings.entry_delay);
+            self.redraw.piece = true;
+            return;
+        }
+        let block = Block::spawn(next, &self.settings);
+        self.run_cicle(block);
+    }
+
     pub fn run_cicle(&mut self, block: Block) {
         let next = self.random_block();
-        if self.field.has_collision(&block) {
-            self.state = GameState::GameOver;
+        self.rotated = false;
+        self.held = false;
+        self.landed = None;
+        self.lock_resets = 0;
+        self.moves = 0;
+        self.redraw.piece = true;
+        self.redraw.preview = true;
+        // Drills are over as soon as they are cleared or out of pieces
+        let drill = self.drill_result();
+        let mut top = -(HIDDEN_ROWS as i16);
+        while self.easy
+            && drill.is_none()
+            && self.field.has_collision(&block)
+            && top < self.settings.rows as i16
+        {
+            self.field.clear_row(top);
+            self.redraw.field = true;
+            top += 1;
+        }
+        if (self.zen || self.invincible) && drill.is_none() && self.field.has_collision(&block) {
+            self.clear_board();
+        }
+        if self.field.has_collision(&block) || drill.is_some() || self.dug_out() || self.sprinted()
+        {
+            self.game_over(drill);
         } else {
             self.state = GameState::Fall(block, next);
+            self.metrics.spawn(block.tetromino);
+            self.initial_actions();
+            if !self.listeners.is_empty() {
+                let replay = self.replay();
+                for listener in self.listeners.iter_mut() {
+                    listener.on_progress(&replay, self.score);
+                }
+            }
+        }
+        self.update_hint();
+    }
+
+    // Zen games lose points for it
+    fn clear_board(&mut self) {
+        self.field = TetrisField::new(&self.settings);
+        if self.zen {
+            self.score = self.score.saturating_sub(ZEN_PENALTY);
+        }
+        self.redraw.field = true;
+        self.redraw.score = true;
+    }
+
+    // Blocks went over the top another way than a block-out, as far as the top-out
+    // rules of the settings say so. Zen and invincible games go on with an empty
+    // board, others are over. Returns whether the game is over.
+    fn top_out(&mut self) -> bool {
+        let drill = self.drill_result();
+        if (self.zen || self.invincible) && drill.is_none() {
+            self.clear_board();
+            return false;
+        }
+        self.game_over(drill);
+        self.update_hint();
+        true
+    }
+
+    // `drill` is the result of the drill being played, if any
+    fn game_over(&mut self, drill: Option<bool>) {
+        self.state = GameState::GameOver;
+        let run = Best {
+            score: self.score,
+            time: self.finish,
+            splits: self.splits.clone(),
+        };
+        match &self.best {
+            _ if self.cheated => {}
+            Some(best) if !run.beats(best) => {}
+            previous => {
+                self.new_best = previous.is_some();
+                self.best = Some(run);
+            }
+        }
+        if let (Some(profile), false) = (&mut self.profile, self.cheated) {
+            profile.add(&self.metrics);
+        }
+        let snapshot = snapshot::snapshot(&self.field.field(), self.score, &self.metrics);
+        let name = self.drill().map(|drill| drill.name.clone());
+        let replay = self.replay();
+        for listener in self.listeners.iter_mut() {
+            if let Some(name) = &name {
+                listener.on_drill(name, drill == Some(true));
+            }
+            listener.on_game_over(self.score);
+            listener.on_metrics(&self.metrics);
+            listener.on_final_board(&snapshot);
+            listener.on_finished(&replay, self.score);
         }
     }
 
     pub fn pause_menu() -> MenuMode<TetrisPause> {
         MenuMode::new(vec![
-            MenuItem {
-                id: TetrisPause::Title,
-                string: "Menu",
-                selectable: false,
-            },
-            MenuItem {
-                id: TetrisPause::Continue,
-                string: "Continue",
-                selectable: true,
-            },
-            MenuItem {
-                id: TetrisPause::Restart,
-                string: "New Game",
-                selectable: true,
-            },
-            MenuItem {
-                id: TetrisPause::Exit,
-                string: "Exit",
-                selectable: true,
-            },
+            MenuItem::label(TetrisPause::Title, "Menu"),
+            MenuItem::button(TetrisPause::Continue, "Continue"),
+            MenuItem::confirm(TetrisPause::Restart, "New Game", RESTART_QUESTION),
+            MenuItem::confirm(TetrisPause::Exit, "Exit", EXIT_QUESTION),
         ])
     }
 
-    pub fn over_menu() -> MenuMode<TetrisPause> {
-        MenuMode::new(vec![
-            MenuItem {
-                id: TetrisPause::Title,
-                string: "You Died",
-                selectable: false,
-            },
-            MenuItem {
-                id: TetrisPause::Restart,
-                string: "New Game",
-                selectable: true,
-            },
-            MenuItem {
-                id: TetrisPause::Exit,
-                string: "Exit",
-                selectable: true,
-            },
-        ])
+    // Pause menu of a single game, with the practice options
+    fn practice_menu(&self) -> MenuMode<TetrisPause> {
+        let values = vec!["Off".to_string(), "On".to_string()];
+        let mut items = vec![
+            MenuItem::label(TetrisPause::Title, "Menu"),
+            MenuItem::button(TetrisPause::Continue, "Continue"),
+            MenuItem::value(TetrisPause::Hints, "Hints", values, self.hints as usize),
+            MenuItem::confirm(TetrisPause::Restart, "New Game", RESTART_QUESTION),
+            MenuItem::confirm(TetrisPause::Exit, "Exit", EXIT_QUESTION),
+        ];
+        if let Some(profile) = &self.profile {
+            let stats = vec![
+                MenuItem::label(TetrisPause::Title, format!("Games {}", profile.games)),
+                MenuItem::label(TetrisPause::Title, format!("Lines {}", profile.lines)),
+                MenuItem::label(
+                    TetrisPause::Title,
+                    format!("Tetris rate {:.0}%", profile.tetris_rate() * 100.),
+                ),
+                MenuItem::label(TetrisPause::Title, format!("PPS {:.2}", profile.pps())),
+                MenuItem::label(TetrisPause::Title, format!("Hours {:.1}", profile.hours())),
+            ];
+            items.insert(4, MenuItem::submenu(TetrisPause::Title, "Stats", stats));
+        }
+        if let Some(saves) = &self.saves {
+            let (mut save, mut load) = (vec![], vec![]);
+            for slot in 0..SLOTS {
+                let name = SaveSlots::name(slot);
+                match saves.get(slot) {
+                    Some(game) => {
+                        let text = format!("{} {}", name, game.summary());
+                        save.push(MenuItem::button(TetrisPause::Save(slot), text.clone()));
+                        // Only games of the same board play the same way again
+                        if settings_checksum(&game.replay.settings)
+                            == settings_checksum(&self.settings)
+                        {
+                            load.push(MenuItem::button(TetrisPause::Load(slot), text));
+                        }
+                    }
+                    None => {
+                        let text = format!("{} Empty", name);
+                        save.push(MenuItem::button(TetrisPause::Save(slot), text));
+                    }
+                }
+            }
+            if load.is_empty() {
+                load.push(MenuItem::label(TetrisPause::Title, "No Saved Games"));
+            }
+            items.insert(3, MenuItem::submenu(TetrisPause::Title, "Save Game", save));
+            items.insert(4, MenuItem::submenu(TetrisPause::Title, "Load Game", load));
+        }
+        if let Some(exit) = items
+            .iter()
+            .position(|item| matches!(item.id, TetrisPause::Exit))
+        {
+            let extra = self
+                .listeners
+                .iter()
+                .flat_map(|listener| listener.menu_items());
+            items.splice(exit..exit, extra.collect::<Vec<_>>());
+        }
+        if let Some(drill) = self.drill() {
+            let goal = format!("{}: {}", drill.name, drill.goal());
+            items.push(MenuItem::label(TetrisPause::Title, goal));
+        }
+        if let Some(fumen) = self.fumen() {
+            items.push(MenuItem::label(TetrisPause::Title, fumen));
+        }
+        MenuMode::new(items)
     }
 
-    pub fn to_drawable(&self) -> GameMode {
+    // Game over menu with whatever the listeners want to add before the buttons
+    pub fn over_menu(&mut self) -> MenuMode<TetrisPause> {
+        let (title, again) = match self.drill_result() {
+            _ if self.dug_out() => ("Dug Out", "New Game"),
+            _ if self.sprinted() => ("Sprint Finished", "New Game"),
+            Some(true) => ("Drill Cleared", "Next Drill"),
+            Some(false) => ("Drill Failed", "Try Again"),
+            None if self.drills.is_some() => ("You Died", "Try Again"),
+            None => ("You Died", "New Game"),
+        };
+        let metrics = &self.metrics;
+        let summary = [
+            format!("Score {}", self.score),
+            format!("Line Points {}", metrics.points.lines),
+            format!(
+                "Drop Points {}",
+                metrics.points.soft_drop + metrics.points.hard_drop
+            ),
+            format!("Level {}", self.level),
+            format!("Lines {}", metrics.lines),
+            format!("Time {}", metrics.clock()),
+            format!("PPS {:.2}", metrics.pps()),
+            format!("Tetris Rate {:.0}%", metrics.tetris_rate() * 100.),
+            format!("Seed {}", self.seed),
+        ];
+        let mut items = vec![MenuItem::label(TetrisPause::Title, title)];
+        if self.new_best {
+            items.push(MenuItem::label(TetrisPause::Title, "New Personal Best"));
+        }
+        if self.blind {
+            items.push(MenuItem::label(TetrisPause::Title, "Blind"));
+        }
+        if self.invisible {
+            items.push(MenuItem::label(TetrisPause::Title, "Invisible"));
+        }
+        if self.dug_out() {
+            let time = format!("{} Rows in {}", self.dig.unwrap_or(0), metrics.clock());
+            items.push(MenuItem::label(TetrisPause::Title, time));
+        }
+        if let (Some(lines), Some(finish)) = (self.sprint, self.finish) {
+            let time = format!("{} Lines in {}", lines, metrics::clock(finish));
+            items.push(MenuItem::label(TetrisPause::Title, time));
+        }
+        items.extend(summary.map(|line| MenuItem::label(TetrisPause::Title, line)));
+        for listener in self.listeners.iter_mut() {
+            items.append(&mut listener.over_items());
+        }
+        items.push(MenuItem::button(TetrisPause::Screenshot, "Screenshot"));
+        if self.plays_back() {
+            items.push(MenuItem::button(TetrisPause::SaveReplay, "Save Replay"));
+        }
+        items.push(MenuItem::button(TetrisPause::Retry, "Retry Seed"));
+        items.push(MenuItem::button(TetrisPause::Restart, again));
+        items.push(MenuItem::button(TetrisPause::Exit, "Exit"));
+        MenuMode::new(items)
+    }
+
+    // Replays start from the seed with the usual rules, games from a puzzle or a
+    // drill, zen, easy, dig and sprint games do not play back
+    fn plays_back(&self) -> bool {
+        self.puzzle.is_none()
+            && self.drills.is_none()
+            && !self.zen
+            && !self.easy
+            && self.dig.is_none()
+            && self.sprint.is_none()
+    }
+
+    fn screenshot(&mut self) {
+        let game = self.to_drawable();
+        for listener in self.listeners.iter_mut() {
+            listener.on_screenshot(&game);
+        }
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn output(&mut self) -> FrameOutput {
+        FrameOutput {
+            game: self.to_drawable(),
+            redraw: mem::take(&mut self.redraw),
+        }
+    }
+
+    // Field with the falling block, without anything drawn for the player only
+    fn board(&self) -> Renderable {
         match &self.state {
+            GameState::Fall(block, _) | GameState::Drop(block, _) => {
+                self.field.field_with_block(block)
+            }
+            GameState::Start
+            | GameState::GameOver
+            | GameState::Clearing(..)
+            | GameState::Entry(..) => self.field.field(),
+        }
+    }
+
+    fn hold_preview(&self) -> Renderable {
+        match &self.hold {
+            Some(held) => held.preview(),
+            None => Renderable(vec![vec![]]),
+        }
+    }
+
+    pub fn to_drawable(&self) -> GameMode {
+        let clearing = match &self.state {
+            GameState::Clearing(rows, _, _) => rows.clone(),
+            _ => vec![],
+        };
+        let mut game = match &self.state {
             GameState::Fall(block, next) | GameState::Drop(block, next) => GameMode {
-                main: self.field.field_with_block(&block),
+                main: match (&self.hint, self.easy) {
+                    (Some(hint), _) => self.field.field_with_hint(block, hint),
+                    // Outline where the block would land
+                    (None, true) => {
+                        let mut landing = *block;
+                        self.field.drop(&mut landing);
+                        self.field.field_with_hint(block, &landing)
+                    }
+                    (None, false) => self.field.field_with_block(block),
+                },
+                preview: next.preview(),
+                hold: self.hold_preview(),
+                score: self.score,
+                level: self.level,
+                metrics: self.metrics,
+                chat: None,
+                garbage: self.garbage,
+                pace: self.pace(),
+                goals: self.goals(),
+                clearing,
+            },
+            GameState::Clearing(_, next, _) | GameState::Entry(next, _) => GameMode {
+                main: self.field.field(),
                 preview: next.preview(),
+                hold: self.hold_preview(),
                 score: self.score,
+                level: self.level,
+                metrics: self.metrics,
+                chat: None,
+                garbage: self.garbage,
+                pace: self.pace(),
+                goals: self.goals(),
+                clearing,
             },
             GameState::Start | GameState::GameOver => GameMode {
                 main: self.field.field(),
                 preview: Renderable(vec![vec![]]),
+                hold: self.hold_preview(),
                 score: self.score,
+                level: self.level,
+                metrics: self.metrics,
+                chat: None,
+                garbage: self.garbage,
+                pace: self.pace(),
+                goals: self.goals(),
+                clearing,
             },
-            GameState::Temp => unreachable!(),
+        };
+        let dark = !matches!(self.state, GameState::Clearing(..) | GameState::GameOver);
+        if self.invisible && self.flash.is_none() && dark {
+            // Blocks never cover locked cells, only the stack goes
+            for (row, locked) in game.main.iter_mut().zip(self.field.field().iter()) {
+                for (cell, locked) in row.iter_mut().zip(locked.iter()) {
+                    if locked.is_filled() {
+                        *cell = Cell::Empty;
+                    }
+                }
+            }
+        }
+        // The previews turn with the board, so the pieces look the way they fall
+        if self.upside_down {
+            game.main.0.reverse();
+            game.preview.0.reverse();
+            game.hold.0.reverse();
+            let rows = self.settings.rows;
+            for y in game.clearing.iter_mut() {
+                *y = rows - 1 - *y;
+            }
         }
+        if self.blind {
+            game.preview = Renderable(vec![vec![]]);
+        }
+        #[cfg(feature = "debug-tools")]
+        if let Some(console) = &self.cheats {
+            game.chat = console.borrow().text();
+        }
+        game
     }
 }
 
+/// Saved game state, see `Tetris::snapshot`
+#[derive(Clone)]
+pub struct TetrisSnapshot {
+    start: Timestamp,
+    moment: Timestamp,
+    paused: Option<Timestamp>,
+    field: TetrisField,
+    state: GameState,
+    pause: Option<MenuMode<TetrisPause>>,
+    score: u32,
+    level: u32,
+    bag: Vec<Tetromino>,
+    queue: Vec<Tetromino>,
+    metrics: Metrics,
+    garbage: u16,
+    attack: u32,
+    rules: AttackRules,
+    rotated: bool,
+    hold: Option<Tetromino>,
+    held: bool,
+    landed: Option<Timestamp>,
+    lock_resets: u32,
+    shift: Option<AutoShift>,
+    buffered: Vec<Action>,
+    seed: u64,
+    random: Random,
+    // Number of inputs recorded at the time
+    inputs: usize,
+}
+
+#[derive(Clone)]
 pub enum GameState {
     Start,
     Fall(Block, Tetromino),
     Drop(Block, Tetromino),
+    // Full rows flashing until the time, before the next block comes
+    Clearing(Vec<u16>, Tetromino, Timestamp),
+    // Board without a block until the time, then the piece spawns
+    Entry(Tetromino, Timestamp),
     GameOver,
-    Temp,
 }
 
-impl Default for GameState {
-    fn default() -> Self {
-        GameState::Temp
-    }
+// Left or right held down, see `Settings::das`
+#[derive(Debug, Clone, Copy)]
+struct AutoShift {
+    dx: i16,
+    // When the block takes the next step
+    next: Timestamp,
 }
 
-const BAG_SIZE: u8 = 3;
+// Moves and rotations that restart the lock delay of a block, like in guideline games
+const MAX_LOCK_RESETS: u32 = 15;
+
+// Points a zen game loses for topping out, as much as a tetris is worth
+const ZEN_PENALTY: u32 = 10;
+// Milliseconds the stack of an invisible game shows after a lock
+const INVISIBLE_FLASH: Timestamp = 150;
+// Points per row of a soft drop and of a hard drop
+const SOFT_DROP_POINTS: u32 = 1;
+const HARD_DROP_POINTS: u32 = 2;
+
+/// How the game is played in a few lines, for the controls screen
+pub const RULES: [&str; 5] = [
+    "Fill a row to clear it",
+    "More rows at once score more",
+    "Spin a T into a slot for a T-spin",
+    "Hold a piece to swap it in later, once per piece",
+    "The game is over once blocks go over the top",
+];
+
+// Asked before the pause menu throws away the game that is going on
+pub const RESTART_QUESTION: &str = "Really start over?";
+pub const EXIT_QUESTION: &str = "Really quit?";
+
+// Bumped whenever the same inputs would play a different game or hash to a different
+// state, peers have to agree on it
+pub const RULES_VERSION: u16 = 18;
+
+// Fingerprint of the settings, peers playing with different ones would not match up
+pub fn settings_checksum(settings: &Settings) -> u64 {
+    let mut hash = Checksum::default();
+    hash.write(&settings.cols.to_le_bytes());
+    hash.write(&settings.rows.to_le_bytes());
+    hash.write(&settings.delay.to_le_bytes());
+    hash.write(&settings.level.to_le_bytes());
+    hash.write(&settings.lock_delay.to_le_bytes());
+    hash.write(&settings.clear_delay.to_le_bytes());
+    hash.write(&settings.das.to_le_bytes());
+    hash.write(&settings.arr.to_le_bytes());
+    hash.write(&settings.entry_delay.to_le_bytes());
+    hash.write(&[settings.top_out.to_byte()]);
+    hash.write(&[settings.rotation.to_byte()]);
+    hash.write(&[settings.big as u8]);
+    hash.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootstrap::TopOut;
+    use crate::game::tetris::tetromino::RotationSystem;
+
+    const SETTINGS: Settings = Settings {
+        cols: 10,
+        rows: 20,
+        delay: 500,
+        level: 1,
+        lock_delay: 500,
+        clear_delay: 0,
+        das: 0,
+        arr: 0,
+        entry_delay: 0,
+        top_out: TopOut::GUIDELINE,
+        rotation: RotationSystem::Srs,
+        big: false,
+    };
+
+    // Saved while paused from outside, like a window losing focus, then loaded and
+    // played on. The recording of the loaded game has to end where it did.
+    #[test]
+    fn resume_and_continue() {
+        let mut game = Tetris::with_seed(SETTINGS, 0, 7);
+        game.frame(0, None);
+        game.frame(100, Some(Action::Left));
+        game.frame(900, Some(Action::Drop));
+        game.frame(1200, None);
+        game.pause();
+        game.frame(1500, None);
+        let saved = game.replay();
+        assert!(saved.verify());
+
+        let mut resumed = Tetris::with_seed(SETTINGS, 5000, 1);
+        resumed.resume(&saved, 5000);
+        resumed.frame(6000, Some(Action::Escape));
+        resumed.frame(6200, Some(Action::Right));
+        resumed.frame(6800, Some(Action::Drop));
+        resumed.frame(7000, None);
+        assert!(resumed.pause.is_none());
+        let replay = resumed.replay();
+        assert_eq!(replay.play().state_hash(), resumed.state_hash());
+    }
+}
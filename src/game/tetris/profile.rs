@@ -0,0 +1,83 @@
+use crate::bootstrap::Timestamp;
+use crate::game::tetris::metrics::Metrics;
+use crate::game::tetris::schema::{self, Migration};
+use std::io::{self, Read, Write};
+
+// Layout changes since the first file, version 1 only added the header
+const MIGRATIONS: [Migration; 1] = [schema::unchanged];
+
+/// Lifetime numbers over every finished game, kept in a text file with a
+/// `name value` line for each after the version header
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Profile {
+    pub games: u32,
+    pub lines: u64,
+    // Clears of four lines at once
+    pub tetrises: u64,
+    pub pieces: u64,
+    // Milliseconds spent in games
+    pub time: Timestamp,
+}
+
+impl Profile {
+    pub fn add(&mut self, metrics: &Metrics) {
+        self.games += 1;
+        self.lines += metrics.lines as u64;
+        self.tetrises += metrics.clears[3] as u64;
+        self.pieces += metrics.pieces as u64;
+        self.time += metrics.elapsed;
+    }
+
+    /// Share of the lines cleared by tetrises
+    pub fn tetris_rate(&self) -> f32 {
+        if self.lines > 0 {
+            (self.tetrises * 4) as f32 / self.lines as f32
+        } else {
+            0.
+        }
+    }
+
+    /// Pieces per second over all games
+    pub fn pps(&self) -> f32 {
+        if self.time > 0 {
+            self.pieces as f32 * 1000. / self.time as f32
+        } else {
+            0.
+        }
+    }
+
+    pub fn hours(&self) -> f32 {
+        self.time as f32 / 3_600_000.
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        schema::write_header(writer, "profile", &MIGRATIONS)?;
+        writeln!(writer, "games {}", self.games)?;
+        writeln!(writer, "lines {}", self.lines)?;
+        writeln!(writer, "tetrises {}", self.tetrises)?;
+        writeln!(writer, "pieces {}", self.pieces)?;
+        writeln!(writer, "time {}", self.time)
+    }
+
+    // Unknown and broken lines are skipped
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Profile> {
+        let text = schema::read_text(reader, "profile", &MIGRATIONS)?;
+        let mut profile = Profile::default();
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            let (name, value) = match (parts.next(), parts.next().map(str::parse::<u64>)) {
+                (Some(name), Some(Ok(value))) => (name, value),
+                _ => continue,
+            };
+            match name {
+                "games" => profile.games = value as u32,
+                "lines" => profile.lines = value,
+                "tetrises" => profile.tetrises = value,
+                "pieces" => profile.pieces = value,
+                "time" => profile.time = value,
+                _ => {}
+            }
+        }
+        Ok(profile)
+    }
+}
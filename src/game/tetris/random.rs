@@ -0,0 +1,47 @@
+/// Small seeded generator (xorshift64*), the same seed always deals the same pieces
+#[derive(Debug, Clone, Copy)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        // Zero state would only ever produce zeros
+        Random {
+            state: seed ^ 0x9E37_79B9_7F4A_7C15,
+        }
+        .warmed()
+    }
+
+    fn warmed(mut self) -> Random {
+        if self.state == 0 {
+            self.state = 1;
+        }
+        self.next_u64();
+        self
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    // Number in 0..bound, bound has to be positive
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    // Fisher-Yates, every order equally likely
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
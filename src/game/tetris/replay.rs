@@ -0,0 +1,374 @@
+use crate::bootstrap::{Action, Game, Settings, Timestamp, TopOut};
+use crate::error::invalid;
+use crate::game::tetris::hmac;
+use crate::game::tetris::tetromino::RotationSystem;
+use crate::game::tetris::{Tetris, RULES_VERSION};
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+// Start of every replay file, so other files are not mistaken for one
+const MAGIC: &[u8; 4] = b"BRKR";
+// Bumped whenever the layout changes, files of every version up to it can be read
+pub const VERSION: u16 = 2;
+// Before the header had a length and said which build recorded it
+const LEGACY_VERSION: u16 = 1;
+
+/// Recorded game: settings, seed and every action with its time since the start.
+/// The core is deterministic, so that is all it takes to play the game again.
+/// The state hash at the end tells whether it still plays the same way.
+///
+/// The file is the same for every frontend, all numbers little endian:
+/// magic, version, header length, then the header with the build that recorded it
+/// as length and text, rules version, cols, rows, delay, seed, level, lock delay,
+/// clear delay, auto shift delay, repeat rate, entry delay, then the top-out rules,
+/// the rotation system and whether blocks are big as a byte each. After it come
+/// action count, actions as time and action byte, end time and state hash.
+/// Fields added to the end of the header later are skipped by older readers.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    // Crate version that recorded it, empty for files from before it was kept
+    pub build: String,
+    pub rules: u16,
+    pub settings: Settings,
+    pub seed: u64,
+    pub inputs: Vec<(Timestamp, Action)>,
+    // Time since the start when the recording stopped
+    pub end: Timestamp,
+    pub hash: u64,
+}
+
+impl Replay {
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.encode())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Replay> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Replay::decode(&bytes)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        let mut header = vec![];
+        let build = &self.build.as_bytes()[..self.build.len().min(u8::MAX as usize)];
+        header.push(build.len() as u8);
+        header.extend_from_slice(build);
+        header.extend_from_slice(&self.rules.to_le_bytes());
+        header.extend_from_slice(&self.settings.cols.to_le_bytes());
+        header.extend_from_slice(&self.settings.rows.to_le_bytes());
+        header.extend_from_slice(&self.settings.delay.to_le_bytes());
+        header.extend_from_slice(&self.seed.to_le_bytes());
+        header.extend_from_slice(&self.settings.level.to_le_bytes());
+        header.extend_from_slice(&self.settings.lock_delay.to_le_bytes());
+        header.extend_from_slice(&self.settings.clear_delay.to_le_bytes());
+        header.extend_from_slice(&self.settings.das.to_le_bytes());
+        header.extend_from_slice(&self.settings.arr.to_le_bytes());
+        header.extend_from_slice(&self.settings.entry_delay.to_le_bytes());
+        header.push(self.settings.top_out.to_byte());
+        header.push(self.settings.rotation.to_byte());
+        header.push(self.settings.big as u8);
+        out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for (time, action) in self.inputs.iter() {
+            out.extend_from_slice(&time.to_le_bytes());
+            out.push(*action as u8);
+        }
+        out.extend_from_slice(&self.end.to_le_bytes());
+        out.extend_from_slice(&self.hash.to_le_bytes());
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Replay> {
+        let mut input = bytes;
+        if take::<4>(&mut input)? != *MAGIC {
+            return Err(invalid("not a replay"));
+        }
+        let version = u16::from_le_bytes(take(&mut input)?);
+        let (build, rules, settings, seed) = match version {
+            LEGACY_VERSION => {
+                let (settings, seed) = decode_setup(&mut input)?;
+                // Replays only started to record the rules after the first ones
+                (String::new(), 1, settings, seed)
+            }
+            VERSION => {
+                let len = u16::from_le_bytes(take(&mut input)?) as usize;
+                if input.len() < len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                let (mut header, rest) = input.split_at(len);
+                input = rest;
+                let [build_len] = take::<1>(&mut header)?;
+                if header.len() < build_len as usize {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                let (build, rest) = header.split_at(build_len as usize);
+                header = rest;
+                let build = String::from_utf8_lossy(build).into_owned();
+                let rules = u16::from_le_bytes(take(&mut header)?);
+                let (mut settings, seed) = decode_setup(&mut header)?;
+                // Replays from before there were levels have none after the seed
+                if header.len() >= 4 {
+                    settings.level = u32::from_le_bytes(take(&mut header)?);
+                }
+                // Nor the delays after the level, each one came later than the last
+                if header.len() >= 8 {
+                    settings.lock_delay = u64::from_le_bytes(take(&mut header)?);
+                }
+                if header.len() >= 8 {
+                    settings.clear_delay = u64::from_le_bytes(take(&mut header)?);
+                }
+                if header.len() >= 16 {
+                    settings.das = u64::from_le_bytes(take(&mut header)?);
+                    settings.arr = u64::from_le_bytes(take(&mut header)?);
+                }
+                if header.len() >= 8 {
+                    settings.entry_delay = u64::from_le_bytes(take(&mut header)?);
+                }
+                if !header.is_empty() {
+                    let [byte] = take(&mut header)?;
+                    settings.top_out = TopOut::from_byte(byte);
+                }
+                if !header.is_empty() {
+                    let [byte] = take(&mut header)?;
+                    settings.rotation = RotationSystem::from_byte(byte);
+                }
+                if !header.is_empty() {
+                    let [byte] = take(&mut header)?;
+                    settings.big = byte != 0;
+                }
+                (build, rules, settings, seed)
+            }
+            version if version > VERSION => {
+                return Err(invalid(format!(
+                    "replay version {} is from a newer build, this one reads up to {}",
+                    version, VERSION
+                )))
+            }
+            version => {
+                return Err(invalid(format!(
+                    "replay version {} is not supported",
+                    version
+                )))
+            }
+        };
+        let count = u32::from_le_bytes(take(&mut input)?);
+        let mut inputs = vec![];
+        for _ in 0..count {
+            let time = Timestamp::from_le_bytes(take(&mut input)?);
+            let [byte] = take::<1>(&mut input)?;
+            let action = Action::from_byte(byte).ok_or_else(|| invalid("unknown action"))?;
+            inputs.push((time, action));
+        }
+        Ok(Replay {
+            build,
+            rules,
+            settings,
+            seed,
+            inputs,
+            end: Timestamp::from_le_bytes(take(&mut input)?),
+            hash: u64::from_le_bytes(take(&mut input)?),
+        })
+    }
+
+    // Plays the whole game again without drawing anything
+    pub fn play(&self) -> Tetris {
+        let mut tetris = Tetris::with_seed(self.settings, 0, self.seed);
+        for &(time, action) in self.inputs.iter() {
+            tetris.frame(time, Some(action));
+        }
+        tetris.frame(self.end, None);
+        tetris
+    }
+
+    // Whether playing it again ends up where the recording did, which other rules never do
+    pub fn verify(&self) -> bool {
+        self.rules == RULES_VERSION && self.play().state_hash() == self.hash
+    }
+
+    /// Signature of a run for leaderboards as hex: HMAC-SHA256 with `key` over the
+    /// seed, the inputs and the result, that is the whole recording and the score
+    pub fn sign(&self, key: &[u8], score: u32) -> String {
+        let mut message = self.encode();
+        message.extend_from_slice(&score.to_le_bytes());
+        hmac::hmac(key, &message)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Whether a submitted run is what it says: signed with `key` and playing out
+    /// to the recorded state and `score` here, for servers checking runs
+    pub fn verify_run(&self, key: &[u8], score: u32, signature: &str) -> bool {
+        let expected = self.sign(key, score);
+        hmac::same(
+            expected.as_bytes(),
+            signature.to_ascii_lowercase().as_bytes(),
+        ) && self.verify()
+            && self.play().score() == score
+    }
+
+    // Time of the last action, the game may have gone on a little longer
+    pub fn duration(&self) -> Timestamp {
+        self.inputs.last().map_or(0, |(time, _)| *time)
+    }
+}
+
+// Settings and seed, in the same layout in every version so far. The level, the
+// delays and the top-out rules came later, they are the usual level, none and
+// block-outs only until the header says otherwise.
+fn decode_setup(input: &mut &[u8]) -> io::Result<(Settings, u64)> {
+    let settings = Settings {
+        cols: u16::from_le_bytes(take(input)?),
+        rows: u16::from_le_bytes(take(input)?),
+        delay: Timestamp::from_le_bytes(take(input)?),
+        level: 1,
+        lock_delay: 0,
+        clear_delay: 0,
+        das: 0,
+        arr: 0,
+        entry_delay: 0,
+        top_out: TopOut::BLOCK_OUT,
+        rotation: RotationSystem::Srs,
+        big: false,
+    };
+    Ok((settings, u64::from_le_bytes(take(input)?)))
+}
+
+fn take<const N: usize>(input: &mut &[u8]) -> io::Result<[u8; N]> {
+    if input.len() < N {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (head, rest) = input.split_at(N);
+    *input = rest;
+    Ok(head.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay() -> Replay {
+        Replay {
+            build: "1.1.0".to_string(),
+            rules: RULES_VERSION,
+            settings: Settings {
+                cols: 10,
+                rows: 20,
+                delay: 500,
+                level: 5,
+                lock_delay: 500,
+                clear_delay: 200,
+                das: 150,
+                arr: 30,
+                entry_delay: 100,
+                top_out: TopOut::GUIDELINE,
+                rotation: RotationSystem::Ars,
+                big: true,
+            },
+            seed: 42,
+            inputs: vec![(120, Action::Left), (300, Action::Up), (940, Action::Drop)],
+            end: 1000,
+            hash: 0xdead_beef,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let written = replay();
+        let mut bytes = vec![];
+        written.write_to(&mut bytes).unwrap();
+        let read = Replay::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.build, written.build);
+        assert_eq!(read.rules, written.rules);
+        assert_eq!(read.seed, written.seed);
+        assert_eq!(read.inputs, written.inputs);
+        assert_eq!((read.end, read.hash), (written.end, written.hash));
+        let (settings, expected) = (read.settings, written.settings);
+        assert_eq!(
+            (settings.cols, settings.rows),
+            (expected.cols, expected.rows)
+        );
+        assert_eq!(settings.level, expected.level);
+        assert_eq!(settings.lock_delay, expected.lock_delay);
+        assert_eq!(settings.clear_delay, expected.clear_delay);
+        assert_eq!((settings.das, settings.arr), (expected.das, expected.arr));
+        assert_eq!(settings.entry_delay, expected.entry_delay);
+        assert_eq!(settings.top_out, expected.top_out);
+        assert_eq!(settings.rotation, expected.rotation);
+        assert!(settings.big);
+        assert_eq!(read.encode(), bytes);
+
+        let rotations = [
+            RotationSystem::Srs,
+            RotationSystem::Nes,
+            RotationSystem::Ars,
+        ];
+        for &rotation in rotations.iter() {
+            let mut written = replay();
+            written.settings.rotation = rotation;
+            let read = Replay::decode(&written.encode()).unwrap();
+            assert_eq!(read.settings.rotation, rotation);
+        }
+    }
+
+    // Big blocks are the last byte of the header, replays from before it have
+    // normal ones
+    #[test]
+    fn big_mode() {
+        for &big in [false, true].iter() {
+            let mut written = replay();
+            written.settings.big = big;
+            let read = Replay::decode(&written.encode()).unwrap();
+            assert_eq!(read.settings.big, big);
+        }
+
+        let mut bytes = replay().encode();
+        let at = MAGIC.len() + 2;
+        let len = u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize;
+        bytes[at..at + 2].copy_from_slice(&(len as u16 - 1).to_le_bytes());
+        bytes.remove(at + 2 + len - 1);
+        let read = Replay::decode(&bytes).unwrap();
+        assert!(!read.settings.big);
+        assert_eq!(read.settings.rotation, RotationSystem::Ars);
+        assert_eq!(read.inputs, replay().inputs);
+    }
+
+    #[test]
+    fn legacy() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&LEGACY_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&12u16.to_le_bytes());
+        bytes.extend_from_slice(&22u16.to_le_bytes());
+        bytes.extend_from_slice(&(400 as Timestamp).to_le_bytes());
+        bytes.extend_from_slice(&7u64.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&(250 as Timestamp).to_le_bytes());
+        bytes.push(Action::Right as u8);
+        bytes.extend_from_slice(&(600 as Timestamp).to_le_bytes());
+        bytes.extend_from_slice(&99u64.to_le_bytes());
+        let read = Replay::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.build, "");
+        assert_eq!(read.rules, 1);
+        assert_eq!((read.settings.cols, read.settings.rows), (12, 22));
+        assert_eq!(read.settings.delay, 400);
+        assert_eq!(read.settings.level, 1);
+        assert_eq!(read.settings.top_out, TopOut::BLOCK_OUT);
+        assert_eq!(read.settings.rotation, RotationSystem::Srs);
+        assert_eq!(read.seed, 7);
+        assert_eq!(read.inputs, vec![(250, Action::Right)]);
+        assert_eq!((read.end, read.hash), (600, 99));
+    }
+
+    #[test]
+    fn newer_version() {
+        let mut bytes = replay().encode();
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let error = Replay::read_from(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("newer build"));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::bootstrap::Timestamp;
+use crate::error::invalid;
+use crate::game::tetris::daily;
+use crate::game::tetris::replay::Replay;
+use crate::game::tetris::schema::{self, Migration};
+use std::io::{self, Read, Write};
+
+// Slots offered in the menu
+pub const SLOTS: usize = 4;
+
+// Layout changes since the first file, version 1 only added the header
+const MIGRATIONS: [Migration; 1] = [schema::unchanged];
+
+/// Suspended game. The replay up to the moment it was saved is all it takes to
+/// play it again to where it was left.
+///
+/// The file is the version header and a text line with mode, score and the time it was saved as
+/// milliseconds since the epoch, tab separated, followed by the replay. Reading
+/// one plays the replay again, saves that do not end on the recorded state are
+/// broken or from other rules and not read.
+#[derive(Debug, Clone)]
+pub struct Save {
+    pub mode: String,
+    pub score: u32,
+    pub time: Timestamp,
+    pub replay: Replay,
+}
+
+impl Save {
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        schema::write_header(writer, "save", &MIGRATIONS)?;
+        writeln!(writer, "{}\t{}\t{}", self.mode, self.score, self.time)?;
+        self.replay.write_to(writer)
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Save> {
+        let bytes = schema::read(reader, "save", &MIGRATIONS)?;
+        let broken = || invalid("broken save header");
+        let end = bytes.iter().position(|&b| b == b'\n').ok_or_else(broken)?;
+        let header = String::from_utf8_lossy(&bytes[..end]).into_owned();
+        let mut fields = header.split('\t');
+        let mode = fields.next().ok_or_else(broken)?.to_string();
+        let score = fields
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(broken)?;
+        let time = fields
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(broken)?;
+        let replay = Replay::decode(&bytes[end + 1..])?;
+        if !replay.verify() {
+            return Err(invalid("save does not play back to the saved game"));
+        }
+        Ok(Save {
+            mode,
+            score,
+            time,
+            replay,
+        })
+    }
+
+    // What the menus show about it
+    pub fn summary(&self) -> String {
+        format!("{} {} {}", self.mode, self.score, date(self.time))
+    }
+}
+
+/// Numbered slots, saving to one replaces what was there
+#[derive(Debug, Clone)]
+pub struct SaveSlots {
+    // Of the games saved from here on
+    mode: String,
+    slots: [Option<Save>; SLOTS],
+    // Saved to since they were loaded
+    changed: bool,
+}
+
+impl SaveSlots {
+    pub fn new(mode: &str) -> SaveSlots {
+        SaveSlots {
+            mode: mode.to_string(),
+            slots: Default::default(),
+            changed: false,
+        }
+    }
+
+    pub fn mode(&self) -> &str {
+        &self.mode
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&Save> {
+        self.slots.get(slot)?.as_ref()
+    }
+
+    // Loaded slots do not count as changes, saving in game does
+    pub fn set(&mut self, slot: usize, save: Save) {
+        if let Some(place) = self.slots.get_mut(slot) {
+            *place = Some(save);
+        }
+    }
+
+    pub fn save(&mut self, slot: usize, save: Save) {
+        self.set(slot, save);
+        self.changed = true;
+    }
+
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    pub fn name(slot: usize) -> String {
+        format!("Slot {}", slot + 1)
+    }
+}
+
+// UTC date and time of milliseconds since the epoch, like 2020-05-17 14:03
+pub fn date(time: Timestamp) -> String {
+    let (year, month, date) = daily::civil(daily::day(time));
+    let minutes = time / 60_000;
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        date,
+        minutes / 60 % 24,
+        minutes % 60
+    )
+}
@@ -0,0 +1,83 @@
+use crate::error::invalid;
+use std::io::{self, Read, Write};
+
+// Starts the first line of every versioned file
+const MAGIC: &str = "brick_game";
+
+/// Turns a file body of one version into the layout of the next
+pub type Migration = fn(Vec<u8>) -> Vec<u8>;
+
+/// Files the game keeps between runs start with a line like `brick_game bests 1`:
+/// what the file is and the version of its layout. Files written before there was
+/// one are version 0. Each kind has a migration per version bump, so its version
+/// is the number of migrations.
+pub fn write_header(
+    writer: &mut impl Write,
+    kind: &str,
+    migrations: &[Migration],
+) -> io::Result<()> {
+    writeln!(writer, "{} {} {}", MAGIC, kind, migrations.len())
+}
+
+/// Body of a file in the current layout, older ones go through the migrations
+/// since their version. Files of a later version are refused rather than read in
+/// part, writing them back would lose what this build does not know about.
+pub fn read(reader: &mut impl Read, kind: &str, migrations: &[Migration]) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    let (version, start) = header(&bytes, kind)?;
+    if version > migrations.len() {
+        return Err(invalid(format!(
+            "{} file of version {}, this build reads up to {}",
+            kind,
+            version,
+            migrations.len()
+        )));
+    }
+    let body = bytes.split_off(start);
+    Ok(migrations[version..]
+        .iter()
+        .fold(body, |body, migrate| migrate(body)))
+}
+
+/// Body of a text file in the current layout
+pub fn read_text(
+    reader: &mut impl Read,
+    kind: &str,
+    migrations: &[Migration],
+) -> io::Result<String> {
+    String::from_utf8(read(reader, kind, migrations)?).map_err(|error| invalid(error.to_string()))
+}
+
+/// For layouts that stayed the same apart from the header
+pub fn unchanged(body: Vec<u8>) -> Vec<u8> {
+    body
+}
+
+// Version and where the body starts
+fn header(bytes: &[u8], kind: &str) -> io::Result<(usize, usize)> {
+    let end = bytes
+        .iter()
+        .position(|&b| b == b'\n')
+        .unwrap_or(bytes.len());
+    let line = String::from_utf8_lossy(&bytes[..end]);
+    let mut words = line.trim_end().split(' ');
+    if words.next() != Some(MAGIC) {
+        return Ok((0, 0));
+    }
+    match words.next() {
+        Some(found) if found == kind => {}
+        found => {
+            return Err(invalid(format!(
+                "expected a {} file, got {}",
+                kind,
+                found.unwrap_or("none")
+            )))
+        }
+    }
+    let version = words
+        .next()
+        .and_then(|version| version.parse().ok())
+        .ok_or_else(|| invalid(format!("broken {} header", kind)))?;
+    Ok((version, (end + 1).min(bytes.len())))
+}
@@ -0,0 +1,79 @@
+use crate::bootstrap::{Cell, Renderable, Settings};
+use crate::error::invalid;
+use crate::game::tetris::metrics::Metrics;
+use std::io;
+
+// Cell values as letters of their pieces, garbage and anything else as X
+const LETTERS: &[u8; 8] = b".ITJLSZO";
+
+/// Board as text for sharing without a screenshot, one line per row with `.` for
+/// empty cells and piece letters for the stack
+pub fn board_text(field: &Renderable) -> String {
+    let mut text = String::new();
+    for row in field.iter() {
+        for &cell in row.iter() {
+            text.push(
+                LETTERS
+                    .get(cell.to_byte() as usize)
+                    .map_or('X', |&letter| letter as char),
+            );
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Board from text like `board_text` makes, for puzzles, cheese layouts and bug
+/// reports. Besides `.` and piece letters cells can be digits of their value or `X`
+/// for garbage. The board ends at the first empty line, so snapshots load too, and
+/// sits at the bottom of `settings` with narrower rows filled up with empty cells.
+pub fn parse_board(text: &str, settings: &Settings) -> io::Result<Renderable> {
+    let (cols, rows) = (settings.cols as usize, settings.rows as usize);
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim_end)
+        .take_while(|line| !line.is_empty())
+        .collect();
+    if lines.len() > rows {
+        return Err(invalid(format!("board has more than {} rows", rows)));
+    }
+    let mut field = Renderable(vec![vec![Cell::Empty; cols]; rows - lines.len()]);
+    for line in lines {
+        let mut row = vec![Cell::Empty; cols];
+        for (x, c) in line.chars().enumerate() {
+            let cell = match c.to_ascii_uppercase() {
+                '.' | ' ' => Cell::Empty,
+                '1'..='7' => Cell::Piece(c as u8 - b'0'),
+                upper if upper.is_ascii_digit() || upper == 'X' => Cell::Garbage,
+                upper => LETTERS
+                    .iter()
+                    .position(|&letter| letter as char == upper)
+                    .map(|id| Cell::Piece(id as u8))
+                    .ok_or_else(|| invalid(format!("unknown cell {:?} on the board", c)))?,
+            };
+            match row.get_mut(x) {
+                Some(slot) => *slot = cell,
+                None => return Err(invalid(format!("board has more than {} columns", cols))),
+            }
+        }
+        field.push(row);
+    }
+    Ok(field)
+}
+
+/// Final board followed by the numbers of the game, the empty line between them
+/// keeps the board apart
+pub fn snapshot(field: &Renderable, score: u32, metrics: &Metrics) -> String {
+    let seconds = metrics.elapsed / 1000;
+    format!(
+        "{}\nScore {}  Pieces {}  Time {}:{:02}\nPPS {:.2}  APM {:.1}  KPP {:.2}\n",
+        board_text(field),
+        score,
+        metrics.pieces,
+        seconds / 60,
+        seconds % 60,
+        metrics.pps(),
+        metrics.apm(),
+        metrics.kpp()
+    )
+}
@@ -0,0 +1,56 @@
+use crate::game::tetris::metrics::Metrics;
+
+// Piece names in the order of `Metrics::placed` and `Metrics::spawned`
+const PIECES: [&str; 7] = ["I", "T", "J", "L", "S", "Z", "O"];
+const CLEARS: [&str; 4] = ["single", "double", "triple", "tetris"];
+
+/// Summary of a finished game as JSON, for tracking progress outside the game.
+pub fn to_json(mode: &str, seed: u64, score: u32, level: u32, metrics: &Metrics) -> String {
+    let counts = |names: &[&str], values: &[u32]| {
+        let fields: Vec<String> = names
+            .iter()
+            .zip(values.iter())
+            .map(|(name, value)| format!("\"{}\":{}", name, value))
+            .collect();
+        fields.join(",")
+    };
+    format!(
+        concat!(
+            "{{\"mode\":\"{}\",\"seed\":{},\"score\":{},\"lines\":{},\"level\":{},",
+            "\"duration_ms\":{},\"pieces\":{},\"piece_counts\":{{{}}},",
+            "\"spawn_counts\":{{{}}},",
+            "\"clears\":{{{},\"tspin\":{}}},\"finesse_faults\":{},",
+            "\"pps\":{:.3},\"apm\":{:.3},\"kpp\":{:.3}}}\n"
+        ),
+        escape(mode),
+        seed,
+        score,
+        metrics.lines,
+        level,
+        metrics.elapsed,
+        metrics.pieces,
+        counts(&PIECES, &metrics.placed),
+        counts(&PIECES, &metrics.spawned),
+        counts(&CLEARS, &metrics.clears),
+        metrics.tspins,
+        metrics.finesse,
+        metrics.pps(),
+        metrics.apm(),
+        metrics.kpp()
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
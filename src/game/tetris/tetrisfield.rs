@@ -1,12 +1,19 @@
-use crate::bootstrap::Renderable;
 use crate::bootstrap::Settings;
-use crate::game::tetris::tetromino::Block;
+use crate::bootstrap::{Cell, Renderable};
+use crate::game::tetris::tetromino::{Block, Tetromino};
 use std::cmp::min;
 use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::iter::FromIterator;
 
+/// Board of the game with `HIDDEN_ROWS` above the rows that are shown. Rows are
+/// counted from the top of the visible ones, the hidden ones have negative `y`.
+#[derive(Clone)]
 pub struct TetrisField {
+    // Hidden rows first
     field: Renderable,
+    // Number of rows from the floor up to and including the topmost filled cell
+    heights: Vec<u16>,
 }
 
 impl TetrisField {
@@ -14,24 +21,119 @@ impl TetrisField {
         assert!(settings.rows >= 4);
         assert!(settings.cols >= 4);
         let field = Renderable(vec![
-            vec![0; settings.cols as usize];
-            settings.rows as usize
+            vec![Cell::Empty; settings.cols as usize];
+            (settings.rows + HIDDEN_ROWS) as usize
         ]);
-        TetrisField { field }
+        let heights = vec![0; settings.cols as usize];
+        TetrisField { field, heights }
     }
 
+    // Field with these visible cells already in it, like a puzzle setup
+    pub fn from_field(field: Renderable) -> TetrisField {
+        let cols = field[0].len();
+        let heights = vec![0; cols];
+        let mut rows = vec![vec![Cell::Empty; cols]; HIDDEN_ROWS as usize];
+        rows.extend(field.0);
+        let mut field = TetrisField {
+            field: Renderable(rows),
+            heights,
+        };
+        field.update_heights();
+        field
+    }
+
+    // Visible rows only, what renderers get
     pub fn field(&self) -> Renderable {
-        self.field.clone()
+        Renderable(self.field[HIDDEN_ROWS as usize..].to_vec())
+    }
+
+    // Rows above the visible ones, top first
+    pub fn hidden(&self) -> &[Vec<Cell>] {
+        &self.field[..HIDDEN_ROWS as usize]
+    }
+
+    // Cell at `y` counted from the top visible row, none outside of the field
+    fn cell(&self, x: i16, y: i16) -> Option<Cell> {
+        let row = self
+            .field
+            .get(usize::try_from(y + HIDDEN_ROWS as i16).ok()?)?;
+        row.get(usize::try_from(x).ok()?).copied()
+    }
+
+    fn rows(&self) -> i16 {
+        self.field.len() as i16 - HIDDEN_ROWS as i16
+    }
+
+    pub fn column_heights(&self) -> &[u16] {
+        &self.heights
+    }
+
+    /// Sum of all column heights
+    pub fn aggregate_height(&self) -> u32 {
+        self.heights.iter().map(|&h| h as u32).sum()
+    }
+
+    /// Empty cells having a filled cell somewhere above them
+    pub fn holes(&self) -> u32 {
+        let rows = self.field.len();
+        let mut holes = 0;
+        for (x, &height) in self.heights.iter().enumerate() {
+            for y in (rows - height as usize)..rows {
+                if !self.field[y][x].is_filled() {
+                    holes += 1;
+                }
+            }
+        }
+        holes
+    }
+
+    /// Sum of height differences between neighbouring columns
+    pub fn bumpiness(&self) -> u32 {
+        self.heights
+            .windows(2)
+            .map(|pair| (pair[0] as i32 - pair[1] as i32).unsigned_abs())
+            .sum()
+    }
+
+    /// Sum of well depths, where a well is a column lower than both of its neighbours.
+    /// Walls count as neighbours of infinite height.
+    pub fn wells(&self) -> u32 {
+        let wall = self.field.len() as u16;
+        let mut wells = 0;
+        for (x, &height) in self.heights.iter().enumerate() {
+            let left = if x == 0 { wall } else { self.heights[x - 1] };
+            let right = self.heights.get(x + 1).copied().unwrap_or(wall);
+            let edge = min(left, right);
+            if edge > height {
+                wells += (edge - height) as u32;
+            }
+        }
+        wells
+    }
+
+    /// Number of filled/empty changes along each row, walls count as filled
+    pub fn row_transitions(&self) -> u32 {
+        let mut transitions = 0;
+        for row in self.field.iter() {
+            let mut filled = true;
+            for &cell in row.iter().chain(std::iter::once(&Cell::Garbage)) {
+                if filled != cell.is_filled() {
+                    transitions += 1;
+                    filled = cell.is_filled();
+                }
+            }
+        }
+        transitions
     }
 
     pub fn field_with_block(&self, block: &Block) -> Renderable {
-        let mut field = self.field.clone();
+        let mut field = self.field();
         let shape = block.shape();
         for (y, row) in shape.iter().enumerate() {
             for (x, &cell) in row.iter().enumerate() {
                 let new_x = block.x + (x as i16);
                 let new_y = block.y + (y as i16);
-                if cell > 0 && self.in_bounds(new_x, new_y, false) {
+                if cell.is_filled() && self.in_bounds(new_x, new_y, false) {
                     field[new_y as usize][new_x as usize] = cell;
                 }
             }
@@ -39,41 +141,72 @@ impl TetrisField {
         field
     }
 
+    // Like `field_with_block`, with the cells of `hint` the block does not cover marked
+    pub fn field_with_hint(&self, block: &Block, hint: &Block) -> Renderable {
+        let mut field = self.field_with_block(block);
+        let hinted = self.field_with_block(hint);
+        for (row, hinted) in field.iter_mut().zip(hinted.iter()) {
+            for (cell, &hinted) in row.iter_mut().zip(hinted.iter()) {
+                if *cell == Cell::Empty && hinted.is_filled() {
+                    *cell = Cell::Ghost;
+                }
+            }
+        }
+        field
+    }
+
     pub fn has_collision(&self, block: &Block) -> bool {
         let shape = block.shape();
-        self.field
+        for (j, row) in shape.iter().enumerate() {
+            for (i, &cell) in row.iter().enumerate() {
+                let x = block.x + (i as i16);
+                let y = block.y + (j as i16);
+                // Cells outside of the field are has_overflow's business
+                if cell.is_filled() && self.cell(x, y).is_some_and(Cell::is_filled) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // Some cell of the block is above the visible rows
+    pub fn over_top(&self, block: &Block) -> bool {
+        let shape = block.shape();
+        shape
             .iter()
             .enumerate()
-            .map(|(j, row)| {
-                row.iter()
-                    .enumerate()
-                    .map(|(i, cell)| {
-                        let inner_x = i as isize - block.x as isize;
-                        let inner_y = j as isize - block.y as isize;
-                        let x_in = 0 <= inner_x && inner_x < shape.len() as isize;
-                        let y_in = 0 <= inner_y && inner_y < shape.len() as isize;
-                        if x_in && y_in {
-                            let element = shape[inner_y as usize][inner_x as usize];
-                            if element > 0 && *cell > 0 {
-                                Err(())
-                            } else {
-                                Ok(())
-                            }
-                        } else {
-                            Ok(())
-                        }
-                    })
-                    .collect()
+            .any(|(j, row)| block.y + (j as i16) < 0 && row.iter().any(|cell| cell.is_filled()))
+    }
+
+    // Pushing up `lines` of garbage would take filled cells over the hidden rows
+    pub fn overflows(&self, lines: u16) -> bool {
+        let rows = self.field.len() as u16;
+        self.heights.iter().any(|&height| height + lines > rows)
+    }
+
+    // Three of the four corners around the T center are taken, walls and floor count as taken
+    pub fn is_tspin(&self, block: &Block) -> bool {
+        if !matches!(block.tetromino, Tetromino::T(_)) {
+            return false;
+        }
+        let taken = [(0, 0), (2, 0), (0, 2), (2, 2)]
+            .iter()
+            .filter(|&&(i, j)| {
+                let (x, y) = (block.x + i * block.size, block.y + j * block.size);
+                !self.in_bounds(x, y, true) || self.cell(x, y).is_some_and(Cell::is_filled)
             })
-            .collect::<Result<(), ()>>()
-            .is_err()
+            .count();
+        taken >= 3
     }
 
     pub fn has_overflow(&self, block: &Block) -> bool {
         let shape = block.shape();
         for (j, row) in shape.iter().enumerate() {
             for (i, &cell) in row.iter().enumerate() {
-                if cell > 0 && !self.in_bounds(block.x + (i as i16), block.y + (j as i16), true) {
+                if cell.is_filled()
+                    && !self.in_bounds(block.x + (i as i16), block.y + (j as i16), true)
+                {
                     return true;
                 }
             }
@@ -81,36 +214,74 @@ impl TetrisField {
         false
     }
 
+    // Inside the visible rows, or anywhere above them as well when `relaxed`
     pub fn in_bounds(&self, x: i16, y: i16, relaxed: bool) -> bool {
-        (relaxed || 0 <= y)
-            && y < self.field.len() as i16
-            && 0 <= x
-            && x < self.field[0].len() as i16
+        (relaxed || 0 <= y) && y < self.rows() && 0 <= x && x < self.field[0].len() as i16
+    }
+
+    // Neither overflow nor collision, with the shape computed only once
+    fn fits(&self, block: &Block) -> bool {
+        let shape = block.shape();
+        for (j, row) in shape.iter().enumerate() {
+            for (i, &cell) in row.iter().enumerate() {
+                let (x, y) = (block.x + (i as i16), block.y + (j as i16));
+                if cell.is_filled()
+                    && (!self.in_bounds(x, y, true) || self.cell(x, y).is_some_and(Cell::is_filled))
+                {
+                    return false;
+                }
+            }
+        }
+        true
     }
 
     pub fn try_move(&self, block: &mut Block, x: i16, y: i16) -> bool {
         block.begin(block.x + x, block.y + y, block.rotation);
-        let ok = !self.has_overflow(&block) && !self.has_collision(&block);
+        let ok = self.fits(block);
         block.end(ok);
         ok
     }
 
-    pub fn try_rotate(&self, block: &mut Block) -> bool {
-        for (x, y) in block.tetromino.wallkick(block.rotation).into_iter() {
-            block.begin(
-                block.x + x,
-                block.y + y,
-                block.rotation.next(block.tetromino.dir()),
-            );
-            let ok = !self.has_overflow(&block) && !self.has_collision(&block);
+    pub fn try_rotate(&self, block: &mut Block, clockwise: bool) -> bool {
+        if !block.tetromino.turns() {
+            return false;
+        }
+        let kicks = block
+            .system
+            .kicks(block.tetromino, block.rotation, clockwise);
+        for (i, &(x, y)) in kicks.iter().enumerate() {
+            let (x, y) = (x * block.size, y * block.size);
+            block.begin(block.x + x, block.y + y, block.rotation.turn(clockwise));
+            let ok = self.fits(block);
+            let stuck = !ok
+                && i == 0
+                && block.system.center_column(block.tetromino)
+                && self.center_blocked(block);
             block.end(ok);
-            if ok {
+            if ok || stuck {
                 return ok;
             }
         }
         false
     }
 
+    // The first cell in the way of the block, reading its rows from the top, is in
+    // the middle column of its box
+    fn center_blocked(&self, block: &Block) -> bool {
+        let shape = block.shape();
+        for (j, row) in shape.iter().enumerate() {
+            for (i, &cell) in row.iter().enumerate() {
+                let (x, y) = (block.x + (i as i16), block.y + (j as i16));
+                if cell.is_filled()
+                    && (!self.in_bounds(x, y, true) || self.cell(x, y).is_some_and(Cell::is_filled))
+                {
+                    return i as i16 / block.size == 1;
+                }
+            }
+        }
+        false
+    }
+
     pub fn drop(&self, block: &mut Block) -> i16 {
         let altitude = self.altitude(block);
         block.y += altitude;
@@ -119,25 +290,31 @@ impl TetrisField {
 
     pub fn altitude(&self, block: &Block) -> i16 {
         let shape = block.shape();
-        let len = shape.len() as usize;
+        let len = shape.len();
         let mut altitude = self.field.len() as i16;
 
         // For each column of falling tetromino
         for i in 0..len {
             // Get the lowest cell of column
+            // Counted from the top hidden row like the heights
             let mut lowest: Option<i16> = None;
             for (j, _) in shape.iter().enumerate() {
-                if shape[j][i] > 0 {
-                    lowest = Some(j as i16 + block.y)
+                if shape[j][i].is_filled() {
+                    lowest = Some(j as i16 + block.y + HIDDEN_ROWS as i16)
                 }
             }
             if let Some(lowest) = lowest {
-                // Then the highest cell of ground but lower than tetromino
-                let mut highest = self.field.len() as i16;
-                for y in (lowest as usize)..self.field.len() {
-                    if self.field[y][(block.x + i as i16) as usize] > 0 {
-                        highest = y as i16;
-                        break;
+                let x = (block.x + i as i16) as usize;
+                // Top of the stack is below the block, so it lands right on it
+                let mut highest = self.field.len() as i16 - self.heights[x] as i16;
+                if highest <= lowest {
+                    // Block was tucked under an overhang, look for the ground beneath it
+                    highest = self.field.len() as i16;
+                    for y in (lowest.max(0) as usize)..self.field.len() {
+                        if self.field[y][x].is_filled() {
+                            highest = y as i16;
+                            break;
+                        }
                     }
                 }
 
@@ -148,41 +325,222 @@ impl TetrisField {
         altitude
     }
 
-    // Returns number of dropped line
-    pub fn consume(&mut self, block: Block) -> u16 {
+    // Copy where `block` has landed, without clearing lines. Used to let
+    // a falling block stand in the way of another.
+    pub fn with_block(&self, block: &Block) -> TetrisField {
+        let mut field = self.clone();
+        field.fill(block);
+        field
+    }
+
+    // Returns indices of dropped lines
+    pub fn consume(&mut self, block: Block) -> Vec<u16> {
+        let filled = self.place(block);
+        self.remove_lines(&filled);
+        filled
+    }
+
+    // Like `consume` but the filled rows stay until `remove_lines`, returns them
+    pub fn place(&mut self, block: Block) -> Vec<u16> {
+        let affected_lines = self.fill(&block);
+        self.filled_rows(affected_lines)
+    }
+
+    // Puts the cells of the block into the field, returns the visible rows it touched.
+    // Hidden rows are never cleared.
+    fn fill(&mut self, block: &Block) -> Vec<u16> {
         let shape = block.shape();
         let mut affected_lines = HashSet::new();
         for (j, row) in shape.iter().enumerate() {
             for (i, &cell) in row.iter().enumerate() {
                 let x = block.x + (i as i16);
                 let y = block.y + (j as i16);
-                if cell > 0 && self.in_bounds(x, y, false) {
-                    affected_lines.insert(y as u16);
-                    self.field[y as usize][x as usize] = cell;
+                let index = y + HIDDEN_ROWS as i16;
+                if cell.is_filled() && index >= 0 && self.in_bounds(x, y, true) {
+                    if y >= 0 {
+                        affected_lines.insert(y as u16);
+                    }
+                    self.field[index as usize][x as usize] = cell;
+                    let height = self.field.len() as u16 - index as u16;
+                    if self.heights[x as usize] < height {
+                        self.heights[x as usize] = height;
+                    }
                 }
             }
         }
         let mut affected_lines = Vec::from_iter(affected_lines);
         affected_lines.sort();
-        self.check_filled(affected_lines)
+        affected_lines
     }
 
-    // Returns number of dropped line
-    pub fn check_filled(&mut self, lines: Vec<u16>) -> u16 {
-        let mut drop = vec![];
-        'lines: for line in lines.iter() {
-            for i in 0..self.field[*line as usize].len() {
-                if self.field[*line as usize][i] == 0 {
-                    continue 'lines;
-                }
-            }
-            drop.push(*line);
-        }
-        for line in drop.iter() {
-            self.field.remove(*line as usize);
+    // Those of `lines` without a gap
+    fn filled_rows(&self, lines: Vec<u16>) -> Vec<u16> {
+        lines
+            .into_iter()
+            .filter(|&line| {
+                self.field[(line + HIDDEN_ROWS) as usize]
+                    .iter()
+                    .all(|cell| cell.is_filled())
+            })
+            .collect()
+    }
+
+    // Takes out the rows in ascending order, the stack above falls down
+    pub fn remove_lines(&mut self, lines: &[u16]) {
+        for &line in lines.iter() {
+            self.field.remove((line + HIDDEN_ROWS) as usize);
             let len = self.field[0].len();
-            self.field.insert(0, vec![0; len])
+            self.field.insert(0, vec![Cell::Empty; len])
+        }
+        if !lines.is_empty() {
+            self.update_heights();
+        }
+    }
+
+    // Pushes the stack up and fills the bottom with rows having a single hole.
+    // Whatever is pushed above the hidden rows is lost.
+    pub fn add_garbage(&mut self, lines: u16, hole: u16) {
+        let cols = self.field[0].len();
+        let lines = min(lines as usize, self.field.len());
+        self.field.drain(0..lines);
+        for _ in 0..lines {
+            let mut row = vec![Cell::Garbage; cols];
+            row[hole as usize % cols] = Cell::Empty;
+            self.field.push(row);
         }
-        drop.len() as u16
+        self.update_heights();
+    }
+
+    // Rows with some garbage left in them
+    pub fn garbage_rows(&self) -> u16 {
+        self.field
+            .iter()
+            .filter(|row| row.contains(&Cell::Garbage))
+            .count() as u16
+    }
+
+    // Empties a row without moving the others, negative for the hidden ones
+    pub fn clear_row(&mut self, y: i16) {
+        let index = usize::try_from(y + HIDDEN_ROWS as i16);
+        if let Some(row) = index.ok().and_then(|index| self.field.get_mut(index)) {
+            row.iter_mut().for_each(|cell| *cell = Cell::Empty);
+            self.update_heights();
+        }
+    }
+
+    // Takes away the bottom rows, the stack falls down and empty rows come in on top
+    pub fn remove_rows(&mut self, lines: u16) {
+        let cols = self.field[0].len();
+        let rows = self.field.len();
+        let lines = min(lines as usize, rows);
+        self.field.truncate(rows - lines);
+        for _ in 0..lines {
+            self.field.insert(0, vec![Cell::Empty; cols]);
+        }
+        self.update_heights();
+    }
+
+    // Rescans the whole field, needed when rows move or disappear
+    fn update_heights(&mut self) {
+        let field = &self.field;
+        let rows = field.len();
+        for (x, height) in self.heights.iter_mut().enumerate() {
+            let top = (0..rows).find(|&y| field[y][x].is_filled()).unwrap_or(rows);
+            *height = (rows - top) as u16;
+        }
+    }
+}
+
+/// Rows above the visible field that blocks can spawn, turn and lock in
+pub const HIDDEN_ROWS: u16 = 2;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootstrap::TopOut;
+    use crate::game::tetris::tetromino::{RotationSystem, I, T};
+
+    const SETTINGS: Settings = Settings {
+        cols: 10,
+        rows: 20,
+        delay: 500,
+        level: 1,
+        lock_delay: 500,
+        clear_delay: 0,
+        das: 0,
+        arr: 0,
+        entry_delay: 0,
+        top_out: TopOut::GUIDELINE,
+        rotation: RotationSystem::Srs,
+        big: false,
+    };
+
+    // The flat I fills the second row of its box, the T the top middle and the
+    // second row of its own
+    fn block(tetromino: Tetromino, x: i16, y: i16) -> Block {
+        let mut block = Block::spawn(tetromino, &SETTINGS);
+        block.x = x;
+        block.y = y;
+        block
+    }
+
+    // Empty field with garbage in the visible cells given as (x, y)
+    fn field_with(cells: &[(usize, usize)]) -> TetrisField {
+        let mut rows = vec![vec![Cell::Empty; SETTINGS.cols as usize]; SETTINGS.rows as usize];
+        for &(x, y) in cells {
+            rows[y][x] = Cell::Garbage;
+        }
+        TetrisField::from_field(Renderable(rows))
+    }
+
+    #[test]
+    fn walls() {
+        let field = TetrisField::new(&SETTINGS);
+        let left = block(Tetromino::from(I()), 0, 5);
+        assert!(!field.has_collision(&left) && !field.has_overflow(&left));
+        let right = block(Tetromino::from(I()), 6, 5);
+        assert!(!field.has_collision(&right) && !field.has_overflow(&right));
+        // Cells past a wall are overflow, there is nothing there to collide with
+        for x in [-1, 7] {
+            let outside = block(Tetromino::from(I()), x, 5);
+            assert!(!field.has_collision(&outside));
+            assert!(field.has_overflow(&outside));
+        }
+    }
+
+    #[test]
+    fn floor() {
+        let field = TetrisField::new(&SETTINGS);
+        let bottom = block(Tetromino::from(I()), 3, 18);
+        assert!(!field.has_collision(&bottom) && !field.has_overflow(&bottom));
+        let below = block(Tetromino::from(I()), 3, 19);
+        assert!(!field.has_collision(&below));
+        assert!(field.has_overflow(&below));
+    }
+
+    #[test]
+    fn hidden_rows() {
+        let mut field = TetrisField::new(&SETTINGS);
+        let top = -(HIDDEN_ROWS as i16);
+        let hidden = block(Tetromino::from(I()), 3, top - 1);
+        assert!(!field.has_collision(&hidden) && !field.has_overflow(&hidden));
+        // Nothing is above the hidden rows, a block there is only out of sight
+        let above = block(Tetromino::from(I()), 3, top - 5);
+        assert!(!field.has_collision(&above) && !field.has_overflow(&above));
+        field.place(hidden);
+        assert!(field.has_collision(&hidden));
+        assert!(!field.has_collision(&block(Tetromino::from(I()), 3, top)));
+    }
+
+    #[test]
+    fn locked_cells() {
+        let field = field_with(&[(4, 10)]);
+        assert!(field.has_collision(&block(Tetromino::from(I()), 3, 9)));
+        assert!(field.has_collision(&block(Tetromino::from(I()), 1, 9)));
+        assert!(!field.has_collision(&block(Tetromino::from(I()), 0, 9)));
+        assert!(!field.has_collision(&block(Tetromino::from(I()), 3, 8)));
+        // Only the cells of the shape count, not the empty corners of its box
+        assert!(field.has_collision(&block(Tetromino::from(T()), 3, 10)));
+        assert!(!field.has_collision(&block(Tetromino::from(T()), 4, 10)));
     }
 }
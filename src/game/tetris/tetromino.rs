@@ -1,8 +1,15 @@
-use crate::bootstrap::{Renderable, Settings};
+use crate::bootstrap::{Cell, Renderable, Settings};
+use std::iter;
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
 
+#[derive(Clone, Copy)]
 pub struct Block {
     pub tetromino: Tetromino,
     pub rotation: Rotation,
+    pub system: RotationSystem,
+    // Cells every mino is wide and high, 2 in big games
+    pub size: i16,
     pub x: i16,
     pub y: i16,
     saved_rotation: Option<Rotation>,
@@ -13,11 +20,14 @@ pub struct Block {
 impl Block {
     pub fn spawn(tetromino: Tetromino, settings: &Settings) -> Block {
         let rotation = Rotation::Default;
-        let y = -1;
-        let x = settings.cols as i16 / 2 - (tetromino.shape().len() as i16 + 1) / 2;
+        let size = if settings.big { 2 } else { 1 };
+        let y = -size;
+        let x = settings.cols as i16 / 2 - (tetromino.shape().len() as i16 * size + 1) / 2;
         Block {
             tetromino,
             rotation,
+            system: settings.rotation,
+            size,
             x,
             y,
             saved_rotation: None,
@@ -29,8 +39,9 @@ impl Block {
     pub fn shape(&self) -> Renderable {
         let shape = self.tetromino.shape();
         let len = shape.len() as u8;
-        let mut result = Renderable(vec![vec![0u8; len as usize]; len as usize]);
-        let transform: Box<dyn Fn(u8, u8) -> (u8, u8)> = match self.rotation {
+        let mut result = Renderable(vec![vec![Cell::Empty; len as usize]; len as usize]);
+        let (rotation, lower) = self.system.orientation(self.tetromino, self.rotation);
+        let transform: Box<dyn Fn(u8, u8) -> (u8, u8)> = match rotation {
             Rotation::Default => Box::new(|x, y| (x, y)),
             Rotation::CCW => Box::new(|x, y| (y, len - x - 1)),
             Rotation::Reverse => Box::new(|x, y| (len - x - 1, len - y - 1)),
@@ -39,9 +50,21 @@ impl Block {
         for i in 0..len {
             for j in 0..len {
                 let (x, y) = &transform(i, j);
-                result[i as usize][j as usize] = shape[*x as usize][*y as usize]
+                result[i as usize][j as usize] = Cell::from_byte(shape[*x as usize][*y as usize])
             }
         }
+        result.0.rotate_right(lower);
+        if self.size > 1 {
+            let size = self.size as usize;
+            let rows = result.iter().flat_map(|row| {
+                let row: Vec<Cell> = row
+                    .iter()
+                    .flat_map(|&cell| iter::repeat_n(cell, size))
+                    .collect();
+                iter::repeat_n(row, size)
+            });
+            result = Renderable(rows.collect());
+        }
         result
     }
 
@@ -84,13 +107,7 @@ impl Block {
     }
 }
 
-#[derive(Copy, Clone)]
-pub enum Direction {
-    None,
-    Half,
-    Full,
-}
-
+// In the order of clockwise turns from the spawn rotation, 0, R, 2 and L in SRS
 #[derive(Copy, Clone)]
 pub enum Rotation {
     Default,
@@ -100,22 +117,22 @@ pub enum Rotation {
 }
 
 impl Rotation {
-    pub fn next(self, dir: Direction) -> Self {
-        match (dir, self) {
-            (Direction::None, Rotation::Default) => Rotation::Default,
-
-            (Direction::Half, Rotation::Default) => Rotation::CCW,
-            (Direction::Half, Rotation::CCW) => Rotation::Default,
-
-            (Direction::Full, Rotation::Default) => Rotation::CW,
-            (Direction::Full, Rotation::CW) => Rotation::Reverse,
-            (Direction::Full, Rotation::Reverse) => Rotation::CCW,
-            (Direction::Full, Rotation::CCW) => Rotation::Default,
-            _ => unreachable!(),
+    // A quarter turn clockwise, or counterclockwise when not
+    pub fn turn(self, clockwise: bool) -> Self {
+        match (clockwise, self) {
+            (true, Rotation::Default) => Rotation::CW,
+            (true, Rotation::CW) => Rotation::Reverse,
+            (true, Rotation::Reverse) => Rotation::CCW,
+            (true, Rotation::CCW) => Rotation::Default,
+            (false, Rotation::Default) => Rotation::CCW,
+            (false, Rotation::CCW) => Rotation::Reverse,
+            (false, Rotation::Reverse) => Rotation::CW,
+            (false, Rotation::CW) => Rotation::Default,
         }
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum Tetromino {
     I(I),
     T(T),
@@ -127,6 +144,19 @@ pub enum Tetromino {
 }
 
 impl Tetromino {
+    // One of each, in the order bags are filled
+    pub fn all() -> [Tetromino; 7] {
+        [
+            Tetromino::from(I()),
+            Tetromino::from(T()),
+            Tetromino::from(J()),
+            Tetromino::from(L()),
+            Tetromino::from(S()),
+            Tetromino::from(Z()),
+            Tetromino::from(O()),
+        ]
+    }
+
     pub fn shape(&self) -> &[&[u8]] {
         match &self {
             Self::I(_) => I::SHAPE,
@@ -139,34 +169,50 @@ impl Tetromino {
         }
     }
 
-    pub fn dir(&self) -> Direction {
+    // Letter the piece is known by
+    pub fn name(&self) -> char {
         match &self {
-            Self::T(_) | Self::L(_) | Self::J(_) => Direction::Full,
-            Self::I(_) | Self::S(_) | Self::Z(_) => Direction::Half,
-            Self::O(_) => Direction::None,
+            Self::I(_) => 'I',
+            Self::T(_) => 'T',
+            Self::J(_) => 'J',
+            Self::L(_) => 'L',
+            Self::S(_) => 'S',
+            Self::Z(_) => 'Z',
+            Self::O(_) => 'O',
         }
     }
 
-    pub fn wallkick(&self, rotation: Rotation) -> Vec<(i16, i16)> {
-        match &self {
-            Self::T(_) | Self::L(_) | Self::J(_) => match rotation {
-                Rotation::Default => vec![(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
-                Rotation::CW => vec![(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
-                Rotation::Reverse => vec![(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
-                Rotation::CCW => vec![(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
-            },
-            Self::S(_) | Self::Z(_) => match rotation {
-                Rotation::Default => vec![(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
-                Rotation::CCW => vec![(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
-                _ => unreachable!(),
-            },
-            Self::I(_) => match rotation {
-                Rotation::Default => vec![(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
-                Rotation::CCW => vec![(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
-                _ => unreachable!(),
-            },
-            Self::O(_) => vec![(0, 0)],
-        }
+    // Number the cells of this block have on the board
+    pub fn id(&self) -> u8 {
+        self.shape()
+            .iter()
+            .flat_map(|row| row.iter())
+            .copied()
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Piece whose cells have this number, reverse of `id`
+    pub fn from_id(id: u8) -> Option<Tetromino> {
+        Tetromino::all()
+            .iter()
+            .copied()
+            .find(|piece| piece.id() == id)
+    }
+
+    // Every piece but the O has four rotations
+    pub fn turns(&self) -> bool {
+        !matches!(self, Self::O(_))
+    }
+
+    // Offsets tried in order when the block turns away from `rotation` in SRS
+    pub fn wallkick(&self, rotation: Rotation, clockwise: bool) -> &'static [(i16, i16)] {
+        let kicks = match &self {
+            Self::I(_) => &I_KICKS,
+            Self::O(_) => return NO_KICKS,
+            _ => &JLSTZ_KICKS,
+        };
+        kicks[!clockwise as usize][rotation as usize]
     }
 
     pub fn preview(&self) -> Renderable {
@@ -174,27 +220,170 @@ impl Tetromino {
             self.shape()
                 .iter()
                 .map(|v| {
-                    let mut vec = v.to_vec();
+                    let mut vec: Vec<Cell> = v.iter().copied().map(Cell::from_byte).collect();
                     if vec.len() < 3 {
-                        vec.insert(0, 0)
+                        vec.insert(0, Cell::Empty)
                     }
-                    vec.resize(4, 0);
+                    vec.resize(4, Cell::Empty);
                     vec
                 })
                 .collect(),
         );
         if v.len() < 4 {
-            v.insert(0, vec![0u8, 0u8, 0u8, 0u8]);
+            v.insert(0, vec![Cell::Empty; 4]);
         }
-        v.resize(4, vec![0u8, 0u8, 0u8, 0u8]);
+        v.resize(4, vec![Cell::Empty; 4]);
         v
     }
 }
 
+/// How blocks look in each rotation and where they go when turning into the wall
+/// or the stack, see `Settings::rotation`
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RotationSystem {
+    // Guideline rules, blocks come in pointing up and kick a long way
+    Srs,
+    // Like the NES, blocks come in pointing down and never kick. The I, S and Z
+    // only have two rotations.
+    Nes,
+    // Like the arcade, pointing down and resting on the bottom of their box. Blocks
+    // kick one column right or left, the I never.
+    Ars,
+}
+
+impl RotationSystem {
+    // Rotation of the SRS shape that looks like `rotation` here, and how many rows
+    // lower it sits
+    fn orientation(self, tetromino: Tetromino, rotation: Rotation) -> (Rotation, usize) {
+        // The I, S and Z lie down in even rotations and stand up in odd ones
+        let flat = (rotation as u8).is_multiple_of(2);
+        let two_way =
+            |lying: Rotation, standing: Rotation| (if flat { lying } else { standing }, 0);
+        match (self, tetromino) {
+            (RotationSystem::Srs, _) | (_, Tetromino::O(_)) => (rotation, 0),
+            (RotationSystem::Nes, Tetromino::I(_) | Tetromino::S(_) | Tetromino::Z(_))
+            | (RotationSystem::Ars, Tetromino::Z(_)) => two_way(Rotation::Reverse, Rotation::CW),
+            (RotationSystem::Ars, Tetromino::S(_)) => two_way(Rotation::Reverse, Rotation::CCW),
+            (RotationSystem::Ars, Tetromino::I(_)) => two_way(Rotation::Default, Rotation::CW),
+            // The J, L and T come in the other way round
+            (RotationSystem::Nes, _) => (rotation.turn(true).turn(true), 0),
+            (RotationSystem::Ars, _) => match rotation.turn(true).turn(true) {
+                Rotation::Default => (Rotation::Default, 1),
+                turned => (turned, 0),
+            },
+        }
+    }
+
+    // Offsets tried in order when a block turns away from `rotation`
+    pub fn kicks(
+        self,
+        tetromino: Tetromino,
+        rotation: Rotation,
+        clockwise: bool,
+    ) -> &'static [(i16, i16)] {
+        match (self, tetromino) {
+            (RotationSystem::Srs, _) => tetromino.wallkick(rotation, clockwise),
+            (RotationSystem::Nes, _) | (_, Tetromino::I(_) | Tetromino::O(_)) => NO_KICKS,
+            (RotationSystem::Ars, _) => ARS_KICKS,
+        }
+    }
+
+    // The arcade does not kick a J, L or T whose way is blocked in its middle column
+    pub fn center_column(self, tetromino: Tetromino) -> bool {
+        self == RotationSystem::Ars
+            && matches!(
+                tetromino,
+                Tetromino::T(_) | Tetromino::J(_) | Tetromino::L(_)
+            )
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RotationSystem::Srs => "srs",
+            RotationSystem::Nes => "nes",
+            RotationSystem::Ars => "ars",
+        }
+    }
+
+    // For replays and checksums
+    pub fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    // Unknown bytes are taken for SRS
+    pub fn from_byte(byte: u8) -> RotationSystem {
+        match byte {
+            1 => RotationSystem::Nes,
+            2 => RotationSystem::Ars,
+            _ => RotationSystem::Srs,
+        }
+    }
+}
+
+/// Parses `srs`, `nes` or `ars`
+impl FromStr for RotationSystem {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<RotationSystem, String> {
+        [
+            RotationSystem::Srs,
+            RotationSystem::Nes,
+            RotationSystem::Ars,
+        ]
+        .iter()
+        .copied()
+        .find(|system| text.eq_ignore_ascii_case(system.name()))
+        .ok_or_else(|| format!("unknown rotation system {}, try srs, nes or ars", text))
+    }
+}
+
+// SRS wall kick offsets tried in order, clockwise turns first and counterclockwise
+// ones second, each by the rotation the block leaves. Rows count downwards like on
+// the board, the guideline tables have y the other way round.
+#[rustfmt::skip]
+const JLSTZ_KICKS: [[&[(i16, i16)]; 4]; 2] = [
+    [
+        &[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+        &[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+        &[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+        &[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    ],
+    [
+        &[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+        &[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+        &[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+        &[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    ],
+];
+
+#[rustfmt::skip]
+const I_KICKS: [[&[(i16, i16)]; 4]; 2] = [
+    [
+        &[(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+        &[(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+        &[(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+        &[(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+    ],
+    [
+        &[(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+        &[(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+        &[(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+        &[(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+    ],
+];
+
+// Turning in place or not at all
+const NO_KICKS: &[(i16, i16)] = &[(0, 0)];
+
+const ARS_KICKS: &[(i16, i16)] = &[(0, 0), (1, 0), (-1, 0)];
+
 pub trait Figure {
     const SHAPE: &'static [&'static [u8]];
 }
 
+#[derive(Clone, Copy)]
 pub struct I();
 
 impl Figure for I {
@@ -213,6 +402,7 @@ impl From<I> for Tetromino {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct T();
 
 impl Figure for T {
@@ -230,6 +420,7 @@ impl From<T> for Tetromino {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct J();
 
 impl Figure for J {
@@ -247,6 +438,7 @@ impl From<J> for Tetromino {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct L();
 
 impl Figure for L {
@@ -264,6 +456,7 @@ impl From<L> for Tetromino {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct S();
 
 impl Figure for S {
@@ -281,6 +474,7 @@ impl From<S> for Tetromino {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Z();
 
 impl Figure for Z {
@@ -298,6 +492,7 @@ impl From<Z> for Tetromino {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct O();
 
 impl Figure for O {
@@ -0,0 +1,247 @@
+use crate::bootstrap::{Action, Game, GameChange, MenuItem, MenuMode, Settings, Timestamp};
+use crate::game::tetris::ai::Bot;
+use crate::game::tetris::attack::{AttackTable, Targeting};
+use crate::game::tetris::handicap::Handicap;
+use crate::game::tetris::random::Random;
+use crate::game::tetris::{menu_action, MenuOutcome, Tetris, TetrisPause};
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlayerAction {
+    pub player: usize,
+    pub action: Action,
+}
+
+/// Two boards side by side, lines cleared on one board come as garbage to the other.
+/// A match can go over several rounds, it is won by the first to win `first_to` of them.
+pub struct Versus {
+    players: Vec<Tetris>,
+    // Players controlled by the computer, their keyboard input is ignored
+    bots: Vec<Option<Bot>>,
+    menu: Option<MenuMode<TetrisPause>>,
+    // The boards do not see the menu, the time it was open is left out of theirs
+    paused: Option<Timestamp>,
+    first_to: u32,
+    wins: Vec<u32>,
+    // Player whose board goes last every frame. When both lock at the same time
+    // that player already sees the incoming garbage and can cancel it. Changes hands
+    // every round.
+    advantage: usize,
+    targeting: Targeting,
+    random: Random,
+}
+
+impl Versus {
+    pub fn new(settings: Settings, start: Timestamp) -> Self {
+        Versus {
+            players: vec![Tetris::new(settings, start), Tetris::new(settings, start)],
+            bots: vec![None, None],
+            menu: None,
+            paused: None,
+            first_to: 1,
+            wins: vec![0, 0],
+            advantage: 1,
+            targeting: Targeting::default(),
+            random: Random::new(start),
+        }
+    }
+
+    // Only makes a difference with more than one opponent
+    pub fn with_targeting(mut self, targeting: Targeting) -> Self {
+        self.targeting = targeting;
+        self
+    }
+
+    pub fn with_first_to(mut self, wins: u32) -> Self {
+        self.first_to = wins.max(1);
+        self
+    }
+
+    pub fn with_bot(mut self, player: usize, bot: Bot) -> Self {
+        self.bots[player] = Some(bot);
+        self
+    }
+
+    pub fn with_handicap(mut self, player: usize, handicap: Handicap) -> Self {
+        self.players[player].set_handicap(handicap);
+        self
+    }
+}
+
+impl Game for Versus {
+    type Pause = TetrisPause;
+    type Input = PlayerAction;
+
+    fn frame(
+        &mut self,
+        now: Timestamp,
+        input: Option<PlayerAction>,
+    ) -> GameChange<'_, TetrisPause> {
+        let action = input.map(|input| input.action);
+        let mut frames = vec![];
+        if self.menu.is_some() {
+            self.paused.get_or_insert(now);
+        }
+        match &mut self.menu {
+            Some(menu) => match menu_action(menu, action) {
+                MenuOutcome::Continue => self.resume(now),
+                MenuOutcome::Restart => return GameChange::Restart,
+                MenuOutcome::Exit => return GameChange::Exit,
+                MenuOutcome::Changed | MenuOutcome::Selected(_) => {}
+                MenuOutcome::Unchanged => return GameChange::Idle,
+            },
+            None if matches!(action, Some(Action::Escape)) => self.pause(),
+            None => {
+                let mut changed = false;
+                let count = self.players.len();
+                let advantage = self.advantage;
+                let mut outputs: Vec<_> = (0..count).map(|_| None).collect();
+                for i in (1..=count).map(|turn| (advantage + turn) % count) {
+                    let tetris = &mut self.players[i];
+                    let action = match &mut self.bots[i] {
+                        Some(bot) => bot.act(tetris, now),
+                        None => input
+                            .filter(|input| input.player == i)
+                            .map(|input| input.action),
+                    };
+                    let frame = match tetris.frame(now, action) {
+                        GameChange::Draw(frame) => Some(*frame),
+                        _ => None,
+                    };
+                    changed |= frame.is_some();
+                    outputs[i] = Some(frame.unwrap_or_else(|| tetris.output()));
+                    self.send_garbage(i);
+                }
+                frames = outputs.into_iter().flatten().collect();
+                if let Some(loser) = self.players.iter().position(|tetris| tetris.is_over()) {
+                    self.round_over(loser);
+                } else if !changed {
+                    return GameChange::Idle;
+                }
+            }
+        }
+        match self.menu {
+            Some(ref menu) => GameChange::Text(menu),
+            None if frames.is_empty() => GameChange::Split(
+                self.players
+                    .iter_mut()
+                    .map(|tetris| tetris.output())
+                    .collect(),
+            ),
+            None => GameChange::Split(frames),
+        }
+    }
+
+    fn pause(&mut self) {
+        if self.menu.is_none() {
+            self.menu = Some(Tetris::pause_menu());
+        }
+    }
+
+    // A whole new match
+    fn restart(&mut self, now: Timestamp) {
+        self.wins.iter_mut().for_each(|wins| *wins = 0);
+        self.advantage = 1;
+        self.next_round(now);
+    }
+}
+
+impl Versus {
+    fn resume(&mut self, now: Timestamp) {
+        // Continue after a round means the next one, unless the match is decided
+        if self.players.iter().any(|tetris| tetris.is_over()) {
+            if !self.match_over() {
+                self.next_round(now);
+            }
+            return;
+        }
+        self.menu = None;
+        let paused = now.saturating_sub(self.paused.take().unwrap_or(now));
+        for tetris in self.players.iter_mut() {
+            tetris.skip(paused);
+            tetris.invalidate();
+        }
+    }
+
+    fn next_round(&mut self, now: Timestamp) {
+        self.menu = None;
+        self.paused = None;
+        for tetris in self.players.iter_mut() {
+            tetris.restart(now);
+        }
+    }
+
+    // Attacks go to the opponents still playing, split by the targeting
+    fn send_garbage(&mut self, player: usize) {
+        let attack = self.players[player].take_attack();
+        if attack == 0 {
+            return;
+        }
+        let opponents: Vec<usize> = (0..self.players.len())
+            .filter(|&i| i != player && !self.players[i].is_over())
+            .collect();
+        let scores: Vec<u32> = opponents.iter().map(|&i| self.players[i].score()).collect();
+        let split = self.targeting.split(attack, &scores, &mut self.random);
+        for (&i, &lines) in opponents.iter().zip(split.iter()) {
+            if lines > 0 {
+                self.players[i].add_garbage(lines as u16);
+            }
+        }
+    }
+
+    fn round_over(&mut self, loser: usize) {
+        let winner = (loser + 1) % 2;
+        self.wins[winner] += 1;
+        self.advantage = (self.advantage + 1) % self.players.len();
+        self.menu = Some(self.over_menu(winner));
+    }
+
+    fn match_over(&self) -> bool {
+        self.wins.iter().any(|&wins| wins >= self.first_to)
+    }
+
+    fn over_menu(&self, winner: usize) -> MenuMode<TetrisPause> {
+        let title = MenuItem::label(TetrisPause::Title, format!("Player {} Wins", winner + 1));
+        if self.first_to == 1 {
+            return MenuMode::new(vec![
+                title,
+                MenuItem::button(TetrisPause::Restart, "New Game"),
+                MenuItem::button(TetrisPause::Exit, "Exit"),
+            ]);
+        }
+        let score: Vec<String> = self.wins.iter().map(u32::to_string).collect();
+        let score = MenuItem::label(TetrisPause::Title, format!("Score {}", score.join(" - ")));
+        if self.match_over() {
+            MenuMode::new(vec![
+                MenuItem::label(
+                    TetrisPause::Title,
+                    format!("Player {} Wins The Match", winner + 1),
+                ),
+                score,
+                MenuItem::button(TetrisPause::Restart, "New Match"),
+                MenuItem::button(TetrisPause::Exit, "Exit"),
+            ])
+        } else {
+            MenuMode::new(vec![
+                title,
+                score,
+                MenuItem::label(TetrisPause::Title, format!("First To {}", self.first_to)),
+                MenuItem::button(TetrisPause::Continue, "Next Round"),
+                MenuItem::button(TetrisPause::Exit, "Exit"),
+            ])
+        }
+    }
+
+    pub fn wins(&self) -> &[u32] {
+        &self.wins
+    }
+
+    pub fn set_attack_table(&mut self, table: AttackTable) {
+        for tetris in self.players.iter_mut() {
+            tetris.set_attack_table(table.clone());
+        }
+    }
+
+    pub fn players(&self) -> &[Tetris] {
+        &self.players
+    }
+}
@@ -0,0 +1,319 @@
+use crate::bootstrap::{Action, Game, GameChange, MenuItem, MenuMode, Settings, Timestamp};
+use crate::error::invalid;
+use crate::game::tetris::schema::{self, Migration};
+use std::io::{self, Read, Write};
+
+// Layout changes since the first settings file, none so far
+const MIGRATIONS: [Migration; 0] = [];
+
+// Gravity choices with the milliseconds a block takes to fall one row
+const GRAVITY: [(&str, u64); 4] = [
+    ("Slow", 1000),
+    ("Normal", 500),
+    ("Fast", 250),
+    ("Faster", 100),
+];
+// Lock delay choices with the milliseconds a landed block can still be moved
+const LOCK_DELAYS: [(&str, u64); 4] = [("Off", 0), ("Short", 250), ("Normal", 500), ("Long", 1000)];
+const HEIGHTS: [u32; 3] = [16, 20, 24];
+// Levels a marathon can start at
+const LEVELS: [u32; 4] = [1, 5, 10, 15];
+const SWITCH: [&str; 2] = ["Off", "On"];
+
+/// What there is to play from the main menu
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Marathon,
+    // Marathon from the autosave
+    Resume,
+    // Marathon without the preview
+    Blind,
+    // Marathon without a game over
+    Zen,
+    // Marathon with the board upside down
+    UpsideDown,
+    // Marathon without the stack shown
+    Invisible,
+    // Race to clear rows of garbage
+    Dig,
+    // Race to clear 40 lines against the personal best
+    Sprint,
+    // Two players taking turns on one board
+    HotSeat,
+    // Small board of large cells falling slowly, without a game over
+    Easy,
+    Daily,
+    // The drills that come with the game
+    Practice,
+    Versus,
+    // Versus against the computer
+    Computer,
+    Coop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TitleItem {
+    Title,
+    Play(Mode),
+    Level,
+    Gravity,
+    LockDelay,
+    Height,
+    Bell,
+    Scores,
+    Controls,
+    Quit,
+}
+
+/// Settings the settings menu changes, kept in a text file with a line per
+/// setting after the version header: its name and value separated by a space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Preferences {
+    pub delay: u64,
+    pub lock_delay: u64,
+    pub rows: u16,
+    // Marathons start there, the others at the first level
+    pub level: u32,
+    // Terminal bell on tetrises, level ups and game overs
+    pub bell: bool,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Preferences {
+            delay: 500,
+            lock_delay: 500,
+            rows: 20,
+            level: 1,
+            bell: false,
+        }
+    }
+}
+
+impl Preferences {
+    pub fn apply(&self, settings: Settings) -> Settings {
+        Settings {
+            delay: self.delay,
+            lock_delay: self.lock_delay,
+            rows: self.rows,
+            ..settings
+        }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        schema::write_header(writer, "settings", &MIGRATIONS)?;
+        writeln!(writer, "delay {}", self.delay)?;
+        writeln!(writer, "lock_delay {}", self.lock_delay)?;
+        writeln!(writer, "rows {}", self.rows)?;
+        writeln!(writer, "level {}", self.level)?;
+        writeln!(writer, "bell {}", SWITCH[self.bell as usize].to_lowercase())
+    }
+
+    // Settings the file does not have keep their defaults
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Preferences> {
+        let text = schema::read_text(reader, "settings", &MIGRATIONS)?;
+        let mut preferences = Preferences::default();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (name, value) = line
+                .split_once(' ')
+                .ok_or_else(|| invalid(format!("expected a setting and a value, got {}", line)))?;
+            let number = || invalid(format!("{} needs a number, got {}", name, value));
+            match name {
+                "delay" => preferences.delay = value.parse().map_err(|_| number())?,
+                "lock_delay" => preferences.lock_delay = value.parse().map_err(|_| number())?,
+                "rows" => preferences.rows = value.parse().map_err(|_| number())?,
+                "level" => preferences.level = value.parse().map_err(|_| number())?,
+                "bell" => {
+                    preferences.bell = match value {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(invalid(format!("bell is on or off, got {}", value))),
+                    }
+                }
+                _ => return Err(invalid(format!("unknown setting {}", name))),
+            }
+        }
+        Ok(preferences)
+    }
+}
+
+/// Main menu the game starts in. It exits once a mode is picked, the frontend
+/// plays it and shows the menu again with `reopen` until the player quits.
+/// High scores and controls are lines of text the frontend knows about, so is the
+/// summary of the autosaved game when there is one to resume.
+pub struct Title {
+    menu: MenuMode<TitleItem>,
+    controls: Vec<String>,
+    chosen: Option<Mode>,
+    redraw: bool,
+}
+
+impl Title {
+    pub fn new(
+        preferences: Preferences,
+        scores: Vec<String>,
+        controls: Vec<String>,
+        resume: Option<String>,
+    ) -> Title {
+        let modes = vec![
+            MenuItem::button(TitleItem::Play(Mode::Marathon), "Marathon"),
+            MenuItem::button(TitleItem::Play(Mode::Blind), "Blind Marathon"),
+            MenuItem::button(TitleItem::Play(Mode::Zen), "Zen"),
+            MenuItem::button(TitleItem::Play(Mode::UpsideDown), "Upside Down"),
+            MenuItem::button(TitleItem::Play(Mode::Invisible), "Invisible"),
+            MenuItem::button(TitleItem::Play(Mode::Dig), "Dig Race"),
+            MenuItem::button(TitleItem::Play(Mode::Sprint), "Sprint 40 Lines"),
+            MenuItem::button(TitleItem::Play(Mode::HotSeat), "Hot Seat"),
+            MenuItem::button(TitleItem::Play(Mode::Easy), "Easy"),
+            MenuItem::button(TitleItem::Play(Mode::Daily), "Daily Challenge"),
+            MenuItem::button(TitleItem::Play(Mode::Practice), "Practice"),
+            MenuItem::button(TitleItem::Play(Mode::Versus), "Versus"),
+            MenuItem::button(TitleItem::Play(Mode::Computer), "Versus Computer"),
+            MenuItem::button(TitleItem::Play(Mode::Coop), "Co-op"),
+            MenuItem::choice(
+                TitleItem::Level,
+                "Marathon Level",
+                &LEVELS,
+                "",
+                preferences.level,
+            ),
+        ];
+        let gravity = GRAVITY
+            .iter()
+            .position(|&(_, delay)| delay == preferences.delay)
+            .unwrap_or(1);
+        let lock_delay = LOCK_DELAYS
+            .iter()
+            .position(|&(_, delay)| delay == preferences.lock_delay)
+            .unwrap_or(2);
+        let settings = vec![
+            MenuItem::value(
+                TitleItem::Gravity,
+                "Gravity",
+                GRAVITY.iter().map(|(name, _)| name.to_string()).collect(),
+                gravity,
+            ),
+            MenuItem::value(
+                TitleItem::LockDelay,
+                "Lock Delay",
+                LOCK_DELAYS
+                    .iter()
+                    .map(|(name, _)| name.to_string())
+                    .collect(),
+                lock_delay,
+            ),
+            MenuItem::choice(
+                TitleItem::Height,
+                "Height",
+                &HEIGHTS,
+                "",
+                preferences.rows as u32,
+            ),
+            MenuItem::value(
+                TitleItem::Bell,
+                "Bell",
+                SWITCH.iter().map(|switch| switch.to_string()).collect(),
+                preferences.bell as usize,
+            ),
+        ];
+        let mut items = vec![MenuItem::label(TitleItem::Title, "Brick Game")];
+        if let Some(summary) = resume {
+            items.push(MenuItem::button(
+                TitleItem::Play(Mode::Resume),
+                "Resume Last Game",
+            ));
+            items.push(MenuItem::label(TitleItem::Title, summary));
+        }
+        items.extend(vec![
+            MenuItem::button(TitleItem::Play(Mode::Marathon), "Play"),
+            MenuItem::submenu(TitleItem::Title, "Mode", modes),
+            MenuItem::submenu(TitleItem::Title, "Settings", settings),
+            Title::lines(TitleItem::Scores, "High Scores", scores),
+            Title::lines(TitleItem::Controls, "Controls", controls.clone()),
+            MenuItem::button(TitleItem::Quit, "Quit"),
+        ]);
+        Title {
+            menu: MenuMode::new(items),
+            controls,
+            chosen: None,
+            redraw: true,
+        }
+    }
+
+    // Mode picked when the menu exited, none when the player quit
+    pub fn chosen(&self) -> Option<Mode> {
+        self.chosen
+    }
+
+    pub fn preferences(&self) -> Preferences {
+        let gravity = self.menu.value_of(&TitleItem::Gravity).unwrap_or(1);
+        let lock_delay = self.menu.value_of(&TitleItem::LockDelay).unwrap_or(2);
+        let height = self.menu.value_of(&TitleItem::Height).unwrap_or(1);
+        let level = self.menu.value_of(&TitleItem::Level).unwrap_or(0);
+        Preferences {
+            delay: GRAVITY[gravity.min(GRAVITY.len() - 1)].1,
+            lock_delay: LOCK_DELAYS[lock_delay.min(LOCK_DELAYS.len() - 1)].1,
+            rows: HEIGHTS[height.min(HEIGHTS.len() - 1)] as u16,
+            level: LEVELS[level.min(LEVELS.len() - 1)],
+            bell: self.menu.value_of(&TitleItem::Bell) == Some(1),
+        }
+    }
+
+    // After a game, with the scores and the autosave it may have changed
+    pub fn reopen(&mut self, scores: Vec<String>, resume: Option<String>) {
+        let controls = std::mem::take(&mut self.controls);
+        *self = Title::new(self.preferences(), scores, controls, resume);
+    }
+
+    fn lines(id: TitleItem, name: &str, lines: Vec<String>) -> MenuItem<TitleItem> {
+        let mut items: Vec<_> = lines
+            .into_iter()
+            .map(|line| MenuItem::label(TitleItem::Title, line))
+            .collect();
+        if items.is_empty() {
+            items.push(MenuItem::label(TitleItem::Title, "Nothing Yet"));
+        }
+        MenuItem::submenu(id, name, items)
+    }
+}
+
+impl Game for Title {
+    type Pause = TitleItem;
+    type Input = Action;
+
+    fn frame(&mut self, _now: Timestamp, input: Option<Action>) -> GameChange<'_, TitleItem> {
+        let mut changed = input.is_some() || self.redraw;
+        self.redraw = false;
+        match input {
+            Some(Action::Up) => self.menu.up(),
+            Some(Action::Down) => self.menu.down(),
+            Some(Action::Left) => self.menu.left(),
+            Some(Action::Right) => self.menu.right(),
+            Some(Action::Escape) => changed = self.menu.back(),
+            Some(Action::Drop) => match self.menu.select().cloned() {
+                Some(TitleItem::Play(mode)) => {
+                    self.chosen = Some(mode);
+                    return GameChange::Exit;
+                }
+                Some(TitleItem::Quit) => return GameChange::Exit,
+                _ => {}
+            },
+            Some(
+                Action::Screenshot
+                | Action::Hold
+                | Action::ReleaseLeft
+                | Action::ReleaseRight
+                | Action::RotateLeft,
+            ) => changed = false,
+            None => {}
+        }
+        match changed {
+            true => GameChange::Text(&self.menu),
+            false => GameChange::Idle,
+        }
+    }
+
+    fn pause(&mut self) {}
+
+    fn restart(&mut self, _now: Timestamp) {}
+}
@@ -0,0 +1,220 @@
+use crate::bootstrap::MenuItem;
+use crate::error::invalid;
+use crate::game::tetris::listener::TetrisListener;
+use crate::game::tetris::replay::Replay;
+use crate::game::tetris::TetrisPause;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Finished run as it is submitted
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    pub player: String,
+    pub mode: String,
+    pub score: u32,
+    // Not every run is seeded or recorded
+    pub seed: Option<u64>,
+    pub replay_hash: Option<String>,
+    // Recording and its `Replay::sign` signature, for servers to play the run again
+    pub replay: Option<Vec<u8>>,
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub player: String,
+    pub score: u32,
+}
+
+/// Plain HTTP client for a leaderboard server. Runs are posted as a form to `<url>/scores`,
+/// the top list comes back from the same path as `player<TAB>score` lines.
+pub struct Leaderboard {
+    host: String,
+    path: String,
+}
+
+// Nobody wants the game to hang on a dead server
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+impl Leaderboard {
+    // Accepts urls like http://example.com:8080/tetris
+    pub fn new(url: &str) -> io::Result<Leaderboard> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http:// urls are supported"))?;
+        let (host, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], rest[idx..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let host = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(Leaderboard {
+            host,
+            path: path.to_string(),
+        })
+    }
+
+    pub fn submit(&self, run: &RunResult) -> io::Result<()> {
+        let mut form = vec![
+            ("player", run.player.clone()),
+            ("mode", run.mode.clone()),
+            ("score", run.score.to_string()),
+        ];
+        if let Some(seed) = run.seed {
+            form.push(("seed", seed.to_string()));
+        }
+        if let Some(hash) = &run.replay_hash {
+            form.push(("replay", hash.clone()));
+        }
+        if let Some(replay) = &run.replay {
+            form.push(("run", hex(replay)));
+        }
+        if let Some(signature) = &run.signature {
+            form.push(("signature", signature.clone()));
+        }
+        let body = form
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        self.request("POST", &format!("{}/scores", self.path), Some(&body))?;
+        Ok(())
+    }
+
+    pub fn top(&self, mode: &str, limit: usize) -> io::Result<Vec<Entry>> {
+        let path = format!("{}/scores?mode={}&limit={}", self.path, encode(mode), limit);
+        let body = self.request("GET", &path, None)?;
+        Ok(body
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split('\t');
+                let player = parts.next()?.to_string();
+                let score = parts.next()?.trim().parse().ok()?;
+                Some(Entry { player, score })
+            })
+            .take(limit)
+            .collect())
+    }
+
+    // HTTP/1.0 keeps responses simple: no chunks, connection closes after the body
+    fn request(&self, method: &str, path: &str, body: Option<&str>) -> io::Result<String> {
+        let addr = self
+            .host
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid("unknown host"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut request = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, self.host);
+        if let Some(body) = body {
+            request.push_str("Content-Type: application/x-www-form-urlencoded\r\n");
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        request.push_str("\r\n");
+        request.push_str(body.unwrap_or(""));
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let status = response.split(' ').nth(1).unwrap_or("");
+        if !status.starts_with('2') {
+            return Err(invalid(format!("server answered {}", status)));
+        }
+        match response.find("\r\n\r\n") {
+            Some(idx) => Ok(response[idx + 4..].to_string()),
+            None => Ok(String::new()),
+        }
+    }
+}
+
+/// Submits every finished game and offers the fresh top list in the game over menu.
+/// With a key the runs go with their replay and its signature.
+pub struct LeaderboardListener {
+    leaderboard: Leaderboard,
+    player: String,
+    mode: String,
+    key: Option<Vec<u8>>,
+    top: Option<io::Result<Vec<Entry>>>,
+}
+
+const TOP_SIZE: usize = 10;
+
+impl LeaderboardListener {
+    pub fn new(
+        leaderboard: Leaderboard,
+        player: impl Into<String>,
+        mode: impl Into<String>,
+    ) -> Self {
+        LeaderboardListener {
+            leaderboard,
+            player: player.into(),
+            mode: mode.into(),
+            key: None,
+            top: None,
+        }
+    }
+
+    // Shared with the server, which checks the signatures with it
+    pub fn with_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+}
+
+impl TetrisListener for LeaderboardListener {
+    fn on_finished(&mut self, replay: &Replay, score: u32) {
+        let signed = self.key.as_ref().map(|key| replay.sign(key, score));
+        let run = RunResult {
+            player: self.player.clone(),
+            mode: self.mode.clone(),
+            score,
+            seed: Some(replay.seed),
+            replay_hash: Some(format!("{:016x}", replay.hash)),
+            replay: signed.as_ref().map(|_| replay.encode()),
+            signature: signed,
+        };
+        if let Err(e) = self.leaderboard.submit(&run) {
+            log::warn!("Leaderboard submission failed: {}", e);
+        }
+        self.top = Some(self.leaderboard.top(&self.mode, TOP_SIZE));
+    }
+
+    fn over_items(&mut self) -> Vec<MenuItem<TetrisPause>> {
+        let items = match &self.top {
+            None => return vec![],
+            Some(Ok(entries)) if !entries.is_empty() => entries
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    let text = format!("{}. {} {}", i + 1, entry.player, entry.score);
+                    MenuItem::label(TetrisPause::Title, text)
+                })
+                .collect(),
+            Some(Ok(_)) => vec![MenuItem::label(TetrisPause::Title, "No scores yet")],
+            Some(Err(_)) => vec![MenuItem::label(TetrisPause::Title, "Unavailable")],
+        };
+        vec![MenuItem::submenu(TetrisPause::Title, "Leaderboard", items)]
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn encode(value: &str) -> String {
+    let mut out = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
@@ -0,0 +1,433 @@
+use crate::bootstrap::{Action, Game, GameChange, MenuItem, MenuMode, Settings, Timestamp};
+use crate::game::tetris::handicap::Handicap;
+use crate::net::tcp::TcpPeer;
+use crate::net::{check_hello, hello, invalid, Message, Transport};
+use std::io;
+use std::net::{
+    IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, ToSocketAddrs, UdpSocket,
+};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LobbyItem {
+    Title,
+    Host,
+    Code,
+    Join,
+    Garbage,
+    Gravity,
+    Attack,
+    Ready,
+    Back,
+    Exit,
+}
+
+/// Lobby input, unlike games it takes typed text
+#[derive(Debug, Clone, Copy)]
+pub enum LobbyInput {
+    Action(Action),
+    Char(char),
+    Erase,
+}
+
+/// Menu for meeting the other player on the local network. The host shows a room code
+/// made from its address and the other player types it in. Both pick their handicaps,
+/// once both are ready the lobby exits and `take_peer` hands over the connection.
+/// Both say hello first, a peer with other rules or settings is turned away.
+pub struct Lobby {
+    settings: Settings,
+    port: u16,
+    menu: MenuMode<LobbyItem>,
+    listener: Option<TcpListener>,
+    peer: Option<TcpPeer>,
+    ready: bool,
+    remote_ready: bool,
+    // The other side's hello checked out
+    remote_hello: bool,
+    handicap: Handicap,
+    remote_handicap: Option<Handicap>,
+    redraw: bool,
+}
+
+impl Lobby {
+    // Hosting listens on `port` of every interface
+    pub fn new(settings: Settings, port: u16) -> Lobby {
+        Lobby {
+            settings,
+            port,
+            menu: Lobby::start_menu(None),
+            listener: None,
+            peer: None,
+            ready: false,
+            remote_ready: false,
+            remote_hello: false,
+            handicap: Handicap::default(),
+            remote_handicap: None,
+            redraw: true,
+        }
+    }
+
+    /// Connection to the opponent once both sides are ready
+    pub fn take_peer(&mut self) -> Option<TcpPeer> {
+        if self.started() {
+            self.peer.take()
+        } else {
+            None
+        }
+    }
+
+    // What the local player picked, to be applied to the local board
+    pub fn handicap(&self) -> Handicap {
+        self.handicap
+    }
+
+    fn started(&self) -> bool {
+        self.peer.is_some() && self.remote_hello && self.ready && self.remote_ready
+    }
+
+    fn host(&mut self) {
+        let listener = TcpListener::bind(("0.0.0.0", self.port))
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener));
+        match listener {
+            Ok(listener) => {
+                let port = listener.local_addr().map_or(self.port, |addr| addr.port());
+                let ip = local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+                self.menu = MenuMode::new(vec![
+                    MenuItem::label(LobbyItem::Title, "Room code"),
+                    MenuItem::label(LobbyItem::Title, room_code(SocketAddr::new(ip, port))),
+                    MenuItem::label(LobbyItem::Title, "Waiting for opponent"),
+                    MenuItem::button(LobbyItem::Back, "Cancel"),
+                ]);
+                self.listener = Some(listener);
+            }
+            Err(_) => self.menu = Lobby::start_menu(Some("Cannot host")),
+        }
+    }
+
+    fn join(&mut self) {
+        let code = self.menu.text_of(&LobbyItem::Code).unwrap_or_default();
+        let stream = room_addr(code)
+            .and_then(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT))
+            .and_then(TcpPeer::from_stream);
+        match stream {
+            Ok(peer) => self.connected(peer),
+            Err(_) => self.menu = Lobby::start_menu(Some("Could not connect")),
+        }
+    }
+
+    fn connected(&mut self, mut peer: TcpPeer) {
+        if peer.send(&hello(&self.settings)).is_err() {
+            return self.leave(Some("Connection failed"));
+        }
+        self.listener = None;
+        self.peer = Some(peer);
+        self.ready = false;
+        self.remote_ready = false;
+        self.remote_hello = false;
+        self.remote_handicap = None;
+        self.menu = self.ready_menu();
+    }
+
+    // Rebuilds the menu after a change, keeping the handicap picked so far
+    fn refresh(&mut self) {
+        let value = |item, values: &[u32], current| {
+            self.menu
+                .value_of(&item)
+                .and_then(|i| values.get(i).copied())
+                .unwrap_or(current)
+        };
+        self.handicap = Handicap {
+            garbage: value(
+                LobbyItem::Garbage,
+                &GARBAGE_ROWS,
+                self.handicap.garbage as u32,
+            ) as u16,
+            gravity: value(LobbyItem::Gravity, &PERCENTS, self.handicap.gravity),
+            attack: value(LobbyItem::Attack, &PERCENTS, self.handicap.attack),
+        };
+        self.menu = self.ready_menu();
+    }
+
+    // Back to the start menu, the opponent is told when there is one
+    fn leave(&mut self, message: Option<&str>) {
+        if let Some(peer) = &mut self.peer {
+            let _ = peer.send(&Message::Leave);
+        }
+        self.listener = None;
+        self.peer = None;
+        self.menu = Lobby::start_menu(message);
+    }
+
+    fn set_ready(&mut self) {
+        self.refresh();
+        let handicap = Message::Handicap {
+            garbage: self.handicap.garbage,
+            gravity: self.handicap.gravity,
+            attack: self.handicap.attack,
+        };
+        if let Some(peer) = &mut self.peer {
+            match peer
+                .send(&handicap)
+                .and_then(|_| peer.send(&Message::Ready))
+            {
+                Ok(()) => {
+                    self.ready = true;
+                    self.refresh();
+                }
+                Err(_) => self.leave(Some("Opponent Left")),
+            }
+        }
+    }
+
+    // Accepts the opponent and reads whatever it sent, returns whether the menu changed
+    fn poll(&mut self) -> bool {
+        if let Some(listener) = &self.listener {
+            return match listener.accept() {
+                Ok((stream, _)) => {
+                    match stream
+                        .set_nonblocking(false)
+                        .and_then(|_| TcpPeer::from_stream(stream))
+                    {
+                        Ok(peer) => self.connected(peer),
+                        Err(_) => self.leave(Some("Connection failed")),
+                    }
+                    true
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => false,
+                Err(_) => {
+                    self.leave(Some("Cannot host"));
+                    true
+                }
+            };
+        }
+        let mut changed = false;
+        while let Some(peer) = &mut self.peer {
+            match peer.receive() {
+                Ok(Some(message @ Message::Hello { .. })) => {
+                    match check_hello(&message, &self.settings) {
+                        Ok(()) => self.remote_hello = true,
+                        Err(reason) => {
+                            let _ = peer.send(&Message::Refused {
+                                reason: reason.clone(),
+                            });
+                            self.peer = None;
+                            self.leave(Some(&reason));
+                            return true;
+                        }
+                    }
+                }
+                Ok(Some(Message::Refused { reason })) => {
+                    self.peer = None;
+                    self.leave(Some(&reason));
+                    return true;
+                }
+                Ok(Some(Message::Ready)) => {
+                    self.remote_ready = true;
+                    self.refresh();
+                    changed = true;
+                }
+                Ok(Some(Message::Handicap {
+                    garbage,
+                    gravity,
+                    attack,
+                })) => {
+                    self.remote_handicap = Some(Handicap {
+                        garbage,
+                        gravity,
+                        attack,
+                    });
+                    self.refresh();
+                    changed = true;
+                }
+                Ok(Some(Message::Leave)) | Err(_) => {
+                    self.peer = None;
+                    self.leave(Some("Opponent Left"));
+                    return true;
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+            }
+        }
+        changed
+    }
+
+    fn start_menu(message: Option<&str>) -> MenuMode<LobbyItem> {
+        let mut items = vec![MenuItem::label(LobbyItem::Title, "LAN Game")];
+        if let Some(message) = message {
+            items.push(MenuItem::label(LobbyItem::Title, message));
+        }
+        items.push(MenuItem::button(LobbyItem::Host, "Host"));
+        items.push(MenuItem::input(LobbyItem::Code, "Room code", MAX_CODE));
+        items.push(MenuItem::button(LobbyItem::Join, "Join"));
+        items.push(MenuItem::button(LobbyItem::Exit, "Exit"));
+        MenuMode::new(items)
+    }
+
+    fn ready_menu(&self) -> MenuMode<LobbyItem> {
+        let opponent = if self.remote_ready {
+            "Opponent is ready"
+        } else {
+            "Opponent connected"
+        };
+        let mut items = vec![MenuItem::label(LobbyItem::Title, opponent)];
+        if let Some(handicap) = self.remote_handicap.filter(|h| *h != Handicap::default()) {
+            items.push(MenuItem::label(LobbyItem::Title, describe(&handicap)));
+        }
+        if self.ready {
+            items.push(MenuItem::label(LobbyItem::Title, "Waiting for opponent"));
+        } else {
+            let h = &self.handicap;
+            items.push(MenuItem::choice(
+                LobbyItem::Garbage,
+                "Garbage rows",
+                &GARBAGE_ROWS,
+                "",
+                h.garbage as u32,
+            ));
+            items.push(MenuItem::choice(
+                LobbyItem::Gravity,
+                "Gravity",
+                &PERCENTS,
+                "%",
+                h.gravity,
+            ));
+            items.push(MenuItem::choice(
+                LobbyItem::Attack,
+                "Attack",
+                &PERCENTS,
+                "%",
+                h.attack,
+            ));
+            items.push(MenuItem::button(LobbyItem::Ready, "Ready"));
+        }
+        items.push(MenuItem::button(LobbyItem::Back, "Leave"));
+        MenuMode::new(items)
+    }
+}
+
+impl Game for Lobby {
+    type Pause = LobbyItem;
+    type Input = LobbyInput;
+
+    fn frame(&mut self, _now: Timestamp, input: Option<LobbyInput>) -> GameChange<'_, LobbyItem> {
+        let mut changed = self.poll() || input.is_some() || self.redraw;
+        self.redraw = false;
+        match input {
+            Some(LobbyInput::Char(c)) => self.menu.type_char(c),
+            Some(LobbyInput::Erase) => self.menu.erase(),
+            Some(LobbyInput::Action(action)) => match action {
+                Action::Up => self.menu.up(),
+                Action::Down => self.menu.down(),
+                Action::Left => self.menu.left(),
+                Action::Right => self.menu.right(),
+                Action::Escape if self.listener.is_some() || self.peer.is_some() => {
+                    self.leave(None)
+                }
+                Action::Escape => changed = self.menu.back(),
+                Action::Screenshot
+                | Action::Hold
+                | Action::ReleaseLeft
+                | Action::ReleaseRight
+                | Action::RotateLeft => changed = false,
+                Action::Drop => match self.menu.select().cloned() {
+                    Some(LobbyItem::Host) => self.host(),
+                    Some(LobbyItem::Join) => self.join(),
+                    Some(LobbyItem::Ready) => self.set_ready(),
+                    Some(LobbyItem::Back) => self.leave(None),
+                    Some(LobbyItem::Exit) => return GameChange::Exit,
+                    _ => {}
+                },
+            },
+            None => {}
+        }
+        if self.started() {
+            GameChange::Exit
+        } else if changed {
+            GameChange::Text(&self.menu)
+        } else {
+            GameChange::Idle
+        }
+    }
+
+    fn pause(&mut self) {}
+
+    fn restart(&mut self, _now: Timestamp) {}
+}
+
+// Handicap values to pick from
+const GARBAGE_ROWS: [u32; 9] = [0, 1, 2, 3, 4, 5, 6, 7, 8];
+const PERCENTS: [u32; 5] = [50, 75, 100, 150, 200];
+
+fn describe(handicap: &Handicap) -> String {
+    format!(
+        "Opponent: {} rows, {}% gravity, {}% attack",
+        handicap.garbage, handicap.gravity, handicap.attack
+    )
+}
+
+// Joining gives up after that long
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+// Long enough for an IPv6 address with port typed instead of a code
+const MAX_CODE: usize = 47;
+
+// Address other machines on the network reach this one at. Connecting a UDP socket
+// sends nothing, it only makes the system pick the interface it would route through.
+fn local_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+// Crockford's base32, without letters that are easy to confuse
+const CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+// Ten base32 digits hold the 48 bits of address and port
+const CODE_LEN: u64 = 10;
+
+/// Short code for an IPv4 address with port, like `60N00-H27P6` for 192.168.1.17:7878.
+/// Other addresses are shown as they are.
+pub fn room_code(addr: SocketAddr) -> String {
+    let addr = match addr {
+        SocketAddr::V4(addr) => addr,
+        SocketAddr::V6(_) => return addr.to_string(),
+    };
+    let bits = (u32::from(*addr.ip()) as u64) << 16 | addr.port() as u64;
+    let mut code = String::new();
+    for i in (0..CODE_LEN).rev() {
+        code.push(CODE_ALPHABET[(bits >> (i * 5)) as usize & 31] as char);
+        if i == CODE_LEN / 2 {
+            code.push('-');
+        }
+    }
+    code
+}
+
+/// Address of a room code, or of a plain `host:port` typed instead
+pub fn room_addr(code: &str) -> io::Result<SocketAddr> {
+    let code = code.trim();
+    if code.contains(':') {
+        return code
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid("unknown host"));
+    }
+    let mut bits = 0u64;
+    let mut len = 0;
+    for c in code.chars().filter(|&c| c != '-' && c != ' ') {
+        let c = match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        };
+        let value = CODE_ALPHABET
+            .iter()
+            .position(|&letter| letter as char == c)
+            .ok_or_else(|| invalid("not a room code"))?;
+        bits = bits << 5 | value as u64;
+        len += 1;
+    }
+    if len != CODE_LEN || bits >> 48 != 0 {
+        return Err(invalid("not a room code"));
+    }
+    let ip = Ipv4Addr::from((bits >> 16) as u32);
+    Ok(SocketAddr::V4(SocketAddrV4::new(ip, bits as u16)))
+}
@@ -0,0 +1,394 @@
+use crate::bootstrap::{
+    Action, FrameOutput, Game, GameChange, GameMode, MenuItem, MenuMode, Redraw, Settings,
+    Timestamp,
+};
+use crate::game::tetris::{menu_action, MenuOutcome, Tetris, TetrisPause, TetrisSnapshot};
+use crate::net::{Message, Transport};
+use std::collections::BTreeMap;
+
+/// Length of one simulation step in milliseconds
+pub const TICK: Timestamp = 16;
+
+// Boards are compared every that many ticks
+const HASH_EVERY: u32 = 32;
+
+/// Versus where both sides simulate both boards and only inputs go over the wire.
+/// Local inputs are scheduled `delay` ticks ahead, so they usually reach the other side
+/// before they are needed. When they don't, the game waits for them.
+/// The host picks the seed, the other side waits for it.
+///
+/// With rollback the boards are drawn up to `rollback` ticks ahead of the confirmed state,
+/// guessing that missing remote inputs are empty. Every frame starts again from a snapshot
+/// of the confirmed state, so wrong guesses are corrected as soon as the inputs come.
+pub struct Lockstep<T: Transport> {
+    settings: Settings,
+    // Host's board first, the same order on both sides
+    boards: Vec<Tetris>,
+    me: usize,
+    peer: T,
+    delay: u32,
+    // Local time of the first tick
+    start: Timestamp,
+    // Next tick to simulate
+    tick: u32,
+    // Next tick to send local inputs for
+    sent: u32,
+    pending: Vec<Action>,
+    // Actions of every player by tick, dropped once simulated
+    inputs: Vec<BTreeMap<u32, Vec<Action>>>,
+    hashes: BTreeMap<u32, u64>,
+    remote_hashes: BTreeMap<u32, u64>,
+    menu: Option<MenuMode<TetrisPause>>,
+    // No seed for the current round yet
+    waiting: bool,
+    restart: bool,
+    remote_restart: bool,
+    connected: bool,
+    redraw: Redraw,
+    rollback: u32,
+    // Boards as predicted during the last frame
+    predicted: Option<Vec<GameMode>>,
+}
+
+impl<T: Transport> Lockstep<T> {
+    pub fn host(settings: Settings, start: Timestamp, peer: T, delay: u32) -> Lockstep<T> {
+        let mut lockstep = Lockstep::new(settings, 0, peer, delay);
+        lockstep.new_round(start, start);
+        lockstep
+    }
+
+    pub fn join(settings: Settings, peer: T, delay: u32) -> Lockstep<T> {
+        Lockstep::new(settings, 1, peer, delay)
+    }
+
+    fn new(settings: Settings, me: usize, peer: T, delay: u32) -> Lockstep<T> {
+        Lockstep {
+            settings,
+            boards: vec![Tetris::new(settings, 0), Tetris::new(settings, 0)],
+            me,
+            peer,
+            delay,
+            start: 0,
+            tick: 0,
+            sent: 0,
+            pending: vec![],
+            inputs: vec![BTreeMap::new(), BTreeMap::new()],
+            hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            menu: Some(Lockstep::<T>::waiting_menu()),
+            waiting: true,
+            restart: false,
+            remote_restart: false,
+            connected: true,
+            redraw: Redraw::full(),
+            rollback: 0,
+            predicted: None,
+        }
+    }
+
+    // Ticks the game may run ahead of the remote inputs, none by default
+    pub fn with_rollback(mut self, ticks: u32) -> Lockstep<T> {
+        self.rollback = ticks;
+        self
+    }
+
+    // Only the host decides the seed, the other side starts once it arrives
+    fn new_round(&mut self, now: Timestamp, seed: u64) {
+        if self.me == 0 {
+            self.send(Message::Seed { seed });
+        }
+        let settings = self.settings;
+        self.boards = vec![
+            Tetris::with_seed(settings, 0, seed),
+            Tetris::with_seed(settings, 0, seed),
+        ];
+        self.start = now;
+        self.tick = 0;
+        self.sent = 0;
+        self.pending.clear();
+        self.inputs.iter_mut().for_each(BTreeMap::clear);
+        self.hashes.clear();
+        self.remote_hashes.clear();
+        self.menu = None;
+        self.waiting = false;
+        self.restart = false;
+        self.remote_restart = false;
+        self.redraw = Redraw::full();
+        self.predicted = None;
+    }
+
+    fn send(&mut self, message: Message) {
+        if self.connected && self.peer.send(&message).is_err() {
+            self.disconnected();
+        }
+    }
+
+    fn disconnected(&mut self) {
+        self.connected = false;
+        self.menu = Some(Lockstep::<T>::message_menu("Opponent Left"));
+    }
+
+    fn receive(&mut self, now: Timestamp) {
+        let other = 1 - self.me;
+        while self.connected {
+            let message = match self.peer.receive() {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(_) => {
+                    self.disconnected();
+                    break;
+                }
+            };
+            match message {
+                Message::Seed { seed } if self.me == 1 => self.new_round(now, seed),
+                Message::Inputs { tick, actions } => {
+                    self.inputs[other].insert(tick, actions);
+                }
+                Message::Hash { tick, hash } => match self.hashes.remove(&tick) {
+                    Some(own) => self.compare(tick, own, hash),
+                    None => {
+                        self.remote_hashes.insert(tick, hash);
+                    }
+                },
+                Message::Restart => {
+                    self.remote_restart = true;
+                    self.try_restart(now);
+                }
+                Message::Leave => self.disconnected(),
+                _ => {}
+            }
+        }
+    }
+
+    fn compare(&mut self, tick: u32, own: u64, remote: u64) {
+        if own != remote {
+            log::error!("Desync at tick {}: {:x} vs {:x}", tick, own, remote);
+            self.menu = Some(Lockstep::<T>::message_menu("Desync"));
+        }
+    }
+
+    fn try_restart(&mut self, now: Timestamp) {
+        if self.restart && self.remote_restart {
+            self.waiting = true;
+            if self.me == 0 {
+                let seed = now ^ self.boards[0].state_hash();
+                self.new_round(now, seed);
+            }
+        }
+    }
+
+    // Local inputs go out for every tick up to the delayed one, empty ticks included
+    fn send_inputs(&mut self, now: Timestamp) {
+        let target = self.current_tick(now) + self.delay;
+        while self.sent <= target {
+            let actions = if self.sent == target {
+                std::mem::take(&mut self.pending)
+            } else {
+                vec![]
+            };
+            self.inputs[self.me].insert(self.sent, actions.clone());
+            self.send(Message::Inputs {
+                tick: self.sent,
+                actions,
+            });
+            self.sent += 1;
+        }
+    }
+
+    // Runs every tick both inputs are known for, without getting ahead of the local time
+    fn simulate(&mut self, now: Timestamp) -> bool {
+        let mut changed = false;
+        while self.tick <= self.current_tick(now) && !self.is_over() {
+            let tick = self.tick;
+            if !self.inputs.iter().all(|inputs| inputs.contains_key(&tick)) {
+                break;
+            }
+            self.step(tick, false);
+            if tick.is_multiple_of(HASH_EVERY) {
+                self.check_hash(tick);
+            }
+            self.tick += 1;
+            changed = true;
+        }
+        if self.rollback > 0 && !self.is_over() {
+            changed |= self.predict(now);
+        }
+        changed
+    }
+
+    // One tick of both boards. Predicted ticks keep their inputs for the real run later.
+    fn step(&mut self, tick: u32, predicted: bool) {
+        let time = tick as Timestamp * TICK;
+        for (board, inputs) in self.boards.iter_mut().zip(self.inputs.iter_mut()) {
+            let actions = if predicted {
+                inputs.get(&tick).cloned()
+            } else {
+                inputs.remove(&tick)
+            };
+            for action in actions.unwrap_or_default() {
+                board.frame(time, Some(action));
+            }
+            board.frame(time, None);
+        }
+        let attacks: Vec<u32> = self.boards.iter_mut().map(Tetris::take_attack).collect();
+        for (i, attack) in attacks.into_iter().enumerate() {
+            if attack > 0 {
+                self.boards[1 - i].add_garbage(attack as u16);
+            }
+        }
+    }
+
+    fn predict(&mut self, now: Timestamp) -> bool {
+        let last = self.current_tick(now).min(self.tick + self.rollback - 1);
+        if self.tick > last {
+            return false;
+        }
+        let confirmed: Vec<TetrisSnapshot> = self.boards.iter().map(Tetris::snapshot).collect();
+        for tick in self.tick..=last {
+            if self.is_over() {
+                break;
+            }
+            self.step(tick, true);
+        }
+        self.predicted = Some(self.boards.iter().map(Tetris::to_drawable).collect());
+        for (board, snapshot) in self.boards.iter_mut().zip(confirmed.iter()) {
+            board.restore(snapshot);
+        }
+        true
+    }
+
+    fn check_hash(&mut self, tick: u32) {
+        let hash = self.state_hash();
+        self.send(Message::Hash { tick, hash });
+        match self.remote_hashes.remove(&tick) {
+            Some(remote) => self.compare(tick, hash, remote),
+            None => {
+                self.hashes.insert(tick, hash);
+            }
+        }
+    }
+
+    fn current_tick(&self, now: Timestamp) -> u32 {
+        (now.saturating_sub(self.start) / TICK) as u32
+    }
+
+    fn is_over(&self) -> bool {
+        self.boards.iter().any(Tetris::is_over)
+    }
+
+    pub fn state_hash(&self) -> u64 {
+        self.boards
+            .iter()
+            .fold(0, |hash, board| hash.rotate_left(5) ^ board.state_hash())
+    }
+
+    fn output(&mut self, index: usize) -> FrameOutput {
+        FrameOutput {
+            game: match &self.predicted {
+                Some(boards) => boards[index].clone(),
+                None => self.boards[index].to_drawable(),
+            },
+            redraw: self.redraw.clone(),
+        }
+    }
+
+    fn waiting_menu() -> MenuMode<TetrisPause> {
+        Lockstep::<T>::message_menu("Waiting for opponent")
+    }
+
+    fn message_menu(title: &str) -> MenuMode<TetrisPause> {
+        MenuMode::new(vec![
+            MenuItem::label(TetrisPause::Title, title),
+            MenuItem::button(TetrisPause::Exit, "Exit"),
+        ])
+    }
+
+    fn over_menu(title: &str) -> MenuMode<TetrisPause> {
+        MenuMode::new(vec![
+            MenuItem::label(TetrisPause::Title, title),
+            MenuItem::button(TetrisPause::Restart, "New Game"),
+            MenuItem::button(TetrisPause::Exit, "Exit"),
+        ])
+    }
+}
+
+impl<T: Transport> Game for Lockstep<T> {
+    type Pause = TetrisPause;
+    type Input = Action;
+
+    fn frame(&mut self, now: Timestamp, action: Option<Action>) -> GameChange<'_, TetrisPause> {
+        self.receive(now);
+        let mut menu_changed = false;
+        match &mut self.menu {
+            // The game goes on behind the menu, nobody can stop time for the opponent
+            Some(menu) => match menu_action(menu, action) {
+                MenuOutcome::Continue if !self.waiting && !self.is_over() && self.connected => {
+                    self.menu = None;
+                    self.redraw = Redraw::full();
+                }
+                MenuOutcome::Restart if self.connected => {
+                    self.send(Message::Restart);
+                    self.restart = true;
+                    self.menu = Some(Lockstep::<T>::waiting_menu());
+                    self.try_restart(now);
+                    menu_changed = true;
+                }
+                MenuOutcome::Exit => {
+                    self.send(Message::Leave);
+                    return GameChange::Exit;
+                }
+                MenuOutcome::Changed | MenuOutcome::Selected(_) => menu_changed = true,
+                _ => {}
+            },
+            None if matches!(action, Some(Action::Escape)) => {
+                self.menu = Some(Tetris::pause_menu());
+                menu_changed = true;
+            }
+            None => self.pending.extend(action),
+        }
+        if self.waiting || self.restart {
+            return match self.menu {
+                Some(ref menu) if menu_changed => GameChange::Text(menu),
+                _ => GameChange::Idle,
+            };
+        }
+        self.send_inputs(now);
+        let changed = self.simulate(now);
+        if self.is_over() && self.menu.is_none() {
+            let title = if self.boards[self.me].is_over() {
+                "You Lose"
+            } else {
+                "You Win"
+            };
+            self.menu = Some(Lockstep::<T>::over_menu(title));
+            menu_changed = true;
+        }
+        if self.menu.is_some() {
+            return match self.menu {
+                Some(ref menu) if menu_changed => GameChange::Text(menu),
+                _ => GameChange::Idle,
+            };
+        }
+        if !changed && !self.redraw.full {
+            return GameChange::Idle;
+        }
+        let frames = vec![self.output(self.me), self.output(1 - self.me)];
+        // Renderers compare cells themselves, offering the whole board is cheap
+        self.redraw = Redraw {
+            field: true,
+            preview: true,
+            score: true,
+            ..Redraw::default()
+        };
+        GameChange::Split(frames)
+    }
+
+    fn pause(&mut self) {
+        if self.menu.is_none() {
+            self.menu = Some(Tetris::pause_menu());
+        }
+    }
+
+    // Restarts go through the menu, both players have to agree
+    fn restart(&mut self, _now: Timestamp) {}
+}
@@ -0,0 +1,536 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lobby;
+pub mod lockstep;
+pub mod observer;
+pub mod remote;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tcp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod websocket;
+
+use crate::bootstrap::{Action, Cell, Renderable, Settings, Timestamp};
+use crate::error::invalid;
+use crate::game::tetris::{settings_checksum, RULES_VERSION};
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+/// Way of getting messages to the other player. Sending may block briefly,
+/// receiving never does.
+pub trait Transport {
+    fn send(&mut self, message: &Message) -> io::Result<()>;
+    /// Next received message if there is any
+    fn receive(&mut self) -> io::Result<Option<Message>>;
+}
+
+/// Transport for hosts that own the actual connection, like the browser with its WebSocket.
+/// Outgoing messages are queued as encoded bytes until the host takes them,
+/// incoming ones are pushed by the host.
+#[derive(Default)]
+pub struct QueueTransport {
+    inbox: VecDeque<Vec<u8>>,
+    outbox: VecDeque<Vec<u8>>,
+    closed: bool,
+}
+
+impl QueueTransport {
+    pub fn new() -> QueueTransport {
+        QueueTransport::default()
+    }
+
+    pub fn push(&mut self, bytes: Vec<u8>) {
+        self.inbox.push_back(bytes);
+    }
+
+    pub fn take(&mut self) -> Option<Vec<u8>> {
+        self.outbox.pop_front()
+    }
+
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+}
+
+impl Transport for QueueTransport {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        if self.closed {
+            return Err(io::ErrorKind::ConnectionAborted.into());
+        }
+        self.outbox.push_back(message.encode());
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        match self.inbox.pop_front() {
+            Some(bytes) => Message::decode(&bytes).map(Some),
+            None if self.closed => Err(io::ErrorKind::ConnectionAborted.into()),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Everything peers tell each other. Times are milliseconds since the sender's game start.
+#[derive(Debug, Clone)]
+pub enum Message {
+    Input {
+        time: Timestamp,
+        action: Action,
+    },
+    Garbage {
+        time: Timestamp,
+        lines: u16,
+    },
+    // Sender's board as it should be drawn on the other side
+    Board {
+        time: Timestamp,
+        main: Renderable,
+        preview: Renderable,
+        score: u32,
+        // Incoming lines not on the field yet
+        garbage: u16,
+    },
+    GameOver {
+        time: Timestamp,
+    },
+    Restart,
+    Leave,
+    // Lockstep: the game both sides simulate starts from this seed
+    Seed {
+        seed: u64,
+    },
+    // Lockstep: all actions of one tick, sent even when there are none
+    Inputs {
+        tick: u32,
+        actions: Vec<Action>,
+    },
+    // Lockstep: state of both boards after the tick, differing hashes mean a desync
+    Hash {
+        tick: u32,
+        hash: u64,
+    },
+    // Lobby: the sender wants to start the match
+    Ready,
+    Chat {
+        text: String,
+    },
+    // Lobby: handicap the sender will play with
+    Handicap {
+        garbage: u16,
+        gravity: u32,
+        attack: u32,
+    },
+    // Handshake: first message to a peer or the server. Peers also have to play by the
+    // same rules and settings, the server only cares about the protocol version.
+    Hello {
+        version: u16,
+        // Crate version of the sender, for telling the player what to update
+        build: String,
+        rules: u16,
+        rotation: String,
+        settings: u64,
+    },
+    // Server: puts the client into a room, the match starts with `Ready` once it is full
+    Join {
+        room: String,
+    },
+    // Server: why the client is disconnected
+    Refused {
+        reason: String,
+    },
+    // Server: like `Join` for an observer, who only gets told what the players send
+    Watch {
+        room: String,
+    },
+    // Server: message of the player in the seat, as passed on to observers
+    Seat {
+        seat: u8,
+        message: Box<Message>,
+    },
+}
+
+// Messages larger than this are treated as garbage on the wire
+const MAX_MESSAGE: usize = 64 * 1024;
+// Bytes of a chat line or other text, longer ones are cut
+pub const MAX_TEXT: usize = 64;
+// Bumped whenever messages change, peers and servers with other versions do not mix
+pub const PROTOCOL_VERSION: u16 = 3;
+#[cfg(not(target_arch = "wasm32"))]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(not(target_arch = "wasm32"))]
+const HANDSHAKE_TICK: Duration = Duration::from_millis(10);
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        match self {
+            Message::Input { time, action } => {
+                out.push(0);
+                out.extend_from_slice(&time.to_le_bytes());
+                out.push(*action as u8);
+            }
+            Message::Garbage { time, lines } => {
+                out.push(1);
+                out.extend_from_slice(&time.to_le_bytes());
+                out.extend_from_slice(&lines.to_le_bytes());
+            }
+            Message::Board {
+                time,
+                main,
+                preview,
+                score,
+                garbage,
+            } => {
+                out.push(2);
+                out.extend_from_slice(&time.to_le_bytes());
+                out.extend_from_slice(&score.to_le_bytes());
+                out.extend_from_slice(&garbage.to_le_bytes());
+                encode_grid(&mut out, main);
+                encode_grid(&mut out, preview);
+            }
+            Message::GameOver { time } => {
+                out.push(3);
+                out.extend_from_slice(&time.to_le_bytes());
+            }
+            Message::Restart => out.push(4),
+            Message::Leave => out.push(5),
+            Message::Seed { seed } => {
+                out.push(6);
+                out.extend_from_slice(&seed.to_le_bytes());
+            }
+            Message::Inputs { tick, actions } => {
+                out.push(7);
+                out.extend_from_slice(&tick.to_le_bytes());
+                out.push(actions.len() as u8);
+                out.extend(actions.iter().map(|action| *action as u8));
+            }
+            Message::Hash { tick, hash } => {
+                out.push(8);
+                out.extend_from_slice(&tick.to_le_bytes());
+                out.extend_from_slice(&hash.to_le_bytes());
+            }
+            Message::Ready => out.push(9),
+            Message::Chat { text } => {
+                out.push(10);
+                encode_text(&mut out, text);
+            }
+            Message::Handicap {
+                garbage,
+                gravity,
+                attack,
+            } => {
+                out.push(11);
+                out.extend_from_slice(&garbage.to_le_bytes());
+                out.extend_from_slice(&gravity.to_le_bytes());
+                out.extend_from_slice(&attack.to_le_bytes());
+            }
+            Message::Hello {
+                version,
+                build,
+                rules,
+                rotation,
+                settings,
+            } => {
+                out.push(12);
+                out.extend_from_slice(&version.to_le_bytes());
+                encode_text(&mut out, build);
+                out.extend_from_slice(&rules.to_le_bytes());
+                encode_text(&mut out, rotation);
+                out.extend_from_slice(&settings.to_le_bytes());
+            }
+            Message::Join { room } => {
+                out.push(13);
+                encode_text(&mut out, room);
+            }
+            Message::Refused { reason } => {
+                out.push(14);
+                encode_text(&mut out, reason);
+            }
+            Message::Watch { room } => {
+                out.push(15);
+                encode_text(&mut out, room);
+            }
+            Message::Seat { seat, message } => {
+                out.push(16);
+                out.push(*seat);
+                out.extend(message.encode());
+            }
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Message> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+        let message = match reader.u8()? {
+            0 => Message::Input {
+                time: reader.u64()?,
+                action: reader.action()?,
+            },
+            1 => Message::Garbage {
+                time: reader.u64()?,
+                lines: reader.u16()?,
+            },
+            2 => Message::Board {
+                time: reader.u64()?,
+                score: reader.u32()?,
+                garbage: reader.u16()?,
+                main: reader.grid()?,
+                preview: reader.grid()?,
+            },
+            3 => Message::GameOver {
+                time: reader.u64()?,
+            },
+            4 => Message::Restart,
+            5 => Message::Leave,
+            6 => Message::Seed {
+                seed: reader.u64()?,
+            },
+            7 => {
+                let tick = reader.u32()?;
+                let count = reader.u8()?;
+                let mut actions = vec![];
+                for _ in 0..count {
+                    actions.push(reader.action()?);
+                }
+                Message::Inputs { tick, actions }
+            }
+            8 => Message::Hash {
+                tick: reader.u32()?,
+                hash: reader.u64()?,
+            },
+            9 => Message::Ready,
+            10 => Message::Chat {
+                text: reader.text()?,
+            },
+            11 => Message::Handicap {
+                garbage: reader.u16()?,
+                gravity: reader.u32()?,
+                attack: reader.u32()?,
+            },
+            12 => {
+                let version = reader.u16()?;
+                // Version 2 and older sent nothing else, they still get a clear refusal
+                if reader.is_done() {
+                    Message::Hello {
+                        version,
+                        build: String::new(),
+                        rules: 0,
+                        rotation: String::new(),
+                        settings: 0,
+                    }
+                } else {
+                    Message::Hello {
+                        version,
+                        build: reader.text()?,
+                        rules: reader.u16()?,
+                        rotation: reader.text()?,
+                        settings: reader.u64()?,
+                    }
+                }
+            }
+            13 => Message::Join {
+                room: reader.text()?,
+            },
+            14 => Message::Refused {
+                reason: reader.text()?,
+            },
+            15 => Message::Watch {
+                room: reader.text()?,
+            },
+            16 => Message::Seat {
+                seat: reader.u8()?,
+                message: Box::new(Message::decode(reader.rest())?),
+            },
+            _ => return Err(invalid("unknown message")),
+        };
+        Ok(message)
+    }
+
+    // Length prefixed frame, suitable for stream transports
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let body = self.encode();
+        writer.write_all(&(body.len() as u32).to_le_bytes())?;
+        writer.write_all(&body)?;
+        writer.flush()
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Message> {
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_MESSAGE {
+            return Err(invalid("message is too long"));
+        }
+        let mut body = vec![0u8; len];
+        reader.read_exact(&mut body)?;
+        Message::decode(&body)
+    }
+}
+
+/// What this build tells the other side first
+pub fn hello(settings: &Settings) -> Message {
+    Message::Hello {
+        version: PROTOCOL_VERSION,
+        build: env!("CARGO_PKG_VERSION").to_string(),
+        rules: RULES_VERSION,
+        rotation: settings.rotation.name().to_string(),
+        settings: settings_checksum(settings),
+    }
+}
+
+/// Why a match with the sender of `hello` would not work, short enough for `Refused`
+pub fn check_hello(hello: &Message, settings: &Settings) -> Result<(), String> {
+    let (version, build, rules, rotation, checksum) = match hello {
+        Message::Hello {
+            version,
+            build,
+            rules,
+            rotation,
+            settings,
+        } => (*version, build, *rules, rotation, *settings),
+        _ => return Err("Expected hello first".to_string()),
+    };
+    if version != PROTOCOL_VERSION {
+        Err(format!(
+            "Peer speaks protocol {}, this build speaks {}",
+            version, PROTOCOL_VERSION
+        ))
+    } else if rules != RULES_VERSION || rotation != settings.rotation.name() {
+        Err(format!(
+            "Peer {} has rules {} {}, need {} {}",
+            build,
+            rules,
+            rotation,
+            RULES_VERSION,
+            settings.rotation.name()
+        ))
+    } else if checksum != settings_checksum(settings) {
+        Err("Peer plays with different board settings".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Both sides say hello and check the other one before the match starts, instead
+/// of desyncing halfway through it. A mismatched peer is told why.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn handshake<T: Transport>(mut peer: T, settings: &Settings) -> io::Result<T> {
+    peer.send(&hello(settings))?;
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    loop {
+        match peer.receive()? {
+            Some(message @ Message::Hello { .. }) => {
+                return match check_hello(&message, settings) {
+                    Ok(()) => Ok(peer),
+                    Err(reason) => {
+                        let _ = peer.send(&Message::Refused {
+                            reason: reason.clone(),
+                        });
+                        Err(invalid(reason))
+                    }
+                };
+            }
+            Some(Message::Refused { reason }) => {
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, reason))
+            }
+            Some(_) => {}
+            None if Instant::now() >= deadline => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Peer never said hello, it may be an older build",
+                ))
+            }
+            None => thread::sleep(HANDSHAKE_TICK),
+        }
+    }
+}
+
+fn encode_grid(out: &mut Vec<u8>, grid: &Renderable) {
+    let cols = grid.first().map_or(0, |row| row.len());
+    out.extend_from_slice(&(grid.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(cols as u16).to_le_bytes());
+    for row in grid.bytes() {
+        out.extend_from_slice(&row);
+    }
+}
+
+// Length prefixed, cut to `MAX_TEXT` bytes on a character boundary
+fn encode_text(out: &mut Vec<u8>, text: &str) {
+    let mut len = text.len().min(MAX_TEXT);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    out.push(len as u8);
+    out.extend_from_slice(&text.as_bytes()[..len]);
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn is_done(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    // Everything not read yet, for messages wrapping another one
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.pos..];
+        self.pos = self.bytes.len();
+        rest
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err(invalid("message is truncated"));
+        }
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn action(&mut self) -> io::Result<Action> {
+        Action::from_byte(self.u8()?).ok_or_else(|| invalid("unknown action"))
+    }
+
+    fn text(&mut self) -> io::Result<String> {
+        let len = self.u8()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn grid(&mut self) -> io::Result<Renderable> {
+        let rows = self.u16()? as usize;
+        let cols = self.u16()? as usize;
+        let cells = self.take(rows * cols)?;
+        Ok(Renderable(
+            cells
+                .chunks(cols.max(1))
+                .map(|row| row.iter().copied().map(Cell::from_byte).collect())
+                .take(rows)
+                .collect(),
+        ))
+    }
+}
@@ -0,0 +1,166 @@
+use crate::bootstrap::{
+    Action, Cell, FrameOutput, Game, GameChange, GameMode, MenuItem, MenuMode, Redraw, Renderable,
+    Settings, Timestamp,
+};
+use crate::game::tetris::metrics::Metrics;
+use crate::game::tetris::{menu_action, MenuOutcome, TetrisPause};
+use crate::net::{Message, Transport};
+use std::mem;
+
+/// Watches a match through the server: the boards of both players side by side,
+/// each with the garbage on its way. Nothing is sent but leaving.
+pub struct Observer<T: Transport> {
+    boards: Vec<(GameMode, Redraw)>,
+    peer: T,
+    menu: Option<MenuMode<TetrisPause>>,
+    connected: bool,
+}
+
+impl<T: Transport> Observer<T> {
+    pub fn new(settings: Settings, peer: T) -> Observer<T> {
+        let board = GameMode {
+            main: Renderable(vec![
+                vec![Cell::Empty; settings.cols as usize];
+                settings.rows as usize
+            ]),
+            preview: Renderable(vec![vec![]]),
+            hold: Renderable(vec![vec![]]),
+            score: 0,
+            level: settings.level,
+            clearing: vec![],
+            metrics: Metrics::default(),
+            chat: None,
+            garbage: 0,
+            pace: None,
+            goals: vec![],
+        };
+        Observer {
+            boards: vec![(board.clone(), Redraw::full()), (board, Redraw::full())],
+            peer,
+            menu: None,
+            connected: true,
+        }
+    }
+
+    fn disconnected(&mut self) {
+        self.connected = false;
+        self.menu = Some(MenuMode::new(vec![
+            MenuItem::label(TetrisPause::Title, "Match Closed"),
+            MenuItem::button(TetrisPause::Exit, "Exit"),
+        ]));
+    }
+
+    // Returns whether a board or the connection changed
+    fn receive(&mut self) -> bool {
+        let mut changed = false;
+        while self.connected {
+            let (seat, message) = match self.peer.receive() {
+                Ok(Some(Message::Seat { seat, message })) => (seat as usize, *message),
+                Ok(Some(Message::Leave)) | Err(_) => {
+                    self.disconnected();
+                    changed = true;
+                    break;
+                }
+                Ok(Some(_)) => continue,
+                Ok(None) => break,
+            };
+            // Rooms with more players only show the first two
+            let (board, redraw) = match self.boards.get_mut(seat) {
+                Some(board) => board,
+                None => continue,
+            };
+            match message {
+                Message::Board {
+                    main,
+                    preview,
+                    score,
+                    garbage,
+                    ..
+                } => {
+                    redraw.score |= board.score != score;
+                    redraw.garbage |= board.garbage != garbage;
+                    board.main = main;
+                    board.preview = preview;
+                    board.score = score;
+                    board.garbage = garbage;
+                    redraw.field = true;
+                    redraw.preview = true;
+                }
+                Message::GameOver { .. } => {
+                    board.chat = Some("Topped out".to_string());
+                    redraw.chat = true;
+                }
+                Message::Restart => {
+                    board.chat = None;
+                    redraw.chat = true;
+                }
+                Message::Chat { text } => {
+                    board.chat = Some(text);
+                    redraw.chat = true;
+                }
+                Message::Leave => {
+                    board.chat = Some("Left".to_string());
+                    redraw.chat = true;
+                }
+                _ => continue,
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    fn output(&mut self) -> Vec<FrameOutput> {
+        self.boards
+            .iter_mut()
+            .map(|(board, redraw)| FrameOutput {
+                game: board.clone(),
+                redraw: mem::take(redraw),
+            })
+            .collect()
+    }
+}
+
+impl<T: Transport> Game for Observer<T> {
+    type Pause = TetrisPause;
+    type Input = Action;
+
+    fn frame(&mut self, _: Timestamp, action: Option<Action>) -> GameChange<'_, TetrisPause> {
+        let changed = self.receive();
+        match &mut self.menu {
+            Some(menu) => match menu_action(menu, action) {
+                MenuOutcome::Continue if self.connected => {
+                    self.menu = None;
+                    for (_, redraw) in self.boards.iter_mut() {
+                        *redraw = Redraw::full();
+                    }
+                }
+                MenuOutcome::Exit => {
+                    let _ = self.peer.send(&Message::Leave);
+                    return GameChange::Exit;
+                }
+                MenuOutcome::Unchanged if !changed => return GameChange::Idle,
+                _ => {}
+            },
+            None if matches!(action, Some(Action::Escape)) => self.pause(),
+            None if !changed => return GameChange::Idle,
+            None => {}
+        }
+        match self.menu {
+            Some(ref menu) => GameChange::Text(menu),
+            None => GameChange::Split(self.output()),
+        }
+    }
+
+    fn pause(&mut self) {
+        if self.menu.is_none() {
+            self.menu = Some(MenuMode::new(vec![
+                MenuItem::label(TetrisPause::Title, "Watching"),
+                MenuItem::button(TetrisPause::Continue, "Continue"),
+                MenuItem::button(TetrisPause::Exit, "Exit"),
+            ]));
+        }
+    }
+
+    // Nothing to restart, the players decide when a new game starts
+    fn restart(&mut self, _: Timestamp) {}
+}
@@ -0,0 +1,305 @@
+use crate::bootstrap::{
+    Action, Cell, FrameOutput, Game, GameChange, GameMode, MenuItem, MenuMode, Redraw, Renderable,
+    Settings, Timestamp,
+};
+use crate::game::tetris::attack::AttackTable;
+use crate::game::tetris::handicap::Handicap;
+use crate::game::tetris::metrics::Metrics;
+use crate::game::tetris::{
+    menu_action, MenuOutcome, Tetris, TetrisPause, EXIT_QUESTION, RESTART_QUESTION,
+};
+use crate::net::{check_hello, Message, Transport};
+use std::mem;
+
+/// Versus against a player on another machine. Only the local board is simulated,
+/// the remote one is drawn from the board states the other side sends.
+pub struct RemoteVersus<T: Transport> {
+    settings: Settings,
+    start: Timestamp,
+    local: Tetris,
+    remote: GameMode,
+    remote_redraw: Redraw,
+    peer: T,
+    menu: Option<MenuMode<TetrisPause>>,
+    // The local board does not see the menu, the time it was open is left out of it
+    paused: Option<Timestamp>,
+    connected: bool,
+}
+
+impl<T: Transport> RemoteVersus<T> {
+    pub fn new(settings: Settings, start: Timestamp, peer: T) -> RemoteVersus<T> {
+        RemoteVersus {
+            settings,
+            start,
+            local: Tetris::new(settings, start),
+            remote: Self::empty_board(&settings),
+            remote_redraw: Redraw::full(),
+            peer,
+            menu: None,
+            paused: None,
+            connected: true,
+        }
+    }
+
+    // Only the local board is ours to handicap, the other side applies its own
+    pub fn with_handicap(mut self, handicap: Handicap) -> Self {
+        self.local.set_handicap(handicap);
+        self
+    }
+
+    // Both sides should agree on it, otherwise attacks are unfair
+    pub fn set_attack_table(&mut self, table: AttackTable) {
+        self.local.set_attack_table(table);
+    }
+
+    // Hosts that drive the transport themselves need to reach it between frames
+    pub fn peer_mut(&mut self) -> &mut T {
+        &mut self.peer
+    }
+
+    fn empty_board(settings: &Settings) -> GameMode {
+        GameMode {
+            main: Renderable(vec![
+                vec![Cell::Empty; settings.cols as usize];
+                settings.rows as usize
+            ]),
+            preview: Renderable(vec![vec![]]),
+            hold: Renderable(vec![vec![]]),
+            score: 0,
+            level: settings.level,
+            clearing: vec![],
+            metrics: Metrics::default(),
+            chat: None,
+            garbage: 0,
+            pace: None,
+            goals: vec![],
+        }
+    }
+
+    fn send(&mut self, message: Message) {
+        if self.connected && self.peer.send(&message).is_err() {
+            self.disconnected();
+        }
+    }
+
+    fn disconnected(&mut self) {
+        self.closed("Opponent Left");
+    }
+
+    fn closed(&mut self, reason: &str) {
+        self.connected = false;
+        self.menu = Some(MenuMode::new(vec![
+            MenuItem::label(TetrisPause::Title, reason),
+            MenuItem::button(TetrisPause::Exit, "Exit"),
+        ]));
+    }
+
+    // Handles everything that came from the other side, returns whether the remote board changed
+    fn receive(&mut self, now: Timestamp) -> bool {
+        let mut changed = false;
+        while self.connected {
+            let message = match self.peer.receive() {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(_) => {
+                    self.disconnected();
+                    break;
+                }
+            };
+            match message {
+                // Lockstep and lobby only
+                Message::Input { .. }
+                | Message::Seed { .. }
+                | Message::Inputs { .. }
+                | Message::Hash { .. }
+                | Message::Ready
+                | Message::Handicap { .. } => {}
+                // Transports without a handshake of their own say hello in the match
+                message @ Message::Hello { .. } => {
+                    if let Err(reason) = check_hello(&message, &self.settings) {
+                        let _ = self.peer.send(&Message::Refused {
+                            reason: reason.clone(),
+                        });
+                        self.closed(&reason);
+                        changed = true;
+                    }
+                }
+                Message::Refused { reason } => {
+                    self.closed(&reason);
+                    changed = true;
+                }
+                // Server only, it is done talking before the match
+                Message::Join { .. } | Message::Watch { .. } | Message::Seat { .. } => {}
+                Message::Garbage { lines, .. } => self.local.add_garbage(lines),
+                Message::Board {
+                    main,
+                    preview,
+                    score,
+                    garbage,
+                    ..
+                } => {
+                    self.remote_redraw.garbage |= self.remote.garbage != garbage;
+                    self.remote.garbage = garbage;
+                    self.remote.main = main;
+                    self.remote.preview = preview;
+                    self.remote_redraw.score |= self.remote.score != score;
+                    self.remote.score = score;
+                    self.remote_redraw.field = true;
+                    self.remote_redraw.preview = true;
+                    changed = true;
+                }
+                Message::GameOver { .. } => {
+                    if self.menu.is_none() {
+                        self.menu = Some(Self::over_menu("You Win"));
+                    }
+                }
+                Message::Restart => self.reset(now),
+                Message::Leave => self.disconnected(),
+                Message::Chat { text } => {
+                    self.show_chat(format!("Opponent: {}", text));
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    fn reset(&mut self, now: Timestamp) {
+        self.start = now;
+        self.local.restart(now);
+        self.remote = Self::empty_board(&self.settings);
+        self.remote_redraw = Redraw::full();
+        self.menu = None;
+        self.paused = None;
+    }
+
+    fn resume(&mut self, now: Timestamp) {
+        if self.local.is_over() {
+            return;
+        }
+        self.menu = None;
+        self.local
+            .skip(now.saturating_sub(self.paused.take().unwrap_or(now)));
+        self.local.invalidate();
+        self.remote_redraw.full = true;
+    }
+
+    // Chat lines go under the opponent's board, whoever wrote them
+    fn show_chat(&mut self, line: String) {
+        self.remote.chat = Some(line);
+        self.remote_redraw.chat = true;
+    }
+
+    fn pause_menu() -> MenuMode<TetrisPause> {
+        let presets = CHAT_PRESETS
+            .iter()
+            .enumerate()
+            .map(|(i, &text)| MenuItem::button(TetrisPause::Chat(i), text))
+            .collect();
+        MenuMode::new(vec![
+            MenuItem::label(TetrisPause::Title, "Menu"),
+            MenuItem::button(TetrisPause::Continue, "Continue"),
+            MenuItem::submenu(TetrisPause::Title, "Chat", presets),
+            MenuItem::confirm(TetrisPause::Restart, "New Game", RESTART_QUESTION),
+            MenuItem::confirm(TetrisPause::Exit, "Exit", EXIT_QUESTION),
+        ])
+    }
+
+    fn over_menu(title: &str) -> MenuMode<TetrisPause> {
+        MenuMode::new(vec![
+            MenuItem::label(TetrisPause::Title, title),
+            MenuItem::button(TetrisPause::Restart, "New Game"),
+            MenuItem::button(TetrisPause::Exit, "Exit"),
+        ])
+    }
+
+    fn remote_output(&mut self) -> FrameOutput {
+        FrameOutput {
+            game: self.remote.clone(),
+            redraw: mem::take(&mut self.remote_redraw),
+        }
+    }
+}
+
+impl<T: Transport> Game for RemoteVersus<T> {
+    type Pause = TetrisPause;
+    type Input = Action;
+
+    fn frame(&mut self, now: Timestamp, action: Option<Action>) -> GameChange<'_, TetrisPause> {
+        let time = now.saturating_sub(self.start);
+        let remote_changed = self.receive(now);
+        let mut local = None;
+        if self.menu.is_some() {
+            self.paused.get_or_insert(now);
+        }
+        match &mut self.menu {
+            Some(menu) => match menu_action(menu, action) {
+                MenuOutcome::Continue => self.resume(now),
+                MenuOutcome::Restart => {
+                    self.send(Message::Restart);
+                    return GameChange::Restart;
+                }
+                MenuOutcome::Exit => {
+                    self.send(Message::Leave);
+                    return GameChange::Exit;
+                }
+                MenuOutcome::Selected(TetrisPause::Chat(i)) => {
+                    let text = CHAT_PRESETS[i].to_string();
+                    self.show_chat(format!("You: {}", text));
+                    self.send(Message::Chat { text });
+                    self.resume(now);
+                }
+                MenuOutcome::Changed | MenuOutcome::Selected(_) => {}
+                MenuOutcome::Unchanged => return GameChange::Idle,
+            },
+            None if matches!(action, Some(Action::Escape)) => self.pause(),
+            None => {
+                if let Some(action) = action {
+                    self.send(Message::Input { time, action });
+                }
+                if let GameChange::Draw(frame) = self.local.frame(now, action) {
+                    local = Some(*frame);
+                }
+                let lines = self.local.take_attack() as u16;
+                if lines > 0 {
+                    self.send(Message::Garbage { time, lines });
+                }
+                if let Some(frame) = &local {
+                    self.send(Message::Board {
+                        time,
+                        main: frame.game.main.clone(),
+                        preview: frame.game.preview.clone(),
+                        score: frame.game.score,
+                        garbage: frame.game.garbage,
+                    });
+                }
+                if self.local.is_over() && self.connected {
+                    self.send(Message::GameOver { time });
+                    self.menu = Some(Self::over_menu("You Lose"));
+                } else if local.is_none() && !remote_changed {
+                    return GameChange::Idle;
+                }
+            }
+        }
+        match self.menu {
+            Some(ref menu) => GameChange::Text(menu),
+            None => {
+                let local = local.unwrap_or_else(|| self.local.output());
+                GameChange::Split(vec![local, self.remote_output()])
+            }
+        }
+    }
+
+    fn pause(&mut self) {
+        if self.menu.is_none() {
+            self.menu = Some(Self::pause_menu());
+        }
+    }
+
+    fn restart(&mut self, now: Timestamp) {
+        self.reset(now);
+    }
+}
+
+// Lines the pause menu offers to send
+const CHAT_PRESETS: [&str; 5] = ["Good luck!", "Nice one", "Oops", "GG", "Rematch?"];
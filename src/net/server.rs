@@ -0,0 +1,407 @@
+use crate::bootstrap::{Settings, Timestamp};
+use crate::game::tetris::attack::Targeting;
+use crate::game::tetris::random::Random;
+use crate::net::tcp::TcpPeer;
+use crate::net::{hello, Message, Transport, PROTOCOL_VERSION};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Relay for players that cannot reach each other directly, like behind NAT.
+/// Clients say hello, join a room by name and once the room is full everything
+/// they send is passed on to the others in the room. Garbage is the exception,
+/// with more than one opponent the targeting decides who gets it. Wins are
+/// counted per room. Observers can watch a room, they get everything the players
+/// send marked with the seat it came from.
+pub struct Server {
+    listener: TcpListener,
+    clients: HashMap<usize, Client>,
+    rooms: HashMap<String, Room>,
+    // Players a room waits for before the match starts
+    room_size: usize,
+    targeting: Targeting,
+    random: Random,
+}
+
+struct Client {
+    stream: TcpStream,
+    hello: bool,
+    room: Option<String>,
+    // Observing the room instead of playing in it
+    watching: bool,
+}
+
+// Players in the order they joined
+#[derive(Default)]
+struct Room {
+    seats: Vec<Seat>,
+    observers: Vec<usize>,
+}
+
+struct Seat {
+    client: usize,
+    wins: u32,
+    // Last score the player's board showed, for targeting the leader
+    score: u32,
+    // Not topped out in the current round
+    playing: bool,
+}
+
+impl Room {
+    fn seat(&mut self, client: usize) -> Option<&mut Seat> {
+        self.seats.iter_mut().find(|seat| seat.client == client)
+    }
+
+    fn others(&self, client: usize) -> Vec<usize> {
+        self.seats
+            .iter()
+            .map(|seat| seat.client)
+            .filter(|&other| other != client)
+            .collect()
+    }
+
+    fn wins(&self) -> String {
+        let wins: Vec<String> = self
+            .seats
+            .iter()
+            .map(|seat| seat.wins.to_string())
+            .collect();
+        wins.join("-")
+    }
+}
+
+enum Event {
+    Connected(usize, TcpStream),
+    Received(usize, io::Result<Message>),
+}
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            clients: HashMap::new(),
+            rooms: HashMap::new(),
+            room_size: 2,
+            targeting: Targeting::default(),
+            random: Random::new(0),
+        })
+    }
+
+    pub fn with_room_size(mut self, players: usize) -> Server {
+        self.room_size = players.max(2);
+        self
+    }
+
+    pub fn with_targeting(mut self, targeting: Targeting) -> Server {
+        self.targeting = targeting;
+        self
+    }
+
+    /// Serves clients until accepting fails
+    pub fn run(mut self) -> io::Result<()> {
+        let (tx, events) = mpsc::channel();
+        let listener = self.listener.try_clone()?;
+        let accepting = thread::spawn(move || Server::accept(listener, tx));
+        for event in events {
+            match event {
+                Event::Connected(id, stream) => {
+                    let client = Client {
+                        stream,
+                        hello: false,
+                        room: None,
+                        watching: false,
+                    };
+                    self.clients.insert(id, client);
+                }
+                Event::Received(id, Ok(message)) => self.handle(id, message),
+                Event::Received(id, Err(_)) => self.disconnect(id),
+            }
+        }
+        accepting
+            .join()
+            .unwrap_or_else(|_| Err(io::ErrorKind::Other.into()))
+    }
+
+    // Every client gets a thread reading its messages into the event queue
+    fn accept(listener: TcpListener, tx: Sender<Event>) -> io::Result<()> {
+        for (id, stream) in listener.incoming().enumerate() {
+            let stream = stream?;
+            stream.set_nodelay(true)?;
+            let mut reader = stream.try_clone()?;
+            if tx.send(Event::Connected(id, stream)).is_err() {
+                return Ok(());
+            }
+            let tx = tx.clone();
+            thread::spawn(move || loop {
+                let message = Message::read_from(&mut reader);
+                let failed = message.is_err();
+                if tx.send(Event::Received(id, message)).is_err() || failed {
+                    break;
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, id: usize, message: Message) {
+        let hello = self.clients.get(&id).is_some_and(|client| client.hello);
+        let watching = self.clients.get(&id).is_some_and(|client| client.watching);
+        match message {
+            // Once in a room players say hello to each other, the server only
+            // checks the protocol and leaves rules and settings to them
+            Message::Hello { .. } if hello && !watching => self.relay(id, &message),
+            Message::Hello { .. } if hello => {}
+            Message::Hello { version, .. } if version == PROTOCOL_VERSION => {
+                if let Some(client) = self.clients.get_mut(&id) {
+                    client.hello = true;
+                }
+            }
+            Message::Hello { version, build, .. } => self.refuse(
+                id,
+                &format!(
+                    "Protocol {} of {} is not supported, need {}",
+                    version, build, PROTOCOL_VERSION
+                ),
+            ),
+            _ if !hello => self.refuse(id, "Expected hello first"),
+            // Observers have nothing to say to the players
+            Message::Leave if watching => self.leave(id),
+            _ if watching => {}
+            Message::Join { room } => self.join(id, room),
+            Message::Watch { room } => self.watch(id, room),
+            Message::Garbage { time, lines } => self.target(id, time, lines),
+            message => {
+                match message {
+                    Message::Board { score, .. } => {
+                        if let Some(seat) = self.room_mut(id).and_then(|room| room.seat(id)) {
+                            seat.score = score;
+                        }
+                    }
+                    Message::GameOver { .. } => self.lost(id),
+                    Message::Restart => {
+                        for seat in self
+                            .room_mut(id)
+                            .into_iter()
+                            .flat_map(|room| room.seats.iter_mut())
+                        {
+                            seat.score = 0;
+                            seat.playing = true;
+                        }
+                    }
+                    _ => {}
+                }
+                let leaving = matches!(message, Message::Leave);
+                self.relay(id, &message);
+                if leaving {
+                    self.leave(id);
+                }
+            }
+        }
+    }
+
+    fn room_mut(&mut self, id: usize) -> Option<&mut Room> {
+        let name = self.clients.get(&id)?.room.as_ref()?;
+        self.rooms.get_mut(name)
+    }
+
+    // Garbage goes to the opponents still playing, as the targeting splits it
+    fn target(&mut self, from: usize, time: Timestamp, lines: u16) {
+        let opponents: Vec<(usize, u32)> = match self.room_mut(from) {
+            Some(room) => room
+                .seats
+                .iter()
+                .filter(|seat| seat.client != from && seat.playing)
+                .map(|seat| (seat.client, seat.score))
+                .collect(),
+            None => return,
+        };
+        let scores: Vec<u32> = opponents.iter().map(|&(_, score)| score).collect();
+        let split = self
+            .targeting
+            .split(lines as u32, &scores, &mut self.random);
+        for (&(client, _), &lines) in opponents.iter().zip(split.iter()) {
+            if lines > 0 {
+                let lines = lines as u16;
+                self.send(client, &Message::Garbage { time, lines });
+            }
+        }
+    }
+
+    fn join(&mut self, id: usize, name: String) {
+        let in_room = self
+            .clients
+            .get(&id)
+            .is_some_and(|client| client.room.is_some());
+        let room = self.rooms.entry(name.clone()).or_default();
+        if in_room || room.seats.len() >= self.room_size {
+            return self.refuse(id, "Room is full");
+        }
+        room.seats.push(Seat {
+            client: id,
+            wins: 0,
+            score: 0,
+            playing: true,
+        });
+        let players: Vec<usize> = room.seats.iter().map(|seat| seat.client).collect();
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.room = Some(name.clone());
+        }
+        if players.len() == self.room_size {
+            log::info!("Room {}: match started", name);
+            let observers = room.observers.clone();
+            for client in players.into_iter().chain(observers) {
+                self.send(client, &Message::Ready);
+            }
+        }
+    }
+
+    // Observers of a room that is not full yet wait for the match like the players
+    fn watch(&mut self, id: usize, name: String) {
+        if self
+            .clients
+            .get(&id)
+            .is_some_and(|client| client.room.is_some())
+        {
+            return self.refuse(id, "Already in a room");
+        }
+        let room = self.rooms.entry(name.clone()).or_default();
+        room.observers.push(id);
+        let started = room.seats.len() == self.room_size;
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.room = Some(name);
+            client.watching = true;
+        }
+        if started {
+            self.send(id, &Message::Ready);
+        }
+    }
+
+    // The last one still playing wins the round
+    fn lost(&mut self, id: usize) {
+        let name = match self.clients.get(&id).and_then(|client| client.room.clone()) {
+            Some(name) => name,
+            None => return,
+        };
+        if let Some(room) = self.rooms.get_mut(&name) {
+            if let Some(seat) = room.seat(id) {
+                seat.playing = false;
+            }
+            let mut playing = room.seats.iter_mut().filter(|seat| seat.playing);
+            if let (Some(winner), None) = (playing.next(), playing.next()) {
+                winner.wins += 1;
+                winner.playing = false;
+                log::info!("Room {}: round over, wins {}", name, room.wins());
+            }
+        }
+    }
+
+    fn relay(&mut self, from: usize, message: &Message) {
+        let (others, observers, seat) = match self.room_mut(from) {
+            Some(room) => match room.seats.iter().position(|seat| seat.client == from) {
+                Some(seat) => (room.others(from), room.observers.clone(), seat),
+                None => return,
+            },
+            None => return,
+        };
+        for other in others {
+            self.send(other, message);
+        }
+        let seat = Message::Seat {
+            seat: seat as u8,
+            message: Box::new(message.clone()),
+        };
+        for observer in observers {
+            self.send(observer, &seat);
+        }
+    }
+
+    fn send(&mut self, id: usize, message: &Message) {
+        let failed = match self.clients.get_mut(&id) {
+            Some(client) => message.write_to(&mut client.stream).is_err(),
+            None => false,
+        };
+        if failed {
+            self.disconnect(id);
+        }
+    }
+
+    fn refuse(&mut self, id: usize, reason: &str) {
+        log::warn!("Client {} refused: {}", id, reason);
+        let refused = Message::Refused {
+            reason: reason.to_string(),
+        };
+        if let Some(client) = self.clients.get_mut(&id) {
+            let _ = refused.write_to(&mut client.stream);
+            let _ = client.stream.shutdown(Shutdown::Both);
+        }
+        self.disconnect(id);
+    }
+
+    // The rest of the room hears that the client left
+    fn disconnect(&mut self, id: usize) {
+        if self.clients.contains_key(&id) {
+            self.relay(id, &Message::Leave);
+            self.leave(id);
+            self.clients.remove(&id);
+        }
+    }
+
+    fn leave(&mut self, id: usize) {
+        let name = match self
+            .clients
+            .get_mut(&id)
+            .and_then(|client| client.room.take())
+        {
+            Some(name) => name,
+            None => return,
+        };
+        if let Some(room) = self.rooms.get_mut(&name) {
+            room.seats.retain(|seat| seat.client != id);
+            room.observers.retain(|&observer| observer != id);
+            if room.seats.is_empty() && room.observers.is_empty() {
+                self.rooms.remove(&name);
+                log::info!("Room {}: closed", name);
+            }
+        }
+    }
+}
+
+/// Client side: connects to a server and waits in the room until the match starts,
+/// the connection then works like a direct one
+pub fn join(addr: impl ToSocketAddrs, room: &str, settings: &Settings) -> io::Result<TcpPeer> {
+    let mut peer = TcpPeer::connect(addr)?;
+    peer.send(&hello(settings))?;
+    peer.send(&Message::Join {
+        room: room.to_string(),
+    })?;
+    wait_for_match(peer)
+}
+
+/// Client side of an observer, the connection then gets `Message::Seat` for
+/// everything the players send
+pub fn watch(addr: impl ToSocketAddrs, room: &str, settings: &Settings) -> io::Result<TcpPeer> {
+    let mut peer = TcpPeer::connect(addr)?;
+    peer.send(&hello(settings))?;
+    peer.send(&Message::Watch {
+        room: room.to_string(),
+    })?;
+    wait_for_match(peer)
+}
+
+fn wait_for_match(mut peer: TcpPeer) -> io::Result<TcpPeer> {
+    loop {
+        match peer.receive()? {
+            Some(Message::Ready) => return Ok(peer),
+            Some(Message::Refused { reason }) => {
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, reason))
+            }
+            Some(_) => {}
+            None => thread::sleep(WAIT_TICK),
+        }
+    }
+}
+
+const WAIT_TICK: Duration = Duration::from_millis(10);
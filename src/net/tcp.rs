@@ -0,0 +1,54 @@
+use crate::net::{Message, Transport};
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Connection to the other player. Incoming messages are read by a background thread,
+/// so receiving never blocks the game loop.
+pub struct TcpPeer {
+    stream: TcpStream,
+    inbox: Receiver<io::Result<Message>>,
+}
+
+impl TcpPeer {
+    // Waits for a single player to connect
+    pub fn host(addr: impl ToSocketAddrs) -> io::Result<TcpPeer> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        TcpPeer::from_stream(stream)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<TcpPeer> {
+        TcpPeer::from_stream(TcpStream::connect(addr)?)
+    }
+
+    // Connection made elsewhere, like by a lobby polling its listener
+    pub fn from_stream(stream: TcpStream) -> io::Result<TcpPeer> {
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        let (tx, inbox) = mpsc::channel();
+        thread::spawn(move || loop {
+            let message = Message::read_from(&mut reader);
+            let failed = message.is_err();
+            if tx.send(message).is_err() || failed {
+                break;
+            }
+        });
+        Ok(TcpPeer { stream, inbox })
+    }
+}
+
+impl Transport for TcpPeer {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        message.write_to(&mut self.stream)
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        match self.inbox.try_recv() {
+            Ok(message) => message.map(Some),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(io::ErrorKind::ConnectionAborted.into()),
+        }
+    }
+}
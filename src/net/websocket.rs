@@ -0,0 +1,329 @@
+use crate::net::{invalid, Message, Transport};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Messages sent as binary WebSocket frames, so browsers can join console players.
+/// Only what netplay needs is implemented: no extensions, no TLS.
+pub struct WsPeer {
+    writer: Arc<Mutex<TcpStream>>,
+    inbox: Receiver<io::Result<Message>>,
+    // Clients have to mask their frames, servers must not
+    masked: bool,
+}
+
+impl WsPeer {
+    // Waits for a single client and completes its handshake
+    pub fn host(addr: impl ToSocketAddrs) -> io::Result<WsPeer> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let headers = read_head(&mut reader)?;
+        let key =
+            header(&headers, "sec-websocket-key").ok_or_else(|| invalid("no websocket key"))?;
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(&key)
+        );
+        (&stream).write_all(response.as_bytes())?;
+        WsPeer::start(stream, reader, false)
+    }
+
+    // Accepts urls like ws://example.com:7879/room
+    pub fn connect(url: &str) -> io::Result<WsPeer> {
+        let rest = url
+            .strip_prefix("ws://")
+            .ok_or_else(|| invalid("only ws:// urls are supported"))?;
+        let (host, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+        let stream = TcpStream::connect(host)?;
+        let key = base64(&mask_key(16));
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path, host, key
+        );
+        (&stream).write_all(request.as_bytes())?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let headers = read_head(&mut reader)?;
+        let status_ok = headers
+            .first()
+            .is_some_and(|line| line.split(' ').nth(1) == Some("101"));
+        if !status_ok || header(&headers, "sec-websocket-accept") != Some(accept_key(&key)) {
+            return Err(invalid("websocket handshake failed"));
+        }
+        WsPeer::start(stream, reader, true)
+    }
+
+    fn start(
+        stream: TcpStream,
+        mut reader: BufReader<TcpStream>,
+        masked: bool,
+    ) -> io::Result<WsPeer> {
+        stream.set_nodelay(true)?;
+        let writer = Arc::new(Mutex::new(stream));
+        let pong = Arc::clone(&writer);
+        let (tx, inbox) = mpsc::channel();
+        thread::spawn(move || loop {
+            let message = match read_frame(&mut reader) {
+                Ok((OP_PING, payload)) => match pong.lock() {
+                    Ok(mut stream) => match write_frame(&mut *stream, OP_PONG, &payload, masked) {
+                        Ok(_) => continue,
+                        Err(e) => Err(e),
+                    },
+                    Err(_) => break,
+                },
+                Ok((OP_CLOSE, _)) => Err(io::ErrorKind::ConnectionAborted.into()),
+                Ok((OP_BINARY, payload)) => Message::decode(&payload),
+                Ok(_) => continue,
+                Err(e) => Err(e),
+            };
+            let failed = message.is_err();
+            if tx.send(message).is_err() || failed {
+                break;
+            }
+        });
+        Ok(WsPeer {
+            writer,
+            inbox,
+            masked,
+        })
+    }
+}
+
+impl Transport for WsPeer {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        let mut stream = self
+            .writer
+            .lock()
+            .map_err(|_| invalid("connection is broken"))?;
+        write_frame(&mut *stream, OP_BINARY, &message.encode(), self.masked)
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        match self.inbox.try_recv() {
+            Ok(message) => message.map(Some),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(io::ErrorKind::ConnectionAborted.into()),
+        }
+    }
+}
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+// Same limit as for plain streams, browsers never need more
+const MAX_FRAME: u64 = 64 * 1024;
+
+// Reads a whole message, gluing fragmented frames together. Returns opcode and payload.
+fn read_frame(reader: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut opcode = None;
+    let mut payload = vec![];
+    loop {
+        let mut head = [0u8; 2];
+        reader.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let op = head[0] & 0x0F;
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                reader.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                reader.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        if payload.len() as u64 + len > MAX_FRAME {
+            return Err(invalid("frame is too long"));
+        }
+        let mut mask = None;
+        if head[1] & 0x80 != 0 {
+            let mut key = [0u8; 4];
+            reader.read_exact(&mut key)?;
+            mask = Some(key);
+        }
+        let start = payload.len();
+        payload.resize(start + len as usize, 0);
+        reader.read_exact(&mut payload[start..])?;
+        if let Some(key) = mask {
+            for (i, byte) in payload[start..].iter_mut().enumerate() {
+                *byte ^= key[i % 4];
+            }
+        }
+        if op != OP_CONTINUATION {
+            opcode = Some(op);
+        }
+        if fin {
+            return Ok((opcode.unwrap_or(OP_BINARY), payload));
+        }
+    }
+}
+
+fn write_frame(
+    writer: &mut impl Write,
+    opcode: u8,
+    payload: &[u8],
+    masked: bool,
+) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    let mask_bit = if masked { 0x80 } else { 0 };
+    match payload.len() {
+        len if len < 126 => frame.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    if masked {
+        let key = mask_key(4);
+        frame.extend_from_slice(&key);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ key[i % 4]),
+        );
+    } else {
+        frame.extend_from_slice(payload);
+    }
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+// Request or status line followed by headers, up to the empty line
+fn read_head(reader: &mut impl BufRead) -> io::Result<Vec<String>> {
+    let mut lines = vec![];
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(lines);
+        }
+        lines.push(line.to_string());
+    }
+}
+
+fn header(lines: &[String], name: &str) -> Option<String> {
+    lines.iter().skip(1).find_map(|line| {
+        let idx = line.find(':')?;
+        if line[..idx].trim().eq_ignore_ascii_case(name) {
+            Some(line[idx + 1..].trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+fn accept_key(key: &str) -> String {
+    let mut input = key.as_bytes().to_vec();
+    input.extend_from_slice(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
+    base64(&sha1(&input))
+}
+
+// Masking only keeps proxies from caching frames, it does not need a good random source
+fn mask_key(len: usize) -> Vec<u8> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let mut state = nanos | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                block[4 * i],
+                block[4 * i + 1],
+                block[4 * i + 2],
+                block[4 * i + 3],
+            ]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+    let mut out = [0u8; 20];
+    for (i, word) in h.iter().enumerate() {
+        out[4 * i..4 * i + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
@@ -0,0 +1,146 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+// Flipped from whatever thread reads the keys, game loops look at it every frame
+static OVERLAY: AtomicBool = AtomicBool::new(false);
+// Allocations since the start, by the allocator wrapped in `Counting`
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+// Frames per second are counted over this many microseconds
+const FPS_WINDOW: u64 = 1_000_000;
+
+// Span of the game loop for `--trace`, nothing at all without the trace feature
+#[cfg(feature = "trace")]
+macro_rules! span {
+    ($name:literal) => {
+        tracing::trace_span!($name).entered()
+    };
+}
+#[cfg(not(feature = "trace"))]
+macro_rules! span {
+    ($name:literal) => {
+        ()
+    };
+}
+pub(crate) use span;
+
+/// Lines of the overlay, see `FrameStats::lines`
+pub const LINES: usize = 4;
+
+/// Global allocator that counts allocations on top of another one, so the overlay
+/// shows how many a frame makes
+pub struct Counting<A>(pub A);
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Counting<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        self.0.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        self.0.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        self.0.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+}
+
+/// Allocations so far, always 0 unless the global allocator is `Counting`
+pub fn allocations() -> usize {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// Shows or hides the overlay of every game loop
+pub fn show(shown: bool) {
+    OVERLAY.store(shown, Ordering::Relaxed);
+}
+
+pub fn toggle() {
+    OVERLAY.fetch_xor(true, Ordering::Relaxed);
+}
+
+pub fn shown() -> bool {
+    OVERLAY.load(Ordering::Relaxed)
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
+}
+
+/// Microseconds from some fixed point, finer than the game clock
+#[cfg(target_arch = "wasm32")]
+pub fn micros() -> u64 {
+    (performance_now() * 1000.) as u64
+}
+
+/// Microseconds from some fixed point, finer than the game clock
+#[cfg(not(target_arch = "wasm32"))]
+pub fn micros() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
+/// Timing of the frames that were drawn while the overlay is shown. Frames that
+/// change nothing are not drawn and do not count.
+#[derive(Debug, Default, Clone)]
+pub struct FrameStats {
+    // Ends of the frames of the last second
+    recent: VecDeque<u64>,
+    // Microseconds from the start of the last frame to the end of its drawing
+    pub frame_time: u64,
+    // Microseconds from receiving the last input to the end of the frame showing it
+    pub latency: Option<u64>,
+    // Made by the last frame
+    pub allocations: usize,
+}
+
+impl FrameStats {
+    // Times in microseconds of `micros`
+    pub fn record(&mut self, start: u64, end: u64, input: Option<u64>, allocations: usize) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|&drawn| end.saturating_sub(drawn) >= FPS_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(end);
+        self.frame_time = end.saturating_sub(start);
+        if let Some(input) = input {
+            self.latency = Some(end.saturating_sub(input));
+        }
+        self.allocations = allocations;
+    }
+
+    pub fn fps(&self) -> usize {
+        self.recent.len()
+    }
+
+    // Like `Frame 1.25ms`, short enough for the sidebar
+    pub fn lines(&self) -> [String; LINES] {
+        let millis = |micros: u64| format!("{:.2}ms", micros as f32 / 1000.);
+        [
+            format!("FPS {}", self.fps()),
+            format!("Frame {}", millis(self.frame_time)),
+            format!("Input {}", self.latency.map_or("-".to_string(), millis)),
+            format!("Allocs {}", self.allocations),
+        ]
+    }
+}
@@ -1,15 +1,135 @@
-use crate::bootstrap::{Action, GameMode, MenuMode, Renderable, Settings};
 use crate::renderer::console::ConsoleSymbol::{Simple, Styled};
-use crossterm::event::{read, Event, KeyCode};
+use brick_game_wasm::bootstrap::{
+    Action, Cell, FrameOutput, Goal, MenuMode, Redraw, Renderable, Renderer, Settings,
+};
+use brick_game_wasm::game::tetris::metrics::Metrics;
+use brick_game_wasm::game::tetris::tetromino::Tetromino;
+use brick_game_wasm::game::tetris::versus::PlayerAction;
+#[cfg(feature = "netplay")]
+use brick_game_wasm::net::lobby::LobbyInput;
+use brick_game_wasm::perf::{self, FrameStats};
+use crossterm::event::{poll, read, Event, KeyCode};
 use crossterm::style::{Color, ContentStyle, Print, PrintStyledContent, StyledContent};
 use crossterm::{cursor, terminal, Command, ExecutableCommand, QueueableCommand};
-use std::cell::RefCell;
+use std::cell::{self, RefCell};
+use std::env;
 use std::fmt::{self, Display};
 use std::io::{self, stdout, Stdout, Write};
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Bumped by every `keypress`, so the reader of a game that ended stops instead of
+// taking the first key of the next one
+static READERS: AtomicUsize = AtomicUsize::new(0);
+// How often readers look whether they are still the newest one
+const READ_POLL: Duration = Duration::from_millis(50);
+// Set once at the start, views draw for old consoles from then on
+static COMPAT: AtomicBool = AtomicBool::new(false);
+// What block characters turn into when the console may not have them
+const ASCII_BLOCK: char = '#';
+
+// Basic colors in the order of their ANSI numbers, with the usual RGB of each
+const PALETTE: [(Color, [u8; 3]); 16] = [
+    (Color::Black, [0, 0, 0]),
+    (Color::DarkRed, [128, 0, 0]),
+    (Color::DarkGreen, [0, 128, 0]),
+    (Color::DarkYellow, [128, 128, 0]),
+    (Color::DarkBlue, [0, 0, 128]),
+    (Color::DarkMagenta, [128, 0, 128]),
+    (Color::DarkCyan, [0, 128, 128]),
+    (Color::Grey, [192, 192, 192]),
+    (Color::DarkGrey, [128, 128, 128]),
+    (Color::Red, [255, 0, 0]),
+    (Color::Green, [0, 255, 0]),
+    (Color::Yellow, [255, 255, 0]),
+    (Color::Blue, [0, 0, 255]),
+    (Color::Magenta, [255, 0, 255]),
+    (Color::Cyan, [0, 255, 255]),
+    (Color::White, [255, 255, 255]),
+];
+
+/// Draws with ASCII and the 16 basic colors from now on, for consoles like the old
+/// Windows console host that show block characters and other colors wrong
+pub fn set_compat(compat: bool) {
+    COMPAT.store(compat, Ordering::SeqCst);
+}
+
+/// Guess whether the terminal is one of those. On Windows only newer terminals say
+/// who they are, elsewhere the locale tells whether there is UTF-8. Terminals that
+/// say nothing are taken to be fine.
+pub fn legacy_terminal() -> bool {
+    if cfg!(windows) {
+        return ["WT_SESSION", "TERM_PROGRAM", "ConEmuANSI"]
+            .iter()
+            .all(|name| env::var_os(name).is_none());
+    }
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()));
+    let utf8 = |locale: &String| {
+        let locale = locale.to_uppercase();
+        locale.contains("UTF-8") || locale.contains("UTF8")
+    };
+    locale.is_some_and(|locale| !utf8(&locale)) || env::var("TERM").as_deref() == Ok("dumb")
+}
+
+// Closest basic color, the others are left as they are
+fn basic(color: Color) -> Color {
+    let rgb = match color {
+        Color::AnsiValue(value) if value < 16 => return PALETTE[value as usize].0,
+        // Grays after the 6x6x6 color cube
+        Color::AnsiValue(value) if value >= 232 => [8 + (value - 232) * 10; 3],
+        Color::AnsiValue(value) => {
+            let level = |step: u8| if step == 0 { 0 } else { 55 + step * 40 };
+            let cube = value - 16;
+            [level(cube / 36), level(cube / 6 % 6), level(cube % 6)]
+        }
+        Color::Rgb { r, g, b } => [r, g, b],
+        color => return color,
+    };
+    let distance = |other: &[u8; 3]| -> u32 {
+        rgb.iter()
+            .zip(other.iter())
+            .map(|(&a, &b)| (a as i32 - b as i32).pow(2) as u32)
+            .sum()
+    };
+    PALETTE
+        .iter()
+        .min_by_key(|(_, other)| distance(other))
+        .map_or(color, |(basic, _)| *basic)
+}
+
+// Board with the rows about to collapse drawn as outlines, so they flash before
+// they go
+fn outline_rows(main: &Renderable, rows: &[u16]) -> Renderable {
+    let mut board = main.clone();
+    for &y in rows {
+        if let Some(row) = board.0.get_mut(y as usize) {
+            row.iter_mut().for_each(|cell| *cell = Cell::Ghost);
+        }
+    }
+    board
+}
+
+// Longest garbage bar, more lines are shown with a plus
+const GARBAGE_METER: u16 = 8;
+
+// Rows kept for goal counters, drills have two
+const GOALS: usize = 2;
+// Width of a goal counter, the widest text of the sidebar
+const GOAL_WIDTH: u16 = 16;
+
+// Whether the terminal has room for what is drawn
+enum Room {
+    Enough,
+    // Too small, the message about it is shown instead
+    Lacking,
+    // Enough again after it was not, the screen is empty and drawn anew
+    Regained,
+}
 
 pub struct ConsoleView {
     settings: Settings,
@@ -18,6 +138,12 @@ pub struct ConsoleView {
     height: u16,
     char: char,
     color: Option<Color>,
+    // Main field as it is currently shown on the screen
+    drawn: RefCell<Renderable>,
+    // Horizontal position of the view in cells, for several boards on one screen
+    left: u16,
+    // Size of the terminal when it was found too small, the message is shown for it
+    cramped: cell::Cell<Option<(u16, u16)>>,
 }
 
 impl ConsoleView {
@@ -36,10 +162,94 @@ impl ConsoleView {
             height,
             char,
             color,
+            drawn: RefCell::new(Renderable(vec![])),
+            left: 0,
+            cramped: cell::Cell::new(None),
         }
     }
 
+    pub fn with_offset(mut self, left: u16) -> ConsoleView {
+        self.left = left;
+        self
+    }
+
+    // Width of the field with preview and borders in cells
+    pub fn panel_width(&self) -> u16 {
+        self.settings.cols + 8
+    }
+
+    // Terminal row of a line of sidebar text. The text starts under the preview and
+    // goes on a line per row whatever the size of the cells.
+    fn sidebar_row(&self, line: u16) -> u16 {
+        6 * self.height + line
+    }
+
+    /// Columns and rows of the terminal the view takes, from its left edge to the
+    /// end of the sidebar and down to the chat line or the frame overlay while it is shown
+    pub fn size(&self) -> (u16, u16) {
+        let sidebar = (self.left + self.settings.cols + 2) * self.width + GOAL_WIDTH;
+        let width = ((self.left + self.panel_width()) * self.width).max(sidebar);
+        let overlay = match perf::shown() {
+            true => self.stats_top() + perf::LINES as u16,
+            false => 0,
+        };
+        let height = ((self.settings.rows + 1) * self.height + 2).max(overlay);
+        (width, height)
+    }
+
+    // Blocks until the terminal is at least `need` large, before raw mode so the
+    // player can still leave with Ctrl+C
+    fn wait_for(&self, need: (u16, u16)) -> Result<()> {
+        let mut shown = None;
+        loop {
+            let size = terminal::size()?;
+            if size.0 >= need.0 && size.1 >= need.1 {
+                return Ok(());
+            }
+            if shown != Some(size) {
+                self.clear()?;
+                self.print_too_small(size, need)?;
+                shown = Some(size);
+            }
+            thread::sleep(READ_POLL);
+        }
+    }
+
+    // Checked before drawing anything. A terminal resized smaller than `need` gets a
+    // message instead of output at wrapped coordinates, and is cleared once it is
+    // large enough again.
+    fn room(&self, need: (u16, u16)) -> Result<Room> {
+        let size = terminal::size()?;
+        let fits = size.0 >= need.0 && size.1 >= need.1;
+        let shown = self.cramped.replace(Some(size).filter(|_| !fits));
+        match (fits, shown) {
+            (true, None) => Ok(Room::Enough),
+            (true, Some(_)) => {
+                self.clear()?;
+                Ok(Room::Regained)
+            }
+            (false, Some(shown)) if shown == size => Ok(Room::Lacking),
+            (false, _) => {
+                self.clear()?;
+                self.print_too_small(size, need)?;
+                Ok(Room::Lacking)
+            }
+        }
+    }
+
+    fn print_too_small(&self, size: (u16, u16), need: (u16, u16)) -> Result<()> {
+        let text = format!("Terminal too small: need {}x{}", need.0, need.1);
+        let left = (size.0 / 2).saturating_sub(text.len() as u16 / 2);
+        self.stdout
+            .borrow_mut()
+            .queue(cursor::MoveTo(left, size.1 / 2))?
+            .queue(Print(text))?;
+        self.stdout.borrow_mut().flush()?;
+        Ok(())
+    }
+
     pub fn prepare(&self) -> Result<()> {
+        self.wait_for(self.size())?;
         self.stdout.borrow_mut().execute(cursor::Hide)?;
         terminal::enable_raw_mode()?;
         self.clear()
@@ -52,8 +262,18 @@ impl ConsoleView {
         Ok(())
     }
 
+    // Hands the terminal back for line input, the reader of the last `keypress`
+    // stops
+    pub fn restore(&self) -> Result<()> {
+        READERS.fetch_add(1, Ordering::SeqCst);
+        terminal::disable_raw_mode()?;
+        self.stdout.borrow_mut().execute(cursor::Show)?;
+        Ok(())
+    }
+
     pub fn print_cell(&self, x: u16, y: u16, filled: bool, flush: bool) -> Result<()> {
         let symbol = self.styled(if filled { self.char } else { ' ' });
+        let x = x + self.left;
         for i in 0..self.width {
             for j in 0..self.height {
                 self.print_styled(x * self.width + i + 1, y * self.height + j + 1, &symbol)?
@@ -65,12 +285,34 @@ impl ConsoleView {
         Ok(())
     }
 
+    // Outline of a cell, brackets when the cell is wide enough
+    fn print_hint(&self, x: u16, y: u16) -> Result<()> {
+        let x = x + self.left;
+        for i in 0..self.width {
+            let symbol = if self.width == 1 {
+                '.'
+            } else if i == 0 {
+                '['
+            } else if i + 1 == self.width {
+                ']'
+            } else {
+                ' '
+            };
+            for j in 0..self.height {
+                let symbol = self.styled(symbol);
+                self.print_styled(x * self.width + i + 1, y * self.height + j + 1, &symbol)?
+            }
+        }
+        Ok(())
+    }
+
     /// Prints border for square area like this
     ///  +----+
     ///  |    |
     ///  |    |
     ///  +----+
     pub fn print_border(&self, left: u16, top: u16, cols: u16, rows: u16) -> Result<()> {
+        let left = left + self.left;
         let ceil = self.styled('-');
         let wall = self.styled('|');
         let corner = self.styled('+');
@@ -109,20 +351,31 @@ impl ConsoleView {
     fn empty(&self) -> Result<()> {
         for y in 0..self.settings.rows {
             for x in 0..self.settings.cols {
-                self.print_cell(x as u16, y as u16, false, false)?;
+                self.print_cell(x, y, false, false)?;
             }
         }
         Ok(())
     }
 
-    pub fn print_all(&self, frame: &Renderable) -> Result<()> {
+    // Unless `full` is set only cells that differ from the screen are printed
+    pub fn print_all(&self, frame: &Renderable, full: bool) -> Result<()> {
+        let mut drawn = self.drawn.borrow_mut();
         for (y, row) in frame.iter().enumerate() {
             for (x, cell) in row.iter().enumerate() {
-                if (x as u16) < self.settings.cols && (y as u16) < self.settings.rows {
-                    self.print_cell(x as u16, y as u16, *cell > 0u8, false)?;
+                let same = matches!(drawn.get(y).and_then(|r| r.get(x)), Some(c) if c == cell);
+                if (full || !same)
+                    && (x as u16) < self.settings.cols
+                    && (y as u16) < self.settings.rows
+                {
+                    if *cell == Cell::Ghost {
+                        self.print_hint(x as u16, y as u16)?;
+                    } else {
+                        self.print_cell(x as u16, y as u16, cell.is_filled(), false)?;
+                    }
                 }
             }
         }
+        *drawn = frame.clone();
         self.stdout.borrow_mut().flush()?;
         Ok(())
     }
@@ -134,7 +387,7 @@ impl ConsoleView {
                     self.print_cell(
                         self.settings.cols + 2 + x as u16,
                         y as u16,
-                        *cell > 0u8,
+                        cell.is_filled(),
                         false,
                     )?;
                 }
@@ -145,8 +398,8 @@ impl ConsoleView {
     }
 
     pub fn print_score(&self, score: u32) -> Result<()> {
-        let left = (self.settings.cols + 2) * self.width;
-        let top = 6 * self.height;
+        let left = (self.left + self.settings.cols + 2) * self.width;
+        let top = self.sidebar_row(0);
         self.stdout
             .borrow_mut()
             .execute(cursor::MoveTo(left, top))?;
@@ -155,25 +408,206 @@ impl ConsoleView {
         Ok(())
     }
 
-    pub fn draw_game(&self, game: &GameMode) -> Result<()> {
-        self.print_all(&game.main)?;
-        self.print_preview(&game.preview)?;
-        self.print_score(game.score)?;
+    // Letter of the held piece on the line under the score
+    pub fn print_hold(&self, hold: &Renderable) -> Result<()> {
+        let left = (self.left + self.settings.cols + 2) * self.width;
+        let top = self.sidebar_row(1);
+        let held = hold.iter().flatten().find_map(|&cell| match cell {
+            Cell::Piece(id) => Tetromino::from_id(id),
+            _ => None,
+        });
+        let line = match held {
+            Some(piece) => format!("Hold {}", piece.name()),
+            None => String::new(),
+        };
+        self.stdout
+            .borrow_mut()
+            .execute(cursor::MoveTo(left, top))?;
+        write!(self.stdout.borrow_mut(), "{:<10}", line)?;
+        self.stdout.borrow_mut().flush()?;
+        Ok(())
+    }
+
+    pub fn print_metrics(&self, metrics: &Metrics, level: u32, pace: Option<&str>) -> Result<()> {
+        let left = (self.left + self.settings.cols + 2) * self.width;
+        let top = self.sidebar_row(2);
+        let lines = [
+            metrics.clock(),
+            format!("Level {}", level),
+            format!("Lines {}", metrics.lines),
+            format!("PPS {:.2}", metrics.pps()),
+            format!("APM {:.1}", metrics.apm()),
+            format!("KPP {:.2}", metrics.kpp()),
+            pace.unwrap_or("").to_string(),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            self.stdout
+                .borrow_mut()
+                .execute(cursor::MoveTo(left, top + i as u16))?;
+            write!(self.stdout.borrow_mut(), "{:<10}", line)?;
+        }
+        self.stdout.borrow_mut().flush()?;
+        Ok(())
+    }
+
+    pub fn draw_game(&self, frame: &FrameOutput) -> Result<()> {
+        match self.room(self.size())? {
+            Room::Enough => self.draw_frame(frame, false),
+            Room::Lacking => Ok(()),
+            Room::Regained => {
+                self.init_field()?;
+                self.draw_frame(frame, true)
+            }
+        }
+    }
+
+    // Every part of the frame is drawn when `full` is set, whatever changed
+    fn draw_frame(&self, frame: &FrameOutput, full: bool) -> Result<()> {
+        let game = &frame.game;
+        let everything = Redraw::full();
+        let redraw = if full { &everything } else { &frame.redraw };
+        if redraw.main() && game.clearing.is_empty() {
+            self.print_all(&game.main, redraw.full)?;
+        } else if redraw.main() {
+            self.print_all(&outline_rows(&game.main, &game.clearing), redraw.full)?;
+        }
+        if redraw.preview() {
+            self.print_preview(&game.preview)?;
+        }
+        if redraw.score() {
+            self.print_score(game.score)?;
+        }
+        if redraw.hold() {
+            self.print_hold(&game.hold)?;
+        }
+        if redraw.time() || redraw.field {
+            self.print_metrics(&game.metrics, game.level, game.pace.as_deref())?;
+            self.print_goals(&game.goals)?;
+        }
+        if redraw.chat() {
+            self.print_chat(game.chat.as_deref())?;
+        }
+        if redraw.garbage() {
+            self.print_garbage(game.garbage)?;
+        }
+        Ok(())
+    }
+
+    // Bar under the metrics, one symbol per incoming line
+    pub fn print_garbage(&self, lines: u16) -> Result<()> {
+        let left = (self.left + self.settings.cols + 2) * self.width;
+        let top = self.sidebar_row(9);
+        let bar: String =
+            std::iter::repeat_n(self.char, lines.min(GARBAGE_METER) as usize).collect();
+        self.stdout
+            .borrow_mut()
+            .execute(cursor::MoveTo(left, top))?;
+        let meter = if lines > GARBAGE_METER {
+            format!("{}+", bar)
+        } else {
+            bar
+        };
+        write!(
+            self.stdout.borrow_mut(),
+            "{:<width$}",
+            meter,
+            width = GARBAGE_METER as usize + 1
+        )?;
+        self.stdout.borrow_mut().flush()?;
+        Ok(())
+    }
+
+    // Counters under the garbage meter, the rows of goals a game does not have are
+    // cleared
+    pub fn print_goals(&self, goals: &[Goal]) -> Result<()> {
+        let left = (self.left + self.settings.cols + 2) * self.width;
+        let top = self.sidebar_row(10);
+        for i in 0..GOALS {
+            let line = goals.get(i).map_or(String::new(), Goal::text);
+            self.stdout
+                .borrow_mut()
+                .execute(cursor::MoveTo(left, top + i as u16))?;
+            write!(
+                self.stdout.borrow_mut(),
+                "{:<width$}",
+                line,
+                width = GOAL_WIDTH as usize
+            )?;
+        }
+        self.stdout.borrow_mut().flush()?;
+        Ok(())
+    }
+
+    // Under the goal counters after a blank row
+    fn stats_top(&self) -> u16 {
+        self.sidebar_row(11 + GOALS as u16)
+    }
+
+    // Frame overlay in the corner under the goals, cleared with `None`
+    pub fn print_stats(&self, stats: Option<&FrameStats>) -> Result<()> {
+        let left = (self.left + self.settings.cols + 2) * self.width;
+        let top = self.stats_top();
+        let lines = stats.map_or(Default::default(), FrameStats::lines);
+        for (i, line) in lines.iter().enumerate() {
+            self.stdout
+                .borrow_mut()
+                .execute(cursor::MoveTo(left, top + i as u16))?;
+            write!(
+                self.stdout.borrow_mut(),
+                "{:<width$}",
+                line,
+                width = GOAL_WIDTH as usize
+            )?;
+        }
+        self.stdout.borrow_mut().flush()?;
+        Ok(())
+    }
+
+    // One line under the field, cut to the width of the panel
+    pub fn print_chat(&self, chat: Option<&str>) -> Result<()> {
+        let left = self.left * self.width;
+        let top = (self.settings.rows + 1) * self.height + 1;
+        let width = (self.panel_width() * self.width) as usize;
+        let line: String = chat.unwrap_or_default().chars().take(width).collect();
+        self.stdout
+            .borrow_mut()
+            .execute(cursor::MoveTo(left, top))?;
+        write!(self.stdout.borrow_mut(), "{:<width$}", line, width = width)?;
+        self.stdout.borrow_mut().flush()?;
         Ok(())
     }
 
     pub fn draw_text<T>(&self, menu: &MenuMode<T>) -> Result<()> {
+        match self.room(self.size())? {
+            Room::Enough => self.draw_menu(menu),
+            Room::Lacking => Ok(()),
+            Room::Regained => {
+                self.init_field()?;
+                self.draw_menu(menu)
+            }
+        }
+    }
+
+    // Menus longer than the field scroll, the selected item stays in view
+    fn draw_menu<T>(&self, menu: &MenuMode<T>) -> Result<()> {
         self.empty()?;
-        for (idx, item) in menu.get_items().iter().enumerate() {
+        let items = menu.get_items();
+        let fits = ((self.settings.rows * self.height) as usize / 2).max(1);
+        let first = menu
+            .get_selected()
+            .map_or(0, |selected| (selected + 1).saturating_sub(fits));
+        for (idx, item) in items.iter().enumerate().skip(first).take(fits) {
             let out = if matches!(menu.get_selected(), Some(x) if *x == idx) {
-                String::from("-> ") + item.string + " <-"
+                format!("-> {} <-", item.text())
             } else {
-                item.string.to_string()
+                item.text()
             };
-            let left = self.settings.cols * self.width / 2 - (1 + out.len() as u16) / 2;
+            let left = self.left * self.width
+                + (self.settings.cols * self.width / 2)
+                    .saturating_sub((out.len() as u16).div_ceil(2));
             self.stdout
                 .borrow_mut()
-                .execute(cursor::MoveTo(1 + left, idx as u16 * 2 + 1))?;
+                .execute(cursor::MoveTo(1 + left, (idx - first) as u16 * 2 + 1))?;
             write!(self.stdout.borrow_mut(), "{}", out)?;
         }
         self.stdout.borrow_mut().flush()?;
@@ -188,14 +622,30 @@ impl ConsoleView {
         Ok(())
     }
 
-    pub fn keypress(&self, tx: Sender<Action>) -> JoinHandle<Result<()>> {
+    pub fn keypress<I: Send + 'static>(
+        &self,
+        tx: Sender<I>,
+        map: impl Fn(KeyCode) -> Option<I> + Send + 'static,
+    ) -> JoinHandle<Result<()>> {
+        let reader = READERS.fetch_add(1, Ordering::SeqCst) + 1;
         thread::spawn(move || loop {
-            let action = match read()? {
-                Event::Key(event) => ConsoleView::key_to_action(event.code),
+            if READERS.load(Ordering::SeqCst) != reader {
+                return Ok(());
+            }
+            if !poll(READ_POLL)? {
+                continue;
+            }
+            let input = match read()? {
+                // Frame overlay of every game, never passed on
+                Event::Key(event) if event.code == KeyCode::F(3) => {
+                    perf::toggle();
+                    None
+                }
+                Event::Key(event) => map(event.code),
                 _ => None,
             };
-            if let Some(action) = action {
-                tx.send(action)?;
+            if let Some(input) = input {
+                tx.send(input).map_err(|_| ConsoleViewError::Disconnected)?;
             };
         })
     }
@@ -209,7 +659,12 @@ impl ConsoleView {
     }
 
     fn styled(&self, symbol: char) -> ConsoleSymbol<char> {
-        match self.color {
+        let (symbol, color) = match COMPAT.load(Ordering::SeqCst) {
+            true if !symbol.is_ascii() => (ASCII_BLOCK, self.color.map(basic)),
+            true => (symbol, self.color.map(basic)),
+            false => (symbol, self.color),
+        };
+        match color {
             Some(color) => ConsoleSymbol::Styled(PrintStyledContent(StyledContent::new(
                 ContentStyle::new().foreground(color),
                 symbol,
@@ -220,12 +675,12 @@ impl ConsoleView {
 
     fn print_styled(&self, x: u16, y: u16, symbol: &ConsoleSymbol<char>) -> Result<()> {
         match &symbol {
-            Styled(s) => self.print(x, y, &s),
-            Simple(s) => self.print(x, y, &s),
+            Styled(s) => self.print(x, y, s),
+            Simple(s) => self.print(x, y, s),
         }
     }
 
-    fn key_to_action(key: KeyCode) -> Option<Action> {
+    pub fn key_to_action(key: KeyCode) -> Option<Action> {
         match key {
             KeyCode::Up | KeyCode::Char('w') => Some(Action::Up),
             KeyCode::Down | KeyCode::Char('s') => Some(Action::Down),
@@ -233,11 +688,185 @@ impl ConsoleView {
             KeyCode::Right | KeyCode::Char('d') => Some(Action::Right),
             KeyCode::Enter | KeyCode::Char(' ') => Some(Action::Drop),
             KeyCode::Esc | KeyCode::Backspace => Some(Action::Escape),
+            KeyCode::Char('p') => Some(Action::Screenshot),
+            KeyCode::Char('c') => Some(Action::Hold),
+            KeyCode::Char('z') => Some(Action::RotateLeft),
             _ => None,
         }
     }
 }
 
+// WASD, Space, C and Q for the first player, arrows, Enter, / and . for the second one
+pub fn key_to_versus_action(key: KeyCode) -> Option<PlayerAction> {
+    let (player, action) = match key {
+        KeyCode::Char('w') => (0, Action::Up),
+        KeyCode::Char('s') => (0, Action::Down),
+        KeyCode::Char('a') => (0, Action::Left),
+        KeyCode::Char('d') => (0, Action::Right),
+        KeyCode::Char(' ') => (0, Action::Drop),
+        KeyCode::Char('c') => (0, Action::Hold),
+        KeyCode::Char('q') => (0, Action::RotateLeft),
+        KeyCode::Up => (1, Action::Up),
+        KeyCode::Down => (1, Action::Down),
+        KeyCode::Left => (1, Action::Left),
+        KeyCode::Right => (1, Action::Right),
+        KeyCode::Enter => (1, Action::Drop),
+        KeyCode::Char('/') => (1, Action::Hold),
+        KeyCode::Char('.') => (1, Action::RotateLeft),
+        KeyCode::Esc | KeyCode::Backspace => (0, Action::Escape),
+        _ => return None,
+    };
+    Some(PlayerAction { player, action })
+}
+
+// Letters and digits are typed, the rest works like in the game
+#[cfg(feature = "netplay")]
+pub fn key_to_lobby_input(key: KeyCode) -> Option<LobbyInput> {
+    match key {
+        KeyCode::Char(c) if c.is_ascii_alphanumeric() || c == '.' || c == ':' || c == '-' => {
+            Some(LobbyInput::Char(c))
+        }
+        KeyCode::Backspace => Some(LobbyInput::Erase),
+        KeyCode::Char(_) => None,
+        key => Some(LobbyInput::Action(ConsoleView::key_to_action(key)?)),
+    }
+}
+
+// Versus against the computer, the only human gets every key
+pub fn key_to_first_player(key: KeyCode) -> Option<PlayerAction> {
+    let action = ConsoleView::key_to_action(key)?;
+    Some(PlayerAction { player: 0, action })
+}
+
+impl Renderer for ConsoleView {
+    type Error = ConsoleViewError;
+
+    fn draw_game(&self, frame: &FrameOutput) -> Result<()> {
+        ConsoleView::draw_game(self, frame)
+    }
+
+    fn draw_text<T>(&self, menu: &MenuMode<T>) -> Result<()> {
+        ConsoleView::draw_text(self, menu)
+    }
+
+    fn draw_stats(&self, stats: Option<&FrameStats>) -> Result<()> {
+        match self.cramped.get() {
+            Some(_) => Ok(()),
+            None => self.print_stats(stats),
+        }
+    }
+}
+
+/// Several console views next to each other, one per board
+pub struct SplitView {
+    views: Vec<ConsoleView>,
+}
+
+impl SplitView {
+    // Places boards left to right starting from the given view
+    pub fn new(view: ConsoleView, boards: u16) -> SplitView {
+        let width = view.panel_width();
+        let views = (0..boards)
+            .map(|i| {
+                ConsoleView::new(
+                    view.settings,
+                    view.width,
+                    view.height,
+                    view.char,
+                    view.color,
+                )
+                .with_offset(view.left + i * width)
+            })
+            .collect();
+        SplitView { views }
+    }
+
+    // Draws one of the boards in its own color, a ghost is easier to tell apart when dimmed
+    pub fn with_color(mut self, index: usize, color: Option<Color>) -> SplitView {
+        self.views[index].color = color;
+        self
+    }
+
+    // Columns and rows of all boards together, the last one ends on the right
+    pub fn size(&self) -> (u16, u16) {
+        self.views[self.views.len() - 1].size()
+    }
+
+    pub fn prepare(&self) -> Result<()> {
+        self.views[0].wait_for(self.size())?;
+        self.views[0].prepare()
+    }
+
+    pub fn init_field(&self) -> Result<()> {
+        for view in self.views.iter() {
+            view.init_field()?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.views[0].clear()
+    }
+
+    pub fn keypress<I: Send + 'static>(
+        &self,
+        tx: Sender<I>,
+        map: impl Fn(KeyCode) -> Option<I> + Send + 'static,
+    ) -> JoinHandle<Result<()>> {
+        self.views[0].keypress(tx, map)
+    }
+}
+
+impl Renderer for SplitView {
+    type Error = ConsoleViewError;
+
+    fn draw_game(&self, frame: &FrameOutput) -> Result<()> {
+        match self.views[0].room(self.size())? {
+            Room::Enough => self.views[0].draw_frame(frame, false),
+            Room::Lacking => Ok(()),
+            Room::Regained => {
+                self.init_field()?;
+                self.views[0].draw_frame(frame, true)
+            }
+        }
+    }
+
+    fn draw_text<T>(&self, menu: &MenuMode<T>) -> Result<()> {
+        match self.views[0].room(self.size())? {
+            Room::Enough => self.views[0].draw_menu(menu),
+            Room::Lacking => Ok(()),
+            Room::Regained => {
+                self.init_field()?;
+                self.views[0].draw_menu(menu)
+            }
+        }
+    }
+
+    // Under the first board, timing is the same for all of them
+    fn draw_stats(&self, stats: Option<&FrameStats>) -> Result<()> {
+        match self.views[0].cramped.get() {
+            Some(_) => Ok(()),
+            None => self.views[0].print_stats(stats),
+        }
+    }
+
+    // One size check for all boards, they are drawn anew together
+    fn draw_split(&self, frames: &[FrameOutput]) -> Result<()> {
+        let full = match self.views[0].room(self.size())? {
+            Room::Enough => false,
+            Room::Lacking => return Ok(()),
+            Room::Regained => {
+                self.init_field()?;
+                true
+            }
+        };
+        for (view, frame) in self.views.iter().zip(frames) {
+            view.draw_frame(frame, full)?;
+        }
+        Ok(())
+    }
+}
+
 pub enum ConsoleSymbol<D: Display + Clone> {
     Styled(PrintStyledContent<D>),
     Simple(Print<D>),
@@ -249,7 +878,8 @@ type Result<T> = std::result::Result<T, ConsoleViewError>;
 pub enum ConsoleViewError {
     ConsoleErr(crossterm::ErrorKind),
     IOErr(io::Error),
-    SendError(mpsc::SendError<Action>),
+    // Nobody listens to the input anymore
+    Disconnected,
 }
 
 impl Display for ConsoleViewError {
@@ -257,7 +887,7 @@ impl Display for ConsoleViewError {
         match *self {
             ConsoleViewError::ConsoleErr(ref e) => e.fmt(f),
             ConsoleViewError::IOErr(ref e) => e.fmt(f),
-            ConsoleViewError::SendError(ref e) => e.fmt(f),
+            ConsoleViewError::Disconnected => write!(f, "input receiver disconnected"),
         }
     }
 }
@@ -267,7 +897,7 @@ impl std::error::Error for ConsoleViewError {
         match *self {
             ConsoleViewError::ConsoleErr(ref e) => Some(e),
             ConsoleViewError::IOErr(ref e) => Some(e),
-            ConsoleViewError::SendError(ref e) => Some(e),
+            ConsoleViewError::Disconnected => None,
         }
     }
 }
@@ -283,9 +913,3 @@ impl From<io::Error> for ConsoleViewError {
         ConsoleViewError::IOErr(err)
     }
 }
-
-impl From<mpsc::SendError<Action>> for ConsoleViewError {
-    fn from(err: mpsc::SendError<Action>) -> ConsoleViewError {
-        ConsoleViewError::SendError(err)
-    }
-}
@@ -0,0 +1,149 @@
+use crate::renderer::png::{picture, Image, PALETTE};
+use brick_game_wasm::bootstrap::{Game, Timestamp};
+use brick_game_wasm::game::tetris::replay::Replay;
+use brick_game_wasm::game::tetris::Tetris;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// Time between the pictures taken of the game, 10 a second
+const FRAME: Timestamp = 100;
+// The last picture stays a while before the animation starts over
+const END: Timestamp = 2000;
+// The palette of the screenshots padded to a power of two, 16 colors
+const COLOR_BITS: u8 = 4;
+// Codes of the compression never get longer
+const MAX_CODES: u16 = 4096;
+
+/// Animation of a replay, looping forever. The game is played again without
+/// drawing it and a picture like the screenshots is taken every `FRAME`, the ones
+/// where nothing moved only make the one before last longer.
+pub fn export(replay: &Replay, writer: &mut impl Write) -> io::Result<()> {
+    let mut tetris = Tetris::with_seed(replay.settings, 0, replay.seed);
+    let mut inputs = replay.inputs.iter().peekable();
+    let mut shown = picture(&tetris.to_drawable());
+    let mut delay = 0;
+    header(writer, &shown)?;
+    let mut now = 0;
+    while now < replay.end {
+        now = (now + FRAME).min(replay.end);
+        while let Some(&&(time, action)) = inputs.peek() {
+            if time > now {
+                break;
+            }
+            tetris.frame(time, Some(action));
+            inputs.next();
+        }
+        tetris.frame(now, None);
+        delay += FRAME;
+        let image = picture(&tetris.to_drawable());
+        if image != shown {
+            frame(writer, &shown, delay)?;
+            shown = image;
+            delay = 0;
+        }
+    }
+    frame(writer, &shown, delay + END)?;
+    writer.write_all(&[0x3b])
+}
+
+// Screen and palette, then the extension that makes viewers loop
+fn header(writer: &mut impl Write, image: &Image) -> io::Result<()> {
+    writer.write_all(b"GIF89a")?;
+    writer.write_all(&(image.width as u16).to_le_bytes())?;
+    writer.write_all(&(image.height as u16).to_le_bytes())?;
+    writer.write_all(&[0x80 | (COLOR_BITS - 1) << 4 | (COLOR_BITS - 1), 0, 0])?;
+    for i in 0..1 << COLOR_BITS {
+        writer.write_all(&PALETTE.get(i).copied().unwrap_or_default())?;
+    }
+    writer.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")
+}
+
+fn frame(writer: &mut impl Write, image: &Image, delay: Timestamp) -> io::Result<()> {
+    // Hundredths of a second
+    let delay = (delay / 10).min(u16::MAX as Timestamp) as u16;
+    writer.write_all(&[0x21, 0xf9, 4, 0])?;
+    writer.write_all(&delay.to_le_bytes())?;
+    writer.write_all(&[0, 0, 0x2c, 0, 0, 0, 0])?;
+    writer.write_all(&(image.width as u16).to_le_bytes())?;
+    writer.write_all(&(image.height as u16).to_le_bytes())?;
+    writer.write_all(&[0, COLOR_BITS])?;
+    for block in lzw(&image.pixels).chunks(255) {
+        writer.write_all(&[block.len() as u8])?;
+        writer.write_all(block)?;
+    }
+    writer.write_all(&[0])
+}
+
+// Variable length LZW of the palette indices, starting over once all codes are used
+fn lzw(pixels: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << COLOR_BITS;
+    let end = clear + 1;
+    let mut out = Bits::default();
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = COLOR_BITS + 1;
+    out.push(clear, size);
+    let mut pixels = pixels.iter();
+    let mut prefix = match pixels.next() {
+        Some(&pixel) => pixel as u16,
+        None => {
+            out.push(end, size);
+            return out.finish();
+        }
+    };
+    for &pixel in pixels {
+        if let Some(&code) = codes.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+        out.push(prefix, size);
+        // The decoder gets to each new code one code later, so the width goes up
+        // as soon as the next code does not fit
+        if next == 1 << size && size < 12 {
+            size += 1;
+        }
+        if next < MAX_CODES {
+            codes.insert((prefix, pixel), next);
+            next += 1;
+        } else {
+            out.push(clear, size);
+            codes.clear();
+            next = end + 1;
+            size = COLOR_BITS + 1;
+        }
+        prefix = pixel as u16;
+    }
+    out.push(prefix, size);
+    if next == 1 << size && size < 12 {
+        size += 1;
+    }
+    out.push(end, size);
+    out.finish()
+}
+
+// Codes packed from the lowest bit up
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    pending: u32,
+    count: u8,
+}
+
+impl Bits {
+    fn push(&mut self, code: u16, size: u8) {
+        self.pending |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.pending as u8);
+            self.pending >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.pending as u8);
+        }
+        self.bytes
+    }
+}
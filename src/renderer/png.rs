@@ -0,0 +1,165 @@
+use brick_game_wasm::bootstrap::{Cell, GameMode};
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Side of a board cell in pixels
+const CELL: usize = 12;
+// Pixels of the score digits are this many image pixels wide
+const DIGIT_SCALE: usize = 3;
+
+pub type Rgb = [u8; 3];
+
+// Pictures are drawn with indices into `PALETTE`
+const BACKGROUND: u8 = 0;
+const GARBAGE: u8 = 8;
+const GHOST: u8 = 9;
+const BORDER: u8 = GHOST + 1;
+const TEXT: u8 = GHOST + 2;
+
+/// Colors of the blocks by the number their cells have on the board, then garbage,
+/// the hint, the border and the score
+pub const PALETTE: [Rgb; TEXT as usize + 1] = [
+    [16, 16, 24],
+    [0, 200, 224],
+    [160, 64, 208],
+    [40, 80, 224],
+    [232, 136, 24],
+    [64, 200, 64],
+    [216, 48, 48],
+    [232, 208, 32],
+    [128, 128, 128],
+    [56, 56, 72],
+    [96, 96, 112],
+    [224, 224, 224],
+];
+
+// Rows of the digits 0-9, three pixels each with the highest bit on the left
+#[rustfmt::skip]
+const DIGITS: [[u8; 5]; 10] = [
+    [7, 5, 5, 5, 7], [2, 6, 2, 2, 7], [7, 1, 7, 4, 7], [7, 1, 7, 1, 7], [5, 5, 7, 1, 1],
+    [7, 4, 7, 1, 7], [7, 4, 7, 5, 7], [7, 1, 2, 2, 2], [7, 5, 7, 5, 7], [7, 5, 7, 1, 7],
+];
+
+/// PNG of the board
+pub fn screenshot(game: &GameMode) -> io::Result<Vec<u8>> {
+    picture(game).encode()
+}
+
+/// The board like the console draws it: the field in a border with the next block
+/// and the score to the right
+pub fn picture(game: &GameMode) -> Image {
+    let rows = game.main.len();
+    let cols = game.main.first().map_or(0, |row| row.len());
+    let mut image = Image::new(cols + 8, rows + 2);
+    for x in 0..cols + 2 {
+        image.cell(x, 0, BORDER);
+        image.cell(x, rows + 1, BORDER);
+    }
+    for y in 1..=rows {
+        image.cell(0, y, BORDER);
+        image.cell(cols + 1, y, BORDER);
+    }
+    for (y, row) in game.main.iter().enumerate() {
+        for (x, &cell) in row.iter().enumerate() {
+            image.cell(x + 1, y + 1, color(cell));
+        }
+    }
+    for (y, row) in game.preview.iter().enumerate() {
+        for (x, &cell) in row.iter().enumerate() {
+            image.cell(x + cols + 3, y + 1, color(cell));
+        }
+    }
+    let (left, top) = ((cols + 3) * CELL, 6 * CELL);
+    for (i, digit) in game.score.to_string().bytes().enumerate() {
+        image.digit(left + i * 4 * DIGIT_SCALE, top, (digit - b'0') as usize);
+    }
+    image
+}
+
+fn color(cell: Cell) -> u8 {
+    match cell {
+        Cell::Empty => BACKGROUND,
+        Cell::Piece(id) if id < GARBAGE => id,
+        Cell::Piece(_) | Cell::Garbage => GARBAGE,
+        Cell::Ghost => GHOST,
+    }
+}
+
+/// Writes the screenshot into `dir`, named by the time it was taken
+pub fn save(dir: &Path, game: &GameMode) -> io::Result<PathBuf> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    let path = dir.join(format!("screenshot-{}.png", time));
+    fs::write(&path, screenshot(game)?)?;
+    Ok(path)
+}
+
+// Palette index of every pixel, row by row
+#[derive(PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    fn new(cols: usize, rows: usize) -> Image {
+        let (width, height) = (cols * CELL, rows * CELL);
+        Image {
+            width,
+            height,
+            pixels: vec![BACKGROUND; width * height],
+        }
+    }
+
+    fn fill(&mut self, left: usize, top: usize, size: usize, color: u8) {
+        let right = (left + size).min(self.width).max(left);
+        for y in top..(top + size).min(self.height) {
+            let row = y * self.width;
+            for pixel in &mut self.pixels[row + left..row + right] {
+                *pixel = color;
+            }
+        }
+    }
+
+    // Filled cells keep a dark line to the next one, like the gaps in the console
+    fn cell(&mut self, x: usize, y: usize, color: u8) {
+        self.fill(x * CELL, y * CELL, CELL, BACKGROUND);
+        self.fill(x * CELL, y * CELL, CELL - 1, color);
+    }
+
+    fn digit(&mut self, left: usize, top: usize, digit: usize) {
+        for (y, bits) in DIGITS[digit].iter().enumerate() {
+            for x in 0..3 {
+                if bits & (4 >> x) != 0 {
+                    let (x, y) = (left + x * DIGIT_SCALE, top + y * DIGIT_SCALE);
+                    self.fill(x, y, DIGIT_SCALE, TEXT);
+                }
+            }
+        }
+    }
+
+    // 8-bit RGB with the colors of the palette
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        let rgb: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|&pixel| PALETTE[pixel as usize])
+            .collect();
+        let mut png = vec![];
+        PngEncoder::new(&mut png)
+            .write_image(
+                &rgb,
+                self.width as u32,
+                self.height as u32,
+                ExtendedColorType::Rgb8,
+            )
+            .map_err(io::Error::other)?;
+        Ok(png)
+    }
+}
@@ -0,0 +1,29 @@
+use brick_game_wasm::game::tetris::attack::Targeting;
+use brick_game_wasm::net::server::Server;
+use log::LevelFilter;
+use std::env;
+use std::error::Error;
+
+const DEFAULT_ADDR: &str = "0.0.0.0:7880";
+
+// Takes the address, the players per room and the targeting: leader, random or even.
+// What goes on in the rooms is logged to stderr, stdout only says where it serves.
+fn main() -> Result<(), Box<dyn Error>> {
+    simple_logging::log_to_stderr(LevelFilter::Info);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let addr = args.first().map_or(DEFAULT_ADDR, String::as_str);
+    let players = match args.get(1) {
+        Some(players) => players.parse()?,
+        None => 2,
+    };
+    let targeting = match args.get(2) {
+        Some(targeting) => targeting.parse()?,
+        None => Targeting::default(),
+    };
+    let server = Server::bind(addr)?
+        .with_room_size(players)
+        .with_targeting(targeting);
+    println!("Serving rooms of {} on {}", players, addr);
+    server.run()?;
+    Ok(())
+}
@@ -1,86 +1,308 @@
 use wasm_bindgen::prelude::*;
 
-use crate::bootstrap::{Action, Game, GameChange, GameMode, MenuMode, Settings, Timestamp};
+use crate::bootstrap::{
+    Action, FrameOutput, GameLoop, ManualClock, MenuMode, Renderer, Settings, Timestamp, TopOut,
+};
+use crate::game::tetris::metrics::Metrics;
+use crate::game::tetris::replay::Replay;
+use crate::game::tetris::tetromino::RotationSystem;
 use crate::game::tetris::Tetris;
+#[cfg(feature = "netplay")]
+use crate::net::{hello, remote::RemoteVersus, QueueTransport, Transport};
+use crate::perf::FrameStats;
+use std::cell::RefCell;
+use std::convert::Infallible;
 
-mod bootstrap;
+pub mod bootstrap;
+pub mod error;
+pub mod perf;
 
-mod game {
+pub mod game {
     pub mod tetris;
+    pub mod title;
 }
 
+#[cfg(feature = "netplay")]
+pub mod net;
+
+#[cfg(all(feature = "leaderboard", not(target_arch = "wasm32")))]
+pub mod leaderboard;
+
+// Allocations are counted for the frame overlay, whichever allocator does them
+#[cfg(all(
+    target_arch = "wasm32",
+    feature = "wee_alloc",
+    not(feature = "lol_alloc")
+))]
 #[global_allocator]
-static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+static ALLOC: perf::Counting<wee_alloc::WeeAlloc> = perf::Counting(wee_alloc::WeeAlloc::INIT);
+
+// The page runs the game on a single thread
+#[cfg(all(target_arch = "wasm32", feature = "lol_alloc"))]
+#[global_allocator]
+static ALLOC: perf::Counting<lol_alloc::AssumeSingleThreaded<lol_alloc::FreeListAllocator>> =
+    perf::Counting(unsafe {
+        lol_alloc::AssumeSingleThreaded::new(lol_alloc::FreeListAllocator::new())
+    });
+
+#[cfg(all(
+    target_arch = "wasm32",
+    not(any(feature = "wee_alloc", feature = "lol_alloc"))
+))]
+#[global_allocator]
+static ALLOC: perf::Counting<std::alloc::System> = perf::Counting(std::alloc::System);
 
 #[wasm_bindgen]
 pub struct JSGame {
-    settings: Settings,
-    tetris: Tetris,
+    game: GameLoop<Tetris, JSRenderer, ManualClock>,
 }
 
 #[wasm_bindgen]
 impl JSGame {
     pub fn start(time: Timestamp) -> JSGame {
+        JSGame::start_with(time, RotationSystem::Srs, false)
+    }
+
+    // Blocks turn like older games with the NES or arcade rules, and take two by two
+    // cells for every mino when `big`
+    pub fn start_with(time: Timestamp, rotation: RotationSystem, big: bool) -> JSGame {
         let settings = Settings {
-            cols: 10,
-            rows: 20,
-            delay: 500,
+            rotation,
+            big,
+            ..SETTINGS
         };
-        let tetris = Tetris::new(settings, time);
-        JSGame { settings, tetris }
+        JSGame {
+            game: GameLoop::new(
+                Tetris::new(settings, time),
+                JSRenderer::new(),
+                ManualClock::new(time),
+            ),
+        }
     }
 
     pub fn tick(&mut self, time: Timestamp, action: Option<Action>) -> JSRender {
-        let frame = self.tetris.frame(time, action);
-        match frame {
-            GameChange::Draw(gamemode) => JSRender {
-                action: JSAction::Draw,
-                gameview: Some(JSGame::gameview(&gamemode)),
-                textview: None,
-            },
-            GameChange::Text(menumode) => JSRender {
-                action: JSAction::Text,
-                gameview: None,
-                textview: Some(JSGame::textview(menumode)),
-            },
-            GameChange::Restart => {
-                self.restart(time);
-                JSRender {
-                    action: JSAction::Idle,
-                    gameview: None,
-                    textview: None,
-                }
-            }
-            GameChange::Exit => JSRender {
-                action: JSAction::Exit,
-                gameview: None,
-                textview: None,
-            },
-            GameChange::Idle => JSRender {
-                action: JSAction::Idle,
-                gameview: None,
-                textview: None,
-            },
+        self.game.clock().set(time);
+        let running = self.game.step(action);
+        self.game.renderer().take(running)
+    }
+
+    // Should be called when the page loses focus
+    pub fn blur(&mut self) {
+        self.game.focus_lost();
+    }
+
+    // Locked blocks only show for a moment after they lock, the game plays the same
+    pub fn set_invisible(&mut self, invisible: bool) {
+        self.game.game_mut().set_invisible(invisible);
+    }
+
+    // Race to clear `rows` rows of garbage, before the first tick
+    pub fn set_dig(&mut self, rows: u16) {
+        self.game.game_mut().set_dig(Some(rows));
+    }
+
+    // Race to clear `lines` lines, before the first tick
+    pub fn set_sprint(&mut self, lines: u32) {
+        self.game.game_mut().set_sprint(Some(lines));
+    }
+
+    // Garbage from an opponent the JS side manages, goes through the same cancelling rules
+    pub fn add_garbage(&mut self, lines: u16) {
+        self.game.game_mut().add_garbage(lines);
+    }
+
+    pub fn take_attack(&mut self) -> u32 {
+        self.game.game_mut().take_attack()
+    }
+
+    // Same bytes the console writes with --record, so replays go both ways
+    pub fn replay(&self) -> Vec<u8> {
+        self.game.game().replay().encode()
+    }
+
+    pub fn verify_replay(bytes: &[u8]) -> bool {
+        Replay::decode(bytes).is_ok_and(|replay| replay.verify())
+    }
+
+    // Frames are timed only while the stats are shown, the page draws them itself
+    pub fn show_stats(&mut self, shown: bool) {
+        perf::show(shown);
+    }
+
+    pub fn stats(&self) -> JSStats {
+        JSStats::new(self.game.stats())
+    }
+}
+
+/// Frame timing for a debug overlay of the page, times in microseconds
+#[wasm_bindgen]
+pub struct JSStats {
+    stats: FrameStats,
+}
+
+impl JSStats {
+    fn new(stats: &FrameStats) -> JSStats {
+        JSStats {
+            stats: stats.clone(),
         }
     }
+}
+
+#[wasm_bindgen]
+impl JSStats {
+    pub fn fps(&self) -> u32 {
+        self.stats.fps() as u32
+    }
+
+    pub fn frame_time(&self) -> u32 {
+        self.stats.frame_time as u32
+    }
+
+    // None before the first input
+    pub fn latency(&self) -> Option<u32> {
+        self.stats.latency.map(|latency| latency as u32)
+    }
 
-    fn restart(&mut self, time: Timestamp) {
-        self.tetris = Tetris::new(self.settings, time);
+    pub fn allocations(&self) -> u32 {
+        self.stats.allocations as u32
     }
+}
+
+const SETTINGS: Settings = Settings {
+    cols: 10,
+    rows: 20,
+    delay: 500,
+    level: 1,
+    lock_delay: 500,
+    clear_delay: 200,
+    das: 167,
+    arr: 33,
+    entry_delay: 0,
+    top_out: TopOut::GUIDELINE,
+    rotation: RotationSystem::Srs,
+    big: false,
+};
+
+/// Versus against a remote player. JS owns the WebSocket: it feeds received
+/// binary messages in and sends out whatever `outgoing` returns.
+#[cfg(feature = "netplay")]
+#[wasm_bindgen]
+pub struct JSNetGame {
+    game: GameLoop<RemoteVersus<QueueTransport>, JSRenderer, ManualClock>,
+}
+
+#[cfg(feature = "netplay")]
+#[wasm_bindgen]
+impl JSNetGame {
+    pub fn start(time: Timestamp) -> JSNetGame {
+        // The other side checks it before the first board arrives
+        let mut peer = QueueTransport::new();
+        let _ = peer.send(&hello(&SETTINGS));
+        JSNetGame {
+            game: GameLoop::new(
+                RemoteVersus::new(SETTINGS, time, peer),
+                JSRenderer::new(),
+                ManualClock::new(time),
+            ),
+        }
+    }
+
+    pub fn tick(&mut self, time: Timestamp, action: Option<Action>) -> JSRender {
+        self.game.clock().set(time);
+        let running = self.game.step(action);
+        self.game.renderer().take(running)
+    }
+
+    pub fn receive(&mut self, bytes: Vec<u8>) {
+        self.game.game_mut().peer_mut().push(bytes);
+    }
+
+    pub fn outgoing(&mut self) -> Option<Vec<u8>> {
+        self.game.game_mut().peer_mut().take()
+    }
+
+    // Should be called when the socket closes or fails
+    pub fn disconnected(&mut self) {
+        self.game.game_mut().peer_mut().close();
+    }
+
+    pub fn blur(&mut self) {
+        self.game.focus_lost();
+    }
+}
+
+// Keeps the last rendered frame until JS side picks it up
+struct JSRenderer {
+    render: RefCell<Option<JSRender>>,
+}
+
+impl Renderer for JSRenderer {
+    type Error = Infallible;
 
-    fn gameview(gamemode: &GameMode) -> GameView {
+    fn draw_game(&self, frame: &FrameOutput) -> Result<(), Infallible> {
+        *self.render.borrow_mut() = Some(JSRender {
+            action: JSAction::Draw,
+            gameview: Some(JSRenderer::gameview(frame)),
+            opponent: None,
+            textview: None,
+        });
+        Ok(())
+    }
+
+    fn draw_split(&self, frames: &[FrameOutput]) -> Result<(), Infallible> {
+        *self.render.borrow_mut() = Some(JSRender {
+            action: JSAction::Draw,
+            gameview: frames.first().map(JSRenderer::gameview),
+            opponent: frames.get(1).map(JSRenderer::gameview),
+            textview: None,
+        });
+        Ok(())
+    }
+
+    fn draw_text<T>(&self, menu: &MenuMode<T>) -> Result<(), Infallible> {
+        *self.render.borrow_mut() = Some(JSRender {
+            action: JSAction::Text,
+            gameview: None,
+            opponent: None,
+            textview: Some(JSRenderer::textview(menu)),
+        });
+        Ok(())
+    }
+}
+
+impl JSRenderer {
+    fn new() -> JSRenderer {
+        JSRenderer {
+            render: RefCell::new(None),
+        }
+    }
+
+    fn take(&self, running: Result<bool, Infallible>) -> JSRender {
+        match running {
+            Ok(true) => self
+                .render
+                .borrow_mut()
+                .take()
+                .unwrap_or_else(|| JSRender::empty(JSAction::Idle)),
+            _ => JSRender::empty(JSAction::Exit),
+        }
+    }
+
+    fn gameview(frame: &FrameOutput) -> GameView {
+        let gamemode = &frame.game;
         GameView {
-            main: gamemode
-                .main
-                .iter()
-                .flat_map(|row| row.to_owned())
-                .collect(),
-            preview: gamemode
-                .preview
-                .iter()
-                .flat_map(|row| row.to_owned())
-                .collect(),
+            main: gamemode.main.bytes().flatten().collect(),
+            preview: gamemode.preview.bytes().flatten().collect(),
+            hold: gamemode.hold.bytes().flatten().collect(),
             score: gamemode.score,
+            level: gamemode.level,
+            clearing: gamemode.clearing.clone(),
+            metrics: gamemode.metrics,
+            chat: gamemode.chat.clone(),
+            redraw_main: frame.redraw.main(),
+            redraw_preview: frame.redraw.preview(),
+            redraw_hold: frame.redraw.hold(),
+            redraw_score: frame.redraw.score(),
         }
     }
 
@@ -89,7 +311,7 @@ impl JSGame {
             items: menuview
                 .get_items()
                 .iter()
-                .map(|item| item.string)
+                .map(|item| item.text())
                 .collect(),
             selected: *menuview.get_selected(),
         }
@@ -99,11 +321,22 @@ impl JSGame {
 pub struct GameView {
     main: Vec<u8>,
     preview: Vec<u8>,
+    // 4x4 like the preview, empty while nothing is held
+    hold: Vec<u8>,
     score: u32,
+    level: u32,
+    // Rows of `main` flashing before they collapse
+    clearing: Vec<u16>,
+    metrics: Metrics,
+    chat: Option<String>,
+    redraw_main: bool,
+    redraw_preview: bool,
+    redraw_hold: bool,
+    redraw_score: bool,
 }
 
 pub struct TextView {
-    items: Vec<&'static str>,
+    items: Vec<String>,
     selected: Option<usize>,
 }
 
@@ -121,9 +354,22 @@ pub enum JSAction {
 pub struct JSRender {
     pub action: JSAction,
     gameview: Option<GameView>,
+    // Second board of a versus game
+    opponent: Option<GameView>,
     textview: Option<TextView>,
 }
 
+impl JSRender {
+    fn empty(action: JSAction) -> JSRender {
+        JSRender {
+            action,
+            gameview: None,
+            opponent: None,
+            textview: None,
+        }
+    }
+}
+
 #[wasm_bindgen]
 impl JSRender {
     pub fn main(&self) -> Option<Vec<u8>> {
@@ -134,10 +380,103 @@ impl JSRender {
         Some(self.gameview.as_ref()?.preview.clone())
     }
 
+    pub fn hold(&self) -> Option<Vec<u8>> {
+        Some(self.gameview.as_ref()?.hold.clone())
+    }
+
     pub fn score(&self) -> Option<u32> {
         Some(self.gameview.as_ref()?.score)
     }
 
+    pub fn level(&self) -> Option<u32> {
+        Some(self.gameview.as_ref()?.level)
+    }
+
+    pub fn lines(&self) -> Option<u32> {
+        Some(self.gameview.as_ref()?.metrics.lines)
+    }
+
+    pub fn pieces(&self) -> Option<u32> {
+        Some(self.gameview.as_ref()?.metrics.pieces)
+    }
+
+    // Blocks that came into play by piece, in the order I, T, J, L, S, Z, O
+    pub fn spawned(&self) -> Option<Vec<u32>> {
+        Some(self.gameview.as_ref()?.metrics.spawned.to_vec())
+    }
+
+    // Clears of one, two, three and four lines
+    pub fn clears(&self) -> Option<Vec<u32>> {
+        Some(self.gameview.as_ref()?.metrics.clears.to_vec())
+    }
+
+    pub fn clearing(&self) -> Option<Vec<u16>> {
+        Some(self.gameview.as_ref()?.clearing.clone())
+    }
+
+    // Score from cleared lines, soft drops and hard drops
+    pub fn line_points(&self) -> Option<u32> {
+        Some(self.gameview.as_ref()?.metrics.points.lines)
+    }
+
+    pub fn soft_drop_points(&self) -> Option<u32> {
+        Some(self.gameview.as_ref()?.metrics.points.soft_drop)
+    }
+
+    pub fn hard_drop_points(&self) -> Option<u32> {
+        Some(self.gameview.as_ref()?.metrics.points.hard_drop)
+    }
+
+    pub fn pps(&self) -> Option<f32> {
+        Some(self.gameview.as_ref()?.metrics.pps())
+    }
+
+    pub fn apm(&self) -> Option<f32> {
+        Some(self.gameview.as_ref()?.metrics.apm())
+    }
+
+    pub fn kpp(&self) -> Option<f32> {
+        Some(self.gameview.as_ref()?.metrics.kpp())
+    }
+
+    // Milliseconds played, without the time spent in menus
+    pub fn elapsed(&self) -> Option<u32> {
+        Some(self.gameview.as_ref()?.metrics.elapsed as u32)
+    }
+
+    pub fn redraw_main(&self) -> Option<bool> {
+        Some(self.gameview.as_ref()?.redraw_main)
+    }
+
+    pub fn redraw_preview(&self) -> Option<bool> {
+        Some(self.gameview.as_ref()?.redraw_preview)
+    }
+
+    pub fn redraw_hold(&self) -> Option<bool> {
+        Some(self.gameview.as_ref()?.redraw_hold)
+    }
+
+    pub fn redraw_score(&self) -> Option<bool> {
+        Some(self.gameview.as_ref()?.redraw_score)
+    }
+
+    pub fn opponent_main(&self) -> Option<Vec<u8>> {
+        Some(self.opponent.as_ref()?.main.clone())
+    }
+
+    pub fn opponent_score(&self) -> Option<u32> {
+        Some(self.opponent.as_ref()?.score)
+    }
+
+    pub fn redraw_opponent(&self) -> Option<bool> {
+        Some(self.opponent.as_ref()?.redraw_main)
+    }
+
+    // Chat line under the opponent's board, empty when there is none
+    pub fn opponent_chat(&self) -> Option<String> {
+        Some(self.opponent.as_ref()?.chat.clone().unwrap_or_default())
+    }
+
     pub fn text_items(&self) -> Option<String> {
         Some(self.textview.as_ref()?.items.join("\n"))
     }
@@ -20,6 +20,7 @@ class Playfield {
         public rows: number,
         public main: HTMLDivElement,
         public preview: HTMLDivElement,
+        public hold: HTMLDivElement,
         public score: HTMLDivElement,
         public menu: HTMLDivElement
     ) {
@@ -27,13 +28,24 @@ class Playfield {
         this.main.innerHTML = cells.join("")
         let pcells = new Array(4 * 4).fill('<div class="cell"></div>')
         this.preview.innerHTML = pcells.join("")
+        this.hold.innerHTML = pcells.join("")
     }
 
-    render(main: number[], preview: number[], score: number) {
+    render(main?: number[], preview?: number[], score?: number, hold?: number[], clearing?: number[]) {
         this.textmode(false);
-        this.draw(main, this.main);
-        this.draw(preview, this.preview)
-        this.score.innerText = score.toString();
+        if (main !== undefined) {
+            this.draw(main, this.main);
+            this.flash(clearing || []);
+        }
+        if (preview !== undefined) {
+            this.draw(preview, this.preview)
+        }
+        if (hold !== undefined) {
+            this.draw(hold, this.hold)
+        }
+        if (score !== undefined) {
+            this.score.innerText = score.toString();
+        }
     }
 
     private draw(cells: number[], block: HTMLDivElement) {
@@ -47,6 +59,14 @@ class Playfield {
         }
     }
 
+    // Rows about to collapse flash until the next frame drawing the board
+    private flash(rows: number[]) {
+        let divs = Array.from(this.main.querySelectorAll(".cell"));
+        for (let i = 0; i < divs.length; i++) {
+            divs[i].classList.toggle("clearing", rows.includes(Math.floor(i / this.cols)));
+        }
+    }
+
     text(items: string[], selected?: number) {
         this.textmode(true);
         this.menu.innerHTML = items.map(item => `<div class="item">${item}</div>`).join("")
@@ -65,55 +85,154 @@ class Playfield {
     }
 }
 
-let LastKey: Action = undefined;
+// Keys since the last tick, the game takes one per tick so quick taps are not lost
+let Keys: Action[] = [];
 
 document.addEventListener('keydown', ev => {
+    let key: Action = undefined;
     switch (ev.code) {
         case "KeyW":
         case "ArrowUp":
-            LastKey = Action.Up
+            key = Action.Up
             break;
+        // The game slides the block while they are held, the repeats are not needed
         case "KeyA":
         case "ArrowLeft":
-            LastKey = Action.Left
+            key = ev.repeat ? undefined : Action.Left
             break;
         case "KeyD":
         case "ArrowRight":
-            LastKey = Action.Right
+            key = ev.repeat ? undefined : Action.Right
             break;
         case "KeyS":
         case "ArrowDown":
-            LastKey = Action.Down
+            key = Action.Down
             break;
         case "Space":
         case "Enter":
-            LastKey = Action.Drop
+            key = Action.Drop
             break;
         case "Escape":
         case "Backspace":
-            LastKey = Action.Escape
+            key = Action.Escape
+            break;
+        case "KeyC":
+            key = Action.Hold
+            break;
+        case "KeyZ":
+            key = Action.RotateLeft
             break;
     }
+    if (key !== undefined) {
+        Keys.push(key)
+    }
 })
 
-let game = wasm.JSGame.start(now());
+document.addEventListener('keyup', ev => {
+    switch (ev.code) {
+        case "KeyA":
+        case "ArrowLeft":
+            Keys.push(Action.ReleaseLeft)
+            break;
+        case "KeyD":
+        case "ArrowRight":
+            Keys.push(Action.ReleaseRight)
+            break;
+    }
+})
+
+let params = new URLSearchParams(window.location.search);
+// Versus over the network when the page is opened with ?ws=ws://host:port,
+// needs the wasm package built with the netplay feature
+let url = params.get("ws");
+let net: wasm.JSNetGame = undefined;
+let game: wasm.JSGame | wasm.JSNetGame;
+if (url) {
+    net = wasm.JSNetGame.start(now());
+    game = net;
+    let socket = new WebSocket(url);
+    socket.binaryType = "arraybuffer";
+    socket.onmessage = ev => net.receive(new Uint8Array(ev.data));
+    socket.onclose = () => net.disconnected();
+    // Outgoing messages are queued by the game, flush them every frame
+    let flush = () => {
+        if (socket.readyState === WebSocket.OPEN) {
+            for (let bytes = net.outgoing(); bytes !== undefined; bytes = net.outgoing()) {
+                socket.send(bytes);
+            }
+        }
+        if (socket.readyState !== WebSocket.CLOSED) {
+            window.requestAnimationFrame(flush);
+        }
+    };
+    window.requestAnimationFrame(flush);
+    document.querySelector<HTMLDivElement>(".opponent").classList.add("visible");
+} else {
+    // Blocks turn like older games with ?rotation=nes or ?rotation=ars
+    let rotation = {
+        nes: wasm.RotationSystem.Nes,
+        ars: wasm.RotationSystem.Ars,
+    }[params.get("rotation")];
+    // Blocks twice as large with ?big
+    let big = params.has("big");
+    let single = wasm.JSGame.start_with(now(), rotation ?? wasm.RotationSystem.Srs, big);
+    // Locked blocks only show for a moment with ?invisible
+    single.set_invisible(params.has("invisible"));
+    // Race to clear that many rows of garbage with ?dig=10
+    let dig = params.get("dig");
+    if (dig) {
+        single.set_dig(parseInt(dig));
+    }
+    // Race to clear 40 lines with ?sprint, or that many with ?sprint=20
+    if (params.has("sprint")) {
+        single.set_sprint(parseInt(params.get("sprint")) || 40);
+    }
+    game = single;
+}
+
+document.addEventListener('visibilitychange', () => {
+    if (document.hidden) {
+        game.blur();
+    }
+})
 let renderer = new Playfield(
     20,
     10,
     document.querySelector<HTMLDivElement>(".playfield"),
     document.querySelector<HTMLDivElement>(".preview"),
+    document.querySelector<HTMLDivElement>(".hold"),
     document.querySelector<HTMLDivElement>(".score"),
     document.querySelector<HTMLDivElement>(".menu")
 )
+let opponent = new Playfield(
+    20,
+    10,
+    document.querySelector<HTMLDivElement>(".opponent .playfield"),
+    document.querySelector<HTMLDivElement>(".opponent .preview"),
+    document.querySelector<HTMLDivElement>(".opponent .hold"),
+    document.querySelector<HTMLDivElement>(".opponent .score"),
+    document.querySelector<HTMLDivElement>(".opponent .menu")
+)
+
+let chat = document.querySelector<HTMLDivElement>(".opponent .chat");
 
 function loop() {
-    let state = game.tick(now(), LastKey);
-    if (LastKey !== undefined) {
-        LastKey = undefined;
-    }
+    let state = game.tick(now(), Keys.shift());
     switch (state.action) {
         case wasm.JSAction.Draw:
-            renderer.render(Array.from(state.main()), Array.from(state.preview()), state.score());
+            renderer.render(
+                state.redraw_main() ? Array.from(state.main()) : undefined,
+                state.redraw_preview() ? Array.from(state.preview()) : undefined,
+                state.redraw_score() ? state.score() : undefined,
+                state.redraw_hold() ? Array.from(state.hold()) : undefined,
+                Array.from(state.clearing())
+            );
+            if (state.redraw_opponent()) {
+                opponent.render(Array.from(state.opponent_main()), undefined, state.opponent_score());
+            }
+            if (state.opponent_chat() !== undefined) {
+                chat.textContent = state.opponent_chat();
+            }
             break;
         case wasm.JSAction.Text:
             renderer.text(state.text_items().split("\n"), state.text_selected())